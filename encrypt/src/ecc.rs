@@ -7,7 +7,7 @@ use rand_core::OsRng;
 use serde::Serialize;
 use std::fs;
 use p256::PublicKey ;
-use aes_gcm::{Aes256Gcm, KeyInit, aead::{Aead, Payload}};
+use aes_gcm::{Aes256Gcm, KeyInit, aead::{Aead, AeadCore, Payload}};
 use p256::ecdh::diffie_hellman;
 use hkdf::Hkdf;
 use sha2::Sha256;
@@ -162,4 +162,63 @@ pub fn ecc_decrypt_key(key: &String, private_key: String) -> Result<String, Cryp
 
     let decrypted_message = String::from_utf8(decrypted_bytes).map_err(|e| CryptError::DecryptionError(format!("Failed to convert decrypted bytes to string: {}", e)))?;
     Ok(decrypted_message)
-}
\ No newline at end of file
+}
+// Server half of an ECDH exchange, returned to the peer as base64 strings
+pub struct SealedEnvelope {
+    pub ephemeral_public_key: String,
+    pub sealed: String,
+}
+
+// Encrypts `message` for the holder of `peer_public_key` (base64 SEC1 P-256).
+// A fresh server key per call means a leaked server key never opens an old envelope
+pub fn seal_for_peer(peer_public_key: &str, info: &[u8], message: &[u8]) -> Result<SealedEnvelope, CryptError> {
+    let peer_bytes = general_purpose::STANDARD.decode(peer_public_key).map_err(|e| CryptError::KeyDecodingError(e.to_string()))?;
+    let peer_public_key = PublicKey::from_sec1_bytes(&peer_bytes).map_err(|e| CryptError::KeyDecodingError(format!("Failed to decode peer public key: {}", e)))?;
+    let ephemeral_secret = SecretKey::random(&mut OsRng);
+    let cipher = envelope_cipher(&ephemeral_secret, &peer_public_key, info)?;
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher.encrypt(&nonce, message).map_err(|e| CryptError::KeyAgreementError(format!("Encryption failed: {}", e)))?;
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    Ok(SealedEnvelope {
+        ephemeral_public_key: general_purpose::STANDARD.encode(ephemeral_secret.public_key().to_sec1_bytes()),
+        sealed: general_purpose::STANDARD.encode(sealed),
+    })
+}
+
+// Peer side of `seal_for_peer`, what a terminal runs with the secret behind the public key it sent
+pub fn open_from_peer(secret: &SecretKey, ephemeral_public_key: &str, info: &[u8], sealed: &str) -> Result<Vec<u8>, CryptError> {
+    let public_bytes = general_purpose::STANDARD.decode(ephemeral_public_key).map_err(|e| CryptError::KeyDecodingError(e.to_string()))?;
+    let public_key = PublicKey::from_sec1_bytes(&public_bytes).map_err(|e| CryptError::KeyDecodingError(format!("Failed to decode ephemeral public key: {}", e)))?;
+    let sealed = general_purpose::STANDARD.decode(sealed).map_err(|e| CryptError::KeyDecodingError(e.to_string()))?;
+    const NONCE_LEN: usize = 12;
+    if sealed.len() <= NONCE_LEN {
+        return Err(CryptError::DecryptionError("sealed box is too short".to_string()));
+    }
+    let cipher = envelope_cipher(secret, &public_key, info)?;
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    cipher.decrypt(aes_gcm::Nonce::from_slice(nonce), ciphertext).map_err(|e| CryptError::DecryptionError(format!("Decryption failed: {}", e)))
+}
+
+fn envelope_cipher(secret: &SecretKey, public_key: &PublicKey, info: &[u8]) -> Result<Aes256Gcm, CryptError> {
+    let shared_secret = diffie_hellman::<NistP256>(secret.to_nonzero_scalar(), public_key.as_affine());
+    let hkdf = Hkdf::<Sha256>::new(None, shared_secret.raw_secret_bytes());
+    let mut key = [0u8; 32];
+    hkdf.expand(info, &mut key).map_err(|e| CryptError::KeyAgreementError(e.to_string()))?;
+    Ok(Aes256Gcm::new(aes_gcm::Key::<Aes256Gcm>::from_slice(&key)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sealed_envelope_opens_only_with_matching_info() {
+        let peer_secret = SecretKey::random(&mut OsRng);
+        let peer_public = general_purpose::STANDARD.encode(peer_secret.public_key().to_sec1_bytes());
+        let envelope = seal_for_peer(&peer_public, b"enroll", b"device keys").ok().unwrap();
+        let opened = open_from_peer(&peer_secret, &envelope.ephemeral_public_key, b"enroll", &envelope.sealed).ok().unwrap();
+        assert_eq!(opened, b"device keys");
+        assert!(open_from_peer(&peer_secret, &envelope.ephemeral_public_key, b"other", &envelope.sealed).is_err());
+    }
+}
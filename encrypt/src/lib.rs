@@ -1,9 +1,12 @@
 pub mod functions;
 use aes_gcm::Error;
+use sha2::{Digest, Sha256};
 use functions::{basic_decipher, generate_cipher_to_connect, price_decipher};
 
 use crate::functions::generate_random_values;
 pub mod ecc;
+pub mod signing;
+pub mod paseto;
 
 // const B: [u8; 16] = [9, 73, 120, 2, 107, 67, 83, 90, 89, 100, 88, 117, 119, 83, 4, 72, 79, 80, 81, 97, 79, 0, 1, 111];
 
@@ -54,13 +57,17 @@ pub fn generate_random_char(size: usize) -> String {
     return key.iter().map(|c| *c as char).collect::<String>();
 }
 
+// SHA-256 hex digest, used to store random tokens and secrets without keeping them in plain text
+pub fn hash_secret(secret: &str) -> String {
+    let digest = Sha256::digest(secret.as_bytes());
+    digest.iter().map(|b| format!("{:02x}", b)).collect::<String>()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use functions::{
-        generate_random_values, generate_cipher_to_connect, 
-        generate_decipher_to_connect, vec_to_string};
-    use crate::{ecc::generate_keys, functions::{aes_cipher, decrypt, encrypt}};
+    use functions::{generate_random_values, generate_cipher_to_connect};
+    use crate::{ecc::generate_keys, functions::{decrypt, encrypt}};
 
     use super::*;
     const ENCRYPTION_DEVICE_ID_KEY: [u8; 16] = [122, 80, 122, 105, 115, 78, 53, 55, 122, 102, 72, 119, 119, 103, 50, 76];
@@ -100,6 +107,52 @@ mod tests {
     //     assert!(result == 124)
     // }
 
+    #[test]
+    fn test_hash_secret_is_stable_hex() {
+        let hashed = hash_secret("secret");
+        assert_eq!(hashed.len(), 64);
+        assert_eq!(hashed, hash_secret("secret"));
+        assert_ne!(hashed, hash_secret("Secret"));
+    }
+
+    #[test]
+    fn test_request_signature_covers_every_field() {
+        use crate::signing::{derive_device_secret, sign_request, verify_request};
+        let secret = derive_device_secret("HlO9AYSfS83JPyNl");
+        let signature = sign_request(&secret, "POST", "/api/metal/payment", 1700000000, "n0nce", b"{}");
+        assert!(verify_request(&secret, "POST", "/api/metal/payment", 1700000000, "n0nce", b"{}", &signature));
+        assert!(!verify_request(&secret, "POST", "/api/metal/payment", 1700000000, "other", b"{}", &signature));
+        assert!(!verify_request(&secret, "POST", "/api/metal/payment", 1700000001, "n0nce", b"{}", &signature));
+        assert!(!verify_request(&secret, "POST", "/api/metal/heathly", 1700000000, "n0nce", b"{}", &signature));
+        assert!(!verify_request(&secret, "POST", "/api/metal/payment", 1700000000, "n0nce", b"{ }", &signature));
+        assert!(!verify_request(&derive_device_secret("other"), "POST", "/api/metal/payment", 1700000000, "n0nce", b"{}", &signature));
+        assert!(!verify_request(&secret, "POST", "/api/metal/payment", 1700000000, "n0nce", b"{}", "zz"));
+    }
+
+    // Vector 4-S-2 from the PASETO test suite
+    #[test]
+    fn test_paseto_v4_public_vector() {
+        let secret = hex_bytes("b4cbfb43df4ce210727d953e4a713307fa19bb7d9f85041438d9e11b942a3774");
+        let public = hex_bytes("1eb9dbbbbc047c03fd70604e0071f0987e16b28b757225c11f00415d0e20b1a2");
+        let message = br#"{"data":"this is a signed message","exp":"2022-01-01T00:00:00+00:00"}"#;
+        let footer = br#"{"kid":"zVhMiPBP9fRf2snEcT7gFTioeA9COcNy9DfgL1W60haN"}"#;
+        let expected = "v4.public.eyJkYXRhIjoidGhpcyBpcyBhIHNpZ25lZCBtZXNzYWdlIiwiZXhwIjoiMjAyMi0wMS0wMVQwMDowMDowMCswMDowMCJ9v3Jt8mx_TdM2ceTGoqwrh4yDFn0XsHvvV_D0DtwQxVrJEBMl0F2caAdgnpKlt4p7xBnx1HcO-SPo8FPp214HDw.eyJraWQiOiJ6VmhNaVBCUDlmUmYyc25FY1Q3Z0ZUaW9lQTlDT2NOeTlEZmdMMVc2MGhhTiJ9";
+        assert_eq!(paseto::public_key_of(&secret).unwrap().to_vec(), public);
+        assert_eq!(paseto::sign(&secret, message, footer, b"").unwrap(), expected);
+        assert_eq!(paseto::verify(&public, expected, b"").unwrap(), message.to_vec());
+        assert_eq!(paseto::footer(expected).unwrap(), footer.to_vec());
+        assert!(paseto::verify(&public, expected, b"other").is_err());
+        let (_, other) = paseto::generate_signing_key();
+        assert!(paseto::verify(&other, expected, b"").is_err());
+        let sealed = paseto::seal_secret(b"session key", &secret).unwrap();
+        assert_eq!(paseto::open_secret(b"session key", &sealed).unwrap(), secret);
+        assert!(paseto::open_secret(b"other key", &sealed).is_err());
+    }
+
+    fn hex_bytes(value: &str) -> Vec<u8> {
+        (0..value.len()).step_by(2).map(|i| u8::from_str_radix(&value[i..i + 2], 16).unwrap()).collect()
+    }
+
     #[test]
     fn test_device_uuid_max() {
         let id_key = b"HlO9AYSfS83JPyNl";
@@ -0,0 +1,96 @@
+use aes_gcm::{aead::Aead, AeadCore, Aes256Gcm, KeyInit, Nonce};
+use ed25519_dalek::SigningKey;
+use hkdf::Hkdf;
+use pasetors::{keys::{AsymmetricPublicKey, AsymmetricSecretKey}, token::UntrustedToken, version4::{PublicToken, V4}, Public};
+use rand_core::OsRng;
+use sha2::Sha256;
+
+// PASETO v4.public through `pasetors`, keys are stored as raw 32 byte Ed25519 seeds and public keys
+const WRAP_INFO: &[u8] = b"paseto-signing-key-wrap";
+const NONCE_LEN: usize = 12;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PasetoError {
+    InvalidToken,
+    InvalidSignature,
+    InvalidKey,
+}
+
+impl std::fmt::Display for PasetoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PasetoError::InvalidToken => write!(f, "Token is not a v4.public PASETO"),
+            PasetoError::InvalidSignature => write!(f, "Token signature does not verify"),
+            PasetoError::InvalidKey => write!(f, "Key bytes are not an Ed25519 key"),
+        }
+    }
+}
+
+// `(secret, public)` raw 32 byte Ed25519 key pair
+pub fn generate_signing_key() -> ([u8; 32], [u8; 32]) {
+    let signing = SigningKey::generate(&mut OsRng);
+    (signing.to_bytes(), signing.verifying_key().to_bytes())
+}
+
+pub fn public_key_of(secret: &[u8]) -> Result<[u8; 32], PasetoError> {
+    let secret: [u8; 32] = secret.try_into().map_err(|_| PasetoError::InvalidKey)?;
+    Ok(SigningKey::from_bytes(&secret).verifying_key().to_bytes())
+}
+
+// `pasetors` wants the seed followed by its public key
+fn secret_key(secret: &[u8]) -> Result<AsymmetricSecretKey<V4>, PasetoError> {
+    let mut bytes = secret.to_vec();
+    bytes.extend_from_slice(&public_key_of(secret)?);
+    AsymmetricSecretKey::<V4>::from(&bytes).map_err(|_| PasetoError::InvalidKey)
+}
+
+fn untrusted(token: &str) -> Result<UntrustedToken<Public, V4>, PasetoError> {
+    UntrustedToken::<Public, V4>::try_from(token).map_err(|_| PasetoError::InvalidToken)
+}
+
+fn optional(bytes: &[u8]) -> Option<&[u8]> {
+    (!bytes.is_empty()).then_some(bytes)
+}
+
+pub fn sign(secret: &[u8], message: &[u8], footer: &[u8], implicit: &[u8]) -> Result<String, PasetoError> {
+    PublicToken::sign(&secret_key(secret)?, message, optional(footer), optional(implicit))
+        .map_err(|_| PasetoError::InvalidToken)
+}
+
+// Footer is not authenticated until `verify` succeeds, only use it to pick the key
+pub fn footer(token: &str) -> Result<Vec<u8>, PasetoError> {
+    Ok(untrusted(token)?.untrusted_footer().to_vec())
+}
+
+// Returns the message once the signature checks out
+pub fn verify(public: &[u8], token: &str, implicit: &[u8]) -> Result<Vec<u8>, PasetoError> {
+    let public = AsymmetricPublicKey::<V4>::from(public).map_err(|_| PasetoError::InvalidKey)?;
+    let token = untrusted(token)?;
+    PublicToken::verify(&public, &token, None, optional(implicit))
+        .map(|trusted| trusted.payload().as_bytes().to_vec())
+        .map_err(|_| PasetoError::InvalidSignature)
+}
+
+fn wrapping_cipher(kek: &[u8]) -> Aes256Gcm {
+    let hkdf = Hkdf::<Sha256>::new(None, kek);
+    let mut key = [0u8; 32];
+    hkdf.expand(WRAP_INFO, &mut key).unwrap();
+    Aes256Gcm::new(aes_gcm::Key::<Aes256Gcm>::from_slice(&key))
+}
+
+// Encrypts a secret key for storage, `nonce || ciphertext` with a fresh random nonce
+pub fn seal_secret(kek: &[u8], secret: &[u8]) -> Result<Vec<u8>, PasetoError> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = wrapping_cipher(kek).encrypt(&nonce, secret).map_err(|_| PasetoError::InvalidKey)?;
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+pub fn open_secret(kek: &[u8], sealed: &[u8]) -> Result<Vec<u8>, PasetoError> {
+    if sealed.len() < NONCE_LEN {
+        return Err(PasetoError::InvalidKey);
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    wrapping_cipher(kek).decrypt(Nonce::from_slice(nonce), ciphertext).map_err(|_| PasetoError::InvalidKey)
+}
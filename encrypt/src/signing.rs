@@ -0,0 +1,47 @@
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const SIGNING_INFO: &[u8] = b"metal-request-signing";
+
+// Per-device HMAC key, derived so the raw `id_key` never signs anything itself
+pub fn derive_device_secret(id_key: &str) -> [u8; 32] {
+    let hkdf = Hkdf::<Sha256>::new(None, id_key.as_bytes());
+    let mut secret = [0u8; 32];
+    hkdf.expand(SIGNING_INFO, &mut secret).unwrap();
+    secret
+}
+
+// `METHOD\npath\ntimestamp\nnonce\nhex(sha256(body))`, what the terminal and the server both sign
+pub fn canonical_request(method: &str, path: &str, timestamp: i64, nonce: &str, body: &[u8]) -> String {
+    let body_hash = Sha256::digest(body).iter().map(|b| format!("{:02x}", b)).collect::<String>();
+    format!("{}\n{}\n{}\n{}\n{}", method.to_uppercase(), path, timestamp, nonce, body_hash)
+}
+
+pub fn sign_request(secret: &[u8], method: &str, path: &str, timestamp: i64, nonce: &str, body: &[u8]) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret).unwrap();
+    mac.update(canonical_request(method, path, timestamp, nonce, body).as_bytes());
+    mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect::<String>()
+}
+
+// Constant time comparison, a malformed hex signature simply fails
+pub fn verify_request(secret: &[u8], method: &str, path: &str, timestamp: i64, nonce: &str, body: &[u8], signature: &str) -> bool {
+    let Some(expected) = decode_hex(signature) else {
+        return false;
+    };
+    let mut mac = HmacSha256::new_from_slice(secret).unwrap();
+    mac.update(canonical_request(method, path, timestamp, nonce, body).as_bytes());
+    mac.verify_slice(&expected).is_ok()
+}
+
+fn decode_hex(value: &str) -> Option<Vec<u8>> {
+    if value.len() % 2 != 0 {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| value.get(i..i + 2).and_then(|pair| u8::from_str_radix(pair, 16).ok()))
+        .collect()
+}
@@ -9,8 +9,11 @@ pub enum Error {
     ParseError(std::num::ParseIntError),
     MissingParameters,
     WrongPassword,
+    InvalidCredentials,
+    TooManyAttempts(i64),
     CannotDecryptToken,
     Unauthorized,
+    Forbidden,
     ArgonLibraryError(ArgonError),
     DatabaseQueryError(sqlx::Error),
     MigrationError(sqlx::migrate::MigrateError),
@@ -20,6 +23,17 @@ pub enum Error {
     EnvError(VarError),
     AcmError(aes_gcm::Error),
     DeviceNotFound,
+    DeviceNotActive(String),
+    UpgradeRequired(String),
+    OutsideGeofence,
+    InvalidSignature,
+    InvalidInvitation,
+    PasskeyRejected,
+    InvalidMagicLink,
+    InvalidActivationCode,
+    MessageDeliveryError(String),
+    Conflict(String),
+    NotFound(String),
     InvalidSessionKey(String),
     TokenCreationError(String),
     // other variants...
@@ -56,8 +70,11 @@ impl std::fmt::Display for Error {
             Error::ParseError(ref err) => write!(f, "Cannot parse parameter: {}", err),
             Error::MissingParameters => write!(f, "Missing parameter"),
             Error::WrongPassword => write!(f, "Wrong password"),
+            Error::InvalidCredentials => write!(f, "Invalid email or password"),
+            Error::TooManyAttempts(seconds) => write!(f, "Too many login attempts, retry in {} seconds", seconds),
             Error::CannotDecryptToken => write!(f, "Cannot decrypt error"),
             Error::Unauthorized => write!(f, "No permission to change the underlying resource"),
+            Error::Forbidden => write!(f, "Role does not grant access to the underlying resource"),
             Error::ArgonLibraryError(_) => write!(f, "Cannot verifiy password"),
             Error::DatabaseQueryError(_) => write!(f, "Cannot update, invalid data"),
             Error::MigrationError(_) => write!(f, "Cannot migrate data"),
@@ -67,6 +84,17 @@ impl std::fmt::Display for Error {
             Error::EnvError(var_error) => write!(f, "Environment variable error: {}", var_error),
             Error::AcmError(error) => write!(f, "Aes GCM error: {}", error),
             Error::DeviceNotFound => write!(f, "Device not found"),
+            Error::DeviceNotActive(status) => write!(f, "Device is {}", status),
+            Error::UpgradeRequired(version) => write!(f, "Terminal app is older than {}", version),
+            Error::OutsideGeofence => write!(f, "Terminal is outside the business geofence"),
+            Error::InvalidSignature => write!(f, "Request signature is missing, invalid or outside the time window"),
+            Error::InvalidInvitation => write!(f, "Invitation is invalid or expired"),
+            Error::PasskeyRejected => write!(f, "Passkey ceremony failed"),
+            Error::InvalidMagicLink => write!(f, "Sign-in link is invalid, used or expired"),
+            Error::InvalidActivationCode => write!(f, "Activation code is invalid, used or expired"),
+            Error::MessageDeliveryError(var) => write!(f, "Message delivery failed {}", *var),
+            Error::Conflict(var) => write!(f, "Conflict {}", *var),
+            Error::NotFound(var) => write!(f, "Not found {}", *var),
             Error::InvalidSessionKey(var) => write!(f, "Session key invalid {}", *var),
             Error::TokenCreationError(var) => write!(f, "Token generation key invalid {}", *var)
         }
@@ -98,6 +126,50 @@ impl IntoResponse for Error {
                         StatusCode::NOT_FOUND,
                         "device not found".to_owned(),
                     ),
+            Error::DeviceNotActive(status) => (
+                        StatusCode::FORBIDDEN,
+                        format!("device is {}, it cannot take payments", status),
+                    ),
+            Error::UpgradeRequired(version) => (
+                        StatusCode::UPGRADE_REQUIRED,
+                        format!("terminal app must be updated to at least {}", version),
+                    ),
+            Error::OutsideGeofence => (
+                        StatusCode::FORBIDDEN,
+                        "payment was taken too far from the business location".to_owned(),
+                    ),
+            Error::InvalidSignature => (
+                        StatusCode::UNAUTHORIZED,
+                        "request signature is missing, invalid or outside the time window".to_owned(),
+                    ),
+            Error::InvalidInvitation => (
+                        StatusCode::BAD_REQUEST,
+                        "invitation is invalid, expired or addressed to another email".to_owned(),
+                    ),
+            Error::PasskeyRejected => (
+                        StatusCode::UNAUTHORIZED,
+                        "the passkey response could not be verified".to_owned(),
+                    ),
+            Error::InvalidMagicLink => (
+                        StatusCode::UNAUTHORIZED,
+                        "the sign-in link is invalid, already used or expired".to_owned(),
+                    ),
+            Error::InvalidActivationCode => (
+                        StatusCode::UNAUTHORIZED,
+                        "the activation code is invalid, already used or expired".to_owned(),
+                    ),
+            Error::MessageDeliveryError(error) => (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("message delivery failed: {}", error),
+                    ),
+            Error::Conflict(conflict) => (
+                        StatusCode::CONFLICT,
+                        format!("conflict: {}", conflict),
+                    ),
+            Error::NotFound(entity) => (
+                        StatusCode::NOT_FOUND,
+                        format!("{} not found", entity),
+                    ),
             Error::ParseError(parse_int_error) => (
                         StatusCode::BAD_REQUEST,
                         format!("failed to parse input: {}", parse_int_error),
@@ -110,6 +182,14 @@ impl IntoResponse for Error {
                         StatusCode::UNAUTHORIZED,
                         "incorrect password".to_owned(),
                     ),
+            Error::InvalidCredentials => (
+                        StatusCode::UNAUTHORIZED,
+                        "invalid email or password".to_owned(),
+                    ),
+            Error::TooManyAttempts(seconds) => (
+                        StatusCode::TOO_MANY_REQUESTS,
+                        format!("too many login attempts, retry in {} seconds", seconds),
+                    ),
             Error::CannotDecryptToken => (
                         StatusCode::UNAUTHORIZED,
                         "could not decrypt authentication token".to_owned(),
@@ -118,6 +198,10 @@ impl IntoResponse for Error {
                         StatusCode::UNAUTHORIZED,
                         "unauthorized access".to_owned(),
                     ),
+            Error::Forbidden => (
+                        StatusCode::FORBIDDEN,
+                        "your role does not permit this action".to_owned(),
+                    ),
             Error::ArgonLibraryError(error) => (
                         StatusCode::INTERNAL_SERVER_ERROR,
                         format!("argon2 hashing error: {}", error),
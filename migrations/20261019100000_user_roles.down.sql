@@ -0,0 +1,3 @@
+-- Add down migration script here
+ALTER TABLE "users" DROP CONSTRAINT IF EXISTS users_role_check;
+ALTER TABLE "users" DROP COLUMN IF EXISTS "role";
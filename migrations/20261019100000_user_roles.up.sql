@@ -0,0 +1,8 @@
+-- Roles decide which /api/auth routes a user may call
+ALTER TABLE "users"
+ADD COLUMN "role" varchar NOT NULL DEFAULT 'merchant';
+ALTER TABLE "users"
+ADD CONSTRAINT users_role_check CHECK ("role" IN ('merchant', 'bank_operator', 'admin'));
+-- Users that already own a bank row keep access to it
+UPDATE "users" SET "role" = 'bank_operator'
+WHERE "id" IN (SELECT "user_id" FROM "banks");
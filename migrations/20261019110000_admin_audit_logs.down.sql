@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS admin_audit_logs_entity_idx;
+DROP TABLE IF EXISTS "admin_audit_logs";
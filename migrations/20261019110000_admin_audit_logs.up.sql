@@ -0,0 +1,13 @@
+-- Every write made through /api/admin is recorded here
+CREATE TABLE "admin_audit_logs" (
+    "id" uuid UNIQUE PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4()),
+    "actor_id" uuid NOT NULL,
+    "action" varchar NOT NULL,
+    "entity" varchar NOT NULL,
+    "entity_id" varchar NOT NULL,
+    "detail" jsonb NOT NULL DEFAULT '{}',
+    "created_at" timestamptz NOT NULL DEFAULT (now())
+);
+ALTER TABLE "admin_audit_logs"
+ADD FOREIGN KEY ("actor_id") REFERENCES "users" ("id");
+CREATE INDEX admin_audit_logs_entity_idx ON "admin_audit_logs" ("entity", "entity_id");
@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS login_lockouts_key_idx;
+DROP TABLE IF EXISTS "login_lockouts";
+DROP TABLE IF EXISTS "login_attempts";
@@ -0,0 +1,19 @@
+-- Failed login counters, one row per email and per client IP
+CREATE TABLE "login_attempts" (
+    "scope" varchar NOT NULL,
+    "key" varchar NOT NULL,
+    "failures" integer NOT NULL DEFAULT 0,
+    "last_failed_at" timestamptz NOT NULL DEFAULT (now()),
+    "locked_until" timestamptz,
+    PRIMARY KEY ("scope", "key")
+);
+-- Append only record of every lockout that was applied
+CREATE TABLE "login_lockouts" (
+    "id" uuid UNIQUE PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4()),
+    "scope" varchar NOT NULL,
+    "key" varchar NOT NULL,
+    "failures" integer NOT NULL,
+    "locked_until" timestamptz NOT NULL,
+    "created_at" timestamptz NOT NULL DEFAULT (now())
+);
+CREATE INDEX login_lockouts_key_idx ON "login_lockouts" ("scope", "key");
@@ -0,0 +1,6 @@
+-- Add down migration script here
+ALTER TABLE "businesses" DROP CONSTRAINT IF EXISTS businesses_organization_id_fkey;
+ALTER TABLE "businesses" DROP COLUMN IF EXISTS "organization_id";
+DROP TABLE IF EXISTS "organization_invitations";
+DROP TABLE IF EXISTS "organization_members";
+DROP TABLE IF EXISTS "organizations";
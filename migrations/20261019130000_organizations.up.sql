@@ -0,0 +1,48 @@
+-- Businesses belong to an organization, users reach them through membership
+CREATE TABLE "organizations" (
+    "id" uuid UNIQUE PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4()),
+    "name" varchar NOT NULL,
+    "created_at" timestamptz NOT NULL DEFAULT (now()),
+    "updated_at" timestamptz NOT NULL DEFAULT (now())
+);
+CREATE TABLE "organization_members" (
+    "organization_id" uuid NOT NULL,
+    "user_id" uuid NOT NULL,
+    "role" varchar NOT NULL,
+    "created_at" timestamptz NOT NULL DEFAULT (now()),
+    "updated_at" timestamptz NOT NULL DEFAULT (now()),
+    PRIMARY KEY ("organization_id", "user_id"),
+    CONSTRAINT organization_members_role_check CHECK ("role" IN ('owner', 'manager', 'viewer'))
+);
+CREATE TABLE "organization_invitations" (
+    "id" uuid UNIQUE PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4()),
+    "organization_id" uuid NOT NULL,
+    "email" varchar NOT NULL,
+    "role" varchar NOT NULL,
+    "token_hash" varchar NOT NULL UNIQUE,
+    "invited_by" uuid NOT NULL,
+    "expires_at" timestamptz NOT NULL,
+    "accepted_at" timestamptz,
+    "created_at" timestamptz NOT NULL DEFAULT (now()),
+    CONSTRAINT organization_invitations_role_check CHECK ("role" IN ('owner', 'manager', 'viewer'))
+);
+ALTER TABLE "organization_members"
+ADD FOREIGN KEY ("organization_id") REFERENCES "organizations" ("id") ON DELETE CASCADE;
+ALTER TABLE "organization_members"
+ADD FOREIGN KEY ("user_id") REFERENCES "users" ("id");
+ALTER TABLE "organization_invitations"
+ADD FOREIGN KEY ("organization_id") REFERENCES "organizations" ("id") ON DELETE CASCADE;
+ALTER TABLE "organization_invitations"
+ADD FOREIGN KEY ("invited_by") REFERENCES "users" ("id");
+ALTER TABLE "businesses"
+ADD COLUMN "organization_id" uuid;
+-- Every current business owner gets an organization that reuses their user id
+INSERT INTO "organizations" ("id", "name")
+SELECT DISTINCT u.id, u.first_name || ' ' || u.last_name
+FROM "businesses" b JOIN "users" u ON u.id = b.user_id;
+INSERT INTO "organization_members" ("organization_id", "user_id", "role")
+SELECT "id", "id", 'owner' FROM "organizations";
+UPDATE "businesses" SET "organization_id" = "user_id";
+ALTER TABLE "businesses" ALTER COLUMN "organization_id" SET NOT NULL;
+ALTER TABLE "businesses"
+ADD FOREIGN KEY ("organization_id") REFERENCES "organizations" ("id");
@@ -0,0 +1,5 @@
+-- Add down migration script here
+-- Plain text keys cannot be recovered from their hashes, banks get an empty key
+ALTER TABLE "banks" ADD COLUMN "apk_key" varchar NOT NULL DEFAULT '';
+DROP INDEX IF EXISTS api_keys_bank_id_idx;
+DROP TABLE IF EXISTS "api_keys";
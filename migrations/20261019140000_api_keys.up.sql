@@ -0,0 +1,20 @@
+-- Bank API keys, only the SHA-256 of the full key is kept
+CREATE TABLE "api_keys" (
+    "id" uuid UNIQUE PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4()),
+    "bank_id" varchar NOT NULL REFERENCES "banks" ("id") ON DELETE CASCADE,
+    "name" varchar NOT NULL,
+    "prefix" varchar UNIQUE NOT NULL,
+    "secret_hash" varchar UNIQUE NOT NULL,
+    "scopes" text[] NOT NULL,
+    "expires_at" timestamptz,
+    "last_used_at" timestamptz,
+    "revoked_at" timestamptz,
+    "created_by" uuid REFERENCES "users" ("id") ON DELETE SET NULL,
+    "created_at" timestamptz NOT NULL DEFAULT (now())
+);
+CREATE INDEX api_keys_bank_id_idx ON "api_keys" ("bank_id");
+-- Existing plain text keys keep working until the bank rotates them
+INSERT INTO "api_keys" ("bank_id", "name", "prefix", "secret_hash", "scopes")
+SELECT "id", 'legacy', 'legacy_' || "id", encode(sha256(convert_to("apk_key", 'UTF8')), 'hex'), ARRAY['public', 'metal']
+FROM "banks";
+ALTER TABLE "banks" DROP COLUMN "apk_key";
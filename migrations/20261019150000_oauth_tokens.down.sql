@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS oauth_access_tokens_api_key_id_idx;
+DROP TABLE IF EXISTS "oauth_access_tokens";
@@ -0,0 +1,12 @@
+-- Opaque client_credentials access tokens, issued to bank API keys acting as OAuth clients
+CREATE TABLE "oauth_access_tokens" (
+    "id" uuid UNIQUE PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4()),
+    "api_key_id" uuid NOT NULL REFERENCES "api_keys" ("id") ON DELETE CASCADE,
+    "bank_id" varchar NOT NULL REFERENCES "banks" ("id") ON DELETE CASCADE,
+    "token_hash" varchar UNIQUE NOT NULL,
+    "scopes" text[] NOT NULL,
+    "expires_at" timestamptz NOT NULL,
+    "revoked_at" timestamptz,
+    "created_at" timestamptz NOT NULL DEFAULT (now())
+);
+CREATE INDEX oauth_access_tokens_api_key_id_idx ON "oauth_access_tokens" ("api_key_id");
@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS signing_keys_one_active_idx;
+DROP TABLE IF EXISTS "signing_keys";
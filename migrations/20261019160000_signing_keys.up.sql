@@ -0,0 +1,13 @@
+-- Ed25519 keys for v4.public tokens, the secret half is sealed with SESSION_KEY
+CREATE TABLE "signing_keys" (
+    "kid" varchar UNIQUE PRIMARY KEY NOT NULL,
+    "public_key" bytea NOT NULL,
+    "sealed_secret_key" bytea NOT NULL,
+    "status" varchar NOT NULL DEFAULT 'active' CHECK ("status" IN ('active', 'retired')),
+    "created_at" timestamptz NOT NULL DEFAULT (now()),
+    "retired_at" timestamptz
+);
+-- Exactly one key signs at a time, retired keys only verify
+CREATE UNIQUE INDEX signing_keys_one_active_idx ON "signing_keys" ("status") WHERE "status" = 'active';
+-- Sessions issued as v2.local can no longer be verified
+DELETE FROM "sessions";
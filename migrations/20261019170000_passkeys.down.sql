@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS "webauthn_challenges";
+DROP INDEX IF EXISTS passkeys_user_id_idx;
+DROP TABLE IF EXISTS "passkeys";
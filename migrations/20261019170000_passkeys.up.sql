@@ -0,0 +1,20 @@
+-- WebAuthn credentials, one row per authenticator a user registered
+CREATE TABLE "passkeys" (
+    "id" uuid UNIQUE PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4()),
+    "user_id" uuid NOT NULL REFERENCES "users" ("id") ON DELETE CASCADE,
+    "credential_id" varchar UNIQUE NOT NULL,
+    "public_key" bytea NOT NULL,
+    "sign_count" bigint NOT NULL DEFAULT 0,
+    "name" varchar NOT NULL,
+    "created_at" timestamptz NOT NULL DEFAULT (now()),
+    "last_used_at" timestamptz
+);
+CREATE INDEX passkeys_user_id_idx ON "passkeys" ("user_id");
+-- Single use challenges handed out by the start endpoints, login challenges have no user yet
+CREATE TABLE "webauthn_challenges" (
+    "challenge" varchar UNIQUE PRIMARY KEY NOT NULL,
+    "user_id" uuid REFERENCES "users" ("id") ON DELETE CASCADE,
+    "kind" varchar NOT NULL CHECK ("kind" IN ('registration', 'authentication')),
+    "expires_at" timestamptz NOT NULL,
+    "created_at" timestamptz NOT NULL DEFAULT (now())
+);
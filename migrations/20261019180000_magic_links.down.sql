@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS magic_links_user_id_idx;
+DROP TABLE IF EXISTS "magic_links";
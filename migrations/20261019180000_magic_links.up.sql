@@ -0,0 +1,11 @@
+-- One-time sign-in links, only the token hash is stored and the link only works from the requesting browser
+CREATE TABLE "magic_links" (
+    "id" uuid UNIQUE PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4()),
+    "user_id" uuid NOT NULL REFERENCES "users" ("id") ON DELETE CASCADE,
+    "token_hash" varchar UNIQUE NOT NULL,
+    "user_agent_hash" varchar NOT NULL,
+    "expires_at" timestamptz NOT NULL,
+    "used_at" timestamptz,
+    "created_at" timestamptz NOT NULL DEFAULT (now())
+);
+CREATE INDEX magic_links_user_id_idx ON "magic_links" ("user_id");
@@ -0,0 +1,3 @@
+-- Deleted users keep their row, with PII overwritten, so payments still point at someone
+ALTER TABLE "users"
+ADD COLUMN "deleted_at" timestamptz;
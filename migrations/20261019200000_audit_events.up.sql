@@ -0,0 +1,27 @@
+-- Security events from the whole API, rows are only ever inserted
+CREATE TABLE "audit_events" (
+    "id" uuid UNIQUE PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4()),
+    "event_type" varchar NOT NULL,
+    "outcome" varchar NOT NULL CHECK ("outcome" IN ('success', 'failure')),
+    "actor_type" varchar NOT NULL CHECK ("actor_type" IN ('anonymous', 'user', 'api_key', 'device')),
+    "actor_id" varchar,
+    "entity" varchar,
+    "entity_id" varchar,
+    "ip" varchar NOT NULL,
+    "user_agent" varchar NOT NULL,
+    "request_id" varchar NOT NULL,
+    "detail" jsonb NOT NULL DEFAULT '{}',
+    "created_at" timestamptz NOT NULL DEFAULT (now())
+);
+CREATE INDEX audit_events_created_at_idx ON "audit_events" ("created_at");
+CREATE INDEX audit_events_actor_idx ON "audit_events" ("actor_type", "actor_id", "created_at");
+CREATE INDEX audit_events_entity_idx ON "audit_events" ("entity", "entity_id");
+CREATE FUNCTION audit_events_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_events is append-only';
+END;
+$$ LANGUAGE plpgsql;
+CREATE TRIGGER audit_events_no_update BEFORE UPDATE OR DELETE ON "audit_events"
+FOR EACH ROW EXECUTE FUNCTION audit_events_append_only();
+CREATE TRIGGER audit_events_no_truncate BEFORE TRUNCATE ON "audit_events"
+FOR EACH STATEMENT EXECUTE FUNCTION audit_events_append_only();
@@ -0,0 +1,5 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS device_activations_device_id_idx;
+DROP TABLE IF EXISTS "device_activations";
+ALTER TABLE "devices" DROP COLUMN IF EXISTS "activated_at";
+ALTER TABLE "devices" DROP COLUMN IF EXISTS "status";
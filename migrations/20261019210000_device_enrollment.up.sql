@@ -0,0 +1,15 @@
+-- New terminals start pending and only get their keys by redeeming a one-time activation code
+ALTER TABLE "devices" ADD COLUMN "status" varchar NOT NULL DEFAULT 'active' CHECK ("status" IN ('pending', 'active'));
+ALTER TABLE "devices" ADD COLUMN "activated_at" timestamptz;
+UPDATE "devices" SET "activated_at" = "created_at";
+
+CREATE TABLE "device_activations" (
+    "id" uuid UNIQUE PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4()),
+    "device_id" uuid NOT NULL REFERENCES "devices" ("id") ON DELETE CASCADE,
+    "code_hash" varchar UNIQUE NOT NULL,
+    "created_by" uuid REFERENCES "users" ("id") ON DELETE SET NULL,
+    "expires_at" timestamptz NOT NULL,
+    "used_at" timestamptz,
+    "created_at" timestamptz NOT NULL DEFAULT (now())
+);
+CREATE INDEX device_activations_device_id_idx ON "device_activations" ("device_id");
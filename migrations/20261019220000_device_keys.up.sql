@@ -0,0 +1,15 @@
+-- Every key a terminal has held, a rotated key keeps working until `retires_at` so terminals can catch up
+CREATE TABLE "device_keys" (
+    "id" uuid UNIQUE PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4()),
+    "device_id" uuid NOT NULL REFERENCES "devices" ("id") ON DELETE CASCADE,
+    "version" integer NOT NULL,
+    "price_key" varchar NOT NULL,
+    "id_key" varchar NOT NULL,
+    "activated_at" timestamptz NOT NULL DEFAULT (now()),
+    "retires_at" timestamptz,
+    "created_at" timestamptz NOT NULL DEFAULT (now()),
+    UNIQUE ("device_id", "version")
+);
+
+INSERT INTO "device_keys" ("device_id", "version", "price_key", "id_key", "activated_at")
+SELECT "id", 1, "price_key", "id_key", COALESCE("activated_at", "created_at") FROM "devices" WHERE "status" = 'active';
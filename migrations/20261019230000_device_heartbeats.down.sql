@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS device_heartbeats_device_id_received_at_idx;
+DROP TABLE IF EXISTS "device_heartbeats";
+DROP TABLE IF EXISTS "device_health";
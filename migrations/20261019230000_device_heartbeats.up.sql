@@ -0,0 +1,24 @@
+-- Latest state reported by each terminal, one row per device
+CREATE TABLE "device_health" (
+    "device_id" uuid PRIMARY KEY NOT NULL REFERENCES "devices" ("id") ON DELETE CASCADE,
+    "last_seen_at" timestamptz NOT NULL,
+    "firmware_version" varchar NOT NULL,
+    "app_version" varchar NOT NULL,
+    "battery" smallint NOT NULL,
+    "signal" smallint NOT NULL,
+    "clock_drift_seconds" integer NOT NULL
+);
+
+-- Recent heartbeats, pruned to a rolling window when new ones arrive
+CREATE TABLE "device_heartbeats" (
+    "id" uuid UNIQUE PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4()),
+    "device_id" uuid NOT NULL REFERENCES "devices" ("id") ON DELETE CASCADE,
+    "firmware_version" varchar NOT NULL,
+    "app_version" varchar NOT NULL,
+    "battery" smallint NOT NULL,
+    "signal" smallint NOT NULL,
+    "device_time" timestamptz NOT NULL,
+    "clock_drift_seconds" integer NOT NULL,
+    "received_at" timestamptz NOT NULL DEFAULT (now())
+);
+CREATE INDEX device_heartbeats_device_id_received_at_idx ON "device_heartbeats" ("device_id", "received_at");
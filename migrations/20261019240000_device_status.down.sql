@@ -0,0 +1,6 @@
+-- Add down migration script here
+UPDATE "devices" SET "status" = 'active' WHERE "status" IN ('suspended', 'retired');
+ALTER TABLE "devices" DROP COLUMN IF EXISTS "status_changed_at";
+ALTER TABLE "devices" DROP COLUMN IF EXISTS "status_reason";
+ALTER TABLE "devices" DROP CONSTRAINT IF EXISTS "devices_status_check";
+ALTER TABLE "devices" ADD CONSTRAINT "devices_status_check" CHECK ("status" IN ('pending', 'active'));
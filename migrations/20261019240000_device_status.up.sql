@@ -0,0 +1,6 @@
+-- Suspended terminals can come back, retired ones never do
+ALTER TABLE "devices" DROP CONSTRAINT IF EXISTS "devices_status_check";
+ALTER TABLE "devices" ADD CONSTRAINT "devices_status_check" CHECK ("status" IN ('pending', 'active', 'suspended', 'retired'));
+ALTER TABLE "devices" ADD COLUMN "status_reason" varchar;
+ALTER TABLE "devices" ADD COLUMN "status_changed_at" timestamptz;
+UPDATE "devices" SET "status_changed_at" = COALESCE("activated_at", "created_at");
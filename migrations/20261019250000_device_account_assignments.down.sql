@@ -0,0 +1,3 @@
+-- Add down migration script here
+ALTER TABLE "payments" DROP COLUMN IF EXISTS "account_id";
+DROP TABLE IF EXISTS "device_account_assignments";
@@ -0,0 +1,16 @@
+-- Every period a terminal paid out into one account, the row without `effective_to` is the one in force
+CREATE TABLE "device_account_assignments" (
+    "id" uuid UNIQUE PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4()),
+    "device_id" uuid NOT NULL REFERENCES "devices" ("id") ON DELETE CASCADE,
+    "account_id" uuid NOT NULL REFERENCES "accounts" ("id"),
+    "effective_from" timestamptz NOT NULL DEFAULT (now()),
+    "effective_to" timestamptz,
+    "assigned_by" uuid REFERENCES "users" ("id"),
+    "created_at" timestamptz NOT NULL DEFAULT (now())
+);
+CREATE UNIQUE INDEX device_account_assignments_open_idx ON "device_account_assignments" ("device_id") WHERE "effective_to" IS NULL;
+INSERT INTO "device_account_assignments" ("device_id", "account_id", "effective_from")
+SELECT "id", "account_id", "created_at" FROM "devices";
+
+-- Payments name the account they were paid into, older rows only have the copied account details
+ALTER TABLE "payments" ADD COLUMN "account_id" uuid REFERENCES "accounts" ("id");
@@ -0,0 +1,5 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS "device_config_acks";
+DROP TABLE IF EXISTS "config_profile_attachments";
+DROP TABLE IF EXISTS "config_profile_versions";
+DROP TABLE IF EXISTS "config_profiles";
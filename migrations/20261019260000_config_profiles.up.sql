@@ -0,0 +1,35 @@
+-- Named terminal settings of one organization, every edit adds a version and the old ones stay readable
+CREATE TABLE "config_profiles" (
+    "id" uuid UNIQUE PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4()),
+    "organization_id" uuid NOT NULL REFERENCES "organizations" ("id") ON DELETE CASCADE,
+    "name" varchar NOT NULL,
+    "created_at" timestamptz NOT NULL DEFAULT (now()),
+    "updated_at" timestamptz NOT NULL DEFAULT (now())
+);
+
+CREATE TABLE "config_profile_versions" (
+    "id" uuid UNIQUE PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4()),
+    "profile_id" uuid NOT NULL REFERENCES "config_profiles" ("id") ON DELETE CASCADE,
+    "version" integer NOT NULL,
+    "settings" jsonb NOT NULL,
+    "created_by" uuid REFERENCES "users" ("id"),
+    "created_at" timestamptz NOT NULL DEFAULT (now()),
+    UNIQUE ("profile_id", "version")
+);
+
+-- A profile follows its newest version wherever it is attached, a device profile wins over its business profile
+CREATE TABLE "config_profile_attachments" (
+    "id" uuid UNIQUE PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4()),
+    "profile_id" uuid NOT NULL REFERENCES "config_profiles" ("id") ON DELETE CASCADE,
+    "business_id" uuid UNIQUE REFERENCES "businesses" ("id") ON DELETE CASCADE,
+    "device_id" uuid UNIQUE REFERENCES "devices" ("id") ON DELETE CASCADE,
+    "created_at" timestamptz NOT NULL DEFAULT (now()),
+    CHECK (("business_id" IS NULL) <> ("device_id" IS NULL))
+);
+
+-- Config version each terminal last reported as applied
+CREATE TABLE "device_config_acks" (
+    "device_id" uuid PRIMARY KEY NOT NULL REFERENCES "devices" ("id") ON DELETE CASCADE,
+    "version" varchar NOT NULL,
+    "applied_at" timestamptz NOT NULL DEFAULT (now())
+);
@@ -0,0 +1,14 @@
+-- Terminal app builds, `file_path` is relative to the release storage directory and `checksum` is its hex SHA-256
+CREATE TABLE "app_releases" (
+    "id" uuid UNIQUE PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4()),
+    "version" varchar NOT NULL,
+    "channel" varchar NOT NULL CHECK ("channel" IN ('stable', 'beta')),
+    "min_supported_version" varchar NOT NULL,
+    "file_path" varchar NOT NULL,
+    "checksum" varchar NOT NULL,
+    "size_bytes" bigint NOT NULL,
+    "notes" varchar,
+    "created_by" uuid REFERENCES "users" ("id"),
+    "created_at" timestamptz NOT NULL DEFAULT (now()),
+    UNIQUE ("channel", "version")
+);
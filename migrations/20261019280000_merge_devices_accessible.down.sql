@@ -0,0 +1,25 @@
+-- Add down migration script here
+CREATE TABLE "devices_accessible" (
+    "id" uuid UNIQUE PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4()),
+    "device_id" varchar NOT NULL,
+    "name" varchar NOT NULL,
+    "device_type" varchar NOT NULL,
+    "user_id" uuid NOT NULL REFERENCES "users" ("id"),
+    "created_at" timestamptz NOT NULL DEFAULT (now()),
+    "updated_at" timestamptz NOT NULL DEFAULT (now())
+);
+-- Devices keep their id as `main_id`, the creator falls back to the business owner
+INSERT INTO "devices_accessible" ("id", "device_id", "name", "device_type", "user_id", "created_at", "updated_at")
+SELECT d."id", d."device_id", d."name", d."device_type", COALESCE(d."created_by", b."user_id"), d."created_at", d."updated_at"
+FROM "devices" d
+JOIN "businesses" b ON b."id" = d."business_id";
+ALTER TABLE "devices" ADD COLUMN "main_id" uuid;
+UPDATE "devices" SET "main_id" = "id";
+ALTER TABLE "devices" ALTER COLUMN "main_id" SET NOT NULL;
+ALTER TABLE "devices" DROP COLUMN IF EXISTS "created_by";
+
+DROP INDEX IF EXISTS payments_device_id_idx;
+ALTER TABLE "payments" DROP CONSTRAINT IF EXISTS "payments_device_id_fkey";
+-- Payments without a device stay, `device_id` remains nullable rather than dropping money records
+ALTER TABLE "payments"
+ADD FOREIGN KEY ("device_id") REFERENCES "devices_accessible" ("id");
@@ -0,0 +1,20 @@
+-- `devices` is the only device table, payments point at it directly instead of at a hand-synced `devices_accessible` row
+ALTER TABLE "devices" ADD COLUMN "created_by" uuid REFERENCES "users" ("id");
+UPDATE "devices" d SET "created_by" = da."user_id"
+FROM "devices_accessible" da
+WHERE da."id" = d."main_id";
+
+-- Payments of devices deleted before the merge have nothing left to point at, they keep their copied account details
+ALTER TABLE "payments" DROP CONSTRAINT IF EXISTS "payments_device_id_fkey";
+ALTER TABLE "payments" ALTER COLUMN "device_id" DROP NOT NULL;
+UPDATE "payments" p SET "device_id" = d."id"
+FROM "devices" d
+WHERE d."main_id" = p."device_id";
+UPDATE "payments" SET "device_id" = NULL
+WHERE "device_id" NOT IN (SELECT "id" FROM "devices");
+ALTER TABLE "payments"
+ADD CONSTRAINT "payments_device_id_fkey" FOREIGN KEY ("device_id") REFERENCES "devices" ("id");
+CREATE INDEX payments_device_id_idx ON "payments" ("device_id");
+
+ALTER TABLE "devices" DROP COLUMN "main_id";
+DROP TABLE "devices_accessible";
@@ -0,0 +1,9 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS payments_geofence_flagged_idx;
+ALTER TABLE "payments" DROP COLUMN IF EXISTS "geofence_flagged";
+ALTER TABLE "payments" DROP COLUMN IF EXISTS "distance_m";
+ALTER TABLE "payments" DROP COLUMN IF EXISTS "longitude";
+ALTER TABLE "payments" DROP COLUMN IF EXISTS "latitude";
+ALTER TABLE "businesses" DROP CONSTRAINT IF EXISTS "businesses_geofence_action_check";
+ALTER TABLE "businesses" DROP COLUMN IF EXISTS "geofence_action";
+ALTER TABLE "businesses" DROP COLUMN IF EXISTS "geofence_radius_m";
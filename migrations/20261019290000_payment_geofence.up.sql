@@ -0,0 +1,11 @@
+-- How far from `lat`/`lon` a business's terminals may take payments, `off` ignores where they are
+ALTER TABLE "businesses" ADD COLUMN "geofence_radius_m" integer;
+ALTER TABLE "businesses" ADD COLUMN "geofence_action" varchar NOT NULL DEFAULT 'off';
+ALTER TABLE "businesses" ADD CONSTRAINT "businesses_geofence_action_check" CHECK ("geofence_action" IN ('off', 'flag', 'reject'));
+
+-- Position the terminal reported, if any, and its distance from the business
+ALTER TABLE "payments" ADD COLUMN "latitude" double precision;
+ALTER TABLE "payments" ADD COLUMN "longitude" double precision;
+ALTER TABLE "payments" ADD COLUMN "distance_m" double precision;
+ALTER TABLE "payments" ADD COLUMN "geofence_flagged" boolean NOT NULL DEFAULT false;
+CREATE INDEX payments_geofence_flagged_idx ON "payments" ("created_at") WHERE "geofence_flagged";
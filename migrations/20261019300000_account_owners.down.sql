@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS accounts_organization_id_idx;
+ALTER TABLE "accounts" DROP COLUMN IF EXISTS "organization_id";
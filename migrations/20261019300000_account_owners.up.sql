@@ -0,0 +1,13 @@
+-- Accounts belong to the organization whose terminals pay into them, only that organization can point terminals at them
+ALTER TABLE "accounts" ADD COLUMN "organization_id" uuid REFERENCES "organizations" ("id") ON DELETE SET NULL;
+-- An account already shared between organizations goes to the one that used it first
+UPDATE "accounts" a SET "organization_id" = owner."organization_id"
+FROM (
+    SELECT DISTINCT ON (das."account_id") das."account_id", b."organization_id"
+    FROM "device_account_assignments" das
+    JOIN "devices" d ON d."id" = das."device_id"
+    JOIN "businesses" b ON b."id" = d."business_id"
+    ORDER BY das."account_id", das."effective_from"
+) owner
+WHERE owner."account_id" = a."id";
+CREATE INDEX accounts_organization_id_idx ON "accounts" ("organization_id");
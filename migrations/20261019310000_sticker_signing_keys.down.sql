@@ -0,0 +1,5 @@
+-- Add down migration script here
+DELETE FROM "signing_keys" WHERE "purpose" = 'sticker';
+DROP INDEX IF EXISTS signing_keys_one_active_idx;
+ALTER TABLE "signing_keys" DROP COLUMN IF EXISTS "purpose";
+CREATE UNIQUE INDEX signing_keys_one_active_idx ON "signing_keys" ("status") WHERE "status" = 'active';
@@ -0,0 +1,4 @@
+-- Stickers are printed once and verified for years, they get their own keys apart from the session keys
+ALTER TABLE "signing_keys" ADD COLUMN "purpose" varchar NOT NULL DEFAULT 'session' CHECK ("purpose" IN ('session', 'sticker'));
+DROP INDEX IF EXISTS signing_keys_one_active_idx;
+CREATE UNIQUE INDEX signing_keys_one_active_idx ON "signing_keys" ("purpose") WHERE "status" = 'active';
@@ -0,0 +1,6 @@
+-- Admin writes are audit events like any other, the old rows move over as `admin.<entity>.<action>`
+INSERT INTO "audit_events" ("id", "event_type", "outcome", "actor_type", "actor_id", "entity", "entity_id", "ip", "user_agent", "request_id", "detail", "created_at")
+SELECT "id", 'admin.' || "entity" || '.' || "action", 'success', 'user', "actor_id"::varchar, "entity", "entity_id", '', '', '', "detail", "created_at"
+FROM "admin_audit_logs";
+DROP INDEX IF EXISTS admin_audit_logs_entity_idx;
+DROP TABLE "admin_audit_logs";
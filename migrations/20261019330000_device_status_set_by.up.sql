@@ -0,0 +1,2 @@
+-- Whether the current status came from the merchant or from an administrator, an admin suspension is not the merchant's to lift
+ALTER TABLE "devices" ADD COLUMN "status_set_by" varchar CHECK ("status_set_by" IN ('merchant', 'admin'));
@@ -0,0 +1,8 @@
+-- Nonces of signed terminal requests, kept until the signature window they were sent in has passed
+CREATE TABLE IF NOT EXISTS "request_nonces" (
+    "device_id" uuid NOT NULL REFERENCES "devices" ("id") ON DELETE CASCADE,
+    "nonce" varchar NOT NULL,
+    "expires_at" timestamptz NOT NULL,
+    PRIMARY KEY ("device_id", "nonce")
+);
+CREATE INDEX IF NOT EXISTS "request_nonces_expires_at_idx" ON "request_nonces" ("expires_at");
@@ -0,0 +1,2 @@
+-- Add down migration script here
+ALTER TABLE "payments" DROP COLUMN IF EXISTS "location_missing";
@@ -0,0 +1,2 @@
+-- A terminal without a GPS fix is not a fence breach, the payment only notes that the fence could not be checked
+ALTER TABLE "payments" ADD COLUMN "location_missing" boolean NOT NULL DEFAULT false;
@@ -1,7 +1,8 @@
 #![warn(clippy::all)]
 
 
-use sqlx::postgres::{PgPool, PgPoolOptions};
+use handle_error::Error;
+use sqlx::{postgres::{PgPool, PgPoolOptions}, Postgres, Transaction};
 
 
 
@@ -27,4 +28,10 @@ impl Store {
             connection: db_pool,
         }
     }
-}
\ No newline at end of file
+
+    // For writes that have to land together with others, like an admin change and its audit event
+    pub async fn begin(&self) -> Result<Transaction<'static, Postgres>, Error> {
+        self.connection.begin().await
+            .map_err(Error::DatabaseQueryError)
+    }
+}
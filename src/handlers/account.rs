@@ -2,6 +2,7 @@ use std::sync::Arc;
 
 use axum::{extract::State, http::StatusCode, response::{IntoResponse, Response}, Extension, Json};
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
 use crate::{db_store::Store, handlers::middleware::AuthenticatedUser, types::cache::Cache};
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -10,6 +11,7 @@ struct UserAccountResponse {
 }
 #[derive(Debug, Clone, Deserialize, Serialize)]
 struct AccountResponse {
+    id: Uuid,
     bank_name: String,
     account_name: String,
     account_number: String,
@@ -30,52 +32,27 @@ pub async fn get_account(
         Ok(u) => u,
         Err(e) => return Ok(e.into_response()),
     };
-    let businesses = match store.get_businesses_user_id(user_data.id).await {
-        Ok(b) => b,
+    let accounts = match store.get_accounts_member_id(user_data.id).await {
+        Ok(a) => a,
         Err(e) => return Ok(e.into_response())
     };
+    // Names are best effort, a bank list being reloaded only leaves them empty
+    let bank_file = cache.bank_files.try_read().ok();
     let mut account_response: Vec<AccountResponse> = Vec::new();
-    for b in businesses {
-        let devices = match store.get_devices_by_business_id(&b.id).await {
-            Ok(b) => b,
-            Err(e) => return Ok(e.into_response())
-        };
-        for device in devices {
-            let account = match store.get_account(&device.account_id).await {
-                Ok(b) => b,
-                Err(e) => return Ok(e.into_response())
-            };
-            let mut found = false;
-            for res in account_response.iter() {
-                if *res.bank_id == account.bank_id {
-                    found = true;
-                    break;
-                }
-            }
-            if found {
-                continue;
-            }
-            let bank_file_result = cache.bank_files.try_read();
-            match bank_file_result {
-                Ok(bank_file) => {
-                    let mut bank_name = String::new();
-                    for file in bank_file.iter() {
-                        if file.serial_number.to_string() == account.bank_id {
-                            bank_name = file.name.to_string();
-                        }
-                    }
-                    account_response.push(AccountResponse {
-                        bank_name: bank_name,
-                        account_name: account.account_name,
-                        account_number: account.account_number,
-                        bank_id: account.bank_id.to_string(),
-                        created_at: account.created_at,
-                        updated_at: account.updated_at
-                    });
-                },
-                Err(_) => continue
-            }
-        }
+    for account in accounts {
+        let bank_name = bank_file.as_ref()
+            .and_then(|files| files.iter().find(|file| file.serial_number.to_string() == account.bank_id))
+            .map(|file| file.name.to_string())
+            .unwrap_or_default();
+        account_response.push(AccountResponse {
+            id: account.id,
+            bank_name,
+            account_name: account.account_name,
+            account_number: account.account_number,
+            bank_id: account.bank_id.to_string(),
+            created_at: account.created_at,
+            updated_at: account.updated_at
+        });
     }
     return Ok((StatusCode::OK, Json(UserAccountResponse{list: account_response})).into_response());
 }
\ No newline at end of file
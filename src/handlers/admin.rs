@@ -0,0 +1,761 @@
+use std::sync::Arc;
+
+use axum::{body::Bytes, extract::{Path, Query, State}, http::{header::CONTENT_TYPE, HeaderMap, StatusCode}, response::{IntoResponse, Response}, Extension, Json};
+use chrono::{DateTime, Duration, Utc};
+use encrypt::{ecc::generate_keys, generate_random_char, hash_secret};
+use handle_error::Error;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{db_store::Store, handlers::{device::{change_device_status, issue_activation, notify_account_change, payout_account, retire_device, revoke_keys, ActivationResponse, DeviceAssignmentsResponse, DeviceStatusRequest, FleetHealthResponse}, middleware::AuthenticatedUser, privacy::ensure_not_sole_owner, token::PublicKeysResponse, user::hash_password}, tools::rand_gene::{activation_code, normalize_activation_code}, types::{account::{add_account_in, delete_account_in, update_account_in, Account}, admin::{Page, PageQuery}, app_release::{add_app_release_in, delete_app_release_in, release_checksum, release_file, AppRelease, ReleaseChannel}, audit::{add_audit_event_in, AuditActor, AuditEvent, AuditEventQuery, RequestContext}, bank::{add_bank_in, delete_bank_in, update_bank_in, Bank}, business::{add_business_in, delete_business_in, update_business_in, Business, GeoPoint}, cache::Cache, customer::{add_customer_in, delete_customer_in, update_customer_in, Customer}, device::{insert_device, Device, DeviceStatus, StatusAuthority}, device_assignment::update_device_and_account_in, device_health::{version_at_least, with_issues, FleetHealthQuery}, geofence::{update_business_geofence_in, GeofencePolicy}, import::{apply_import_in, parse_csv, parse_json, plan_import, ImportActivation, RowError}, organization::{get_or_create_owned_organization_in, MemberRole}, payments::PaymentSearchQuery, role::Role, token::delete_signing_key_in, user::{add_user_in, anonymize_user_in, update_user_in, update_user_role_in, User}}};
+
+// Matches what terminals may report in their heartbeat
+const MAX_RELEASE_VERSION_LEN: usize = 32;
+// Installers reach the shops over several days, so imported codes outlive the usual half hour
+const IMPORT_ACTIVATION_DAYS: i64 = 7;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AdminUserRequest {
+    pub first_name: String,
+    pub last_name: String,
+    pub email: String,
+    pub password: Option<String>,
+    pub role: Role,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AdminUserResponse {
+    pub id: Uuid,
+    pub first_name: String,
+    pub last_name: String,
+    pub email: String,
+    pub role: Role,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AdminBankRequest {
+    pub id: String,
+    pub user_id: Uuid,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AdminBusinessRequest {
+    pub user_id: Uuid,
+    pub organization_id: Option<Uuid>,
+    pub name: String,
+    pub location: String,
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AdminAccountRequest {
+    pub organization_id: Option<Uuid>,
+    pub bank_id: String,
+    pub account_name: String,
+    pub account_number: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AdminDeviceRequest {
+    pub name: String,
+    pub device_type: String,
+    pub business_id: Uuid,
+    pub account_id: Uuid,
+}
+
+// What the admin hands to whoever sets the terminal up, the code is shown once
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AdminCreatedDeviceResponse {
+    #[serde(flatten)]
+    pub device: AdminDeviceResponse,
+    pub activation: ActivationResponse,
+}
+
+// Device keys stay on the server, admins only see the public identity
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AdminDeviceResponse {
+    pub id: Uuid,
+    pub device_id: String,
+    pub name: String,
+    pub device_type: String,
+    pub business_id: Uuid,
+    pub account_id: Uuid,
+    pub status: DeviceStatus,
+    pub activated_at: Option<DateTime<Utc>>,
+    pub status_reason: Option<String>,
+    pub status_changed_at: Option<DateTime<Utc>>,
+    pub status_set_by: Option<StatusAuthority>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+// `file_path` is relative to the release storage directory, the file has to be there with that checksum
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AdminReleaseRequest {
+    pub version: String,
+    pub channel: ReleaseChannel,
+    pub min_supported_version: String,
+    pub file_path: String,
+    pub checksum: String,
+    pub notes: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct AdminReleasesResponse {
+    list: Vec<AppRelease>,
+}
+
+// `dry_run` only validates, nothing is written
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AdminImportQuery {
+    pub organization_id: Uuid,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct ImportedDevice {
+    row: usize,
+    id: Uuid,
+    device_id: String,
+    name: String,
+    business_id: Uuid,
+    account_id: Uuid,
+    activation: ActivationResponse,
+}
+
+// Activation codes are only shown here, once
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+struct AdminImportResponse {
+    dry_run: bool,
+    errors: Vec<RowError>,
+    businesses_created: usize,
+    accounts_created: usize,
+    devices_created: usize,
+    devices: Vec<ImportedDevice>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AdminCustomerRequest {
+    pub first_name: String,
+    pub last_name: String,
+    pub bank_id: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AdminCustomerResponse {
+    pub id: Uuid,
+    pub public_id: Uuid,
+    pub first_name: String,
+    pub last_name: String,
+    pub public_key: String,
+    pub bank_id: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<User> for AdminUserResponse {
+    fn from(user: User) -> Self {
+        AdminUserResponse {
+            id: user.id,
+            first_name: user.first_name,
+            last_name: user.last_name,
+            email: user.email,
+            role: user.role,
+            created_at: user.created_at,
+            updated_at: user.updated_at,
+            deleted_at: user.deleted_at,
+        }
+    }
+}
+
+impl From<Device> for AdminDeviceResponse {
+    fn from(device: Device) -> Self {
+        AdminDeviceResponse {
+            id: device.id,
+            device_id: device.device_id,
+            name: device.name,
+            device_type: device.device_type,
+            business_id: device.business_id,
+            account_id: device.account_id,
+            status: device.status,
+            activated_at: device.activated_at,
+            status_reason: device.status_reason,
+            status_changed_at: device.status_changed_at,
+            status_set_by: device.status_set_by,
+            created_at: device.created_at,
+            updated_at: device.updated_at,
+        }
+    }
+}
+
+impl From<Customer> for AdminCustomerResponse {
+    fn from(customer: Customer) -> Self {
+        AdminCustomerResponse {
+            id: customer.id,
+            public_id: customer.public_id,
+            first_name: customer.first_name,
+            last_name: customer.last_name,
+            public_key: customer.public_key,
+            bank_id: customer.bank_id,
+            created_at: customer.created_at,
+            updated_at: customer.updated_at,
+        }
+    }
+}
+
+fn map_page<T, R: From<T>>(page: Page<T>) -> Page<R> {
+    Page {
+        list: page.list.into_iter().map(R::from).collect(),
+        page: page.page,
+        per_page: page.per_page,
+        total: page.total,
+    }
+}
+
+// Admin writes land in `audit_events` with every other security event, as `admin.<entity>.<action>`.
+// The event commits in the write's own transaction, a change never lands without its record
+async fn audit(mut tx: Transaction<'_, Postgres>, context: &RequestContext, admin: &AuthenticatedUser, event_type: &'static str, entity: &'static str, entity_id: &str, detail: serde_json::Value) -> Result<(), Response> {
+    let event = AuditEvent::success(event_type, AuditActor::User(admin.user_id)).entity(entity, entity_id).detail(detail);
+    add_audit_event_in(&mut tx, context, &event).await.map_err(|e| e.into_response())?;
+    tx.commit().await.map_err(|e| Error::DatabaseQueryError(e).into_response())
+}
+
+// Security events recorded by the rest of the API, filtered by `AuditEventQuery`
+pub async fn list_audit_events(State(state): State<(Store, Arc<Cache>)>, Query(filter): Query<AuditEventQuery>) -> Result<impl IntoResponse, Response> {
+    let events = state.0.search_audit_events(&filter).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::OK, Json(events)).into_response())
+}
+
+// ========== Users ==========
+
+pub async fn list_users(State(state): State<(Store, Arc<Cache>)>, Query(page): Query<PageQuery>) -> Result<impl IntoResponse, Response> {
+    let users = state.0.search_users(&page).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::OK, Json(map_page::<User, AdminUserResponse>(users))).into_response())
+}
+
+pub async fn get_user(State(state): State<(Store, Arc<Cache>)>, Path(id): Path<Uuid>) -> Result<impl IntoResponse, Response> {
+    let user = state.0.get_user(id).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::OK, Json(AdminUserResponse::from(user))).into_response())
+}
+
+pub async fn create_user(State(state): State<(Store, Arc<Cache>)>, Extension(admin): Extension<AuthenticatedUser>, Extension(context): Extension<RequestContext>, Json(packet): Json<AdminUserRequest>) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let password = packet.password.ok_or(Error::MissingParameters.into_response())?;
+    let hashed_password = hash_password(password.as_bytes());
+    let mut tx = store.begin().await.map_err(|e| e.into_response())?;
+    let created = add_user_in(&mut tx, packet.first_name, hashed_password, packet.last_name, packet.email).await.map_err(|e| e.into_response())?;
+    update_user_role_in(&mut tx, &created.id, packet.role).await.map_err(|e| e.into_response())?;
+    audit(tx, &context, &admin, "admin.user.create", "user", &created.id.to_string(), json!({"email": created.email, "role": packet.role})).await?;
+    let user = store.get_user(created.id).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::CREATED, Json(AdminUserResponse::from(user))).into_response())
+}
+
+pub async fn update_user(State(state): State<(Store, Arc<Cache>)>, Extension(admin): Extension<AuthenticatedUser>, Extension(context): Extension<RequestContext>, Path(id): Path<Uuid>, Json(packet): Json<AdminUserRequest>) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let user = store.get_user(id).await.map_err(|e| e.into_response())?;
+    let mut tx = store.begin().await.map_err(|e| e.into_response())?;
+    update_user_in(&mut tx, &packet.first_name, &packet.email, &packet.last_name, &user.id).await.map_err(|e| e.into_response())?;
+    update_user_role_in(&mut tx, &user.id, packet.role).await.map_err(|e| e.into_response())?;
+    audit(tx, &context, &admin, "admin.user.update", "user", &user.id.to_string(), json!({"email": packet.email, "role": packet.role, "previous_role": user.role})).await?;
+    let user = store.get_user(user.id).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::OK, Json(AdminUserResponse::from(user))).into_response())
+}
+
+pub async fn delete_user(State(state): State<(Store, Arc<Cache>)>, Extension(admin): Extension<AuthenticatedUser>, Extension(context): Extension<RequestContext>, Path(id): Path<Uuid>) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    if id == admin.user_id {
+        return Err(Error::Forbidden.into_response());
+    }
+    ensure_not_sole_owner(&store, id).await?;
+    let mut tx = store.begin().await.map_err(|e| e.into_response())?;
+    if !anonymize_user_in(&mut tx, id).await.map_err(|e| e.into_response())? {
+        return Err(Error::Conflict("user is already deleted".to_string()).into_response());
+    }
+    audit(tx, &context, &admin, "admin.user.delete", "user", &id.to_string(), json!({"anonymized": true})).await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+// ========== Banks ==========
+
+pub async fn list_banks(State(state): State<(Store, Arc<Cache>)>, Query(page): Query<PageQuery>) -> Result<impl IntoResponse, Response> {
+    let banks = state.0.search_banks(&page).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::OK, Json(banks)).into_response())
+}
+
+pub async fn get_bank(State(state): State<(Store, Arc<Cache>)>, Path(id): Path<String>) -> Result<impl IntoResponse, Response> {
+    let bank = state.0.get_bank(&id).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::OK, Json(bank)).into_response())
+}
+
+pub async fn create_bank(State(state): State<(Store, Arc<Cache>)>, Extension(admin): Extension<AuthenticatedUser>, Extension(context): Extension<RequestContext>, Json(packet): Json<AdminBankRequest>) -> Result<impl IntoResponse, Response> {
+    let (store, cache) = state;
+    let now = Utc::now();
+    let bank = Bank {
+        id: packet.id,
+        user_id: packet.user_id,
+        created_at: now,
+        updated_at: now,
+    };
+    let mut tx = store.begin().await.map_err(|e| e.into_response())?;
+    add_bank_in(&mut tx, &bank).await.map_err(|e| e.into_response())?;
+    audit(tx, &context, &admin, "admin.bank.create", "bank", &bank.id, json!({"user_id": bank.user_id})).await?;
+    cache.reload_banks(&store).await;
+    Ok((StatusCode::CREATED, Json(bank)).into_response())
+}
+
+pub async fn update_bank(State(state): State<(Store, Arc<Cache>)>, Extension(admin): Extension<AuthenticatedUser>, Extension(context): Extension<RequestContext>, Path(id): Path<String>, Json(packet): Json<AdminBankRequest>) -> Result<impl IntoResponse, Response> {
+    let (store, cache) = state;
+    let mut bank = store.get_bank(&id).await.map_err(|e| e.into_response())?;
+    bank.user_id = packet.user_id;
+    bank.updated_at = Utc::now();
+    let mut tx = store.begin().await.map_err(|e| e.into_response())?;
+    update_bank_in(&mut tx, &bank).await.map_err(|e| e.into_response())?;
+    audit(tx, &context, &admin, "admin.bank.update", "bank", &bank.id, json!({"user_id": bank.user_id})).await?;
+    cache.reload_banks(&store).await;
+    Ok((StatusCode::OK, Json(bank)).into_response())
+}
+
+pub async fn delete_bank(State(state): State<(Store, Arc<Cache>)>, Extension(admin): Extension<AuthenticatedUser>, Extension(context): Extension<RequestContext>, Path(id): Path<String>) -> Result<impl IntoResponse, Response> {
+    let (store, cache) = state;
+    let mut tx = store.begin().await.map_err(|e| e.into_response())?;
+    delete_bank_in(&mut tx, &id).await.map_err(|e| e.into_response())?;
+    audit(tx, &context, &admin, "admin.bank.delete", "bank", &id, json!({})).await?;
+    cache.reload_banks(&store).await;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+// ========== Businesses ==========
+
+pub async fn list_businesses(State(state): State<(Store, Arc<Cache>)>, Query(page): Query<PageQuery>) -> Result<impl IntoResponse, Response> {
+    let businesses = state.0.search_businesses(&page).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::OK, Json(businesses)).into_response())
+}
+
+pub async fn get_business(State(state): State<(Store, Arc<Cache>)>, Path(id): Path<Uuid>) -> Result<impl IntoResponse, Response> {
+    let business = state.0.get_business(id).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::OK, Json(business)).into_response())
+}
+
+pub async fn create_business(State(state): State<(Store, Arc<Cache>)>, Extension(admin): Extension<AuthenticatedUser>, Extension(context): Extension<RequestContext>, Json(packet): Json<AdminBusinessRequest>) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let mut tx = store.begin().await.map_err(|e| e.into_response())?;
+    let organization_id = match packet.organization_id {
+        Some(id) => id,
+        None => get_or_create_owned_organization_in(&mut tx, packet.user_id).await.map_err(|e| e.into_response())?,
+    };
+    let now = Utc::now();
+    let business = Business {
+        id: Uuid::new_v4(),
+        user_id: packet.user_id,
+        organization_id,
+        name: packet.name,
+        location: packet.location,
+        geolocation: (packet.longitude, packet.latitude),
+        lat: packet.latitude,
+        long: packet.longitude,
+        created_at: now,
+        updated_at: now,
+    };
+    add_business_in(&mut tx, &business).await.map_err(|e| e.into_response())?;
+    audit(tx, &context, &admin, "admin.business.create", "business", &business.id.to_string(), json!({"user_id": business.user_id, "organization_id": business.organization_id, "name": business.name})).await?;
+    Ok((StatusCode::CREATED, Json(business)).into_response())
+}
+
+pub async fn update_business(State(state): State<(Store, Arc<Cache>)>, Extension(admin): Extension<AuthenticatedUser>, Extension(context): Extension<RequestContext>, Path(id): Path<Uuid>, Json(packet): Json<AdminBusinessRequest>) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let mut business = store.get_business(id).await.map_err(|e| e.into_response())?;
+    business.name = packet.name;
+    business.location = packet.location;
+    business.geolocation = (packet.longitude, packet.latitude);
+    business.lat = packet.latitude;
+    business.long = packet.longitude;
+    business.updated_at = Utc::now();
+    let mut tx = store.begin().await.map_err(|e| e.into_response())?;
+    update_business_in(&mut tx, &business).await.map_err(|e| e.into_response())?;
+    audit(tx, &context, &admin, "admin.business.update", "business", &business.id.to_string(), json!({"name": business.name})).await?;
+    Ok((StatusCode::OK, Json(business)).into_response())
+}
+
+pub async fn delete_business(State(state): State<(Store, Arc<Cache>)>, Extension(admin): Extension<AuthenticatedUser>, Extension(context): Extension<RequestContext>, Path(id): Path<Uuid>) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let mut tx = store.begin().await.map_err(|e| e.into_response())?;
+    delete_business_in(&mut tx, id).await.map_err(|e| e.into_response())?;
+    audit(tx, &context, &admin, "admin.business.delete", "business", &id.to_string(), json!({})).await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+pub async fn update_business_geofence(State(state): State<(Store, Arc<Cache>)>, Extension(admin): Extension<AuthenticatedUser>, Extension(context): Extension<RequestContext>, Path(id): Path<Uuid>, Json(packet): Json<GeofencePolicy>) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let business = store.get_business(id).await.map_err(|e| e.into_response())?;
+    packet.validate(&GeoPoint { latitude: business.lat, longitude: business.long }).map_err(|e| e.into_response())?;
+    let mut tx = store.begin().await.map_err(|e| e.into_response())?;
+    update_business_geofence_in(&mut tx, business.id, &packet).await.map_err(|e| e.into_response())?;
+    audit(tx, &context, &admin, "admin.business.update_geofence", "business", &business.id.to_string(), json!({"radius_m": packet.radius_m, "action": packet.action})).await?;
+    Ok((StatusCode::OK, Json(packet)).into_response())
+}
+
+// ========== Accounts ==========
+
+pub async fn list_accounts(State(state): State<(Store, Arc<Cache>)>, Query(page): Query<PageQuery>) -> Result<impl IntoResponse, Response> {
+    let accounts = state.0.search_accounts(&page).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::OK, Json(accounts)).into_response())
+}
+
+pub async fn get_account(State(state): State<(Store, Arc<Cache>)>, Path(id): Path<Uuid>) -> Result<impl IntoResponse, Response> {
+    let account = state.0.get_account(&id).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::OK, Json(account)).into_response())
+}
+
+pub async fn create_account(State(state): State<(Store, Arc<Cache>)>, Extension(admin): Extension<AuthenticatedUser>, Extension(context): Extension<RequestContext>, Json(packet): Json<AdminAccountRequest>) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let now = Utc::now();
+    let account = Account {
+        id: Uuid::new_v4(),
+        organization_id: packet.organization_id,
+        bank_id: packet.bank_id,
+        account_name: packet.account_name,
+        account_number: packet.account_number,
+        created_at: now,
+        updated_at: now,
+    };
+    let mut tx = store.begin().await.map_err(|e| e.into_response())?;
+    add_account_in(&mut tx, &account).await.map_err(|e| e.into_response())?;
+    audit(tx, &context, &admin, "admin.account.create", "account", &account.id.to_string(), json!({"organization_id": account.organization_id, "bank_id": account.bank_id, "account_number": account.account_number})).await?;
+    Ok((StatusCode::CREATED, Json(account)).into_response())
+}
+
+pub async fn update_account(State(state): State<(Store, Arc<Cache>)>, Extension(admin): Extension<AuthenticatedUser>, Extension(context): Extension<RequestContext>, Path(id): Path<Uuid>, Json(packet): Json<AdminAccountRequest>) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let mut account = store.get_account(&id).await.map_err(|e| e.into_response())?;
+    let previous_number = account.account_number.clone();
+    account.organization_id = packet.organization_id;
+    account.bank_id = packet.bank_id;
+    account.account_name = packet.account_name;
+    account.account_number = packet.account_number;
+    account.updated_at = Utc::now();
+    let mut tx = store.begin().await.map_err(|e| e.into_response())?;
+    update_account_in(&mut tx, &account).await.map_err(|e| e.into_response())?;
+    audit(tx, &context, &admin, "admin.account.update", "account", &account.id.to_string(), json!({"account_number": account.account_number, "previous_account_number": previous_number})).await?;
+    Ok((StatusCode::OK, Json(account)).into_response())
+}
+
+pub async fn delete_account(State(state): State<(Store, Arc<Cache>)>, Extension(admin): Extension<AuthenticatedUser>, Extension(context): Extension<RequestContext>, Path(id): Path<Uuid>) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let mut tx = store.begin().await.map_err(|e| e.into_response())?;
+    delete_account_in(&mut tx, id).await.map_err(|e| e.into_response())?;
+    audit(tx, &context, &admin, "admin.account.delete", "account", &id.to_string(), json!({})).await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+// ========== Devices ==========
+
+pub async fn list_devices(State(state): State<(Store, Arc<Cache>)>, Query(page): Query<PageQuery>) -> Result<impl IntoResponse, Response> {
+    let devices = state.0.search_devices(&page).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::OK, Json(map_page::<Device, AdminDeviceResponse>(devices))).into_response())
+}
+
+pub async fn list_device_health(State(state): State<(Store, Arc<Cache>)>, Query(query): Query<FleetHealthQuery>) -> Result<impl IntoResponse, Response> {
+    let fleet = state.0.get_fleet_health(None).await.map_err(|e| e.into_response())?;
+    let policy = state.0.get_fleet_policy().await.map_err(|e| e.into_response())?;
+    let list = with_issues(fleet, &policy, query.issue);
+    Ok((StatusCode::OK, Json(FleetHealthResponse { list })).into_response())
+}
+
+pub async fn get_device(State(state): State<(Store, Arc<Cache>)>, Path(id): Path<Uuid>) -> Result<impl IntoResponse, Response> {
+    let device = state.0.get_device(id).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::OK, Json(AdminDeviceResponse::from(device))).into_response())
+}
+
+pub async fn create_device(State(state): State<(Store, Arc<Cache>)>, Extension(admin): Extension<AuthenticatedUser>, Extension(context): Extension<RequestContext>, Json(packet): Json<AdminDeviceRequest>) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let business = store.get_business(packet.business_id).await.map_err(|e| e.into_response())?;
+    payout_account(&store, packet.account_id, business.organization_id, StatusAuthority::Admin).await?;
+    let now = Utc::now();
+    let device = Device {
+        id: Uuid::new_v4(),
+        device_id: generate_random_char(16),
+        name: packet.name,
+        account_id: packet.account_id,
+        device_type: packet.device_type,
+        apk_key: generate_random_char(16),
+        price_key: generate_random_char(16),
+        id_key: generate_random_char(16),
+        business_id: business.id,
+        // Keys above are placeholders until the terminal redeems an activation code
+        status: DeviceStatus::Pending,
+        activated_at: None,
+        status_reason: None,
+        status_changed_at: Some(now),
+        status_set_by: None,
+        created_at: now,
+        updated_at: now,
+    };
+    let mut tx = store.begin().await.map_err(|e| e.into_response())?;
+    insert_device(&mut tx, &device, business.user_id).await.map_err(|e| e.into_response())?;
+    let activation = issue_activation(&mut tx, device.id, admin.user_id).await?;
+    audit(tx, &context, &admin, "admin.device.create", "device", &device.id.to_string(), json!({"business_id": device.business_id, "account_id": device.account_id})).await?;
+    Ok((StatusCode::CREATED, Json(AdminCreatedDeviceResponse { device: AdminDeviceResponse::from(device), activation })).into_response())
+}
+
+pub async fn update_device(State(state): State<(Store, Arc<Cache>)>, Extension(admin): Extension<AuthenticatedUser>, Extension(context): Extension<RequestContext>, Path(id): Path<Uuid>, Json(packet): Json<AdminDeviceRequest>) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let cache = state.1;
+    let mut device = store.get_device(id).await.map_err(|e| e.into_response())?;
+    let business = store.get_business(packet.business_id).await.map_err(|e| e.into_response())?;
+    let previous = store.get_account(&device.account_id).await.map_err(|e| e.into_response())?;
+    let account = payout_account(&store, packet.account_id, business.organization_id, StatusAuthority::Admin).await?;
+    device.name = packet.name;
+    device.device_type = packet.device_type;
+    device.business_id = business.id;
+    device.updated_at = Utc::now();
+    // A new account closes the current assignment, the owners are told like for a merchant change
+    let mut tx = store.begin().await.map_err(|e| e.into_response())?;
+    let reassigned = update_device_and_account_in(&mut tx, &device, account.id, admin.user_id).await.map_err(|e| e.into_response())?;
+    if reassigned {
+        device.account_id = account.id;
+    }
+    audit(tx, &context, &admin, "admin.device.update", "device", &device.id.to_string(), json!({"account_id": device.account_id, "previous_account_id": previous.id})).await?;
+    if reassigned {
+        notify_account_change(&store, &cache, &device, &previous, &account).await?;
+    }
+    Ok((StatusCode::OK, Json(AdminDeviceResponse::from(device))).into_response())
+}
+
+pub async fn list_device_accounts(State(state): State<(Store, Arc<Cache>)>, Path(id): Path<Uuid>) -> Result<impl IntoResponse, Response> {
+    let list = state.0.get_device_assignments(id).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::OK, Json(DeviceAssignmentsResponse { list })).into_response())
+}
+
+pub async fn update_device_status(State(state): State<(Store, Arc<Cache>)>, Extension(admin): Extension<AuthenticatedUser>, Extension(context): Extension<RequestContext>, Path(id): Path<Uuid>, Json(packet): Json<DeviceStatusRequest>) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let device = store.get_device(id).await.map_err(|e| e.into_response())?;
+    let previous = device.status;
+    let mut tx = store.begin().await.map_err(|e| e.into_response())?;
+    let reason = change_device_status(&mut tx, &device, &packet, StatusAuthority::Admin).await?;
+    audit(tx, &context, &admin, "admin.device.update_status", "device", &id.to_string(), json!({"from": previous, "to": packet.status, "reason": reason})).await?;
+    let device = store.get_device(id).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::OK, Json(AdminDeviceResponse::from(device))).into_response())
+}
+
+pub async fn delete_device(State(state): State<(Store, Arc<Cache>)>, Extension(admin): Extension<AuthenticatedUser>, Extension(context): Extension<RequestContext>, Path(id): Path<Uuid>) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let device = store.get_device(id).await.map_err(|e| e.into_response())?;
+    let mut tx = store.begin().await.map_err(|e| e.into_response())?;
+    retire_device(&mut tx, &device, StatusAuthority::Admin).await?;
+    audit(tx, &context, &admin, "admin.device.delete", "device", &id.to_string(), json!({"device_id": device.device_id})).await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+pub async fn revoke_device_keys(State(state): State<(Store, Arc<Cache>)>, Extension(admin): Extension<AuthenticatedUser>, Extension(context): Extension<RequestContext>, Path(id): Path<Uuid>) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let device = store.get_device(id).await.map_err(|e| e.into_response())?;
+    let previous = device.status;
+    let mut tx = store.begin().await.map_err(|e| e.into_response())?;
+    revoke_keys(&mut tx, &device, StatusAuthority::Admin).await?;
+    let activation = issue_activation(&mut tx, device.id, admin.user_id).await?;
+    audit(tx, &context, &admin, "admin.device.revoke_keys", "device", &id.to_string(), json!({"from": previous})).await?;
+    let device = store.get_device(id).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::OK, Json(AdminCreatedDeviceResponse { device: AdminDeviceResponse::from(device), activation })).into_response())
+}
+
+// ========== Customers ==========
+
+pub async fn list_customers(State(state): State<(Store, Arc<Cache>)>, Query(page): Query<PageQuery>) -> Result<impl IntoResponse, Response> {
+    let customers = state.0.search_customers(&page).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::OK, Json(map_page::<Customer, AdminCustomerResponse>(customers))).into_response())
+}
+
+pub async fn get_customer(State(state): State<(Store, Arc<Cache>)>, Path(id): Path<Uuid>) -> Result<impl IntoResponse, Response> {
+    let customer = state.0.get_customer(id).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::OK, Json(AdminCustomerResponse::from(customer))).into_response())
+}
+
+pub async fn create_customer(State(state): State<(Store, Arc<Cache>)>, Extension(admin): Extension<AuthenticatedUser>, Extension(context): Extension<RequestContext>, Json(packet): Json<AdminCustomerRequest>) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    store.get_bank(&packet.bank_id).await.map_err(|e| e.into_response())?;
+    let keys = generate_keys().map_err(|e| e.into_response())?;
+    let mut tx = store.begin().await.map_err(|e| e.into_response())?;
+    let id = add_customer_in(&mut tx, packet.first_name, packet.last_name, &keys, &packet.bank_id, Uuid::new_v4()).await.map_err(|e| e.into_response())?;
+    audit(tx, &context, &admin, "admin.customer.create", "customer", &id.to_string(), json!({"bank_id": packet.bank_id})).await?;
+    let customer = store.get_customer(id).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::CREATED, Json(AdminCustomerResponse::from(customer))).into_response())
+}
+
+pub async fn update_customer(State(state): State<(Store, Arc<Cache>)>, Extension(admin): Extension<AuthenticatedUser>, Extension(context): Extension<RequestContext>, Path(id): Path<Uuid>, Json(packet): Json<AdminCustomerRequest>) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let mut customer = store.get_customer(id).await.map_err(|e| e.into_response())?;
+    customer.first_name = packet.first_name;
+    customer.last_name = packet.last_name;
+    customer.bank_id = packet.bank_id;
+    customer.updated_at = Utc::now();
+    let mut tx = store.begin().await.map_err(|e| e.into_response())?;
+    update_customer_in(&mut tx, &customer).await.map_err(|e| e.into_response())?;
+    audit(tx, &context, &admin, "admin.customer.update", "customer", &customer.id.to_string(), json!({"bank_id": customer.bank_id})).await?;
+    Ok((StatusCode::OK, Json(AdminCustomerResponse::from(customer))).into_response())
+}
+
+pub async fn delete_customer(State(state): State<(Store, Arc<Cache>)>, Extension(admin): Extension<AuthenticatedUser>, Extension(context): Extension<RequestContext>, Path(id): Path<Uuid>) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let mut tx = store.begin().await.map_err(|e| e.into_response())?;
+    delete_customer_in(&mut tx, id).await.map_err(|e| e.into_response())?;
+    audit(tx, &context, &admin, "admin.customer.delete", "customer", &id.to_string(), json!({})).await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+// ========== Imports ==========
+
+// CSV with a header row when sent as `text/csv`, a JSON array of rows otherwise.
+// Every row is checked first, then businesses, accounts and devices are created in one transaction
+pub async fn import_onboarding(State(state): State<(Store, Arc<Cache>)>, Extension(admin): Extension<AuthenticatedUser>, Extension(context): Extension<RequestContext>, Query(query): Query<AdminImportQuery>, headers: HeaderMap, body: Bytes) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let owner = store.get_members(query.organization_id).await.map_err(|e| e.into_response())?
+        .into_iter()
+        .find(|member| member.role == MemberRole::Owner)
+        .ok_or(Error::Conflict("organization has no owner to hold the businesses".to_string()).into_response())?;
+    let is_csv = headers.get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/csv"));
+    let rows = if is_csv { parse_csv(&body) } else { parse_json(&body) };
+    let import_context = store.get_import_context(query.organization_id).await.map_err(|e| e.into_response())?;
+    let plan = match rows.and_then(|rows| plan_import(&rows, &import_context, query.organization_id, owner.user_id)) {
+        Ok(plan) => plan,
+        Err(errors) => {
+            let status = if query.dry_run { StatusCode::OK } else { StatusCode::UNPROCESSABLE_ENTITY };
+            return Ok((status, Json(AdminImportResponse { dry_run: query.dry_run, errors, ..Default::default() })).into_response());
+        }
+    };
+    let mut response = AdminImportResponse {
+        dry_run: query.dry_run,
+        businesses_created: plan.businesses.len(),
+        accounts_created: plan.accounts.len(),
+        devices_created: plan.devices.len(),
+        ..Default::default()
+    };
+    if query.dry_run {
+        return Ok((StatusCode::OK, Json(response)).into_response());
+    }
+    let expires_at = Utc::now() + Duration::days(IMPORT_ACTIVATION_DAYS);
+    let mut activations = Vec::new();
+    for planned in plan.devices.iter() {
+        let code = activation_code();
+        activations.push(ImportActivation { device_id: planned.device.id, code_hash: hash_secret(&normalize_activation_code(&code)), expires_at });
+        response.devices.push(ImportedDevice {
+            row: planned.row,
+            id: planned.device.id,
+            device_id: planned.device.device_id.clone(),
+            name: planned.device.name.clone(),
+            business_id: planned.device.business_id,
+            account_id: planned.device.account_id,
+            activation: ActivationResponse { code, expires_at },
+        });
+    }
+    let mut tx = store.begin().await.map_err(|e| e.into_response())?;
+    apply_import_in(&mut tx, &plan, owner.user_id, admin.user_id, &activations).await.map_err(|e| e.into_response())?;
+    audit(tx, &context, &admin, "admin.organization.import", "organization", &query.organization_id.to_string(), json!({"businesses": response.businesses_created, "accounts": response.accounts_created, "devices": response.devices_created})).await?;
+    Ok((StatusCode::CREATED, Json(response)).into_response())
+}
+
+// ========== Payments ==========
+
+// `?flagged=true` lists payments taken outside their business's geofence
+pub async fn list_payments(State(state): State<(Store, Arc<Cache>)>, Query(filter): Query<PaymentSearchQuery>) -> Result<impl IntoResponse, Response> {
+    let payments = state.0.search_payments(&filter).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::OK, Json(payments)).into_response())
+}
+
+// ========== Releases ==========
+
+pub async fn list_releases(State(state): State<(Store, Arc<Cache>)>) -> Result<impl IntoResponse, Response> {
+    let list = state.0.get_app_releases().await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::OK, Json(AdminReleasesResponse { list })).into_response())
+}
+
+pub async fn create_release(State(state): State<(Store, Arc<Cache>)>, Extension(admin): Extension<AuthenticatedUser>, Extension(context): Extension<RequestContext>, Json(packet): Json<AdminReleaseRequest>) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let (version, min_supported_version) = (packet.version.trim().to_string(), packet.min_supported_version.trim().to_string());
+    let versions_ok = [&version, &min_supported_version].iter().all(|v| !v.is_empty() && v.len() <= MAX_RELEASE_VERSION_LEN);
+    if !versions_ok || !version_at_least(&version, &min_supported_version) {
+        return Err(Error::MissingParameters.into_response());
+    }
+    let checksum = packet.checksum.trim().to_ascii_lowercase();
+    let path = release_file(packet.file_path.trim()).ok_or(Error::MissingParameters.into_response())?;
+    let (stored_checksum, size_bytes) = release_checksum(&path).await
+        .map_err(|_| Error::Conflict(format!("{} is not in release storage", packet.file_path.trim())).into_response())?;
+    if stored_checksum != checksum {
+        return Err(Error::Conflict("checksum does not match the stored file".to_string()).into_response());
+    }
+    let release = AppRelease {
+        id: Uuid::new_v4(),
+        version,
+        channel: packet.channel,
+        min_supported_version,
+        file_path: packet.file_path.trim().to_string(),
+        checksum,
+        size_bytes: size_bytes as i64,
+        notes: packet.notes.map(|notes| notes.trim().to_string()).filter(|notes| !notes.is_empty()),
+        created_by: Some(admin.user_id),
+        created_at: Utc::now(),
+    };
+    let mut tx = store.begin().await.map_err(|e| e.into_response())?;
+    add_app_release_in(&mut tx, &release).await.map_err(|e| e.into_response())?;
+    audit(tx, &context, &admin, "admin.app_release.create", "app_release", &release.id.to_string(), json!({"version": release.version, "channel": release.channel, "min_supported_version": release.min_supported_version})).await?;
+    Ok((StatusCode::CREATED, Json(release)).into_response())
+}
+
+// Only the registry entry goes, the file stays in storage
+pub async fn delete_release(State(state): State<(Store, Arc<Cache>)>, Extension(admin): Extension<AuthenticatedUser>, Extension(context): Extension<RequestContext>, Path(id): Path<Uuid>) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let mut tx = store.begin().await.map_err(|e| e.into_response())?;
+    if !delete_app_release_in(&mut tx, id).await.map_err(|e| e.into_response())? {
+        return Err(Error::NotFound("release".to_string()).into_response());
+    }
+    audit(tx, &context, &admin, "admin.app_release.delete", "app_release", &id.to_string(), json!({})).await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+// ========== Signing keys ==========
+
+pub async fn list_signing_keys(State(state): State<(Store, Arc<Cache>)>) -> Result<impl IntoResponse, Response> {
+    Ok((StatusCode::OK, Json(PublicKeysResponse { keys: state.1.tokens.public_keys() })).into_response())
+}
+
+pub async fn rotate_signing_key(State(state): State<(Store, Arc<Cache>)>, Extension(admin): Extension<AuthenticatedUser>, Extension(context): Extension<RequestContext>) -> Result<impl IntoResponse, Response> {
+    let (store, cache) = state;
+    let mut tx = store.begin().await.map_err(|e| e.into_response())?;
+    let key = cache.tokens.rotate(&mut tx).await.map_err(|e| e.into_response())?;
+    audit(tx, &context, &admin, "admin.signing_key.rotate", "signing_key", &key.kid, json!({})).await?;
+    cache.tokens.reload(&store).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::CREATED, Json(key)).into_response())
+}
+
+pub async fn list_sticker_keys(State(state): State<(Store, Arc<Cache>)>) -> Result<impl IntoResponse, Response> {
+    Ok((StatusCode::OK, Json(PublicKeysResponse { keys: state.1.stickers.public_keys() })).into_response())
+}
+
+// Only for a suspected leak, stickers signed by the retired key keep verifying until they are reprinted
+pub async fn rotate_sticker_key(State(state): State<(Store, Arc<Cache>)>, Extension(admin): Extension<AuthenticatedUser>, Extension(context): Extension<RequestContext>) -> Result<impl IntoResponse, Response> {
+    let (store, cache) = state;
+    let mut tx = store.begin().await.map_err(|e| e.into_response())?;
+    let key = cache.stickers.rotate(&mut tx).await.map_err(|e| e.into_response())?;
+    audit(tx, &context, &admin, "admin.sticker_key.rotate", "sticker_key", &key.kid, json!({})).await?;
+    cache.stickers.reload(&store).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::CREATED, Json(key)).into_response())
+}
+
+pub async fn delete_signing_key(State(state): State<(Store, Arc<Cache>)>, Extension(admin): Extension<AuthenticatedUser>, Extension(context): Extension<RequestContext>, Path(kid): Path<String>) -> Result<impl IntoResponse, Response> {
+    let (store, cache) = state;
+    let mut tx = store.begin().await.map_err(|e| e.into_response())?;
+    if !delete_signing_key_in(&mut tx, &kid).await.map_err(|e| e.into_response())? {
+        return Err(Error::Conflict("only retired session keys can be deleted".to_string()).into_response());
+    }
+    audit(tx, &context, &admin, "admin.signing_key.delete", "signing_key", &kid, json!({})).await?;
+    cache.tokens.reload(&store).await.map_err(|e| e.into_response())?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
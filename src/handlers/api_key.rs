@@ -0,0 +1,92 @@
+use std::sync::Arc;
+
+use axum::{extract::{Path, State}, http::StatusCode, response::{IntoResponse, Response}, Extension, Json};
+use chrono::{DateTime, Duration, Utc};
+use handle_error::Error;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{db_store::Store, handlers::middleware::AuthenticatedUser, types::{api_key::{generate_api_key, ApiKey, ApiKeyType, KeySecret}, cache::Cache}};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ApiKeyRequest {
+    name: String,
+    scopes: Vec<ApiKeyType>,
+    expires_in_days: Option<i64>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct ApiKeysResponse {
+    list: Vec<ApiKey>
+}
+
+// The only response that ever carries the full key
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct IssuedApiKeyResponse {
+    key: String,
+    #[serde(flatten)]
+    api_key: ApiKey,
+}
+
+// Keys belong to the bank the caller operates, admins included
+async fn bank_id_for(store: &Store, user: &AuthenticatedUser) -> Result<String, Response> {
+    store.get_bank_user_id(&user.user_id).await
+        .map(|bank| bank.id)
+        .map_err(|_| Error::Forbidden.into_response())
+}
+
+pub async fn get_api_keys(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let bank_id = bank_id_for(&store, &user).await?;
+    let list = store.get_api_keys_bank_id(&bank_id).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::OK, Json(ApiKeysResponse{list})).into_response())
+}
+
+pub async fn create_api_key(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Json(packet): Json<ApiKeyRequest>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let bank_id = bank_id_for(&store, &user).await?;
+    if packet.name.trim().is_empty() || packet.scopes.is_empty() {
+        return Err(Error::MissingParameters.into_response());
+    }
+    let expires_at: Option<DateTime<Utc>> = match packet.expires_in_days {
+        Some(days) if days <= 0 => return Err(Error::MissingParameters.into_response()),
+        Some(days) => Some(Utc::now() + Duration::days(days)),
+        None => None,
+    };
+    let (prefix, key) = generate_api_key();
+    let api_key = store.add_api_key(&bank_id, packet.name.trim(), &KeySecret::new(prefix, &key), &packet.scopes, expires_at, user.user_id).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::CREATED, Json(IssuedApiKeyResponse{key, api_key})).into_response())
+}
+
+pub async fn rotate_api_key(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let bank_id = bank_id_for(&store, &user).await?;
+    let (prefix, key) = generate_api_key();
+    let api_key = store.rotate_api_key(&bank_id, id, &KeySecret::new(prefix, &key), user.user_id).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::CREATED, Json(IssuedApiKeyResponse{key, api_key})).into_response())
+}
+
+pub async fn revoke_api_key(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let bank_id = bank_id_for(&store, &user).await?;
+    if !store.revoke_api_key(&bank_id, id).await.map_err(|e| e.into_response())? {
+        return Err(Error::ApiKeyRejection.into_response());
+    }
+    let api_key = store.get_api_key_bank_id(&bank_id, id).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::OK, Json(api_key)).into_response())
+}
@@ -10,7 +10,6 @@ use handle_error::Error;
 pub struct BankResponse {
     pub id: String,
     pub user_id: Uuid,
-    pub apk_key: String, // new field
     pub bank_name: String,
     pub location: String,
     pub state: String,
@@ -41,7 +40,6 @@ pub async fn get_bank(
                     bank_response = Some(BankResponse{
                         id: bank.id.to_string(),
                         user_id: bank.user_id,
-                        apk_key: bank.apk_key.to_string(),
                         bank_name: b.name.to_string(),
                         location: b.address.to_string(),
                         state: b.state.to_string()
@@ -1,7 +1,7 @@
 
 
 // Table devices as D {
-//   id uuid [pk, ref: - DA.id, unique, not null]
+//   id uuid [pk, unique, not null]
 //   device_id varchar [not null]
 //   name varchar [not null]
 //   account_id uuid [ref: > A.id, not null]
@@ -15,12 +15,13 @@
 
 use std::sync::Arc;
 
-use axum::{extract::State, http::StatusCode, response::{IntoResponse, Response}, Extension, Json};
-use encrypt::generate_random_char;
+use axum::{extract::{Path, State}, http::StatusCode, response::{IntoResponse, Response}, Extension, Json};
+use handle_error::Error;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use uuid::Uuid;
 
-use crate::{db_store::Store, handlers::middleware::AuthenticatedUser, types::{business::GeoPoint, cache::Cache}};
+use crate::{db_store::Store, handlers::{middleware::AuthenticatedUser, organization::member_role}, types::{audit::{record_audit_event, AuditActor, AuditEvent, RequestContext, BUSINESS_GEOFENCE_UPDATE}, business::{Business, GeoPoint}, cache::Cache, device::DeviceStatus, geofence::GeofencePolicy, organization::MemberRole}};
 #[derive(Debug, Clone, Deserialize, Serialize)]
 struct UserBusinessResponse {
     list: Vec<BusinessResponse>
@@ -34,6 +35,12 @@ struct BusinessResponse {
     devices: Vec<DeviceResponse>
 }
 #[derive(Debug, Clone, Deserialize, Serialize)]
+struct GeofenceResponse {
+    geolocation: GeoPoint,
+    #[serde(flatten)]
+    policy: GeofencePolicy,
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
 struct AccountDeviceResponse {
     bank_name: String,
     account_name: String,
@@ -48,6 +55,7 @@ struct DeviceResponse {
     device_type: String,
     account: AccountDeviceResponse,
     active: bool,
+    status: DeviceStatus,
 }
 
 pub async fn get_business(
@@ -62,7 +70,7 @@ pub async fn get_business(
         Ok(u) => u,
         Err(e) => return Ok(e.into_response()),
     };
-    let businesses = match store.get_businesses_user_id(user_data.id).await {
+    let businesses = match store.get_businesses_member_id(user_data.id).await {
         Ok(b) => b,
         Err(e) => return Ok(e.into_response())
     };
@@ -94,7 +102,8 @@ pub async fn get_business(
                         device_type: device.device_type,
                         name: device.name,
                         account: AccountDeviceResponse { bank_name: bank_name, account_name: account.account_name, account_number: account.account_number, bank_id: serial_number },
-                        active: true,
+                        active: device.status == DeviceStatus::Active,
+                        status: device.status,
                     };
                     device_response.push(response);
                 },
@@ -106,12 +115,43 @@ pub async fn get_business(
     return Ok((StatusCode::OK, Json(UserBusinessResponse{list: business_response})).into_response());
 }
 
+// Businesses of other organizations look forbidden rather than missing
+async fn business_for_member(store: &Store, id: Uuid, user_id: Uuid) -> Result<(Business, MemberRole), Response> {
+    let business = store.get_business(id).await.map_err(|e| match e {
+        Error::DatabaseQueryError(sqlx::Error::RowNotFound) => Error::Forbidden.into_response(),
+        e => e.into_response(),
+    })?;
+    let role = member_role(store, business.organization_id, user_id).await?;
+    Ok((business, role))
+}
 
-pub async fn setup_device(store: &Store) {
-    for device in store.get_devices().await.unwrap() {
-        let device_id = generate_random_char(16);
-        let price_key = generate_random_char(16);
-        let id_key = generate_random_char(16);
-        store.setup_device(device.id, device_id, price_key, id_key).await.unwrap();
+pub async fn get_business_geofence(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let (business, _) = business_for_member(&store, id, user.user_id).await?;
+    let (geolocation, policy) = store.get_business_geofence(business.id).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::OK, Json(GeofenceResponse { geolocation, policy })).into_response())
+}
+
+// Applies to payments from the next one on, payments already taken keep their flag
+pub async fn update_business_geofence(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Extension(context): Extension<RequestContext>,
+    Path(id): Path<Uuid>,
+    Json(packet): Json<GeofencePolicy>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let (business, role) = business_for_member(&store, id, user.user_id).await?;
+    if !role.can_manage_devices() {
+        return Err(Error::Forbidden.into_response());
     }
-}
\ No newline at end of file
+    let geolocation = GeoPoint { latitude: business.lat, longitude: business.long };
+    packet.validate(&geolocation).map_err(|e| e.into_response())?;
+    store.update_business_geofence(business.id, &packet).await.map_err(|e| e.into_response())?;
+    record_audit_event(&store, &context, AuditEvent::success(BUSINESS_GEOFENCE_UPDATE, AuditActor::User(user.user_id)).entity("business", business.id).detail(json!({"radius_m": packet.radius_m, "action": packet.action}))).await;
+    Ok((StatusCode::OK, Json(GeofenceResponse { geolocation, policy: packet })).into_response())
+}
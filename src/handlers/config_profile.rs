@@ -0,0 +1,196 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use handle_error::Error;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{db_store::Store, handlers::{device::device_for_member, middleware::AuthenticatedUser, organization::member_role}, types::{audit::{record_audit_event, AuditActor, AuditEvent, RequestContext, CONFIG_PROFILE_ATTACH, CONFIG_PROFILE_CREATE, CONFIG_PROFILE_UPDATE}, cache::Cache, config_profile::{AppliedConfig, ConfigProfile, ConfigProfileVersion, ConfigTarget, EffectiveConfig, TerminalSettings}, organization::MemberRole}};
+
+const MAX_NAME_LEN: usize = 64;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ConfigProfileRequest {
+    pub organization_id: Uuid,
+    pub name: String,
+    #[serde(default)]
+    pub settings: TerminalSettings,
+}
+
+// A new version replaces the settings as a whole, earlier versions stay as they were
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ConfigVersionRequest {
+    pub settings: TerminalSettings,
+}
+
+// `null` detaches the current profile
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ConfigAttachRequest {
+    pub profile_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct ConfigProfilesResponse {
+    list: Vec<ConfigProfile>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct ConfigProfileResponse {
+    #[serde(flatten)]
+    profile: ConfigProfile,
+    versions: Vec<ConfigProfileVersion>,
+}
+
+// What the terminal should run with next to what it last said it applied
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct DeviceConfigResponse {
+    #[serde(flatten)]
+    effective: EffectiveConfig,
+    applied: Option<AppliedConfig>,
+    up_to_date: bool,
+}
+
+impl ConfigProfileRequest {
+    fn validate(&self) -> Result<(), Error> {
+        if self.name.trim().is_empty() || self.name.trim().chars().count() > MAX_NAME_LEN {
+            return Err(Error::MissingParameters);
+        }
+        self.settings.validate()
+    }
+}
+
+// Profiles of other organizations look forbidden, like businesses do
+async fn profile_for_member(store: &Store, id: Uuid, user_id: Uuid) -> Result<(ConfigProfile, MemberRole), Response> {
+    let profile = store.get_config_profile(id).await.map_err(|e| match e {
+        Error::DatabaseQueryError(sqlx::Error::RowNotFound) => Error::Forbidden.into_response(),
+        e => e.into_response(),
+    })?;
+    let role = member_role(store, profile.organization_id, user_id).await?;
+    Ok((profile, role))
+}
+
+// A profile can only be attached inside the organization it belongs to
+async fn attachable_profile(store: &Store, profile_id: Option<Uuid>, organization_id: Uuid, user_id: Uuid) -> Result<(), Response> {
+    if let Some(profile_id) = profile_id {
+        let (profile, _) = profile_for_member(store, profile_id, user_id).await?;
+        if profile.organization_id != organization_id {
+            return Err(Error::Forbidden.into_response());
+        }
+    }
+    Ok(())
+}
+
+pub async fn get_config_profiles(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+) -> Result<impl IntoResponse, Response> {
+    let list = state.0.get_config_profiles_member_id(user.user_id).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::OK, Json(ConfigProfilesResponse { list })).into_response())
+}
+
+pub async fn create_config_profile(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Extension(context): Extension<RequestContext>,
+    Json(packet): Json<ConfigProfileRequest>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    packet.validate().map_err(|e| e.into_response())?;
+    if !member_role(&store, packet.organization_id, user.user_id).await?.can_manage_devices() {
+        return Err(Error::Forbidden.into_response());
+    }
+    let profile = store.add_config_profile(packet.organization_id, packet.name.trim(), &packet.settings, user.user_id).await
+        .map_err(|e| e.into_response())?;
+    record_audit_event(&store, &context, AuditEvent::success(CONFIG_PROFILE_CREATE, AuditActor::User(user.user_id)).entity("config_profile", profile.id).detail(json!({"organization_id": profile.organization_id}))).await;
+    Ok((StatusCode::CREATED, Json(profile)).into_response())
+}
+
+pub async fn get_config_profile(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let (profile, _) = profile_for_member(&store, id, user.user_id).await?;
+    let versions = store.get_config_profile_versions(profile.id).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::OK, Json(ConfigProfileResponse { profile, versions })).into_response())
+}
+
+// Terminals using the profile pick the new version up on their next config fetch
+pub async fn create_config_version(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Extension(context): Extension<RequestContext>,
+    Path(id): Path<Uuid>,
+    Json(packet): Json<ConfigVersionRequest>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let (profile, role) = profile_for_member(&store, id, user.user_id).await?;
+    if !role.can_manage_devices() {
+        return Err(Error::Forbidden.into_response());
+    }
+    packet.settings.validate().map_err(|e| e.into_response())?;
+    let profile = store.add_config_profile_version(profile.id, &packet.settings, user.user_id).await
+        .map_err(|e| e.into_response())?;
+    record_audit_event(&store, &context, AuditEvent::success(CONFIG_PROFILE_UPDATE, AuditActor::User(user.user_id)).entity("config_profile", profile.id).detail(json!({"version": profile.version}))).await;
+    Ok((StatusCode::CREATED, Json(profile)).into_response())
+}
+
+pub async fn attach_business_config(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Extension(context): Extension<RequestContext>,
+    Path(id): Path<Uuid>,
+    Json(packet): Json<ConfigAttachRequest>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let business = store.get_business(id).await.map_err(|e| match e {
+        Error::DatabaseQueryError(sqlx::Error::RowNotFound) => Error::Forbidden.into_response(),
+        e => e.into_response(),
+    })?;
+    if !member_role(&store, business.organization_id, user.user_id).await?.can_manage_devices() {
+        return Err(Error::Forbidden.into_response());
+    }
+    attachable_profile(&store, packet.profile_id, business.organization_id, user.user_id).await?;
+    store.attach_config_profile(ConfigTarget::Business(business.id), packet.profile_id).await.map_err(|e| e.into_response())?;
+    record_audit_event(&store, &context, AuditEvent::success(CONFIG_PROFILE_ATTACH, AuditActor::User(user.user_id)).entity("business", business.id).detail(json!({"profile_id": packet.profile_id}))).await;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+pub async fn attach_device_config(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Extension(context): Extension<RequestContext>,
+    Path(id): Path<Uuid>,
+    Json(packet): Json<ConfigAttachRequest>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let (device, role) = device_for_member(&store, id, user.user_id).await?;
+    if !role.can_manage_devices() {
+        return Err(Error::Forbidden.into_response());
+    }
+    let business = store.get_business(device.business_id).await.map_err(|e| e.into_response())?;
+    attachable_profile(&store, packet.profile_id, business.organization_id, user.user_id).await?;
+    store.attach_config_profile(ConfigTarget::Device(device.id), packet.profile_id).await.map_err(|e| e.into_response())?;
+    record_audit_event(&store, &context, AuditEvent::success(CONFIG_PROFILE_ATTACH, AuditActor::User(user.user_id)).entity("device", device.id).detail(json!({"profile_id": packet.profile_id}))).await;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+pub async fn get_device_config(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let (device, _) = device_for_member(&store, id, user.user_id).await?;
+    let effective = store.get_config_sources(device.id).await.map_err(|e| e.into_response())?.effective();
+    let applied = store.get_device_config_ack(device.id).await.map_err(|e| e.into_response())?;
+    let up_to_date = applied.as_ref().is_some_and(|applied| applied.version == effective.version);
+    Ok((StatusCode::OK, Json(DeviceConfigResponse { effective, applied, up_to_date })).into_response())
+}
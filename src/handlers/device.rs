@@ -1,173 +1,510 @@
-// use axum::{
-//     extract::{Path, State},
-//     http::StatusCode,
-//     response::{IntoResponse, Response},
-//     Json,
-// };
-// use chrono::Utc;
-// use serde::{Deserialize, Serialize};
-// use uuid::Uuid;
-
-// use crate::{db_store::Store, types::device::Device};
-
-// use super::middleware::AuthenticatedUser; // for authentication
-
-
-// #[derive(Debug, Clone, Deserialize, Serialize)]
-// pub struct DevicePostRequest {
-//     pub device_id: String,
-//     pub name: String,
-//     pub device_type: String,
-//     pub apk_key: String,
-//     pub business_id: Uuid,
-//     pub account_id: Uuid,
-// }
-
-// #[derive(Debug, Clone, Deserialize, Serialize)]
-// pub struct DeviceResponse {
-//     pub id: Uuid,
-//     pub device_id: String,
-//     pub name: String,
-//     pub device_type: String,
-//     pub apk_key: String,
-//     pub business_id: Uuid,
-//     pub account_id: Uuid,
-//     pub created_at: chrono::DateTime<Utc>,
-//     pub updated_at: chrono::DateTime<Utc>,
-// }
-
-// /// Register a new device
-// pub async fn register_device(
-//     State(store): State<(Store, Arc<Cache>)>,
-//     Json(packet): Json<DevicePostRequest>,
-// ) -> Result<impl IntoResponse, Response> {
-//     let device = Device {
-//         id: Uuid::new_v4(),
-//         main_id: Uuid::new_v4(),
-//         device_id: packet.device_id,
-//         name: packet.name,
-//         device_type: packet.device_type,
-//         apk_key: packet.apk_key,
-//         business_id: packet.business_id,
-//         account_id: packet.account_id,
-//         created_at: Utc::now(),
-//         updated_at: Utc::now(),
-//     };
-
-//     match store.add_device(&device).await {
-//         Ok(_) => {
-//             let response = DeviceResponse {
-//                 id: device.id,
-//                 device_id: device.device_id,
-//                 name: device.name,
-//                 device_type: device.device_type,
-//                 apk_key: device.apk_key,
-//                 business_id: device.business_id,
-//                 account_id: device.account_id,
-//                 created_at: device.created_at,
-//                 updated_at: device.updated_at,
-//             };
-//             Ok((StatusCode::CREATED, Json(response)).into_response())
-//         }
-//         Err(e) => Ok(e.into_response()),
-//     }
-// }
-
-// /// Get one device by ID
-// pub async fn get_device_profile(
-//     State(store): State<(Store, Arc<Cache>)>,
-//     Path(id): Path<Uuid>,
-// ) -> Result<impl IntoResponse, Response> {
-//     match store.get_device(id).await {
-//         Ok(device) => {
-//             let response = DeviceResponse {
-//                 id: device.id,
-//                 device_id: device.device_id,
-//                 name: device.name,
-//                 device_type: device.device_type,
-//                 apk_key: device.apk_key,
-//                 business_id: device.business_id,
-//                 account_id: device.account_id,
-//                 created_at: device.created_at,
-//                 updated_at: device.updated_at,
-//             };
-//             Ok((StatusCode::OK, Json(response)).into_response())
-//         }
-//         Err(e) => Ok(e.into_response()),
-//     }
-// }
-
-// /// Get all devices
-// pub async fn get_all_devices(
-//     State(store): State<(Store, Arc<Cache>)>,
-// ) -> Result<impl IntoResponse, Response> {
-//     match store.get_devices().await {
-//         Ok(devices) => {
-//             let response: Vec<DeviceResponse> = devices
-//                 .into_iter()
-//                 .map(|d| DeviceResponse {
-//                     id: d.id,
-//                     device_id: d.device_id,
-//                     name: d.name,
-//                     device_type: d.device_type,
-//                     apk_key: d.apk_key,
-//                     business_id: d.business_id,
-//                     account_id: d.account_id,
-//                     created_at: d.created_at,
-//                     updated_at: d.updated_at,
-//                 })
-//                 .collect();
-//             Ok((StatusCode::OK, Json(response)).into_response())
-//         }
-//         Err(e) => Ok(e.into_response()),
-//     }
-// }
-
-// /// Update a device
-// pub async fn update_device(
-//     State(store): State<(Store, Arc<Cache>)>,
-//     Path(id): Path<Uuid>,
-//     Json(packet): Json<DevicePostRequest>,
-// ) -> Result<impl IntoResponse, Response> {
-//     let updated_device = Device {
-//         id,
-//         main_id: Uuid::new_v4(),
-//         device_id: packet.device_id,
-//         name: packet.name,
-//         device_type: packet.device_type,
-//         apk_key: packet.apk_key,
-//         business_id: packet.business_id,
-//         account_id: packet.account_id,
-//         created_at: Utc::now(), // You may want to fetch the old one instead
-//         updated_at: Utc::now(),
-//     };
-
-//     match store.update_device(&updated_device).await {
-//         Ok(_) => {
-//             let response = DeviceResponse {
-//                 id: updated_device.id,
-//                 device_id: updated_device.device_id,
-//                 name: updated_device.name,
-//                 device_type: updated_device.device_type,
-//                 apk_key: updated_device.apk_key,
-//                 business_id: updated_device.business_id,
-//                 account_id: updated_device.account_id,
-//                 created_at: updated_device.created_at,
-//                 updated_at: updated_device.updated_at,
-//             };
-//             Ok((StatusCode::OK, Json(response)).into_response())
-//         }
-//         Err(e) => Ok(e.into_response()),
-//     }
-// }
-
-// /// Delete a device
-// pub async fn delete_device(
-//     State(store): State<(Store, Arc<Cache>)>,
-//     Path(id): Path<Uuid>,
-// ) -> Result<impl IntoResponse, Response> {
-//     match store.delete_device(id).await {
-//         Ok(_) => Ok(StatusCode::NO_CONTENT.into_response()),
-//         Err(e) => Ok(e.into_response()),
-//     }
-// }
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use encrypt::{generate_random_char, hash_secret};
+use handle_error::Error;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{Postgres, Transaction};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::{db_store::Store, handlers::{middleware::AuthenticatedUser, organization::member_role, user::reauthenticate}, tools::{rand_gene::{activation_code, normalize_activation_code}, transport::OutboundMessage}, types::{account::Account, audit::{record_audit_event, AuditActor, AuditEvent, RequestContext, DEVICE_ACCOUNT_CHANGE, DEVICE_CREATE, DEVICE_DELETE, DEVICE_KEY_REVOKE, DEVICE_STATUS_CHANGE, DEVICE_UPDATE}, business::Business, cache::Cache, device::{add_device_activation_in, insert_device, update_device_status_in, Device, DeviceStatus, StatusAuthority}, device_assignment::DeviceAssignment, device_health::{with_issues, DeviceHealth, FleetHealthQuery, HeartbeatRecord}, device_key::{revoke_device_keys_in, DeviceKey}, organization::MemberRole}};
+
+// `devices.name` and `devices.device_type` are plain varchars, this keeps them readable on a receipt
+const MAX_FIELD_LEN: usize = 64;
+const ACTIVATION_MINUTES: i64 = 30;
+const MAX_REASON_LEN: usize = 256;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DeviceRequest {
+    pub name: String,
+    pub device_type: String,
+    pub business_id: Uuid,
+    pub account_id: Uuid,
+}
+
+// Keys stay on the server, merchants only see the public identity of a terminal
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DeviceResponse {
+    pub id: Uuid,
+    pub device_id: String,
+    pub name: String,
+    pub device_type: String,
+    pub business_id: Uuid,
+    pub account_id: Uuid,
+    pub status: DeviceStatus,
+    pub activated_at: Option<DateTime<Utc>>,
+    pub status_reason: Option<String>,
+    pub status_changed_at: Option<DateTime<Utc>>,
+    pub status_set_by: Option<StatusAuthority>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+// Suspending or retiring needs a reason, it is shown to whoever finds the terminal blocked
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DeviceStatusRequest {
+    pub status: DeviceStatus,
+    pub reason: Option<String>,
+}
+
+// Re-pointing a terminal moves future money, so the owner confirms it with their password
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DeviceAccountRequest {
+    pub account_id: Uuid,
+    pub password: String,
+}
+
+// Shown once, the terminal redeems the code at /api/metal/enroll
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ActivationResponse {
+    pub code: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CreatedDeviceResponse {
+    #[serde(flatten)]
+    pub device: DeviceResponse,
+    pub activation: ActivationResponse,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct DevicesResponse {
+    list: Vec<DeviceResponse>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FleetHealthResponse {
+    pub list: Vec<DeviceHealth>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct HeartbeatsResponse {
+    list: Vec<HeartbeatRecord>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct DeviceKeysResponse {
+    list: Vec<DeviceKey>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DeviceAssignmentsResponse {
+    pub list: Vec<DeviceAssignment>,
+}
+
+impl From<Device> for DeviceResponse {
+    fn from(device: Device) -> Self {
+        DeviceResponse {
+            id: device.id,
+            device_id: device.device_id,
+            name: device.name,
+            device_type: device.device_type,
+            business_id: device.business_id,
+            account_id: device.account_id,
+            status: device.status,
+            activated_at: device.activated_at,
+            status_reason: device.status_reason,
+            status_changed_at: device.status_changed_at,
+            status_set_by: device.status_set_by,
+            created_at: device.created_at,
+            updated_at: device.updated_at,
+        }
+    }
+}
+
+impl DeviceRequest {
+    fn validate(&self) -> Result<(), Error> {
+        for field in [&self.name, &self.device_type] {
+            if field.trim().is_empty() || field.trim().chars().count() > MAX_FIELD_LEN {
+                return Err(Error::MissingParameters);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl DeviceStatusRequest {
+    fn reason(&self) -> Result<Option<String>, Error> {
+        let reason = self.reason.as_deref().map(str::trim).filter(|reason| !reason.is_empty());
+        if reason.is_some_and(|reason| reason.chars().count() > MAX_REASON_LEN) {
+            return Err(Error::MissingParameters);
+        }
+        if reason.is_none() && self.status != DeviceStatus::Active {
+            return Err(Error::MissingParameters);
+        }
+        Ok(reason.map(|reason| reason.to_string()))
+    }
+}
+
+// Shared by the merchant and admin routes, inside the caller's transaction. Returns the reason as stored.
+// A terminal an administrator suspended stays suspended until an administrator lifts it
+pub(crate) async fn change_device_status(tx: &mut Transaction<'_, Postgres>, device: &Device, packet: &DeviceStatusRequest, set_by: StatusAuthority) -> Result<Option<String>, Response> {
+    let reason = packet.reason().map_err(|e| e.into_response())?;
+    if !device.status.can_change_to(packet.status) {
+        return Err(Error::Conflict(format!("a {} device cannot become {}", device.status.as_str(), packet.status.as_str())).into_response());
+    }
+    let admin_hold = device.status == DeviceStatus::Suspended && device.status_set_by == Some(StatusAuthority::Admin);
+    if admin_hold && set_by == StatusAuthority::Merchant && packet.status == DeviceStatus::Active {
+        return Err(Error::Forbidden.into_response());
+    }
+    if !update_device_status_in(tx, device.id, device.status, packet.status, reason.as_deref(), set_by).await.map_err(|e| e.into_response())? {
+        return Err(Error::Conflict("device status changed in the meantime".to_string()).into_response());
+    }
+    Ok(reason)
+}
+
+// Deleting keeps the row so its payments and payout history stay attached, the terminal is retired instead
+pub(crate) async fn retire_device(tx: &mut Transaction<'_, Postgres>, device: &Device, set_by: StatusAuthority) -> Result<(), Response> {
+    if device.status == DeviceStatus::Retired {
+        return Ok(());
+    }
+    if !update_device_status_in(tx, device.id, device.status, DeviceStatus::Retired, Some("deleted"), set_by).await.map_err(|e| e.into_response())? {
+        return Err(Error::Conflict("device status changed in the meantime".to_string()).into_response());
+    }
+    Ok(())
+}
+
+// For a terminal that may be compromised, its keys stop working now instead of after a rotation's overlap.
+// An administrator's suspension is not something a merchant can clear this way
+pub(crate) async fn revoke_keys(tx: &mut Transaction<'_, Postgres>, device: &Device, set_by: StatusAuthority) -> Result<(), Response> {
+    if !matches!(device.status, DeviceStatus::Active | DeviceStatus::Suspended) {
+        return Err(Error::Conflict(format!("a {} device holds no keys", device.status.as_str())).into_response());
+    }
+    let admin_hold = device.status == DeviceStatus::Suspended && device.status_set_by == Some(StatusAuthority::Admin);
+    if admin_hold && set_by == StatusAuthority::Merchant {
+        return Err(Error::Forbidden.into_response());
+    }
+    if !revoke_device_keys_in(tx, device.id, device.status, set_by).await.map_err(|e| e.into_response())? {
+        return Err(Error::Conflict("device status changed in the meantime".to_string()).into_response());
+    }
+    Ok(())
+}
+
+// Every owner of the terminal's organization hears about it, a failed delivery does not undo the change
+pub(crate) async fn notify_account_change(store: &Store, cache: &Cache, device: &Device, previous: &Account, account: &Account) -> Result<(), Response> {
+    let business = store.get_business(device.business_id).await.map_err(|e| e.into_response())?;
+    let members = store.get_members(business.organization_id).await.map_err(|e| e.into_response())?;
+    let ending = |account: &Account| account.account_number.chars().rev().take(4).collect::<Vec<char>>().into_iter().rev().collect::<String>();
+    for owner in members.into_iter().filter(|member| member.role == MemberRole::Owner) {
+        let body = format!(
+            "Hello {},\n\nTerminal {} of {} now pays out into {} (account ending {}) instead of {} (account ending {}).\nPayments taken before the change stay with the previous account. If you did not make this change, contact support right away.\n",
+            owner.first_name, device.name, business.name, account.account_name, ending(account), previous.account_name, ending(previous),
+        );
+        if let Err(e) = cache.transport.send(&OutboundMessage::new(&owner.email, "A terminal's payout account changed", body)) {
+            warn!("payout change notice for device {} not delivered to {}: {}", device.id, owner.user_id, e);
+        }
+    }
+    Ok(())
+}
+
+// Devices outside the caller's organizations look missing rather than forbidden
+pub(crate) async fn device_for_member(store: &Store, id: Uuid, user_id: Uuid) -> Result<(Device, MemberRole), Response> {
+    let device = store.get_device(id).await.map_err(|e| match e {
+        Error::DatabaseQueryError(sqlx::Error::RowNotFound) => Error::DeviceNotFound.into_response(),
+        e => e.into_response(),
+    })?;
+    let business = store.get_business(device.business_id).await.map_err(|e| e.into_response())?;
+    let role = store.get_member_role(business.organization_id, user_id).await
+        .map_err(|e| e.into_response())?
+        .ok_or(Error::DeviceNotFound.into_response())?;
+    Ok((device, role))
+}
+
+// A terminal can only pay into an account of its business's organization, an admin may also pick an unowned account
+pub(crate) async fn payout_account(store: &Store, account_id: Uuid, organization_id: Uuid, assigned_by: StatusAuthority) -> Result<Account, Response> {
+    let account = store.get_account(&account_id).await.map_err(|e| match e {
+        Error::DatabaseQueryError(sqlx::Error::RowNotFound) => Error::MissingParameters.into_response(),
+        e => e.into_response(),
+    })?;
+    let allowed = match account.organization_id {
+        Some(owner) => owner == organization_id,
+        None => assigned_by == StatusAuthority::Admin,
+    };
+    if !allowed {
+        return Err(Error::Forbidden.into_response());
+    }
+    Ok(account)
+}
+
+// The target business has to sit in an organization where the caller manages devices, the account has to be the organization's
+async fn managed_target(store: &Store, packet: &DeviceRequest, user_id: Uuid) -> Result<Business, Response> {
+    packet.validate().map_err(|e| e.into_response())?;
+    let business = store.get_business(packet.business_id).await.map_err(|e| match e {
+        Error::DatabaseQueryError(sqlx::Error::RowNotFound) => Error::Forbidden.into_response(),
+        e => e.into_response(),
+    })?;
+    if !member_role(store, business.organization_id, user_id).await?.can_manage_devices() {
+        return Err(Error::Forbidden.into_response());
+    }
+    payout_account(store, packet.account_id, business.organization_id, StatusAuthority::Merchant).await?;
+    Ok(business)
+}
+
+pub(crate) async fn issue_activation(tx: &mut Transaction<'_, Postgres>, device_id: Uuid, user_id: Uuid) -> Result<ActivationResponse, Response> {
+    let code = activation_code();
+    let expires_at = Utc::now() + Duration::minutes(ACTIVATION_MINUTES);
+    add_device_activation_in(tx, device_id, &hash_secret(&normalize_activation_code(&code)), user_id, expires_at).await
+        .map_err(|e| e.into_response())?;
+    Ok(ActivationResponse { code, expires_at })
+}
+
+pub async fn get_devices(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+) -> Result<impl IntoResponse, Response> {
+    let devices = state.0.get_devices_member_id(user.user_id).await.map_err(|e| e.into_response())?;
+    let list = devices.into_iter().map(DeviceResponse::from).collect();
+    Ok((StatusCode::OK, Json(DevicesResponse { list })).into_response())
+}
+
+pub async fn get_device(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, Response> {
+    let (device, _) = device_for_member(&state.0, id, user.user_id).await?;
+    Ok((StatusCode::OK, Json(DeviceResponse::from(device))).into_response())
+}
+
+pub async fn create_device(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Extension(context): Extension<RequestContext>,
+    Json(packet): Json<DeviceRequest>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let business = managed_target(&store, &packet, user.user_id).await?;
+    let now = Utc::now();
+    let device = Device {
+        id: Uuid::new_v4(),
+        device_id: generate_random_char(16),
+        name: packet.name.trim().to_string(),
+        account_id: packet.account_id,
+        device_type: packet.device_type.trim().to_string(),
+        apk_key: generate_random_char(16),
+        price_key: generate_random_char(16),
+        id_key: generate_random_char(16),
+        business_id: business.id,
+        // Keys above are placeholders, the terminal gets fresh ones when it redeems the activation code
+        status: DeviceStatus::Pending,
+        activated_at: None,
+        status_reason: None,
+        status_changed_at: Some(now),
+        status_set_by: None,
+        created_at: now,
+        updated_at: now,
+    };
+    let mut tx = store.begin().await.map_err(|e| e.into_response())?;
+    insert_device(&mut tx, &device, business.user_id).await.map_err(|e| e.into_response())?;
+    let activation = issue_activation(&mut tx, device.id, user.user_id).await?;
+    tx.commit().await.map_err(|e| Error::DatabaseQueryError(e).into_response())?;
+    record_audit_event(&store, &context, AuditEvent::success(DEVICE_CREATE, AuditActor::User(user.user_id)).entity("device", device.id).detail(json!({"business_id": device.business_id, "account_id": device.account_id}))).await;
+    Ok((StatusCode::CREATED, Json(CreatedDeviceResponse { device: DeviceResponse::from(device), activation })).into_response())
+}
+
+pub async fn update_device_status(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Extension(context): Extension<RequestContext>,
+    Path(id): Path<Uuid>,
+    Json(packet): Json<DeviceStatusRequest>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let (device, role) = device_for_member(&store, id, user.user_id).await?;
+    if !role.can_manage_devices() {
+        return Err(Error::Forbidden.into_response());
+    }
+    let previous = device.status;
+    let mut tx = store.begin().await.map_err(|e| e.into_response())?;
+    change_device_status(&mut tx, &device, &packet, StatusAuthority::Merchant).await?;
+    tx.commit().await.map_err(|e| Error::DatabaseQueryError(e).into_response())?;
+    let device = store.get_device(device.id).await.map_err(|e| e.into_response())?;
+    record_audit_event(&store, &context, AuditEvent::success(DEVICE_STATUS_CHANGE, AuditActor::User(user.user_id)).entity("device", device.id).detail(json!({"from": previous, "to": device.status, "reason": device.status_reason}))).await;
+    Ok((StatusCode::OK, Json(DeviceResponse::from(device))).into_response())
+}
+
+// `?issue=offline|out_of_date|clock_drift` narrows the list to terminals that need attention
+pub async fn get_fleet_health(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Query(query): Query<FleetHealthQuery>,
+) -> Result<impl IntoResponse, Response> {
+    let fleet = state.0.get_fleet_health(Some(user.user_id)).await.map_err(|e| e.into_response())?;
+    let policy = state.0.get_fleet_policy().await.map_err(|e| e.into_response())?;
+    let list = with_issues(fleet, &policy, query.issue);
+    Ok((StatusCode::OK, Json(FleetHealthResponse { list })).into_response())
+}
+
+pub async fn get_device_heartbeats(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let (device, _) = device_for_member(&store, id, user.user_id).await?;
+    let list = store.get_device_heartbeats(device.id).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::OK, Json(HeartbeatsResponse { list })).into_response())
+}
+
+// Versions with their activation and retirement times, the keys themselves never leave the server
+pub async fn get_device_keys(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let (device, _) = device_for_member(&store, id, user.user_id).await?;
+    let list = store.get_device_keys(device.id).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::OK, Json(DeviceKeysResponse { list })).into_response())
+}
+
+// The terminal is back to pending and gets going again with the activation code in the response
+pub async fn revoke_device_keys(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Extension(context): Extension<RequestContext>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let (device, role) = device_for_member(&store, id, user.user_id).await?;
+    if !role.can_manage_devices() {
+        return Err(Error::Forbidden.into_response());
+    }
+    let previous = device.status;
+    let mut tx = store.begin().await.map_err(|e| e.into_response())?;
+    revoke_keys(&mut tx, &device, StatusAuthority::Merchant).await?;
+    let activation = issue_activation(&mut tx, device.id, user.user_id).await?;
+    tx.commit().await.map_err(|e| Error::DatabaseQueryError(e).into_response())?;
+    let device = store.get_device(device.id).await.map_err(|e| e.into_response())?;
+    record_audit_event(&store, &context, AuditEvent::success(DEVICE_KEY_REVOKE, AuditActor::User(user.user_id)).entity("device", device.id).detail(json!({"from": previous}))).await;
+    Ok((StatusCode::OK, Json(CreatedDeviceResponse { device: DeviceResponse::from(device), activation })).into_response())
+}
+
+// New code for a pending terminal, the previous unredeemed code stops working
+pub async fn create_activation(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let (device, role) = device_for_member(&store, id, user.user_id).await?;
+    if !role.can_manage_devices() {
+        return Err(Error::Forbidden.into_response());
+    }
+    if device.status != DeviceStatus::Pending {
+        return Err(Error::Conflict("device is already activated".to_string()).into_response());
+    }
+    let mut tx = store.begin().await.map_err(|e| e.into_response())?;
+    let activation = issue_activation(&mut tx, device.id, user.user_id).await?;
+    tx.commit().await.map_err(|e| Error::DatabaseQueryError(e).into_response())?;
+    Ok((StatusCode::CREATED, Json(activation)).into_response())
+}
+
+pub async fn update_device(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Extension(context): Extension<RequestContext>,
+    Path(id): Path<Uuid>,
+    Json(packet): Json<DeviceRequest>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let (mut device, role) = device_for_member(&store, id, user.user_id).await?;
+    if !role.can_manage_devices() {
+        return Err(Error::Forbidden.into_response());
+    }
+    if packet.account_id != device.account_id {
+        return Err(Error::Conflict("the payout account changes through /devices/{id}/account".to_string()).into_response());
+    }
+    // Moving a terminal needs the right to manage devices on both sides
+    let business = managed_target(&store, &packet, user.user_id).await?;
+    let previous_business = device.business_id;
+    device.name = packet.name.trim().to_string();
+    device.device_type = packet.device_type.trim().to_string();
+    device.business_id = business.id;
+    device.updated_at = Utc::now();
+    store.update_device(&device).await.map_err(|e| e.into_response())?;
+    record_audit_event(&store, &context, AuditEvent::success(DEVICE_UPDATE, AuditActor::User(user.user_id)).entity("device", device.id).detail(json!({"business_id": device.business_id, "previous_business_id": previous_business}))).await;
+    Ok((StatusCode::OK, Json(DeviceResponse::from(device))).into_response())
+}
+
+// Accounts the terminal paid into, newest first, with the period each one was in force
+pub async fn get_device_accounts(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let (device, _) = device_for_member(&store, id, user.user_id).await?;
+    let list = store.get_device_assignments(device.id).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::OK, Json(DeviceAssignmentsResponse { list })).into_response())
+}
+
+// Only an owner, confirming their password, can send a terminal's future payments somewhere else
+pub async fn update_device_account(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Extension(context): Extension<RequestContext>,
+    Path(id): Path<Uuid>,
+    Json(packet): Json<DeviceAccountRequest>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let cache = state.1;
+    let (device, role) = device_for_member(&store, id, user.user_id).await?;
+    if !role.can_change_payouts() {
+        return Err(Error::Forbidden.into_response());
+    }
+    let user_data = store.get_user(user.user_id).await.map_err(|e| e.into_response())?;
+    reauthenticate(&store, &user_data, &packet.password).await?;
+    let business = store.get_business(device.business_id).await.map_err(|e| e.into_response())?;
+    let previous = store.get_account(&device.account_id).await.map_err(|e| e.into_response())?;
+    let account = payout_account(&store, packet.account_id, business.organization_id, StatusAuthority::Merchant).await?;
+    if !store.reassign_device_account(device.id, account.id, user.user_id).await.map_err(|e| e.into_response())? {
+        return Err(Error::Conflict("the device already pays into this account".to_string()).into_response());
+    }
+    record_audit_event(&store, &context, AuditEvent::success(DEVICE_ACCOUNT_CHANGE, AuditActor::User(user.user_id)).entity("device", device.id).detail(json!({"account_id": account.id, "previous_account_id": previous.id}))).await;
+    notify_account_change(&store, &cache, &device, &previous, &account).await?;
+    let device = store.get_device(device.id).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::OK, Json(DeviceResponse::from(device))).into_response())
+}
+
+pub async fn delete_device(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Extension(context): Extension<RequestContext>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let (device, role) = device_for_member(&store, id, user.user_id).await?;
+    if !role.can_manage_devices() {
+        return Err(Error::Forbidden.into_response());
+    }
+    let mut tx = store.begin().await.map_err(|e| e.into_response())?;
+    retire_device(&mut tx, &device, StatusAuthority::Merchant).await?;
+    tx.commit().await.map_err(|e| Error::DatabaseQueryError(e).into_response())?;
+    record_audit_event(&store, &context, AuditEvent::success(DEVICE_DELETE, AuditActor::User(user.user_id)).entity("device", id).detail(json!({"device_id": device.device_id}))).await;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_needs_name_and_type() {
+        let mut packet = DeviceRequest { name: "Till 1".to_string(), device_type: "pos".to_string(), business_id: Uuid::new_v4(), account_id: Uuid::new_v4() };
+        assert!(packet.validate().is_ok());
+        packet.device_type = "  ".to_string();
+        assert!(packet.validate().is_err());
+        packet.device_type = "x".repeat(MAX_FIELD_LEN + 1);
+        assert!(packet.validate().is_err());
+    }
+}
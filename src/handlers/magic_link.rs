@@ -0,0 +1,127 @@
+use std::{env, sync::Arc};
+
+use axum::{extract::State, Extension, http::{header, HeaderMap, StatusCode}, response::{IntoResponse, Response}, Json};
+use chrono::{Duration, Utc};
+use encrypt::{generate_random_char, hash_secret};
+use handle_error::Error;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::{db_store::Store, handlers::user::{session_response, SessionResponse, UserResponse}, tools::{constant::MAGIC_LINK_URL, transport::OutboundMessage}, types::{audit::{record_audit_event, AuditActor, AuditEvent, RequestContext, USER_LOGIN}, cache::Cache, login_attempt::AttemptScope}};
+
+const MAGIC_LINK_MINUTES: i64 = 10;
+const MAGIC_LINK_TOKEN_LEN: usize = 43;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MagicLinkRequest {
+    email: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MagicLinkRedeemRequest {
+    token: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct MagicLinkResponse {
+    message: String,
+}
+
+// The link only opens a session in the browser that asked for it
+fn user_agent_hash(headers: &HeaderMap) -> String {
+    hash_secret(headers.get(header::USER_AGENT).and_then(|value| value.to_str().ok()).unwrap_or(""))
+}
+
+// Also builds the invitation accept link
+pub(crate) fn link_with_token(base: &str, token: &str) -> String {
+    let separator = if base.contains('?') { '&' } else { '?' };
+    format!("{}{}token={}", base, separator, token)
+}
+
+// Same answer whether or not the email has an account, the link goes out in the background so the timing matches too
+pub async fn request_magic_link(
+    State(state): State<(Store, Arc<Cache>)>,
+    headers: HeaderMap,
+    Json(packet): Json<MagicLinkRequest>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let cache = state.1;
+    let base = env::var(MAGIC_LINK_URL).map_err(|e| Error::EnvError(e).into_response())?;
+    let email_key = packet.email.trim().to_lowercase();
+    // Every request is claimed up front, parallel requests cannot all slip past the check
+    let locked_until = match store.get_login_locked_until(AttemptScope::Email, &email_key).await.map_err(|e| e.into_response())? {
+        Some(until) => Some(until),
+        None => store.claim_login_attempt(AttemptScope::MagicLinkSend, &email_key).await.map_err(|e| e.into_response())?,
+    };
+    if let Some(until) = locked_until {
+        let wait = (until - Utc::now()).num_seconds().max(1);
+        return Err(Error::TooManyAttempts(wait).into_response());
+    }
+    let agent_hash = user_agent_hash(&headers);
+    tokio::spawn(async move {
+        if let Err(e) = send_magic_link(&store, &cache, packet.email, &agent_hash, &base).await {
+            warn!("magic link not sent: {}", e);
+        }
+    });
+    let response = MagicLinkResponse { message: "if the email has an account, a sign-in link is on its way".to_string() };
+    Ok((StatusCode::ACCEPTED, Json(response)).into_response())
+}
+
+async fn send_magic_link(store: &Store, cache: &Cache, email: String, agent_hash: &str, base: &str) -> Result<(), Error> {
+    let Some(user) = store.get_user_by_email(email).await? else {
+        return Ok(());
+    };
+    let token = generate_random_char(MAGIC_LINK_TOKEN_LEN);
+    let expires_at = Utc::now() + Duration::minutes(MAGIC_LINK_MINUTES);
+    store.add_magic_link(user.id, &hash_secret(&token), agent_hash, expires_at).await?;
+    let body = format!(
+        "Hello {},\n\nUse this link to sign in, it works once and expires in {} minutes:\n{}\n",
+        user.first_name, MAGIC_LINK_MINUTES, link_with_token(base, &token),
+    );
+    cache.transport.send(&OutboundMessage::new(&user.email, "Your sign-in link", body))?;
+    info!("magic link sent to user {}", user.id);
+    Ok(())
+}
+
+pub async fn redeem_magic_link(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(context): Extension<RequestContext>,
+    headers: HeaderMap,
+    Json(packet): Json<MagicLinkRedeemRequest>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let cache = state.1;
+    let ip = context.ip.clone();
+    if let Some(until) = store.get_login_locked_until(AttemptScope::Ip, &ip).await.map_err(|e| e.into_response())? {
+        let wait = (until - Utc::now()).num_seconds().max(1);
+        return Err(Error::TooManyAttempts(wait).into_response());
+    }
+    let redeemed = store.redeem_magic_link(&hash_secret(&packet.token), &user_agent_hash(&headers)).await.map_err(|e| e.into_response())?;
+    let Some(user_id) = redeemed else {
+        record_audit_event(&store, &context, AuditEvent::failure(USER_LOGIN, AuditActor::Anonymous).detail(json!({"method": "magic_link"}))).await;
+        store.record_login_failure(AttemptScope::Ip, &ip).await.map_err(|e| e.into_response())?;
+        return Err(Error::InvalidMagicLink.into_response());
+    };
+    record_audit_event(&store, &context, AuditEvent::success(USER_LOGIN, AuditActor::User(user_id)).detail(json!({"method": "magic_link"}))).await;
+    let user = store.get_user(user_id).await.map_err(|e| e.into_response())?;
+    let session = store.create_session(&cache.tokens, user.id).await.map_err(|e| e.into_response())?;
+    let response = UserResponse {
+        first_name: user.first_name,
+        last_name: user.last_name,
+        email: user.email,
+        session: SessionResponse { access_token: session.access_token, refresh_token: session.refresh_token, expires_at: session.expires_at}
+    };
+    Ok(session_response(&headers, StatusCode::OK, response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn link_appends_token_to_base_url() {
+        assert_eq!(link_with_token("https://app.example.com/login", "abc"), "https://app.example.com/login?token=abc");
+        assert_eq!(link_with_token("https://app.example.com/login?lang=en", "abc"), "https://app.example.com/login?lang=en&token=abc");
+    }
+}
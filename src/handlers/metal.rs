@@ -1,11 +1,321 @@
-use axum::{http::StatusCode, response::{IntoResponse, Response}};
+use std::sync::Arc;
+
+use axum::{body::Body, extract::{Path, Query, State}, http::{header::{CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_NONE_MATCH}, HeaderMap, StatusCode}, response::{IntoResponse, Response}, Extension, Json};
+use chrono::{DateTime, Duration, Utc};
+use encrypt::{ecc::seal_for_peer, generate_random_char, hash_secret};
+use handle_error::Error;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio_util::io::ReaderStream;
+use uuid::Uuid;
+
+use crate::{db_store::Store, handlers::middleware::{AuthenticatedApk, AuthenticatedDevice}, tools::rand_gene::normalize_activation_code, types::{app_release::{release_file, ReleaseChannel}, audit::{record_audit_event, AuditActor, AuditEvent, RequestContext, DEVICE_ACTIVATE, DEVICE_KEY_ROTATE}, cache::Cache, device_health::Heartbeat, login_attempt::AttemptScope}};
+
+// HKDF info of the enrollment envelope, the terminal derives its AES key with the same label
+const ENROLLMENT_INFO: &[u8] = b"metal-device-enrollment";
+const KEY_ROTATION_INFO: &[u8] = b"metal-device-key-rotation";
+// How long the previous keys keep working once a terminal has rotated
+const KEY_OVERLAP_HOURS: i64 = 24;
+const MAX_VERSION_LEN: usize = 32;
+// Config versions are hex SHA-256 digests
+const CONFIG_VERSION_LEN: usize = 64;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 struct Healthly {
     healthly: bool
 }
 
+// `battery` and `signal` are percentages, `clock` is the terminal's unix time in seconds
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HeartbeatRequest {
+    firmware_version: String,
+    app_version: String,
+    battery: i16,
+    signal: i16,
+    clock: i64,
+}
+
+// The server time lets the terminal correct its clock before signatures start failing
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct HeartbeatResponse {
+    healthly: bool,
+    server_time: i64,
+    clock_drift_seconds: i32,
+}
+
+// `version` is the ETag of the config the terminal finished applying
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ConfigAckRequest {
+    version: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct ConfigAckResponse {
+    version: String,
+    // False when the config changed again while the terminal was applying it
+    up_to_date: bool,
+}
+
+// Terminals may name the version they run, otherwise the one from their last heartbeat is used
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UpdateQuery {
+    version: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct UpdateRelease {
+    id: Uuid,
+    version: String,
+    channel: ReleaseChannel,
+    checksum: String,
+    size_bytes: i64,
+    notes: Option<String>,
+    download_path: String,
+}
+
+// `update_required` means payments are refused until the terminal installs `release`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct UpdateResponse {
+    channel: ReleaseChannel,
+    current_version: Option<String>,
+    min_supported_version: Option<String>,
+    update_available: bool,
+    update_required: bool,
+    release: Option<UpdateRelease>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct EnrollmentRequest {
+    code: String,
+    // Base64 SEC1 P-256 key generated on the terminal for this exchange only
+    public_key: String,
+}
+
+// `sealed` is AES-256-GCM `nonce || ciphertext` under the ECDH key of `public_key` and `server_public_key`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct EnrollmentResponse {
+    id: Uuid,
+    server_public_key: String,
+    sealed: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct EnrollmentSecrets {
+    key_version: i32,
+    device_id: String,
+    apk_key: String,
+    price_key: String,
+    id_key: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct KeyRotationRequest {
+    public_key: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct KeyRotationResponse {
+    version: i32,
+    previous_retires_at: DateTime<Utc>,
+    server_public_key: String,
+    sealed: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct RotatedKeys {
+    version: i32,
+    price_key: String,
+    id_key: String,
+}
+
+impl HeartbeatRequest {
+    fn validate(&self) -> Result<Heartbeat, Error> {
+        let versions_ok = [&self.firmware_version, &self.app_version]
+            .iter()
+            .all(|version| !version.trim().is_empty() && version.len() <= MAX_VERSION_LEN);
+        if !versions_ok || !(0..=100).contains(&self.battery) || !(0..=100).contains(&self.signal) {
+            return Err(Error::MissingParameters);
+        }
+        let device_time = DateTime::from_timestamp(self.clock, 0).ok_or(Error::MissingParameters)?;
+        let now = Utc::now();
+        Ok(Heartbeat {
+            firmware_version: self.firmware_version.trim().to_string(),
+            app_version: self.app_version.trim().to_string(),
+            battery: self.battery,
+            signal: self.signal,
+            device_time,
+            clock_drift_seconds: (self.clock - now.timestamp()).clamp(i32::MIN as i64, i32::MAX as i64) as i32,
+        })
+    }
+}
+
 pub async fn get_metal_health() -> Result<impl IntoResponse, Response> {
     Ok((StatusCode::OK, axum::Json(Healthly{healthly: true})).into_response())
-}
\ No newline at end of file
+}
+
+pub async fn post_heartbeat(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(device): Extension<AuthenticatedDevice>,
+    Json(packet): Json<HeartbeatRequest>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let heartbeat = packet.validate().map_err(|e| e.into_response())?;
+    store.record_heartbeat(device.id, &heartbeat).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::OK, Json(HeartbeatResponse {
+        healthly: true,
+        server_time: Utc::now().timestamp(),
+        clock_drift_seconds: heartbeat.clock_drift_seconds,
+    })).into_response())
+}
+
+// `If-None-Match` with the current ETag answers 304, terminals poll without downloading the same config again
+pub async fn get_config(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(device): Extension<AuthenticatedDevice>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let effective = store.get_config_sources(device.id).await.map_err(|e| e.into_response())?.effective();
+    let etag = format!("\"{}\"", effective.version);
+    let matches = headers.get(IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.split(',').any(|tag| tag.trim().trim_start_matches("W/") == etag || tag.trim() == "*"));
+    if matches {
+        return Ok((StatusCode::NOT_MODIFIED, [(ETAG, etag)]).into_response());
+    }
+    Ok((StatusCode::OK, [(ETAG, etag)], Json(effective)).into_response())
+}
+
+pub async fn ack_config(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(device): Extension<AuthenticatedDevice>,
+    Json(packet): Json<ConfigAckRequest>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let version = packet.version.trim().trim_matches('"').to_ascii_lowercase();
+    if version.len() != CONFIG_VERSION_LEN || !version.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(Error::MissingParameters.into_response());
+    }
+    store.ack_device_config(device.id, &version).await.map_err(|e| e.into_response())?;
+    let current = store.get_config_sources(device.id).await.map_err(|e| e.into_response())?.effective();
+    let up_to_date = current.version == version;
+    Ok((StatusCode::OK, Json(ConfigAckResponse { version, up_to_date })).into_response())
+}
+
+pub async fn get_update(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(device): Extension<AuthenticatedDevice>,
+    Query(query): Query<UpdateQuery>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let state = store.get_release_state(device.id).await.map_err(|e| e.into_response())?;
+    let current_version = query.version.map(|version| version.trim().to_string()).filter(|version| !version.is_empty()).or(state.reported_version.clone());
+    let update_available = state.update_available(current_version.as_deref());
+    let update_required = state.upgrade_required(current_version.as_deref());
+    let min_supported_version = state.latest.as_ref().map(|latest| latest.min_supported_version.clone());
+    let release = state.latest.filter(|_| update_available).map(|latest| UpdateRelease {
+        download_path: format!("/api/metal/update/{}/download", latest.id),
+        id: latest.id,
+        version: latest.version,
+        channel: latest.channel,
+        checksum: latest.checksum,
+        size_bytes: latest.size_bytes,
+        notes: latest.notes,
+    });
+    Ok((StatusCode::OK, Json(UpdateResponse { channel: state.channel, current_version, min_supported_version, update_available, update_required, release })).into_response())
+}
+
+// Serves the build from local storage, the terminal checks it against the checksum before installing
+pub async fn download_release(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(device): Extension<AuthenticatedDevice>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let release = store.get_app_release(id).await.map_err(|e| match e {
+        Error::DatabaseQueryError(sqlx::Error::RowNotFound) => Error::MissingParameters.into_response(),
+        e => e.into_response(),
+    })?;
+    let channel = store.get_config_sources(device.id).await.map_err(|e| e.into_response())?.effective().config.release_channel;
+    if !channel.receives(release.channel) {
+        return Err(Error::Forbidden.into_response());
+    }
+    let path = release_file(&release.file_path).ok_or(Error::MissingParameters.into_response())?;
+    let file = tokio::fs::File::open(&path).await
+        .map_err(|e| Error::Conflict(format!("release {} is not in storage: {}", release.version, e)).into_response())?;
+    let length = file.metadata().await
+        .map_err(|e| Error::Conflict(format!("release {} is not in storage: {}", release.version, e)).into_response())?
+        .len();
+    let disposition = format!("attachment; filename=\"terminal-{}.apk\"", release.version);
+    let headers = [
+        (CONTENT_TYPE, "application/vnd.android.package-archive".to_string()),
+        (CONTENT_LENGTH, length.to_string()),
+        (CONTENT_DISPOSITION, disposition),
+        (ETAG, format!("\"{}\"", release.checksum)),
+    ];
+    Ok((StatusCode::OK, headers, Body::from_stream(ReaderStream::new(file))).into_response())
+}
+
+pub async fn enroll_device(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(apk): Extension<AuthenticatedApk>,
+    Extension(context): Extension<RequestContext>,
+    Json(packet): Json<EnrollmentRequest>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let ip = context.ip.clone();
+    // A throwaway seal checks the public key before the code is burned, a bad key must not cost the merchant their code
+    seal_for_peer(&packet.public_key, ENROLLMENT_INFO, &[]).map_err(|e| e.into_response())?;
+    // Claimed before the code is tried so parallel guesses queue on the counter, a redeemed code clears it
+    if let Some(until) = store.claim_login_attempt(AttemptScope::DeviceActivation, &ip).await.map_err(|e| e.into_response())? {
+        let wait = (until - Utc::now()).num_seconds().max(1);
+        return Err(Error::TooManyAttempts(wait).into_response());
+    }
+    let apk_key = generate_random_char(16);
+    let price_key = generate_random_char(16);
+    let id_key = generate_random_char(16);
+    let code_hash = hash_secret(&normalize_activation_code(&packet.code));
+    // The fresh `device_id` is only taken on first enrollment, a re-enrolled terminal gets its old one back
+    let device = store.activate_device(&code_hash, &apk.bank_id, &generate_random_char(16), &apk_key, &price_key, &id_key).await
+        .map_err(|e| e.into_response())?;
+    let Some(device) = device else {
+        record_audit_event(&store, &context, AuditEvent::failure(DEVICE_ACTIVATE, AuditActor::ApiKey(apk.key_id)).entity("bank", &apk.bank_id)).await;
+        return Err(Error::InvalidActivationCode.into_response());
+    };
+    store.clear_login_failures(AttemptScope::DeviceActivation, &ip).await.map_err(|e| e.into_response())?;
+    let secrets = EnrollmentSecrets { key_version: 1, device_id: device.device_id.clone(), apk_key, price_key, id_key };
+    let envelope = seal_for_peer(&packet.public_key, ENROLLMENT_INFO, json!(secrets).to_string().as_bytes())
+        .map_err(|e| e.into_response())?;
+    record_audit_event(&store, &context, AuditEvent::success(DEVICE_ACTIVATE, AuditActor::Device(device.id)).entity("device", device.id).detail(json!({"bank_id": apk.bank_id, "api_key_id": apk.key_id}))).await;
+    Ok((StatusCode::OK, Json(EnrollmentResponse {
+        id: device.id,
+        server_public_key: envelope.ephemeral_public_key,
+        sealed: envelope.sealed,
+    })).into_response())
+}
+
+// Signed with a live key, so only the terminal itself can ask for its next generation
+pub async fn rotate_keys(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(device): Extension<AuthenticatedDevice>,
+    Extension(context): Extension<RequestContext>,
+    Json(packet): Json<KeyRotationRequest>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let price_key = generate_random_char(16);
+    let id_key = generate_random_char(16);
+    let previous_retires_at = Utc::now() + Duration::hours(KEY_OVERLAP_HOURS);
+    // A throwaway seal checks the public key before anything is rotated
+    seal_for_peer(&packet.public_key, KEY_ROTATION_INFO, &[]).map_err(|e| e.into_response())?;
+    let key = store.rotate_device_keys(device.id, &price_key, &id_key, previous_retires_at).await.map_err(|e| e.into_response())?;
+    let secrets = RotatedKeys { version: key.version, price_key, id_key };
+    let envelope = seal_for_peer(&packet.public_key, KEY_ROTATION_INFO, json!(secrets).to_string().as_bytes())
+        .map_err(|e| e.into_response())?;
+    record_audit_event(&store, &context, AuditEvent::success(DEVICE_KEY_ROTATE, AuditActor::Device(device.id)).entity("device", device.id).detail(json!({"version": key.version, "signed_with": device.key_version, "previous_retires_at": previous_retires_at}))).await;
+    Ok((StatusCode::OK, Json(KeyRotationResponse {
+        version: key.version,
+        previous_retires_at,
+        server_public_key: envelope.ephemeral_public_key,
+        sealed: envelope.sealed,
+    })).into_response())
+}
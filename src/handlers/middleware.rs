@@ -1,62 +1,103 @@
-use std::{env, sync::Arc};
+use std::{net::SocketAddr, sync::Arc};
 
 use axum::{
-    extract::{Request, State},
-    http::StatusCode,
+    body::Body,
+    extract::{ConnectInfo, OriginalUri, Request, State},
+    http::{header, HeaderMap, HeaderValue},
     middleware::Next,
     response::{IntoResponse, Response},
 };
+use chrono::{DateTime, Utc};
+use encrypt::{hash_secret, signing::{derive_device_secret, verify_request}};
 use handle_error::Error;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use uuid::Uuid;
 
-use crate::{db_store::Store, tools::constant::{SESSION_KEY, XMINISTER_API_KEY, XMINISTER_METAL_API_KEY}, types::cache::Cache};
+use crate::{db_store::Store, handlers::user::verify_token, tools::{constant::{XMINISTER_API_KEY, XMINISTER_METAL_API_KEY, X_DEVICE_ID, X_KEY_VERSION, X_NONCE, X_REQUEST_ID, X_SIGNATURE, X_TIMESTAMP}, cookie::{csrf_matches, get_cookie, is_state_changing, ACCESS_COOKIE}, request::client_ip}, types::{api_key::ApiKeyType, audit::{record_audit_event, AuditActor, AuditEvent, RequestContext, API_KEY_USE}, cache::Cache, device::{Device, DeviceStatus}, role::{Permission, Role}}};
+
+const MAX_REQUEST_ID_LEN: usize = 64;
+const MAX_USER_AGENT_LEN: usize = 512;
+
+// Outermost layer, tags the request with an id (kept from Caddy when it sent one) plus IP and user agent
+pub async fn request_context(
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let request_id = request.headers()
+        .get(X_REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| is_valid_request_id(value))
+        .map(|value| value.to_string())
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    let user_agent: String = request.headers()
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("")
+        .chars()
+        .take(MAX_USER_AGENT_LEN)
+        .collect();
+    let peer = request.extensions().get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(addr)| *addr);
+    let context = RequestContext { request_id: request_id.clone(), ip: client_ip(request.headers(), peer), user_agent };
+    request.extensions_mut().insert(context);
+    let mut response = next.run(request).await;
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(X_REQUEST_ID, value);
+    }
+    response
+}
+
+fn is_valid_request_id(value: &str) -> bool {
+    !value.is_empty()
+        && value.len() <= MAX_REQUEST_ID_LEN
+        && value.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+// How far a terminal clock may drift before its signatures are refused
+const SIGNATURE_WINDOW_SECONDS: i64 = 60;
+const MAX_NONCE_LEN: usize = 64;
+const MAX_SIGNED_BODY_BYTES: usize = 1024 * 1024;
 
 pub async fn auth_middleware(
+    State(state): State<(Store, Arc<Cache>)>,
     mut request: Request,
     next: Next,
 ) -> Result<Response, Error> {
-    // Extract token from Authorization header
+    // Bearer tokens from API clients, the session cookie from the website
     let auth_header = request
         .headers()
         .get("Authorization")
-        .and_then(|header| header.to_str().ok())
-        .ok_or(StatusCode::UNAUTHORIZED)
-        .map_err(|_| Error::CannotDecryptToken)?;
-    let token = extract_token_from_headers(auth_header)?;
+        .and_then(|header| header.to_str().ok());
+    let token = match auth_header {
+        Some(auth_header) => extract_token_from_headers(auth_header)?,
+        None => {
+            let token = get_cookie(request.headers(), ACCESS_COOKIE).ok_or(Error::CannotDecryptToken)?;
+            // Browsers attach cookies to cross-site requests, the CSRF header proves the page is ours
+            if is_state_changing(request.method()) && !csrf_matches(request.headers()) {
+                return Err(Error::Forbidden);
+            }
+            token
+        }
+    };
     
     // Validate the access token and extract user ID
-    let user_id = extract_user_from_access_token(&token)?;
+    let user_id = verify_token(&state.0, &state.1, &token).await?;
+
+    // Roles live on the user row so a change applies on the next request
+    let user = state.0.get_user(user_id).await
+        .map_err(|_| Error::Unauthorized)?;
+    // Access tokens outlive the deleted sessions, the flag cuts them off immediately
+    if user.deleted_at.is_some() {
+        return Err(Error::Unauthorized);
+    }
     
     // Add authenticated user to request extensions
-    request.extensions_mut().insert(AuthenticatedUser::new(user_id));
+    request.extensions_mut().insert(AuthenticatedUser::new(user_id, user.role));
     
     // Continue to the next middleware/handler
     Ok(next.run(request).await)
 }
 
-fn extract_user_from_access_token(token: &str) -> Result<Uuid, Error> {
-    let session_key = env::var(SESSION_KEY)
-        .map_err(|e| Error::EnvError(e))?;
-    
-    // Validate the access token
-    let token_data = paseto::tokens::validate_local_token(
-        token,
-        None,
-        &session_key.as_bytes(),
-        &paseto::tokens::TimeBackend::Chrono,
-    )
-    .map_err(|_| Error::CannotDecryptToken)?;
-    
-    // Extract user ID from token data
-    let user_id: Uuid = token_data["id"]
-        .as_str()
-        .and_then(|s| Uuid::parse_str(s).ok())
-        .ok_or(Error::InvalidSessionKey("Missing or invalid user id".to_string()))?;
-    
-    Ok(user_id)
-}
-
 fn extract_token_from_headers(auth_header: &str) -> Result<String, Error> {
     if !auth_header.starts_with("Bearer ") {
         return Err(Error::CannotDecryptToken);
@@ -74,53 +115,218 @@ fn extract_token_from_headers(auth_header: &str) -> Result<String, Error> {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct AuthenticatedUser {
     pub user_id: Uuid,
+    pub role: Role,
 }
+// Bank API key that passed `public_apk` or `metal_apk`
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct AuthenticatedApk {
-    pub apk: String,
+    pub key_id: Uuid,
+    pub bank_id: String,
+    pub scopes: Vec<ApiKeyType>,
 }
 // Extension trait to add user to request extensions
 impl AuthenticatedUser {
-    pub fn new(user_id: Uuid) -> Self {
-        Self { user_id }
+    pub fn new(user_id: Uuid, role: Role) -> Self {
+        Self { user_id, role }
+    }
+
+    pub fn can(&self, permission: Permission) -> bool {
+        self.role.allows(permission)
+    }
+}
+
+// Terminal whose request signature checked out in `metal_apk`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AuthenticatedDevice {
+    pub id: Uuid,
+    pub device_id: String,
+    pub business_id: Uuid,
+    pub account_id: Uuid,
+    // Key generation the request was signed with
+    pub key_version: i32,
+}
+
+impl AuthenticatedDevice {
+    pub fn new(device: &Device, key_version: i32) -> Self {
+        Self {
+            id: device.id,
+            device_id: device.device_id.clone(),
+            business_id: device.business_id,
+            account_id: device.account_id,
+            key_version,
+        }
     }
 }
 
 impl AuthenticatedApk {
-    pub fn new(apk: String) -> Self {
-        Self { apk }
+    pub fn new(key_id: Uuid, bank_id: String, scopes: Vec<ApiKeyType>) -> Self {
+        Self { key_id, bank_id, scopes }
     }
 }
 
-pub async fn metal_apk(mut request: Request, next: Next
+// Runs inside `auth_middleware`, attach with `from_fn_with_state(permission, require_permission)`
+pub async fn require_permission(
+    State(permission): State<Permission>,
+    request: Request,
+    next: Next,
 ) -> Result<Response, Error> {
-    let auth_header = request
-        .headers()
-        .get(XMINISTER_METAL_API_KEY)
-        .and_then(|header| header.to_str().ok())
-        .ok_or(StatusCode::UNAUTHORIZED)
-        .map_err(|_| Error::CannotDecryptToken)?;
-    let key = auth_header.to_string();
-    request.extensions_mut().insert(AuthenticatedApk::new(key));
+    let user = request
+        .extensions()
+        .get::<AuthenticatedUser>()
+        .ok_or(Error::Unauthorized)?;
+    if !user.can(permission) {
+        return Err(Error::Forbidden);
+    }
     Ok(next.run(request).await)
 }
 
-pub async fn public_apk(mut request: Request, next: Next
+// Terminals send the bank key plus an HMAC of the request made with their own derived secret
+pub async fn metal_apk(
+    State(state): State<(Store, Arc<Cache>)>,
+    request: Request,
+    next: Next,
 ) -> Result<Response, Error> {
-    let auth_header = request
-        .headers()
-        .get(XMINISTER_API_KEY)
+    let store = state.0;
+    let target = api_key_audit_target(&request);
+    let verified = verify_api_key(&store, request.headers(), XMINISTER_METAL_API_KEY, ApiKeyType::XMinisterMetal).await;
+    audit_api_key_use(&store, target, ApiKeyType::XMinisterMetal, &verified).await;
+    let apk = verified?;
+    let device_id = header_value(request.headers(), X_DEVICE_ID)?;
+    let timestamp = header_value(request.headers(), X_TIMESTAMP)?
+        .parse::<i64>()
+        .map_err(|_| Error::InvalidSignature)?;
+    let nonce = header_value(request.headers(), X_NONCE)?;
+    let signature = header_value(request.headers(), X_SIGNATURE)?;
+    if (Utc::now().timestamp() - timestamp).abs() > SIGNATURE_WINDOW_SECONDS || nonce.len() > MAX_NONCE_LEN {
+        return Err(Error::InvalidSignature);
+    }
+    let device = store.get_device_device_id(&device_id).await?
+        .ok_or(Error::InvalidSignature)?;
+    // The metal key only vouches for its own bank's terminals
+    let account = store.get_account(&device.account_id).await?;
+    if account.bank_id != apk.bank_id {
+        return Err(Error::InvalidSignature);
+    }
+    // Without a version header the newest key signs, older live keys cover terminals mid-rotation
+    let key_version = match request.headers().get(X_KEY_VERSION) {
+        Some(_) => Some(header_value(request.headers(), X_KEY_VERSION)?
+            .parse::<i32>()
+            .map_err(|_| Error::InvalidSignature)?),
+        None => None,
+    };
+    let key = store.get_live_device_key(device.id, key_version).await?
+        .ok_or(Error::InvalidSignature)?;
+
+    // The body has to be read to be signed, put it back for the handler afterwards
+    let uri = request.extensions().get::<OriginalUri>()
+        .map(|uri| uri.0.clone())
+        .unwrap_or_else(|| request.uri().clone());
+    let path = uri.path_and_query().map(|p| p.as_str()).unwrap_or(uri.path()).to_string();
+    let (parts, body) = request.into_parts();
+    let bytes = axum::body::to_bytes(body, MAX_SIGNED_BODY_BYTES).await
+        .map_err(|_| Error::InvalidSignature)?;
+    let secret = derive_device_secret(&key.id_key);
+    if !verify_request(&secret, parts.method.as_str(), &path, timestamp, &nonce, &bytes, &signature) {
+        return Err(Error::InvalidSignature);
+    }
+    // Claimed only once the signature holds, a replay inside the window finds its nonce taken
+    let expires_at = DateTime::from_timestamp(timestamp + SIGNATURE_WINDOW_SECONDS, 0).ok_or(Error::InvalidSignature)?;
+    if !store.claim_request_nonce(device.id, &nonce, expires_at).await? {
+        return Err(Error::InvalidSignature);
+    }
+    // Checked after the signature so only the terminal itself learns it was suspended or retired
+    if device.status != DeviceStatus::Active {
+        return Err(Error::DeviceNotActive(device.status.as_str().to_string()));
+    }
+    let mut request = Request::from_parts(parts, Body::from(bytes));
+    request.extensions_mut().insert(AuthenticatedDevice::new(&device, key.version));
+    Ok(next.run(request).await)
+}
+
+fn header_value(headers: &HeaderMap, name: &str) -> Result<String, Error> {
+    headers
+        .get(name)
         .and_then(|header| header.to_str().ok())
-        .ok_or(StatusCode::UNAUTHORIZED)
-        .map_err(|_| Error::CannotDecryptToken)?;
-    let key = auth_header.to_string();
-    request.extensions_mut().insert(AuthenticatedApk::new(key));
+        .filter(|value| !value.is_empty())
+        .map(|value| value.to_string())
+        .ok_or(Error::InvalidSignature)
+}
+
+pub async fn public_apk(
+    State(state): State<(Store, Arc<Cache>)>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, Error> {
+    let target = api_key_audit_target(&request);
+    let verified = verify_api_key(&state.0, request.headers(), XMINISTER_API_KEY, ApiKeyType::XMinister).await;
+    audit_api_key_use(&state.0, target, ApiKeyType::XMinister, &verified).await;
+    request.extensions_mut().insert(verified?);
     Ok(next.run(request).await)
 }
 
+// Enrollment runs before the terminal has keys to sign with, the bank's metal key is all it can show
+pub async fn metal_enrollment_apk(
+    State(state): State<(Store, Arc<Cache>)>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, Error> {
+    let target = api_key_audit_target(&request);
+    let verified = verify_api_key(&state.0, request.headers(), XMINISTER_METAL_API_KEY, ApiKeyType::XMinisterMetal).await;
+    audit_api_key_use(&state.0, target, ApiKeyType::XMinisterMetal, &verified).await;
+    request.extensions_mut().insert(verified?);
+    Ok(next.run(request).await)
+}
 
+// Context and path are copied out first, a `&Request` held across the insert is not `Send`
+fn api_key_audit_target(request: &Request) -> Option<(RequestContext, String)> {
+    let context = request.extensions().get::<RequestContext>()?.clone();
+    let path = request.extensions().get::<OriginalUri>()
+        .map(|uri| uri.0.path().to_string())
+        .unwrap_or_else(|| request.uri().path().to_string());
+    Some((context, path))
+}
+
+async fn audit_api_key_use(store: &Store, target: Option<(RequestContext, String)>, scope: ApiKeyType, verified: &Result<AuthenticatedApk, Error>) {
+    let Some((context, path)) = target else {
+        return;
+    };
+    let event = match verified {
+        Ok(apk) => AuditEvent::success(API_KEY_USE, AuditActor::ApiKey(apk.key_id)).entity("bank", &apk.bank_id),
+        Err(_) => AuditEvent::failure(API_KEY_USE, AuditActor::Anonymous),
+    };
+    record_audit_event(store, &context, event.detail(json!({"scope": scope.as_str(), "path": path}))).await;
+}
 
-pub enum ApiKeyType {
-    XMinister,
-    XMinisterMetal,
-}
\ No newline at end of file
+// Looks the key up by its hash, unknown, revoked, expired or out of scope keys never reach a handler.
+// An OAuth access token in `Authorization: Bearer` is accepted in place of the key header
+async fn verify_api_key(store: &Store, headers: &HeaderMap, header: &str, scope: ApiKeyType) -> Result<AuthenticatedApk, Error> {
+    let key = headers
+        .get(header)
+        .and_then(|header| header.to_str().ok())
+        .filter(|key| !key.is_empty());
+    let Some(key) = key else {
+        return verify_access_token(store, headers, scope).await;
+    };
+    let api_key = store.get_active_api_key(&hash_secret(key)).await?
+        .ok_or(Error::ApiKeyRejection)?;
+    if !api_key.allows(scope) {
+        return Err(Error::ApiKeyRejection);
+    }
+    store.touch_api_key(api_key.id).await?;
+    Ok(AuthenticatedApk::new(api_key.id, api_key.bank_id, api_key.scopes))
+}
+
+async fn verify_access_token(store: &Store, headers: &HeaderMap, scope: ApiKeyType) -> Result<AuthenticatedApk, Error> {
+    let auth_header = headers
+        .get("Authorization")
+        .and_then(|header| header.to_str().ok())
+        .ok_or(Error::ApiKeyRejection)?;
+    let token = extract_token_from_headers(auth_header)
+        .map_err(|_| Error::ApiKeyRejection)?;
+    let access_token = store.get_active_oauth_token(&hash_secret(&token)).await?
+        .ok_or(Error::ApiKeyRejection)?;
+    if !access_token.allows(scope) {
+        return Err(Error::ApiKeyRejection);
+    }
+    Ok(AuthenticatedApk::new(access_token.api_key_id, access_token.bank_id, access_token.scopes))
+}
@@ -7,4 +7,14 @@ pub mod bank;
 pub mod account;
 pub mod metal;
 pub mod payment;
-pub mod customer;
\ No newline at end of file
+pub mod customer;
+pub mod admin;
+pub mod organization;
+pub mod api_key;
+pub mod oauth;
+pub mod token;
+pub mod passkey;
+pub mod magic_link;
+pub mod privacy;
+pub mod config_profile;
+pub mod sticker;
@@ -0,0 +1,198 @@
+use std::sync::Arc;
+
+use axum::{extract::State, http::{header, HeaderMap, StatusCode}, response::{IntoResponse, Response}, Form, Json};
+use base64::{engine::general_purpose, Engine as _};
+use chrono::{Duration, Utc};
+use encrypt::{generate_random_char, hash_secret};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{db_store::Store, types::{api_key::ApiKey, cache::Cache, oauth::{format_scopes, parse_scopes}}};
+
+const ACCESS_TOKEN_SECONDS: i64 = 900;
+const ACCESS_TOKEN_LEN: usize = 40;
+
+// RFC 6749 section 5.2 error codes, OAuth clients expect this body rather than ours
+#[derive(Debug, Clone, Copy)]
+pub enum OAuthError {
+    InvalidRequest,
+    InvalidClient,
+    InvalidScope,
+    UnsupportedGrantType,
+    ServerError,
+}
+
+impl IntoResponse for OAuthError {
+    fn into_response(self) -> Response {
+        #[derive(Serialize)]
+        struct OAuthErrorResponse {
+            error: &'static str,
+        }
+
+        let (status, error) = match self {
+            OAuthError::InvalidRequest => (StatusCode::BAD_REQUEST, "invalid_request"),
+            OAuthError::InvalidClient => (StatusCode::UNAUTHORIZED, "invalid_client"),
+            OAuthError::InvalidScope => (StatusCode::BAD_REQUEST, "invalid_scope"),
+            OAuthError::UnsupportedGrantType => (StatusCode::BAD_REQUEST, "unsupported_grant_type"),
+            OAuthError::ServerError => (StatusCode::INTERNAL_SERVER_ERROR, "server_error"),
+        };
+        let mut response = (status, Json(OAuthErrorResponse { error })).into_response();
+        if status == StatusCode::UNAUTHORIZED {
+            response.headers_mut().insert(header::WWW_AUTHENTICATE, "Basic".parse().unwrap());
+        }
+        response
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TokenRequest {
+    grant_type: String,
+    scope: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TokenResponse {
+    access_token: String,
+    token_type: String,
+    expires_in: i64,
+    scope: String,
+}
+
+// Body of both `/oauth/introspect` and `/oauth/revoke`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TokenActionRequest {
+    token: String,
+    token_type_hint: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct IntrospectionResponse {
+    active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scope: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exp: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    iat: Option<i64>,
+}
+
+impl IntrospectionResponse {
+    fn inactive() -> Self {
+        Self { active: false, scope: None, client_id: None, token_type: None, exp: None, iat: None }
+    }
+}
+
+// Bank API keys are the registered clients, `client_id` is the key prefix and `client_secret` the full key
+async fn authenticate_client(store: &Store, headers: &HeaderMap, client_id: Option<String>, client_secret: Option<String>) -> Result<ApiKey, OAuthError> {
+    let (client_id, client_secret) = match basic_credentials(headers) {
+        Some(credentials) => credentials,
+        None => (
+            client_id.ok_or(OAuthError::InvalidClient)?,
+            client_secret.ok_or(OAuthError::InvalidClient)?,
+        ),
+    };
+    let api_key = store.get_active_api_key(&hash_secret(&client_secret)).await
+        .map_err(|e| {
+            warn!("oauth client lookup failed: {}", e);
+            OAuthError::ServerError
+        })?
+        .ok_or(OAuthError::InvalidClient)?;
+    if api_key.prefix != client_id {
+        return Err(OAuthError::InvalidClient);
+    }
+    Ok(api_key)
+}
+
+fn basic_credentials(headers: &HeaderMap) -> Option<(String, String)> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?.strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(general_purpose::STANDARD.decode(value.trim()).ok()?).ok()?;
+    let (client_id, client_secret) = decoded.split_once(':')?;
+    Some((client_id.to_string(), client_secret.to_string()))
+}
+
+pub async fn token(
+    State(state): State<(Store, Arc<Cache>)>,
+    headers: HeaderMap,
+    Form(packet): Form<TokenRequest>,
+) -> Result<impl IntoResponse, OAuthError> {
+    let store = state.0;
+    if packet.grant_type != "client_credentials" {
+        return Err(OAuthError::UnsupportedGrantType);
+    }
+    let client = authenticate_client(&store, &headers, packet.client_id, packet.client_secret).await?;
+    let requested = parse_scopes(packet.scope.as_deref().unwrap_or("")).ok_or(OAuthError::InvalidScope)?;
+    // No scope asks for everything the key may do, never more
+    let scopes = if requested.is_empty() { client.scopes.clone() } else { requested };
+    if scopes.iter().any(|scope| !client.allows(*scope)) {
+        return Err(OAuthError::InvalidScope);
+    }
+    let access_token = generate_random_char(ACCESS_TOKEN_LEN);
+    let expires_at = Utc::now() + Duration::seconds(ACCESS_TOKEN_SECONDS);
+    store.add_oauth_token(client.id, &client.bank_id, &hash_secret(&access_token), &scopes, expires_at).await
+        .map_err(|e| {
+            warn!("oauth token insert failed: {}", e);
+            OAuthError::ServerError
+        })?;
+    Ok((
+        StatusCode::OK,
+        [(header::CACHE_CONTROL, "no-store")],
+        Json(TokenResponse {
+            access_token,
+            token_type: "Bearer".to_string(),
+            expires_in: ACCESS_TOKEN_SECONDS,
+            scope: format_scopes(&scopes),
+        }),
+    ).into_response())
+}
+
+pub async fn introspect(
+    State(state): State<(Store, Arc<Cache>)>,
+    headers: HeaderMap,
+    Form(packet): Form<TokenActionRequest>,
+) -> Result<impl IntoResponse, OAuthError> {
+    let store = state.0;
+    let client = authenticate_client(&store, &headers, packet.client_id, packet.client_secret).await?;
+    if packet.token.is_empty() {
+        return Err(OAuthError::InvalidRequest);
+    }
+    let found = store.get_active_oauth_token(&hash_secret(&packet.token)).await
+        .map_err(|_| OAuthError::ServerError)?;
+    // Another bank's token looks exactly like an unknown one
+    let Some(token) = found.filter(|token| token.bank_id == client.bank_id) else {
+        return Ok(Json(IntrospectionResponse::inactive()).into_response());
+    };
+    let owner = store.get_api_key_bank_id(&client.bank_id, token.api_key_id).await
+        .map_err(|_| OAuthError::ServerError)?;
+    Ok(Json(IntrospectionResponse {
+        active: true,
+        scope: Some(format_scopes(&token.scopes)),
+        client_id: Some(owner.prefix),
+        token_type: Some("Bearer".to_string()),
+        exp: Some(token.expires_at.timestamp()),
+        iat: Some(token.created_at.timestamp()),
+    }).into_response())
+}
+
+// Always 200 for an authenticated client, RFC 7009 does not reveal whether the token existed
+pub async fn revoke(
+    State(state): State<(Store, Arc<Cache>)>,
+    headers: HeaderMap,
+    Form(packet): Form<TokenActionRequest>,
+) -> Result<impl IntoResponse, OAuthError> {
+    let store = state.0;
+    let client = authenticate_client(&store, &headers, packet.client_id, packet.client_secret).await?;
+    if packet.token.is_empty() {
+        return Err(OAuthError::InvalidRequest);
+    }
+    store.revoke_oauth_token(&hash_secret(&packet.token), &client.bank_id).await
+        .map_err(|_| OAuthError::ServerError)?;
+    Ok(StatusCode::OK.into_response())
+}
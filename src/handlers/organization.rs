@@ -0,0 +1,177 @@
+use std::{env, sync::Arc};
+
+use axum::{extract::{Path, State}, http::StatusCode, response::{IntoResponse, Response}, Extension, Json};
+use chrono::{DateTime, Duration, Utc};
+use encrypt::{generate_random_char, hash_secret};
+use handle_error::Error;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{db_store::Store, handlers::{magic_link::link_with_token, middleware::AuthenticatedUser}, tools::{constant::INVITATION_URL, transport::OutboundMessage}, types::{cache::Cache, organization::{Member, MemberRole, Membership}}};
+
+const INVITATION_DAYS: i64 = 7;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OrganizationRequest {
+    name: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct UserOrganizationResponse {
+    list: Vec<Membership>
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct MembersResponse {
+    list: Vec<Member>
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MemberRoleRequest {
+    role: MemberRole,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct InvitationRequest {
+    email: String,
+    role: MemberRole,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct InvitationResponse {
+    id: Uuid,
+    email: String,
+    role: MemberRole,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AcceptInvitationRequest {
+    token: String,
+}
+
+// Role of the caller in the organization, Forbidden when they are not a member
+pub(crate) async fn member_role(store: &Store, organization_id: Uuid, user_id: Uuid) -> Result<MemberRole, Response> {
+    store.get_member_role(organization_id, user_id).await
+        .map_err(|e| e.into_response())?
+        .ok_or(Error::Forbidden.into_response())
+}
+
+pub async fn get_organizations(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let list = store.get_memberships_user_id(user.user_id).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::OK, Json(UserOrganizationResponse{list})).into_response())
+}
+
+pub async fn create_organization(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Json(packet): Json<OrganizationRequest>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    if packet.name.trim().is_empty() {
+        return Err(Error::MissingParameters.into_response());
+    }
+    let organization = store.add_organization(packet.name.trim(), user.user_id).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::CREATED, Json(organization)).into_response())
+}
+
+pub async fn get_members(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Path(organization_id): Path<Uuid>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    member_role(&store, organization_id, user.user_id).await?;
+    let list = store.get_members(organization_id).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::OK, Json(MembersResponse{list})).into_response())
+}
+
+pub async fn update_member(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Path((organization_id, member_id)): Path<(Uuid, Uuid)>,
+    Json(packet): Json<MemberRoleRequest>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    if !member_role(&store, organization_id, user.user_id).await?.can_manage_members() {
+        return Err(Error::Forbidden.into_response());
+    }
+    let current = member_role(&store, organization_id, member_id).await?;
+    if current == MemberRole::Owner && packet.role != MemberRole::Owner && store.count_owners(organization_id).await.map_err(|e| e.into_response())? <= 1 {
+        return Err(Error::Conflict("an organization needs at least one owner".to_string()).into_response());
+    }
+    store.update_member_role(organization_id, member_id, packet.role).await.map_err(|e| e.into_response())?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+pub async fn delete_member(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Path((organization_id, member_id)): Path<(Uuid, Uuid)>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let role = member_role(&store, organization_id, user.user_id).await?;
+    // Anyone may leave, only owners remove others
+    if member_id != user.user_id && !role.can_manage_members() {
+        return Err(Error::Forbidden.into_response());
+    }
+    let current = member_role(&store, organization_id, member_id).await?;
+    if current == MemberRole::Owner && store.count_owners(organization_id).await.map_err(|e| e.into_response())? <= 1 {
+        return Err(Error::Conflict("an organization needs at least one owner".to_string()).into_response());
+    }
+    store.delete_member(organization_id, member_id).await.map_err(|e| e.into_response())?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+pub async fn create_invitation(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Path(organization_id): Path<Uuid>,
+    Json(packet): Json<InvitationRequest>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let cache = state.1;
+    if !member_role(&store, organization_id, user.user_id).await?.can_manage_members() {
+        return Err(Error::Forbidden.into_response());
+    }
+    let email = packet.email.trim().to_lowercase();
+    if email.is_empty() || !email.contains('@') {
+        return Err(Error::MissingParameters.into_response());
+    }
+    let base = env::var(INVITATION_URL).map_err(|e| Error::EnvError(e).into_response())?;
+    let organization_name = store.get_memberships_user_id(user.user_id).await.map_err(|e| e.into_response())?
+        .into_iter()
+        .find(|membership| membership.organization_id == organization_id)
+        .map(|membership| membership.organization_name)
+        .unwrap_or_default();
+    // The token only ever travels in the email, the invitee redeems it at /api/auth/invitations/accept
+    let token = generate_random_char(32);
+    let expires_at = Utc::now() + Duration::days(INVITATION_DAYS);
+    let invitation = store.add_invitation(organization_id, &email, packet.role, &hash_secret(&token), user.user_id, expires_at).await.map_err(|e| e.into_response())?;
+    let body = format!(
+        "Hello,\n\nYou have been invited to join {} as {}. Sign in with this email and open the link to accept, it expires in {} days:\n{}\n",
+        organization_name, invitation.role.as_str(), INVITATION_DAYS, link_with_token(&base, &token),
+    );
+    cache.transport.send(&OutboundMessage::new(&invitation.email, "You have been invited to an organization", body)).map_err(|e| e.into_response())?;
+    Ok((StatusCode::CREATED, Json(InvitationResponse {
+        id: invitation.id,
+        email: invitation.email,
+        role: invitation.role,
+        expires_at: invitation.expires_at,
+    })).into_response())
+}
+
+pub async fn accept_invitation(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Json(packet): Json<AcceptInvitationRequest>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let user_data = store.get_user(user.user_id).await.map_err(|e| e.into_response())?;
+    let membership = store.accept_invitation(&hash_secret(&packet.token), user_data.id, &user_data.email).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::OK, Json(membership)).into_response())
+}
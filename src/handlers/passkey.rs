@@ -0,0 +1,298 @@
+use std::{env, sync::Arc};
+
+use axum::{extract::{Path, State}, http::{HeaderMap, StatusCode}, response::{IntoResponse, Response}, Extension, Json};
+use chrono::{Duration, Utc};
+use handle_error::Error;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::{db_store::Store, handlers::{middleware::AuthenticatedUser, user::{session_response, SessionResponse, UserResponse}}, tools::{constant::{WEBAUTHN_RP_ID, WEBAUTHN_RP_NAME, WEBAUTHN_RP_ORIGIN}, webauthn::{self, COSE_ALG_ES256}}, types::{audit::{record_audit_event, AuditActor, AuditEvent, RequestContext, USER_LOGIN}, cache::Cache, login_attempt::AttemptScope, passkey::{ChallengeKind, Passkey}}};
+
+const CHALLENGE_MINUTES: i64 = 5;
+
+// The relying party the browser checks credentials against, `origin` is the exact web app origin
+struct RelyingParty {
+    id: String,
+    origin: String,
+    name: String,
+}
+
+impl RelyingParty {
+    fn from_env() -> Result<RelyingParty, Error> {
+        Ok(RelyingParty {
+            id: env::var(WEBAUTHN_RP_ID).map_err(Error::EnvError)?,
+            origin: env::var(WEBAUTHN_RP_ORIGIN).map_err(Error::EnvError)?,
+            name: env::var(WEBAUTHN_RP_NAME).unwrap_or_else(|_| "Link".to_string()),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialDescriptor {
+    #[serde(rename = "type")]
+    kind: String,
+    id: String,
+}
+
+impl CredentialDescriptor {
+    fn new(passkey: &Passkey) -> Self {
+        Self { kind: "public-key".to_string(), id: passkey.credential_id.clone() }
+    }
+}
+
+// Shapes below follow the WebAuthn JSON encoding, binary fields are base64url without padding
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreationOptionsResponse {
+    challenge: String,
+    rp: serde_json::Value,
+    user: serde_json::Value,
+    pub_key_cred_params: serde_json::Value,
+    timeout: i64,
+    attestation: String,
+    authenticator_selection: serde_json::Value,
+    exclude_credentials: Vec<CredentialDescriptor>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    client_data_json: String,
+    attestation_object: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RegistrationRequest {
+    name: String,
+    id: String,
+    response: AttestationResponse,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestOptionsResponse {
+    challenge: String,
+    rp_id: String,
+    timeout: i64,
+    user_verification: String,
+    allow_credentials: Vec<CredentialDescriptor>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    client_data_json: String,
+    authenticator_data: String,
+    signature: String,
+    user_handle: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PasskeyLoginRequest {
+    id: String,
+    response: AssertionResponse,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct PasskeysResponse {
+    list: Vec<Passkey>
+}
+
+fn rejected(reason: webauthn::WebauthnError) -> Response {
+    warn!("passkey rejected: {}", reason);
+    Error::PasskeyRejected.into_response()
+}
+
+async fn issue_challenge(store: &Store, user_id: Option<Uuid>, kind: ChallengeKind) -> Result<String, Response> {
+    let challenge = webauthn::encode(&rand::thread_rng().gen::<[u8; 32]>());
+    let expires_at = Utc::now() + Duration::minutes(CHALLENGE_MINUTES);
+    store.add_webauthn_challenge(&challenge, user_id, kind, expires_at).await.map_err(|e| e.into_response())?;
+    Ok(challenge)
+}
+
+pub async fn get_passkeys(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let list = store.get_passkeys_user_id(user.user_id).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::OK, Json(PasskeysResponse{list})).into_response())
+}
+
+pub async fn start_registration(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let rp = RelyingParty::from_env().map_err(|e| e.into_response())?;
+    let user_data = store.get_user(user.user_id).await.map_err(|e| e.into_response())?;
+    let existing = store.get_passkeys_user_id(user.user_id).await.map_err(|e| e.into_response())?;
+    let challenge = issue_challenge(&store, Some(user.user_id), ChallengeKind::Registration).await?;
+    let response = CreationOptionsResponse {
+        challenge,
+        rp: serde_json::json!({"id": rp.id, "name": rp.name}),
+        user: serde_json::json!({
+            "id": webauthn::encode(user_data.id.as_bytes()),
+            "name": user_data.email,
+            "displayName": format!("{} {}", user_data.first_name, user_data.last_name),
+        }),
+        pub_key_cred_params: serde_json::json!([{"type": "public-key", "alg": COSE_ALG_ES256}]),
+        timeout: CHALLENGE_MINUTES * 60 * 1000,
+        attestation: "none".to_string(),
+        authenticator_selection: serde_json::json!({"residentKey": "required", "requireResidentKey": true, "userVerification": "required"}),
+        // Stops the browser from registering the same authenticator twice
+        exclude_credentials: existing.iter().map(CredentialDescriptor::new).collect(),
+    };
+    Ok((StatusCode::OK, Json(response)).into_response())
+}
+
+pub async fn finish_registration(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Json(packet): Json<RegistrationRequest>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let rp = RelyingParty::from_env().map_err(|e| e.into_response())?;
+    if packet.name.trim().is_empty() {
+        return Err(Error::MissingParameters.into_response());
+    }
+    let client_data_json = webauthn::decode(&packet.response.client_data_json, "clientDataJSON").map_err(rejected)?;
+    let client_data = webauthn::parse_client_data(&client_data_json).map_err(rejected)?;
+    webauthn::check_client_data(&client_data, "webauthn.create", &rp.origin).map_err(rejected)?;
+    let owner = store.take_webauthn_challenge(&client_data.challenge, ChallengeKind::Registration).await.map_err(|e| e.into_response())?;
+    if owner != Some(Some(user.user_id)) {
+        return Err(rejected(webauthn::WebauthnError::ChallengeMismatch));
+    }
+    let attestation_object = webauthn::decode(&packet.response.attestation_object, "attestationObject").map_err(rejected)?;
+    let auth_data = webauthn::parse_attestation_object(&attestation_object).map_err(rejected)?;
+    webauthn::check_authenticator_data(&auth_data, &rp.id).map_err(rejected)?;
+    let credential = auth_data.credential.ok_or_else(|| rejected(webauthn::WebauthnError::Malformed("attestedCredentialData")))?;
+    let credential_id = webauthn::encode(&credential.credential_id);
+    if credential_id != packet.id {
+        return Err(rejected(webauthn::WebauthnError::Malformed("credential id")));
+    }
+    if store.get_passkey_credential_id(&credential_id).await.map_err(|e| e.into_response())?.is_some() {
+        return Err(Error::Conflict("passkey already registered".to_string()).into_response());
+    }
+    let passkey = store.add_passkey(user.user_id, &credential_id, &credential.public_key, auth_data.sign_count as i64, packet.name.trim()).await.map_err(|e| e.into_response())?;
+    Ok((StatusCode::CREATED, Json(passkey)).into_response())
+}
+
+pub async fn delete_passkey(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    if !store.delete_passkey(user.user_id, id).await.map_err(|e| e.into_response())? {
+        return Err(Error::Unauthorized.into_response());
+    }
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+pub async fn start_login(
+    State(state): State<(Store, Arc<Cache>)>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let rp = RelyingParty::from_env().map_err(|e| e.into_response())?;
+    let challenge = issue_challenge(&store, None, ChallengeKind::Authentication).await?;
+    let response = RequestOptionsResponse {
+        challenge,
+        rp_id: rp.id,
+        timeout: CHALLENGE_MINUTES * 60 * 1000,
+        user_verification: "required".to_string(),
+        // Left empty so the browser offers its discoverable credentials and nothing hints at which accounts exist
+        allow_credentials: Vec::new(),
+    };
+    Ok((StatusCode::OK, Json(response)).into_response())
+}
+
+pub async fn finish_login(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(context): Extension<RequestContext>,
+    headers: HeaderMap,
+    Json(packet): Json<PasskeyLoginRequest>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let cache = state.1;
+    let rp = RelyingParty::from_env().map_err(|e| e.into_response())?;
+    let ip = context.ip.clone();
+    if let Some(until) = store.get_login_locked_until(AttemptScope::Ip, &ip).await.map_err(|e| e.into_response())? {
+        let wait = (until - Utc::now()).num_seconds().max(1);
+        return Err(Error::TooManyAttempts(wait).into_response());
+    }
+    let verified = verify_assertion(&store, &rp, &packet).await?;
+    let passkey = match verified {
+        Ok(passkey) => passkey,
+        Err(reason) => {
+            record_audit_event(&store, &context, AuditEvent::failure(USER_LOGIN, AuditActor::Anonymous).detail(json!({"method": "passkey", "reason": reason.to_string()}))).await;
+            store.record_login_failure(AttemptScope::Ip, &ip).await.map_err(|e| e.into_response())?;
+            return Err(rejected(reason));
+        }
+    };
+    record_audit_event(&store, &context, AuditEvent::success(USER_LOGIN, AuditActor::User(passkey.user_id)).detail(json!({"method": "passkey", "passkey_id": passkey.id}))).await;
+    let user = store.get_user(passkey.user_id).await.map_err(|e| e.into_response())?;
+    let session = store.create_session(&cache.tokens, user.id).await.map_err(|e| e.into_response())?;
+    let response = UserResponse {
+        first_name: user.first_name,
+        last_name: user.last_name,
+        email: user.email,
+        session: SessionResponse { access_token: session.access_token, refresh_token: session.refresh_token, expires_at: session.expires_at}
+    };
+    Ok(session_response(&headers, StatusCode::OK, response))
+}
+
+// Outer error is a server failure, inner one a response that does not verify
+async fn verify_assertion(store: &Store, rp: &RelyingParty, packet: &PasskeyLoginRequest) -> Result<Result<Passkey, webauthn::WebauthnError>, Response> {
+    let client_data_json = match webauthn::decode(&packet.response.client_data_json, "clientDataJSON") {
+        Ok(bytes) => bytes,
+        Err(e) => return Ok(Err(e)),
+    };
+    let client_data = match webauthn::parse_client_data(&client_data_json)
+        .and_then(|client_data| webauthn::check_client_data(&client_data, "webauthn.get", &rp.origin).map(|_| client_data)) {
+        Ok(client_data) => client_data,
+        Err(e) => return Ok(Err(e)),
+    };
+    if store.take_webauthn_challenge(&client_data.challenge, ChallengeKind::Authentication).await.map_err(|e| e.into_response())?.is_none() {
+        return Ok(Err(webauthn::WebauthnError::ChallengeMismatch));
+    }
+    let Some(passkey) = store.get_passkey_credential_id(&packet.id).await.map_err(|e| e.into_response())? else {
+        return Ok(Err(webauthn::WebauthnError::UnknownCredential));
+    };
+    // Discoverable credentials always return the user id they were registered with
+    let user_handle = match packet.response.user_handle.as_deref().map(|handle| webauthn::decode(handle, "userHandle")) {
+        Some(Ok(user_handle)) => user_handle,
+        Some(Err(e)) => return Ok(Err(e)),
+        None => return Ok(Err(webauthn::WebauthnError::Malformed("userHandle"))),
+    };
+    if user_handle != passkey.user_id.as_bytes() {
+        return Ok(Err(webauthn::WebauthnError::UserHandleMismatch));
+    }
+    let checked = webauthn::decode(&packet.response.authenticator_data, "authenticatorData")
+        .and_then(|authenticator_data| {
+            let signature = webauthn::decode(&packet.response.signature, "signature")?;
+            let auth_data = webauthn::parse_authenticator_data(&authenticator_data)?;
+            webauthn::check_authenticator_data(&auth_data, &rp.id)?;
+            webauthn::verify_assertion(&passkey.public_key, &authenticator_data, &client_data_json, &signature)?;
+            Ok(auth_data.sign_count)
+        });
+    let sign_count = match checked {
+        Ok(sign_count) => sign_count,
+        Err(e) => return Ok(Err(e)),
+    };
+    // A counter that did not move forward points at a cloned authenticator
+    if !webauthn::sign_count_is_valid(passkey.sign_count as u32, sign_count) {
+        warn!("passkey {} sign counter went from {} to {}", passkey.id, passkey.sign_count, sign_count);
+        return Ok(Err(webauthn::WebauthnError::CounterRegressed));
+    }
+    if !store.update_passkey_counter(passkey.id, passkey.sign_count, sign_count as i64).await.map_err(|e| e.into_response())? {
+        return Ok(Err(webauthn::WebauthnError::CounterRegressed));
+    }
+    Ok(Ok(passkey))
+}
@@ -1,12 +1,13 @@
 
 use std::{str::FromStr, sync::Arc};
 
-use axum::{extract::State, http::StatusCode, response::{IntoResponse, Response}, Extension, Json};
+use axum::{extract::{Query, State}, http::StatusCode, response::{IntoResponse, Response}, Extension, Json};
 use chrono::Utc;
 use encrypt::{ecc::{ecc_decrypt_key, generate_keys}, functions::decrypt};
 use handle_error::Error;
-use crate::{db_store::Store, handlers::middleware::{AuthenticatedApk, AuthenticatedUser}, types::{cache::Cache, payments::PaymentResponse}};
+use crate::{db_store::Store, handlers::middleware::{AuthenticatedApk, AuthenticatedDevice, AuthenticatedUser}, types::{audit::{record_audit_event, AuditActor, AuditEvent, RequestContext, PAYMENT_CREATE}, business::GeoPoint, cache::Cache, device::DeviceStatus, geofence::GeofenceCheck, payments::PaymentResponse}};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct MetalPaymentRequest {
@@ -14,6 +15,16 @@ pub struct MetalPaymentRequest {
     device_id: String,
     pipe: String,
     encrypted_price: Vec<u8>,
+    // Key generation the price was encrypted with, terminals from before rotation leave it out
+    #[serde(default)]
+    key_version: Option<i32>,
+    // GPS position of the terminal, checked against the business geofence when it has one
+    #[serde(default)]
+    location: Option<GeoPoint>,
+    // App build taking the payment, covered by the request signature. Older terminals leave it out
+    // and are judged by their last heartbeat
+    #[serde(default)]
+    app_version: Option<String>,
     time: i64
 }
 
@@ -29,10 +40,28 @@ pub struct UserPaymentResponse {
     list: Vec<PaymentResponse>
 }
 
-pub async fn metal_pay(State(state): State<(Store, Arc<Cache>)>, Extension(metal): Extension<AuthenticatedApk>,Json(packet): Json<MetalPaymentRequest>) ->Result<impl IntoResponse, Response> {
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PaymentsQuery {
+    #[serde(default)]
+    flagged: bool,
+}
+
+// A retired or unknown version is refused, the newest live key stands in when the envelope names none
+async fn live_price_key(store: &Store, device_id: uuid::Uuid, version: Option<i32>) -> Result<String, Response> {
+    store.get_live_device_key(device_id, version).await
+        .map_err(|e| e.into_response())?
+        .map(|key| key.price_key)
+        .ok_or(Error::Unauthorized.into_response())
+}
+
+pub async fn metal_pay(State(state): State<(Store, Arc<Cache>)>, Extension(device): Extension<AuthenticatedDevice>, Extension(context): Extension<RequestContext>, Json(packet): Json<MetalPaymentRequest>) ->Result<impl IntoResponse, Response> {
     if (packet.time / 1000) > 8 {
         return Err(Error::ApiKeyRejection.into_response());
     }
+    // A terminal can only charge through itself
+    if packet.device_id != device.device_id {
+        return Err(Error::Unauthorized.into_response());
+    }
     let store = state.0;
     // let cache = state.1;
     let customer = store.get_customer_public_id(packet.customer_id).await.map_err(|e| e.into_response())?;
@@ -46,7 +75,26 @@ pub async fn metal_pay(State(state): State<(Store, Arc<Cache>)>, Extension(metal
         return Err(Error::Unauthorized.into_response());
     }
     let data = store.get_device_with_business_user_account(packet.device_id).await.map_err(|e| e.into_response())?;
-    let decrypt_price = decrypt(&packet.encrypted_price, &data.price_key.as_bytes()).map_err(|e| Error::AcmError(e).into_response())?;
+    if data.status != DeviceStatus::Active {
+        return Err(Error::DeviceNotActive(data.status.as_str().to_string()).into_response());
+    }
+    let release = store.get_release_state(data.id).await.map_err(|e| e.into_response())?;
+    let app_version = packet.app_version.as_deref().map(str::trim).filter(|version| !version.is_empty()).or(release.reported_version.as_deref());
+    if release.upgrade_required(app_version) {
+        let minimum = release.latest.map(|latest| latest.min_supported_version).unwrap_or_default();
+        return Err(Error::UpgradeRequired(minimum).into_response());
+    }
+    if packet.location.as_ref().is_some_and(|location| !location.is_valid()) {
+        return Err(Error::MissingParameters.into_response());
+    }
+    let (business_location, policy) = store.get_business_geofence(data.business_id).await.map_err(|e| e.into_response())?;
+    let geofence = policy.check(&business_location, packet.location.clone());
+    if geofence.rejected {
+        record_audit_event(&store, &context, AuditEvent::failure(PAYMENT_CREATE, AuditActor::Device(device.id)).entity("customer", customer.id).detail(json!({"device_id": data.id, "distance_m": geofence.distance_m, "radius_m": policy.radius_m}))).await;
+        return Err(Error::OutsideGeofence.into_response());
+    }
+    let price_key = live_price_key(&store, data.id, packet.key_version).await?;
+    let decrypt_price = decrypt(&packet.encrypted_price, price_key.as_bytes()).map_err(|e| Error::AcmError(e).into_response())?;
     let mut sum: i64 = 0;
     for c in decrypt_price.iter() {
         if c < &b'0' || c > &b'9' {
@@ -59,7 +107,8 @@ pub async fn metal_pay(State(state): State<(Store, Arc<Cache>)>, Extension(metal
         }
         sum = (sum*10) + (c - b'0') as i64;
     }
-    let result = store.add_payment(data.main_device_id, sum, customer.id, data.user_id, data.account_bank_id, data.account_name, data.account_number).await.map_err(|e| e.into_response())?;
+    let result = store.add_payment(data.id, sum, customer.id, data.user_id, &geofence).await.map_err(|e| e.into_response())?;
+    record_audit_event(&store, &context, AuditEvent::success(PAYMENT_CREATE, AuditActor::Device(device.id)).entity("customer", customer.id).detail(json!({"amount": result.amount, "device_id": result.device_id, "bank_id": result.bank_id, "account_number": result.account_number, "geofence_flagged": geofence.flagged, "location_missing": geofence.location_missing}))).await;
     let response = MetalPaymentResponse {
         first_name: customer.first_name,
         last_name: customer.last_name,
@@ -69,10 +118,14 @@ pub async fn metal_pay(State(state): State<(Store, Arc<Cache>)>, Extension(metal
 }
 
 
-pub async fn customer_pay(State(state): State<(Store, Arc<Cache>)>, Extension(metal): Extension<AuthenticatedApk>,Json(packet): Json<MetalPaymentRequest>) ->Result<impl IntoResponse, Response> {
+pub async fn customer_pay(State(state): State<(Store, Arc<Cache>)>, Extension(apk): Extension<AuthenticatedApk>, Extension(context): Extension<RequestContext>, Json(packet): Json<MetalPaymentRequest>) ->Result<impl IntoResponse, Response> {
     let store = state.0;
     // let cache = state.1;
     let customer = store.get_customer_public_id(packet.customer_id).await.map_err(|e| e.into_response())?;
+    // A bank's key only pays for that bank's customers
+    if customer.bank_id != apk.bank_id {
+        return Err(Error::Unauthorized.into_response());
+    }
     let encrypt_msg = ecc_decrypt_key(&packet.pipe, customer.private_key).map_err(|e| e.into_response())?;
     let encrypt_msg_split: Vec<&str> = encrypt_msg.split('&').collect();
     if encrypt_msg_split.len() != 2 {
@@ -87,7 +140,11 @@ pub async fn customer_pay(State(state): State<(Store, Arc<Cache>)>, Extension(me
         return Err(Error::Unauthorized.into_response());
     }
     let data = store.get_device_with_business_user_account(packet.device_id).await.map_err(|e| e.into_response())?;
-    let decrypt_price = decrypt(&packet.encrypted_price, &data.price_key.as_bytes()).map_err(|e| Error::AcmError(e).into_response())?;
+    if data.status != DeviceStatus::Active {
+        return Err(Error::DeviceNotActive(data.status.as_str().to_string()).into_response());
+    }
+    let price_key = live_price_key(&store, data.id, packet.key_version).await?;
+    let decrypt_price = decrypt(&packet.encrypted_price, price_key.as_bytes()).map_err(|e| Error::AcmError(e).into_response())?;
     let mut sum: i64 = 0;
     for c in decrypt_price.iter() {
         if c < &b'0' || c > &b'9' {
@@ -95,16 +152,20 @@ pub async fn customer_pay(State(state): State<(Store, Arc<Cache>)>, Extension(me
         }
         sum = (sum*10) + (c - b'0') as i64;
     }
-    let result = store.add_payment(data.main_device_id, sum, customer.id, data.user_id, data.account_bank_id, data.account_name, data.account_number).await.map_err(|e| e.into_response())?;
+    // Bank apps report the customer's phone, not a terminal, so the geofence does not apply
+    let result = store.add_payment(data.id, sum, customer.id, data.user_id, &GeofenceCheck::default()).await.map_err(|e| e.into_response())?;
+    record_audit_event(&store, &context, AuditEvent::success(PAYMENT_CREATE, AuditActor::ApiKey(apk.key_id)).entity("customer", customer.id).detail(json!({"amount": result.amount, "device_id": result.device_id, "bank_id": result.bank_id, "account_number": result.account_number}))).await;
     Ok(Json(result).into_response())
 }
 
 
 
 
+// `?flagged=true` keeps only payments taken outside their business's geofence
 pub async fn get_payments(
     State(state): State<(Store, Arc<Cache>)>, 
     Extension(user): Extension<AuthenticatedUser>,
+    Query(query): Query<PaymentsQuery>,
 ) -> Result<impl IntoResponse, Response> {
     let store = state.0;
     let cache = state.1;
@@ -114,7 +175,7 @@ pub async fn get_payments(
         Ok(u) => u,
         Err(e) => return Ok(e.into_response()),
     };
-    let payments = match store.get_payments_user_id(user_data.id).await {
+    let payments = match store.get_payments_member_id(user_data.id, query.flagged).await {
         Ok(b) => b,
         Err(e) => return Ok(e.into_response())
     };
@@ -0,0 +1,137 @@
+use std::sync::Arc;
+
+use axum::{extract::State, http::{header, StatusCode}, response::{IntoResponse, Response}, Extension, Json};
+use chrono::{DateTime, Utc};
+use handle_error::Error;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{db_store::Store, handlers::{middleware::AuthenticatedUser, user::{cleared_session_response, reauthenticate}}, types::{account::Account, cache::Cache, organization::Membership, passkey::Passkey, payments::PaymentResponse, role::Role}};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DeleteAccountRequest {
+    password: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct ExportProfile {
+    id: Uuid,
+    first_name: String,
+    last_name: String,
+    email: String,
+    role: Role,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+// Device keys are secrets of the terminal, not personal data, so they stay out of the archive
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct ExportDevice {
+    id: Uuid,
+    device_id: String,
+    name: String,
+    device_type: String,
+    account_id: Uuid,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct ExportBusiness {
+    id: Uuid,
+    organization_id: Uuid,
+    name: String,
+    location: String,
+    lat: f64,
+    long: f64,
+    created_at: DateTime<Utc>,
+    devices: Vec<ExportDevice>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ExportArchive {
+    generated_at: DateTime<Utc>,
+    profile: ExportProfile,
+    organizations: Vec<Membership>,
+    businesses: Vec<ExportBusiness>,
+    accounts: Vec<Account>,
+    payments: Vec<PaymentResponse>,
+    passkeys: Vec<Passkey>,
+}
+
+// Deleting the last owner would leave an organization nobody can manage
+pub(crate) async fn ensure_not_sole_owner(store: &Store, user_id: Uuid) -> Result<(), Response> {
+    let orphaned = store.get_sole_owned_organizations(user_id).await.map_err(|e| e.into_response())?;
+    if !orphaned.is_empty() {
+        return Err(Error::Conflict("transfer ownership of your organizations before deleting the account".to_string()).into_response());
+    }
+    Ok(())
+}
+
+pub async fn export_data(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let user_data = store.get_user(user.user_id).await.map_err(|e| e.into_response())?;
+    let mut businesses: Vec<ExportBusiness> = Vec::new();
+    let mut accounts: Vec<Account> = Vec::new();
+    for business in store.get_businesses_member_id(user_data.id).await.map_err(|e| e.into_response())? {
+        let mut devices: Vec<ExportDevice> = Vec::new();
+        for device in store.get_devices_by_business_id(&business.id).await.map_err(|e| e.into_response())? {
+            if !accounts.iter().any(|account| account.id == device.account_id) {
+                accounts.push(store.get_account(&device.account_id).await.map_err(|e| e.into_response())?);
+            }
+            devices.push(ExportDevice {
+                id: device.id,
+                device_id: device.device_id,
+                name: device.name,
+                device_type: device.device_type,
+                account_id: device.account_id,
+                created_at: device.created_at,
+            });
+        }
+        businesses.push(ExportBusiness {
+            id: business.id,
+            organization_id: business.organization_id,
+            name: business.name,
+            location: business.location,
+            lat: business.lat,
+            long: business.long,
+            created_at: business.created_at,
+            devices,
+        });
+    }
+    let archive = ExportArchive {
+        generated_at: Utc::now(),
+        profile: ExportProfile {
+            id: user_data.id,
+            first_name: user_data.first_name,
+            last_name: user_data.last_name,
+            email: user_data.email,
+            role: user_data.role,
+            created_at: user_data.created_at,
+            updated_at: user_data.updated_at,
+        },
+        organizations: store.get_memberships_user_id(user_data.id).await.map_err(|e| e.into_response())?,
+        businesses,
+        accounts,
+        payments: store.get_payments_member_id(user_data.id, false).await.map_err(|e| e.into_response())?,
+        passkeys: store.get_passkeys_user_id(user_data.id).await.map_err(|e| e.into_response())?,
+    };
+    let disposition = format!("attachment; filename=\"link-export-{}.json\"", archive.generated_at.format("%Y%m%d"));
+    Ok((StatusCode::OK, [(header::CONTENT_DISPOSITION, disposition)], Json(archive)).into_response())
+}
+
+// The password is asked again so a stolen session alone cannot erase the account
+pub async fn delete_account(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Json(packet): Json<DeleteAccountRequest>,
+) -> Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let user_data = store.get_user(user.user_id).await.map_err(|e| e.into_response())?;
+    reauthenticate(&store, &user_data, &packet.password).await?;
+    ensure_not_sole_owner(&store, user_data.id).await?;
+    store.anonymize_user(user_data.id).await.map_err(|e| e.into_response())?;
+    Ok(cleared_session_response())
+}
@@ -0,0 +1,90 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{header::{CONTENT_DISPOSITION, CONTENT_TYPE}, StatusCode},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use chrono::{DateTime, Utc};
+use handle_error::Error;
+use qrcodegen::QrCode;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{db_store::Store, handlers::{device::device_for_member, middleware::AuthenticatedUser, organization::member_role}, tools::qr::{encode, render_png, render_sheet_pdf, render_svg, Sticker}, types::{cache::Cache, device::Device}};
+
+// Implicit assertion of sticker tokens, customer apps verify them with it and the keys at /api/token/sticker-keys
+pub const DEVICE_QR_ASSERTION: &[u8] = b"device-qr";
+
+// The public payment identity printed on a sticker, signed so a forged sticker fails verification
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DeviceQrClaims {
+    pub device_id: String,
+    pub business_name: String,
+    pub iat: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QrFormat {
+    #[default]
+    Svg,
+    Png,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct QrQuery {
+    #[serde(default)]
+    pub format: QrFormat,
+}
+
+fn sticker_code(cache: &Cache, device: &Device, business_name: &str) -> Result<QrCode, Error> {
+    let claims = DeviceQrClaims { device_id: device.device_id.clone(), business_name: business_name.to_string(), iat: Utc::now() };
+    encode(&cache.stickers.sign(&claims, DEVICE_QR_ASSERTION)?)
+}
+
+// `?format=svg|png`, only enrolled terminals that are active or suspended get a sticker
+pub async fn get_device_qr(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<QrQuery>,
+) -> Result<impl IntoResponse, Response> {
+    let (store, cache) = state;
+    let (device, _) = device_for_member(&store, id, user.user_id).await?;
+    if !device.can_print_sticker() {
+        return Err(Error::DeviceNotActive(device.status.as_str().to_string()).into_response());
+    }
+    let business = store.get_business(device.business_id).await.map_err(|e| e.into_response())?;
+    let code = sticker_code(&cache, &device, &business.name).map_err(|e| e.into_response())?;
+    match query.format {
+        QrFormat::Svg => Ok((StatusCode::OK, [(CONTENT_TYPE, "image/svg+xml")], render_svg(&code)).into_response()),
+        QrFormat::Png => {
+            let png = render_png(&code).map_err(|e| e.into_response())?;
+            Ok((StatusCode::OK, [(CONTENT_TYPE, "image/png")], png).into_response())
+        }
+    }
+}
+
+// One printable PDF with a sticker for every enrolled terminal of the business, pending and retired ones are left out
+pub async fn get_business_qr_sheet(
+    State(state): State<(Store, Arc<Cache>)>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, Response> {
+    let (store, cache) = state;
+    let business = store.get_business(id).await.map_err(|e| match e {
+        Error::DatabaseQueryError(sqlx::Error::RowNotFound) => Error::Forbidden.into_response(),
+        e => e.into_response(),
+    })?;
+    member_role(&store, business.organization_id, user.user_id).await?;
+    let devices = store.get_devices_by_business_id(&business.id).await.map_err(|e| e.into_response())?;
+    let mut stickers = Vec::new();
+    for device in devices.iter().filter(|device| device.can_print_sticker()) {
+        let code = sticker_code(&cache, device, &business.name).map_err(|e| e.into_response())?;
+        stickers.push(Sticker { title: format!("{} - {}", business.name, device.name), subtitle: device.device_id.clone(), code });
+    }
+    let disposition = format!("attachment; filename=\"stickers-{}.pdf\"", business.id);
+    Ok((StatusCode::OK, [(CONTENT_TYPE, "application/pdf".to_string()), (CONTENT_DISPOSITION, disposition)], render_sheet_pdf(&stickers)).into_response())
+}
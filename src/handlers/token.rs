@@ -0,0 +1,23 @@
+use std::sync::Arc;
+
+use axum::{extract::State, http::{header, StatusCode}, response::{IntoResponse, Response}, Json};
+use serde::{Deserialize, Serialize};
+
+use crate::{db_store::Store, types::{cache::Cache, token::PublicKey}};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PublicKeysResponse {
+    pub keys: Vec<PublicKey>,
+}
+
+// Active and retired v4.public keys, anyone holding these can verify our tokens by `kid`
+pub async fn get_public_keys(State(state): State<(Store, Arc<Cache>)>) -> Result<impl IntoResponse, Response> {
+    let keys = state.1.tokens.public_keys();
+    Ok((StatusCode::OK, [(header::CACHE_CONTROL, "public, max-age=300")], Json(PublicKeysResponse { keys })).into_response())
+}
+
+// Sticker keys change rarely and are never deleted, verifiers may cache them for a day
+pub async fn get_sticker_keys(State(state): State<(Store, Arc<Cache>)>) -> Result<impl IntoResponse, Response> {
+    let keys = state.1.stickers.public_keys();
+    Ok((StatusCode::OK, [(header::CACHE_CONTROL, "public, max-age=86400")], Json(PublicKeysResponse { keys })).into_response())
+}
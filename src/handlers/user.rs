@@ -1,14 +1,16 @@
-use std::{env, sync::Arc};
+use std::sync::{Arc, OnceLock};
 
 use argon2::Config;
-use axum::{extract::State, http::StatusCode, response::{IntoResponse, Response}, Extension, Json};
-use chrono::{DateTime, Utc};
+use axum::{extract::State, http::{header, HeaderMap, StatusCode}, response::{IntoResponse, Response}, Extension, Json};
+use chrono::{DateTime, Duration, Utc};
+use encrypt::{generate_random_char, hash_secret};
 use handle_error::Error;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use uuid::Uuid;
 
-use crate::{db_store::Store, handlers::middleware::AuthenticatedUser, tools::constant::SESSION_KEY, types::cache::Cache};
+use crate::{db_store::Store, handlers::middleware::AuthenticatedUser, tools::{cookie::{access_cookie, cleared_cookies, csrf_matches, get_cookie, session_cookies, wants_cookie_session, REFRESH_COOKIE}}, types::{audit::{record_audit_event, AuditActor, AuditEvent, RequestContext, USER_LOGIN, USER_LOGOUT, USER_PROFILE_UPDATE, USER_TOKEN_REFRESH}, cache::Cache, login_attempt::AttemptScope, token::{TokenKind, ACCESS_TOKEN_HOURS, REFRESHED_ACCESS_TOKEN_MINUTES}, user::User}};
 
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -23,9 +25,11 @@ pub struct UserPostRequest {
 pub struct RefreshTokenResponse {
     access_token: String,
 }
-#[derive(Debug, Clone, Deserialize, Serialize)]
+// Cookie sessions send an empty body and the refresh token as a cookie
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct RefreshTokenRequest {
-    refresh_token: String,
+    #[serde(default)]
+    refresh_token: Option<String>,
 }
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct UserResponse {
@@ -42,54 +46,65 @@ pub struct UserOnlyResponse {
     pub email: String,
 }
 
+// Tokens are left out of the body when they went into cookies instead
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct SessionResponse {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub access_token: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub refresh_token: String,
     pub expires_at: DateTime<Utc>,
 }
 
-fn hash_password(password: &[u8]) -> String {
+const CSRF_TOKEN_LEN: usize = 32;
+
+// Every way of signing in answers through here, so each can hand out either bearer tokens or cookies
+pub fn session_response(headers: &HeaderMap, status: StatusCode, mut response: UserResponse) -> Response {
+    if !wants_cookie_session(headers) {
+        return (status, Json(response)).into_response();
+    }
+    let access_expires_at = Utc::now() + Duration::hours(ACCESS_TOKEN_HOURS);
+    let csrf_token = generate_random_char(CSRF_TOKEN_LEN);
+    let cookies = session_cookies(&response.session.access_token, access_expires_at, &response.session.refresh_token, response.session.expires_at, &csrf_token);
+    response.session.access_token.clear();
+    response.session.refresh_token.clear();
+    let mut http_response = (status, Json(response)).into_response();
+    for cookie in cookies {
+        http_response.headers_mut().append(header::SET_COOKIE, cookie);
+    }
+    http_response
+}
+
+pub(crate) fn hash_password(password: &[u8]) -> String {
     let salt = rand::thread_rng().gen::<[u8; 32]>();
     let config = Config::default();
     argon2::hash_encoded(password, &salt, &config).unwrap()
 }
 
-fn verify_password(
+pub(crate) fn verify_password(
     hash: &str,
     password: &[u8],
 ) -> Result<bool, argon2::Error> {
     argon2::verify_encoded(hash, password)
 }
 
-pub fn verify_token(
-    token: String,
+// Access tokens from the `Authorization` header, verified against the cached keyset
+pub async fn verify_token(
+    store: &Store,
+    cache: &Cache,
+    token: &str,
 ) -> Result<Uuid, Error> {
-    let session_key = env::var(SESSION_KEY)
-    .map_err(|e| Error::EnvError(e))?;
-    let token_data = paseto::tokens::validate_local_token(
-        &token,
-        None,
-        &session_key.as_bytes(),
-        &paseto::tokens::TimeBackend::Chrono,
-    )
-    .map_err(|_| Error::CannotDecryptToken)?;
-
-    let user_id: Uuid = token_data["id"]
-        .as_str()
-        .and_then(|s| Uuid::parse_str(s).ok())
-        .ok_or(Error::TokenCreationError("Could not generate token".to_string()))?;
-        
-    Ok(user_id)
-}
-
-pub async fn register(State(state): State<(Store, Arc<Cache>)>, Json(packet): Json<UserPostRequest>) ->Result<impl IntoResponse, Response> {
+    cache.tokens.verify(store, token, TokenKind::Access).await
+}
+
+pub async fn register(State(state): State<(Store, Arc<Cache>)>, headers: HeaderMap, Json(packet): Json<UserPostRequest>) ->Result<impl IntoResponse, Response> {
     let store = state.0;
+    let cache = state.1;
     let hashed_password = hash_password(packet.hashed_password.as_bytes());
     let result = store.add_user(packet.first_name, hashed_password, packet.last_name, packet.email).await;
     match result {
         Ok(user_send) => {
-            let session_result = store.create_session(user_send.id).await;
+            let session_result = store.create_session(&cache.tokens, user_send.id).await;
             let session = match session_result {
                 Ok(s) => s,
                 Err(e) => return Ok(e.into_response()),
@@ -100,15 +115,16 @@ pub async fn register(State(state): State<(Store, Arc<Cache>)>, Json(packet): Js
                 email: user_send.email,
                 session: SessionResponse { access_token: session.access_token, refresh_token: session.refresh_token, expires_at: session.expires_at}
             };
-            return Ok((StatusCode::CREATED, Json(response)).into_response());
+            return Ok(session_response(&headers, StatusCode::CREATED, response));
         },
         Err(e) => {
             return Ok(e.into_response())
         }
     }
 }
-pub async fn update_user(State(state): State<(Store, Arc<Cache>)>, Extension(user): Extension<AuthenticatedUser>, Json(packet): Json<UserPostRequest>) ->Result<impl IntoResponse, Response> {
+pub async fn update_user(State(state): State<(Store, Arc<Cache>)>, Extension(user): Extension<AuthenticatedUser>, Extension(context): Extension<RequestContext>, headers: HeaderMap, Json(packet): Json<UserPostRequest>) ->Result<impl IntoResponse, Response> {
     let store = state.0;
+    let cache = state.1;
     let result_user = store.get_user(user.user_id).await;
     
     let user_data = match result_user {
@@ -120,7 +136,13 @@ pub async fn update_user(State(state): State<(Store, Arc<Cache>)>, Extension(use
     match result {
         Ok(updated) => {
             if updated {
-                let session_result = store.create_session(user_data.id).await;
+                let changes = json!({
+                    "email": {"from": user_data.email, "to": packet.email},
+                    "first_name": {"from": user_data.first_name, "to": packet.first_name},
+                    "last_name": {"from": user_data.last_name, "to": packet.last_name},
+                });
+                record_audit_event(&store, &context, AuditEvent::success(USER_PROFILE_UPDATE, AuditActor::User(user_data.id)).entity("user", user_data.id).detail(changes)).await;
+                let session_result = store.create_session(&cache.tokens, user_data.id).await;
                 let session = match session_result {
                     Ok(s) => s,
                     Err(e) => return Ok(e.into_response()),
@@ -131,7 +153,7 @@ pub async fn update_user(State(state): State<(Store, Arc<Cache>)>, Extension(use
                     email: packet.email,
                     session: SessionResponse { access_token: session.access_token, refresh_token: session.refresh_token, expires_at: session.expires_at}
                 };
-                return Ok((StatusCode::CREATED, Json(response)).into_response());
+                return Ok(session_response(&headers, StatusCode::CREATED, response));
             } else {
                 return Ok(Error::Unauthorized.into_response())
             }
@@ -141,46 +163,135 @@ pub async fn update_user(State(state): State<(Store, Arc<Cache>)>, Extension(use
         }
     }
 }
-pub async fn refresh_token(State(state): State<(Store, Arc<Cache>)>, Json(packet): Json<RefreshTokenRequest>) -> Result<impl IntoResponse, Response> {
+// The body wins over the cookie. A refresh cookie rides along on cross-site requests too, so it needs the CSRF header
+fn presented_refresh_token(headers: &HeaderMap, packet: Option<Json<RefreshTokenRequest>>) -> Result<Option<(String, bool)>, Error> {
+    let packet = packet.map(|Json(packet)| packet).unwrap_or_default();
+    if let Some(refresh) = packet.refresh_token {
+        return Ok(Some((refresh, false)));
+    }
+    match get_cookie(headers, REFRESH_COOKIE) {
+        Some(_) if !csrf_matches(headers) => Err(Error::Forbidden),
+        Some(refresh) => Ok(Some((refresh, true))),
+        None => Ok(None),
+    }
+}
+
+pub async fn refresh_token(State(state): State<(Store, Arc<Cache>)>, Extension(context): Extension<RequestContext>, headers: HeaderMap, packet: Option<Json<RefreshTokenRequest>>) -> Result<impl IntoResponse, Response> {
     let store = state.0;
-    let result = store.refresh_access_token(&packet.refresh_token).await;
-    match result {
+    let cache = state.1;
+    let (refresh, from_cookie) = presented_refresh_token(&headers, packet).map_err(|e| e.into_response())?.ok_or(Error::Unauthorized.into_response())?;
+    let result = store.refresh_access_token(&cache.tokens, &refresh).await;
+    let event = match &result {
+        Ok((user_id, _)) => AuditEvent::success(USER_TOKEN_REFRESH, AuditActor::User(*user_id)),
+        Err(_) => AuditEvent::failure(USER_TOKEN_REFRESH, AuditActor::Anonymous),
+    };
+    record_audit_event(&store, &context, event.detail(json!({"cookie": from_cookie}))).await;
+    match result.map(|(_, token)| token) {
+        Ok(token) if from_cookie => {
+            let access_expires_at = Utc::now() + Duration::minutes(REFRESHED_ACCESS_TOKEN_MINUTES);
+            Ok((StatusCode::NO_CONTENT, [(header::SET_COOKIE, access_cookie(&token, access_expires_at))]).into_response())
+        },
         Ok(token) => Ok((StatusCode::OK, Json(RefreshTokenResponse{access_token: token})).into_response()),
         Err(e) => Ok(e.into_response())
     }
 }
 
-pub async fn login(State(state): State<(Store, Arc<Cache>)>, Json(packet): Json<UserPostRequest>) ->Result<impl IntoResponse, Response> {
+pub fn cleared_session_response() -> Response {
+    let mut response = StatusCode::NO_CONTENT.into_response();
+    for cookie in cleared_cookies() {
+        response.headers_mut().append(header::SET_COOKIE, cookie);
+    }
+    response
+}
+
+// Deletes the session behind the presented refresh token so it cannot mint new access tokens, then clears the cookies
+pub async fn logout(State(state): State<(Store, Arc<Cache>)>, Extension(context): Extension<RequestContext>, headers: HeaderMap, packet: Option<Json<RefreshTokenRequest>>) -> Result<impl IntoResponse, Response> {
     let store = state.0;
-    let result_user = store.get_user_by_email(packet.email).await; 
-    let user = match result_user {
-        Ok(u) => u,
-        Err(e) => return Ok(e.into_response()),
+    let cache = state.1;
+    if let Some((refresh, from_cookie)) = presented_refresh_token(&headers, packet).map_err(|e| e.into_response())? {
+        if let Some(user_id) = store.revoke_session(&cache.tokens, &refresh).await.map_err(|e| e.into_response())? {
+            record_audit_event(&store, &context, AuditEvent::success(USER_LOGOUT, AuditActor::User(user_id)).detail(json!({"cookie": from_cookie}))).await;
+        }
+    }
+    Ok(cleared_session_response())
+}
+
+pub async fn login(State(state): State<(Store, Arc<Cache>)>, Extension(context): Extension<RequestContext>, headers: HeaderMap, Json(packet): Json<UserPostRequest>) ->Result<impl IntoResponse, Response> {
+    let store = state.0;
+    let cache = state.1;
+    let ip = context.ip.clone();
+    let email_key = packet.email.trim().to_lowercase();
+    // The attempt is counted against the IP and the email before the password is checked, refused while either is backing off or locked out
+    if let Some(until) = claim_login(&store, &ip, &email_key).await? {
+        let wait = (until - Utc::now()).num_seconds().max(1);
+        record_audit_event(&store, &context, AuditEvent::failure(USER_LOGIN, AuditActor::Anonymous).detail(json!({"method": "password", "email_hash": hash_secret(&email_key), "reason": "locked"}))).await;
+        return Ok(Error::TooManyAttempts(wait).into_response());
+    }
+    let user = store.get_user_by_email(packet.email).await.map_err(|e| e.into_response())?;
+    // Unknown emails still pay for a hash check so timing does not reveal which emails exist
+    let hashed_password = match &user {
+        Some(u) => u.hashed_password.clone(),
+        None => dummy_password_hash().to_string(),
     };
-    let result_verified = verify_password(&user.hashed_password, &packet.hashed_password.as_bytes());
-    match result_verified {
-        Ok(verified) => {
-            if verified {
-                let session_result = store.create_session(user.id).await;
-                let session = match session_result {
-                    Ok(s) => s,
-                    Err(e) => return Ok(e.into_response()),
-                };
-                let response = UserResponse {
-                    first_name: user.first_name,
-                    last_name: user.last_name,
-                    email: user.email,
-                    session: SessionResponse { access_token: session.access_token, refresh_token: session.refresh_token, expires_at: session.expires_at}
-                };
-                return Ok((StatusCode::OK, Json(response)).into_response());
-            } else {
-                return Ok(Error::WrongPassword.into_response())
-            }
-        },
-        Err(e) => {
-            return Ok(Error::ArgonLibraryError(e).into_response())
+    let verified = verify_password(&hashed_password, packet.hashed_password.as_bytes())
+        .map_err(|e| Error::ArgonLibraryError(e).into_response())?;
+    let user = match user {
+        Some(u) if verified => u,
+        user => {
+            let actor = user.map(|u| AuditActor::User(u.id)).unwrap_or(AuditActor::Anonymous);
+            record_audit_event(&store, &context, AuditEvent::failure(USER_LOGIN, actor).detail(json!({"method": "password", "email_hash": hash_secret(&email_key), "reason": "invalid_credentials"}))).await;
+            return Ok(Error::InvalidCredentials.into_response());
         }
+    };
+    // A good password resets the email, the IP only gets this attempt back
+    store.clear_login_failures(AttemptScope::Email, &email_key).await.map_err(|e| e.into_response())?;
+    store.release_login_attempt(AttemptScope::Ip, &ip).await.map_err(|e| e.into_response())?;
+    record_audit_event(&store, &context, AuditEvent::success(USER_LOGIN, AuditActor::User(user.id)).detail(json!({"method": "password"}))).await;
+    let session_result = store.create_session(&cache.tokens, user.id).await;
+    let session = match session_result {
+        Ok(s) => s,
+        Err(e) => return Ok(e.into_response()),
+    };
+    let response = UserResponse {
+        first_name: user.first_name,
+        last_name: user.last_name,
+        email: user.email,
+        session: SessionResponse { access_token: session.access_token, refresh_token: session.refresh_token, expires_at: session.expires_at}
+    };
+    Ok(session_response(&headers, StatusCode::OK, response))
+}
+
+// Claims the attempt for the IP, then the email. When the email is blocked the IP gets its attempt back
+async fn claim_login(store: &Store, ip: &str, email_key: &str) -> Result<Option<DateTime<Utc>>, Response> {
+    if let Some(until) = store.claim_login_attempt(AttemptScope::Ip, ip).await.map_err(|e| e.into_response())? {
+        return Ok(Some(until));
+    }
+    let locked_until = store.claim_login_attempt(AttemptScope::Email, email_key).await.map_err(|e| e.into_response())?;
+    if locked_until.is_some() {
+        store.release_login_attempt(AttemptScope::Ip, ip).await.map_err(|e| e.into_response())?;
     }
+    Ok(locked_until)
+}
+
+// Password confirmation for sensitive changes, failures count against the same email lockout as `login`
+pub(crate) async fn reauthenticate(store: &Store, user: &User, password: &str) -> Result<(), Response> {
+    let email_key = user.email.trim().to_lowercase();
+    let locked_until = store.claim_login_attempt(AttemptScope::Email, &email_key).await.map_err(|e| e.into_response())?;
+    if let Some(until) = locked_until {
+        return Err(Error::TooManyAttempts((until - Utc::now()).num_seconds().max(1)).into_response());
+    }
+    let verified = verify_password(&user.hashed_password, password.as_bytes())
+        .map_err(|e| Error::ArgonLibraryError(e).into_response())?;
+    if !verified {
+        return Err(Error::WrongPassword.into_response());
+    }
+    store.clear_login_failures(AttemptScope::Email, &email_key).await.map_err(|e| e.into_response())?;
+    Ok(())
+}
+
+fn dummy_password_hash() -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    DUMMY_HASH.get_or_init(|| hash_password(b"link-x-unknown-user"))
 }
 
 
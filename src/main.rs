@@ -3,15 +3,14 @@ mod tools;
 mod handlers;
 mod db_store;
 
-use std::{env::{self, VarError}, sync::Arc};
+use std::{env::{self, VarError}, net::SocketAddr, sync::Arc};
 
-use axum::{http::Method, middleware::{self}, routing::{get, post, put}, Router};
+use axum::{middleware::{self}, routing::{delete, get, post, put}, Router};
 use handle_error::Error;
 use tracing::{debug, info, warn};
 use tracing_appender::rolling;
 use tracing_subscriber::{fmt::format::FmtSpan, EnvFilter};
-use tower_http::cors::{Any, CorsLayer};
-use crate::{db_store::Store, handlers::{account::get_account, bank::get_bank, business::get_business, customer::create_customer, metal::get_metal_health, middleware::{auth_middleware, metal_apk, public_apk}, payment::{customer_pay, get_payments, metal_pay}, user::{get_user_profile, login, refresh_token, register, update_user}}, tools::constant::DATABASE_URL, types::cache::Cache};
+use crate::{db_store::Store, handlers::{admin, api_key, config_profile, device, magic_link, oauth, organization, passkey, privacy, sticker, token, account::get_account, bank::get_bank, business::{get_business, get_business_geofence, update_business_geofence}, customer::create_customer, metal::{ack_config, download_release, enroll_device, get_config, get_metal_health, get_update, post_heartbeat, rotate_keys}, middleware::{auth_middleware, metal_apk, metal_enrollment_apk, public_apk, request_context, require_permission}, payment::{customer_pay, get_payments, metal_pay}, user::{get_user_profile, login, logout, refresh_token, register, update_user}}, tools::{constant::DATABASE_URL, setup::cors_layer}, types::{cache::Cache, role::Permission}};
 
 #[tokio::main]
 async fn main() {
@@ -39,54 +38,179 @@ async fn main() {
         .await
         .expect("Cannot migrate DB");
     let cache = Arc::new(Cache::new(&store).await);
-    // handlers::business::setup_device(&store).await;
     let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await.unwrap();
-    axum::serve(listener, app(store, cache)).await.unwrap();
+    axum::serve(listener, app(store, cache).into_make_service_with_connect_info::<SocketAddr>()).await.unwrap();
 }
 
 
 
 fn app(store: Store, cache: Arc<Cache>) -> Router {
-    // Protected routes that require authentication
+    // Protected routes that require authentication, each gated by the permission it needs
     let protected_routes = Router::new()
-        .route("/profile", get(get_user_profile))
-        .route("/update", put(update_user))
-        .route("/businesses", get(get_business))
-        .route("/bank", get(get_bank))
-        .route("/account", get(get_account))
-        .route("/payments", get(get_payments))
-        .layer(middleware::from_fn(auth_middleware));
+        .route("/profile", get(get_user_profile)
+            .route_layer(middleware::from_fn_with_state(Permission::ReadProfile, require_permission)))
+        .route("/update", put(update_user)
+            .route_layer(middleware::from_fn_with_state(Permission::UpdateProfile, require_permission)))
+        .route("/profile/export", get(privacy::export_data)
+            .route_layer(middleware::from_fn_with_state(Permission::ReadProfile, require_permission)))
+        .route("/profile/delete", post(privacy::delete_account)
+            .route_layer(middleware::from_fn_with_state(Permission::UpdateProfile, require_permission)))
+        .route("/businesses", get(get_business)
+            .route_layer(middleware::from_fn_with_state(Permission::ReadBusinesses, require_permission)))
+        .route("/bank", get(get_bank)
+            .route_layer(middleware::from_fn_with_state(Permission::ReadBank, require_permission)))
+        .route("/bank/keys", get(api_key::get_api_keys).post(api_key::create_api_key)
+            .route_layer(middleware::from_fn_with_state(Permission::ManageApiKeys, require_permission)))
+        .route("/bank/keys/{id}", delete(api_key::revoke_api_key)
+            .route_layer(middleware::from_fn_with_state(Permission::ManageApiKeys, require_permission)))
+        .route("/bank/keys/{id}/rotate", post(api_key::rotate_api_key)
+            .route_layer(middleware::from_fn_with_state(Permission::ManageApiKeys, require_permission)))
+        .route("/account", get(get_account)
+            .route_layer(middleware::from_fn_with_state(Permission::ReadAccounts, require_permission)))
+        .route("/payments", get(get_payments)
+            .route_layer(middleware::from_fn_with_state(Permission::ReadPayments, require_permission)))
+        .route("/organizations", get(organization::get_organizations).post(organization::create_organization)
+            .route_layer(middleware::from_fn_with_state(Permission::ManageOrganizations, require_permission)))
+        .route("/organizations/{id}/members", get(organization::get_members)
+            .route_layer(middleware::from_fn_with_state(Permission::ManageOrganizations, require_permission)))
+        .route("/organizations/{id}/members/{user_id}", put(organization::update_member).delete(organization::delete_member)
+            .route_layer(middleware::from_fn_with_state(Permission::ManageOrganizations, require_permission)))
+        .route("/organizations/{id}/invitations", post(organization::create_invitation)
+            .route_layer(middleware::from_fn_with_state(Permission::ManageOrganizations, require_permission)))
+        .route("/passkeys", get(passkey::get_passkeys)
+            .route_layer(middleware::from_fn_with_state(Permission::ReadProfile, require_permission)))
+        .route("/passkeys/{id}", delete(passkey::delete_passkey)
+            .route_layer(middleware::from_fn_with_state(Permission::UpdateProfile, require_permission)))
+        .route("/passkeys/register/start", post(passkey::start_registration)
+            .route_layer(middleware::from_fn_with_state(Permission::UpdateProfile, require_permission)))
+        .route("/passkeys/register/finish", post(passkey::finish_registration)
+            .route_layer(middleware::from_fn_with_state(Permission::UpdateProfile, require_permission)))
+        .route("/devices", get(device::get_devices).post(device::create_device)
+            .route_layer(middleware::from_fn_with_state(Permission::ManageDevices, require_permission)))
+        .route("/devices/health", get(device::get_fleet_health)
+            .route_layer(middleware::from_fn_with_state(Permission::ManageDevices, require_permission)))
+        .route("/devices/{id}/heartbeats", get(device::get_device_heartbeats)
+            .route_layer(middleware::from_fn_with_state(Permission::ManageDevices, require_permission)))
+        .route("/devices/{id}/status", put(device::update_device_status)
+            .route_layer(middleware::from_fn_with_state(Permission::ManageDevices, require_permission)))
+        .route("/devices/{id}/keys", get(device::get_device_keys)
+            .route_layer(middleware::from_fn_with_state(Permission::ManageDevices, require_permission)))
+        .route("/devices/{id}/keys/revoke", post(device::revoke_device_keys)
+            .route_layer(middleware::from_fn_with_state(Permission::ManageDevices, require_permission)))
+        .route("/devices/{id}/activation", post(device::create_activation)
+            .route_layer(middleware::from_fn_with_state(Permission::ManageDevices, require_permission)))
+        .route("/devices/{id}/account", put(device::update_device_account)
+            .route_layer(middleware::from_fn_with_state(Permission::ManageDevices, require_permission)))
+        .route("/devices/{id}/accounts", get(device::get_device_accounts)
+            .route_layer(middleware::from_fn_with_state(Permission::ManageDevices, require_permission)))
+        .route("/devices/{id}", get(device::get_device).put(device::update_device).delete(device::delete_device)
+            .route_layer(middleware::from_fn_with_state(Permission::ManageDevices, require_permission)))
+        .route("/devices/{id}/config", get(config_profile::get_device_config)
+            .route_layer(middleware::from_fn_with_state(Permission::ManageDevices, require_permission)))
+        .route("/devices/{id}/config-profile", put(config_profile::attach_device_config)
+            .route_layer(middleware::from_fn_with_state(Permission::ManageDevices, require_permission)))
+        .route("/devices/{id}/qr", get(sticker::get_device_qr)
+            .route_layer(middleware::from_fn_with_state(Permission::ManageDevices, require_permission)))
+        .route("/businesses/{id}/qr-sheet", get(sticker::get_business_qr_sheet)
+            .route_layer(middleware::from_fn_with_state(Permission::ManageDevices, require_permission)))
+        .route("/businesses/{id}/config-profile", put(config_profile::attach_business_config)
+            .route_layer(middleware::from_fn_with_state(Permission::ManageDevices, require_permission)))
+        .route("/businesses/{id}/geofence", get(get_business_geofence).put(update_business_geofence)
+            .route_layer(middleware::from_fn_with_state(Permission::ManageDevices, require_permission)))
+        .route("/config-profiles", get(config_profile::get_config_profiles).post(config_profile::create_config_profile)
+            .route_layer(middleware::from_fn_with_state(Permission::ManageDevices, require_permission)))
+        .route("/config-profiles/{id}", get(config_profile::get_config_profile)
+            .route_layer(middleware::from_fn_with_state(Permission::ManageDevices, require_permission)))
+        .route("/config-profiles/{id}/versions", post(config_profile::create_config_version)
+            .route_layer(middleware::from_fn_with_state(Permission::ManageDevices, require_permission)))
+        .route("/invitations/accept", post(organization::accept_invitation)
+            .route_layer(middleware::from_fn_with_state(Permission::ManageOrganizations, require_permission)))
+        .layer(middleware::from_fn_with_state((store.clone(), cache.clone()), auth_middleware));
 
     // Public routes for user operations
     let public_user_routes = Router::new()
         .route("/login", post(login))
+        .route("/login/link", post(magic_link::request_magic_link))
+        .route("/login/link/redeem", post(magic_link::redeem_magic_link))
+        .route("/login/passkey/start", post(passkey::start_login))
+        .route("/login/passkey/finish", post(passkey::finish_login))
         .route("/refresh", post(refresh_token))
+        .route("/logout", post(logout))
         .route("/register", post(register));
 
     // APK routes
     let public_apk_routes = Router::new()
         .route("/connect", post(create_customer))
         .route("/payment", post(customer_pay))
-        .layer(middleware::from_fn(public_apk));
+        .layer(middleware::from_fn_with_state((store.clone(), cache.clone()), public_apk));
 
     let metal_apk_routes = Router::new()
-        .route("/heathly", get(get_metal_health))
+        .route("/heathly", get(get_metal_health).post(post_heartbeat))
         .route("/payment", post(metal_pay))
-        .layer(middleware::from_fn(metal_apk));
+        .route("/keys/rotate", post(rotate_keys))
+        .route("/config", get(get_config))
+        .route("/config/ack", post(ack_config))
+        .route("/update", get(get_update))
+        .route("/update/{id}/download", get(download_release))
+        .layer(middleware::from_fn_with_state((store.clone(), cache.clone()), metal_apk));
+
+    // Terminals redeem their activation code before they hold a key to sign with
+    let metal_enrollment_routes = Router::new()
+        .route("/enroll", post(enroll_device))
+        .layer(middleware::from_fn_with_state((store.clone(), cache.clone()), metal_enrollment_apk));
+
+    // OAuth2 client_credentials for bank back-ends, clients authenticate with their API key
+    let oauth_routes = Router::new()
+        .route("/token", post(oauth::token))
+        .route("/introspect", post(oauth::introspect))
+        .route("/revoke", post(oauth::revoke));
+
+    // Sandbox management, admins only
+    let admin_routes = Router::new()
+        .route("/users", get(admin::list_users).post(admin::create_user))
+        .route("/users/{id}", get(admin::get_user).put(admin::update_user).delete(admin::delete_user))
+        .route("/banks", get(admin::list_banks).post(admin::create_bank))
+        .route("/banks/{id}", get(admin::get_bank).put(admin::update_bank).delete(admin::delete_bank))
+        .route("/businesses", get(admin::list_businesses).post(admin::create_business))
+        .route("/businesses/{id}", get(admin::get_business).put(admin::update_business).delete(admin::delete_business))
+        .route("/businesses/{id}/geofence", put(admin::update_business_geofence))
+        .route("/accounts", get(admin::list_accounts).post(admin::create_account))
+        .route("/accounts/{id}", get(admin::get_account).put(admin::update_account).delete(admin::delete_account))
+        .route("/devices", get(admin::list_devices).post(admin::create_device))
+        .route("/devices/health", get(admin::list_device_health))
+        .route("/devices/{id}/status", put(admin::update_device_status))
+        .route("/devices/{id}/accounts", get(admin::list_device_accounts))
+        .route("/devices/{id}/keys/revoke", post(admin::revoke_device_keys))
+        .route("/devices/{id}", get(admin::get_device).put(admin::update_device).delete(admin::delete_device))
+        .route("/customers", get(admin::list_customers).post(admin::create_customer))
+        .route("/payments", get(admin::list_payments))
+        .route("/imports", post(admin::import_onboarding))
+        .route("/customers/{id}", get(admin::get_customer).put(admin::update_customer).delete(admin::delete_customer))
+        .route("/releases", get(admin::list_releases).post(admin::create_release))
+        .route("/releases/{id}", delete(admin::delete_release))
+        .route("/audit-events", get(admin::list_audit_events))
+        .route("/signing-keys", get(admin::list_signing_keys))
+        .route("/signing-keys/rotate", post(admin::rotate_signing_key))
+        .route("/signing-keys/{kid}", delete(admin::delete_signing_key))
+        .route("/sticker-keys", get(admin::list_sticker_keys))
+        .route("/sticker-keys/rotate", post(admin::rotate_sticker_key))
+        .layer(middleware::from_fn_with_state(Permission::Admin, require_permission))
+        .layer(middleware::from_fn_with_state((store.clone(), cache.clone()), auth_middleware));
 
     let app_router = Router::new()
         .nest("/user", public_user_routes)
         .nest("/auth", protected_routes)
         .nest("/apk", public_apk_routes)
-        .nest("/metal", metal_apk_routes)
+        .nest("/metal", metal_apk_routes.merge(metal_enrollment_routes))
+        .nest("/admin", admin_routes)
+        .nest("/oauth", oauth_routes)
+        .route("/token/keys", get(token::get_public_keys))
+        .route("/token/sticker-keys", get(token::get_sticker_keys))
+        .layer(middleware::from_fn(request_context))
         .with_state((store, cache));
         
 
-    // ✅ FIX: Add allow_headers
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE, Method::OPTIONS])
-        .allow_headers(Any); // <-- this allows Content-Type and Authorization headers
+    let cors = cors_layer();
 
     let app = Router::new()
         .nest("/api", app_router)
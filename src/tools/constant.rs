@@ -5,4 +5,23 @@ pub const CONNECT_MSG: &str = "CONNECT_MSG";
 pub const XMINISTER_API_KEY: &str = "XMINISTER_API_KEY";
 pub const XMINISTER_METAL_API_KEY: &str = "XMINISTER_METAL_API_KEY";
 pub const DATABASE_URL: &str = "DATABASE_URL";
-pub const SESSION_KEY: &str = "SESSION_KEY";
\ No newline at end of file
+pub const SESSION_KEY: &str = "SESSION_KEY";
+pub const X_DEVICE_ID: &str = "X-Device-Id";
+pub const X_TIMESTAMP: &str = "X-Timestamp";
+pub const X_SIGNATURE: &str = "X-Signature";
+pub const WEBAUTHN_RP_ID: &str = "WEBAUTHN_RP_ID";
+pub const WEBAUTHN_RP_ORIGIN: &str = "WEBAUTHN_RP_ORIGIN";
+pub const WEBAUTHN_RP_NAME: &str = "WEBAUTHN_RP_NAME";
+pub const MESSAGE_TRANSPORT: &str = "MESSAGE_TRANSPORT";
+pub const MESSAGE_OUTBOX: &str = "MESSAGE_OUTBOX";
+pub const MAGIC_LINK_URL: &str = "MAGIC_LINK_URL";
+pub const INVITATION_URL: &str = "INVITATION_URL";
+pub const X_CSRF_TOKEN: &str = "X-CSRF-Token";
+pub const X_SESSION_MODE: &str = "X-Session-Mode";
+pub const CORS_ALLOWED_ORIGINS: &str = "CORS_ALLOWED_ORIGINS";
+pub const X_REQUEST_ID: &str = "X-Request-Id";
+pub const X_KEY_VERSION: &str = "X-Key-Version";
+pub const X_NONCE: &str = "X-Nonce";
+pub const MIN_TERMINAL_FIRMWARE_VERSION: &str = "MIN_TERMINAL_FIRMWARE_VERSION";
+pub const RELEASE_STORAGE_DIR: &str = "RELEASE_STORAGE_DIR";
+pub const TRUSTED_PROXIES: &str = "TRUSTED_PROXIES";
@@ -0,0 +1,104 @@
+use axum::http::{header, HeaderMap, HeaderValue, Method};
+use chrono::{DateTime, Utc};
+
+use crate::tools::constant::{X_CSRF_TOKEN, X_SESSION_MODE};
+
+pub const ACCESS_COOKIE: &str = "access_token";
+pub const REFRESH_COOKIE: &str = "refresh_token";
+// Readable by the website so it can echo it back in `X-CSRF-Token`
+pub const CSRF_COOKIE: &str = "csrf_token";
+const REFRESH_COOKIE_PATH: &str = "/api/user/refresh";
+
+// The website opts in per request, API clients keep getting tokens in the body
+pub fn wants_cookie_session(headers: &HeaderMap) -> bool {
+    headers.get(X_SESSION_MODE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.eq_ignore_ascii_case("cookie"))
+        .unwrap_or(false)
+}
+
+pub fn get_cookie(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers.get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.to_string())
+        .filter(|value| !value.is_empty())
+}
+
+fn cookie(name: &str, value: &str, path: &str, expires_at: Option<DateTime<Utc>>, http_only: bool) -> HeaderValue {
+    let mut cookie = format!("{}={}; Path={}; Secure; SameSite=Strict", name, value, path);
+    match expires_at {
+        Some(expires_at) => cookie.push_str(&format!("; Max-Age={}", (expires_at - Utc::now()).num_seconds().max(0))),
+        None => cookie.push_str("; Max-Age=0"),
+    }
+    if http_only {
+        cookie.push_str("; HttpOnly");
+    }
+    HeaderValue::from_str(&cookie).expect("cookie values are token characters")
+}
+
+pub fn session_cookies(access_token: &str, access_expires_at: DateTime<Utc>, refresh_token: &str, expires_at: DateTime<Utc>, csrf_token: &str) -> Vec<HeaderValue> {
+    vec![
+        cookie(ACCESS_COOKIE, access_token, "/api", Some(access_expires_at), true),
+        cookie(REFRESH_COOKIE, refresh_token, REFRESH_COOKIE_PATH, Some(expires_at), true),
+        cookie(CSRF_COOKIE, csrf_token, "/", Some(expires_at), false),
+    ]
+}
+
+pub fn access_cookie(access_token: &str, access_expires_at: DateTime<Utc>) -> HeaderValue {
+    cookie(ACCESS_COOKIE, access_token, "/api", Some(access_expires_at), true)
+}
+
+pub fn cleared_cookies() -> Vec<HeaderValue> {
+    vec![
+        cookie(ACCESS_COOKIE, "", "/api", None, true),
+        cookie(REFRESH_COOKIE, "", REFRESH_COOKIE_PATH, None, true),
+        cookie(CSRF_COOKIE, "", "/", None, false),
+    ]
+}
+
+pub fn is_state_changing(method: &Method) -> bool {
+    !matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+// Double-submit check: a cross-site page can send the cookie but cannot read it into the header
+pub fn csrf_matches(headers: &HeaderMap) -> bool {
+    let Some(cookie) = get_cookie(headers, CSRF_COOKIE) else {
+        return false;
+    };
+    let Some(submitted) = headers.get(X_CSRF_TOKEN).and_then(|value| value.to_str().ok()) else {
+        return false;
+    };
+    cookie.len() == submitted.len()
+        && cookie.bytes().zip(submitted.bytes()).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csrf_header_must_match_cookie() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::COOKIE, HeaderValue::from_static("theme=dark; csrf_token=abc123; access_token=t"));
+        assert_eq!(get_cookie(&headers, ACCESS_COOKIE), Some("t".to_string()));
+        assert!(!csrf_matches(&headers));
+        headers.insert(X_CSRF_TOKEN, HeaderValue::from_static("abc124"));
+        assert!(!csrf_matches(&headers));
+        headers.insert(X_CSRF_TOKEN, HeaderValue::from_static("abc123"));
+        assert!(csrf_matches(&headers));
+    }
+
+    #[test]
+    fn session_cookies_are_locked_down() {
+        let expires_at = Utc::now() + chrono::Duration::days(7);
+        let cookies = session_cookies("a", expires_at, "r", expires_at, "c");
+        let access = cookies[0].to_str().unwrap();
+        assert!(access.starts_with("access_token=a; Path=/api; Secure; SameSite=Strict"));
+        assert!(access.ends_with("HttpOnly"));
+        assert!(!cookies[2].to_str().unwrap().contains("HttpOnly"));
+    }
+}
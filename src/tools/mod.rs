@@ -1,3 +1,8 @@
 pub mod constant;
 pub mod setup;
-pub mod rand_gene;
\ No newline at end of file
+pub mod rand_gene;
+pub mod request;
+pub mod webauthn;
+pub mod transport;
+pub mod cookie;
+pub mod qr;
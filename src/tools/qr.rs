@@ -0,0 +1,143 @@
+use handle_error::Error;
+use pdf_writer::{Content, Finish, Name, Pdf, Rect, Ref, Str};
+use png::{BitDepth, ColorType, Encoder};
+use qrcodegen::{QrCode, QrCodeEcc};
+
+// Blank modules around the code, scanners need at least four
+const QUIET_ZONE: i32 = 4;
+const PNG_MODULE_PIXELS: i32 = 8;
+
+// A4 in points, two columns and three rows of stickers per page
+const PAGE_WIDTH: f32 = 595.0;
+const PAGE_HEIGHT: f32 = 842.0;
+const STICKER_COLUMNS: usize = 2;
+const STICKER_ROWS: usize = 3;
+const STICKER_QR_POINTS: f32 = 170.0;
+
+// One sticker of the printable sheet, the text is printed under the code
+#[derive(Clone)]
+pub struct Sticker {
+    pub title: String,
+    pub subtitle: String,
+    pub code: QrCode,
+}
+
+pub fn encode(payload: &str) -> Result<QrCode, Error> {
+    QrCode::encode_text(payload, QrCodeEcc::Medium)
+        .map_err(|_| Error::Conflict("QR payload is too long".to_string()))
+}
+
+pub fn render_svg(code: &QrCode) -> String {
+    let size = code.size() + QUIET_ZONE * 2;
+    let mut path = String::new();
+    for y in 0..code.size() {
+        for x in 0..code.size() {
+            if code.get_module(x, y) {
+                path.push_str(&format!("M{},{}h1v1h-1z", x + QUIET_ZONE, y + QUIET_ZONE));
+            }
+        }
+    }
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {size} {size}\" shape-rendering=\"crispEdges\"><rect width=\"100%\" height=\"100%\" fill=\"#ffffff\"/><path d=\"{path}\" fill=\"#000000\"/></svg>"
+    )
+}
+
+// 8-bit grayscale, each module is a square of `PNG_MODULE_PIXELS`
+pub fn render_png(code: &QrCode) -> Result<Vec<u8>, Error> {
+    let side = (code.size() + QUIET_ZONE * 2) * PNG_MODULE_PIXELS;
+    let mut pixels = Vec::with_capacity((side * side) as usize);
+    for py in 0..side {
+        for px in 0..side {
+            let (x, y) = (px / PNG_MODULE_PIXELS - QUIET_ZONE, py / PNG_MODULE_PIXELS - QUIET_ZONE);
+            pixels.push(if code.get_module(x, y) { 0u8 } else { 255u8 });
+        }
+    }
+    let mut bytes = Vec::new();
+    let mut encoder = Encoder::new(&mut bytes, side as u32, side as u32);
+    encoder.set_color(ColorType::Grayscale);
+    encoder.set_depth(BitDepth::Eight);
+    let mut writer = encoder.write_header()
+        .map_err(|e| Error::Conflict(format!("cannot render QR code: {}", e)))?;
+    writer.write_image_data(&pixels)
+        .map_err(|e| Error::Conflict(format!("cannot render QR code: {}", e)))?;
+    writer.finish()
+        .map_err(|e| Error::Conflict(format!("cannot render QR code: {}", e)))?;
+    Ok(bytes)
+}
+
+// Without an embedded font only ASCII prints reliably, anything else shows as `?`
+fn pdf_text(text: &str) -> Vec<u8> {
+    text.chars().map(|c| if c.is_ascii() && !c.is_ascii_control() { c as u8 } else { b'?' }).collect()
+}
+
+// Stickers in reading order, the QR codes are drawn as vector squares so they print sharp at any size
+pub fn render_sheet_pdf(stickers: &[Sticker]) -> Vec<u8> {
+    let mut pdf = Pdf::new();
+    let catalog_id = Ref::new(1);
+    let page_tree_id = Ref::new(2);
+    let font_id = Ref::new(3);
+    let font_name = Name(b"F1");
+    let per_page = STICKER_COLUMNS * STICKER_ROWS;
+    let pages = stickers.len().div_ceil(per_page).max(1);
+    let page_ids: Vec<Ref> = (0..pages).map(|i| Ref::new(4 + 2 * i as i32)).collect();
+
+    pdf.catalog(catalog_id).pages(page_tree_id);
+    pdf.pages(page_tree_id).kids(page_ids.iter().copied()).count(pages as i32);
+    pdf.type1_font(font_id).base_font(Name(b"Helvetica"));
+
+    let cell_width = PAGE_WIDTH / STICKER_COLUMNS as f32;
+    let cell_height = PAGE_HEIGHT / STICKER_ROWS as f32;
+    for (index, page_id) in page_ids.iter().enumerate() {
+        let content_id = Ref::new(page_id.get() + 1);
+        let mut page = pdf.page(*page_id);
+        page.media_box(Rect::new(0.0, 0.0, PAGE_WIDTH, PAGE_HEIGHT));
+        page.parent(page_tree_id);
+        page.contents(content_id);
+        page.resources().fonts().pair(font_name, font_id);
+        page.finish();
+
+        let mut content = Content::new();
+        for (slot, sticker) in stickers.iter().skip(index * per_page).take(per_page).enumerate() {
+            let (column, row) = (slot % STICKER_COLUMNS, slot / STICKER_COLUMNS);
+            let left = column as f32 * cell_width + (cell_width - STICKER_QR_POINTS) / 2.0;
+            let top = PAGE_HEIGHT - row as f32 * cell_height - 40.0;
+            let code = &sticker.code;
+            let module = STICKER_QR_POINTS / code.size() as f32;
+            for y in 0..code.size() {
+                for x in 0..code.size() {
+                    if code.get_module(x, y) {
+                        content.rect(left + x as f32 * module, top - (y + 1) as f32 * module, module, module);
+                    }
+                }
+            }
+            content.fill_nonzero();
+            content.begin_text();
+            content.set_font(font_name, 12.0);
+            content.next_line(left, top - STICKER_QR_POINTS - 20.0);
+            content.show(Str(&pdf_text(&sticker.title)));
+            content.set_font(font_name, 9.0);
+            content.next_line(0.0, -14.0);
+            content.show(Str(&pdf_text(&sticker.subtitle)));
+            content.end_text();
+        }
+        pdf.stream(content_id, &content.finish());
+    }
+    pdf.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_every_format() {
+        let code = encode("v4.public.payload").unwrap();
+        assert!(render_svg(&code).starts_with("<svg"));
+        assert!(render_png(&code).unwrap().starts_with(b"\x89PNG"));
+        let sticker = Sticker { title: "Café Till".to_string(), subtitle: "abc123".to_string(), code };
+        let stickers: Vec<Sticker> = (0..7).map(|_| sticker.clone()).collect();
+        let pdf = render_sheet_pdf(&stickers);
+        assert!(pdf.starts_with(b"%PDF"));
+        assert_eq!(pdf_text(&sticker.title), b"Caf? Till");
+    }
+}
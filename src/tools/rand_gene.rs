@@ -11,4 +11,36 @@ fn random_char() -> char {
 
 fn random_string(length: usize) -> String {
     (0..length).map(|_| random_char()).collect()
-}
\ No newline at end of file
+}
+// Typed on a terminal keypad or read out over the phone, so no 0/O or 1/I/L
+const ACTIVATION_CHARSET: &[u8] = b"ABCDEFGHJKMNPQRSTUVWXYZ23456789";
+const ACTIVATION_CODE_LEN: usize = 8;
+
+// `ABCD-EFGH`, the dash is only for reading and is dropped by `normalize_activation_code`
+pub fn activation_code() -> String {
+    let mut rng = thread_rng();
+    let code: String = (0..ACTIVATION_CODE_LEN)
+        .map(|_| ACTIVATION_CHARSET[rng.gen_range(0..ACTIVATION_CHARSET.len())] as char)
+        .collect();
+    format!("{}-{}", &code[..ACTIVATION_CODE_LEN / 2], &code[ACTIVATION_CODE_LEN / 2..])
+}
+
+pub fn normalize_activation_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn activation_code_survives_sloppy_typing() {
+        let code = activation_code();
+        assert_eq!(code.len(), ACTIVATION_CODE_LEN + 1);
+        let typed = format!(" {} ", code.to_lowercase().replace('-', " "));
+        assert_eq!(normalize_activation_code(&typed), code.replace('-', ""));
+    }
+}
@@ -0,0 +1,72 @@
+use std::{env, net::{IpAddr, SocketAddr}, sync::OnceLock};
+
+use axum::http::HeaderMap;
+
+use crate::tools::constant::TRUSTED_PROXIES;
+
+static TRUSTED: OnceLock<Vec<IpAddr>> = OnceLock::new();
+
+// Comma separated proxy addresses, Caddy's in production, read once
+fn trusted_proxies() -> &'static [IpAddr] {
+    TRUSTED.get_or_init(|| {
+        env::var(TRUSTED_PROXIES)
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|value| value.trim().parse().ok())
+            .collect()
+    })
+}
+
+// X-Forwarded-For is only believed when the connection comes from a trusted proxy. Each proxy appends
+// the address it saw, so the client is the rightmost entry that is not one of our proxies
+pub fn resolve_client_ip(headers: &HeaderMap, peer: IpAddr, trusted: &[IpAddr]) -> IpAddr {
+    if !trusted.contains(&peer) {
+        return peer;
+    }
+    let forwarded: Vec<&str> = headers
+        .get_all("X-Forwarded-For")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .collect();
+    let mut client = peer;
+    for entry in forwarded.into_iter().rev() {
+        match entry.trim().parse::<IpAddr>() {
+            Ok(ip) if trusted.contains(&ip) => client = ip,
+            Ok(ip) => return ip,
+            Err(_) => return client,
+        }
+    }
+    client
+}
+
+pub fn client_ip(headers: &HeaderMap, peer: Option<SocketAddr>) -> String {
+    match peer {
+        Some(peer) => resolve_client_ip(headers, peer.ip(), trusted_proxies()).to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn forwarded(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Forwarded-For", value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn forwarded_for_only_counts_behind_a_trusted_proxy() {
+        let proxy: IpAddr = "10.0.0.2".parse().unwrap();
+        let client: IpAddr = "203.0.113.7".parse().unwrap();
+        let spoofed = forwarded("1.2.3.4, 203.0.113.7");
+
+        assert_eq!(resolve_client_ip(&spoofed, client, &[proxy]), client);
+        assert_eq!(resolve_client_ip(&spoofed, proxy, &[proxy]), client);
+        assert_eq!(resolve_client_ip(&forwarded("203.0.113.7, 10.0.0.2"), proxy, &[proxy]), client);
+        assert_eq!(resolve_client_ip(&HeaderMap::new(), proxy, &[proxy]), proxy);
+        assert_eq!(resolve_client_ip(&forwarded("garbage"), proxy, &[proxy]), proxy);
+    }
+}
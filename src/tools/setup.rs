@@ -1,6 +1,10 @@
+use axum::http::{header::{AUTHORIZATION, CONTENT_TYPE}, HeaderName, HeaderValue, Method};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
 use tracing_appender::{non_blocking::{NonBlocking, WorkerGuard}, rolling};
 use tracing_subscriber::{fmt::format::FmtSpan, EnvFilter};
 
+use crate::tools::constant::{CORS_ALLOWED_ORIGINS, XMINISTER_API_KEY, XMINISTER_METAL_API_KEY, X_CSRF_TOKEN, X_DEVICE_ID, X_KEY_VERSION, X_NONCE, X_REQUEST_ID, X_SESSION_MODE, X_SIGNATURE, X_TIMESTAMP};
+
 
 pub fn setup_log() -> (NonBlocking, WorkerGuard, String) {
     let file_appender = rolling::daily("logs", "app.log");
@@ -9,4 +13,33 @@ pub fn setup_log() -> (NonBlocking, WorkerGuard, String) {
     let log_filter = std::env::var("RUST_LOG")
         .unwrap_or_else(|_| "link_server=info,axum=error".to_owned());
     (non_blocking, guard, log_filter)
-}
\ No newline at end of file
+}
+
+// `CORS_ALLOWED_ORIGINS` (comma separated) switches to credentialed CORS for cookie sessions,
+// without it any origin may call the API but browsers will not send cookies
+pub fn cors_layer() -> CorsLayer {
+    let methods = [Method::GET, Method::POST, Method::PUT, Method::DELETE, Method::OPTIONS];
+    let origins: Vec<HeaderValue> = std::env::var(CORS_ALLOWED_ORIGINS)
+        .unwrap_or_default()
+        .split(',')
+        .map(|origin| origin.trim())
+        .filter(|origin| !origin.is_empty())
+        .filter_map(|origin| HeaderValue::from_str(origin).ok())
+        .collect();
+    if origins.is_empty() {
+        return CorsLayer::new()
+            .allow_origin(Any)
+            .allow_methods(methods)
+            .allow_headers(Any);
+    }
+    // Wildcards are not allowed together with credentials, so every header is named
+    let headers = [CONTENT_TYPE.as_str(), AUTHORIZATION.as_str(), X_CSRF_TOKEN, X_SESSION_MODE, XMINISTER_API_KEY, XMINISTER_METAL_API_KEY, X_DEVICE_ID, X_KEY_VERSION, X_TIMESTAMP, X_NONCE, X_SIGNATURE, X_REQUEST_ID]
+        .iter()
+        .map(|name| HeaderName::from_bytes(name.as_bytes()).expect("valid header name"))
+        .collect::<Vec<HeaderName>>();
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_methods(methods)
+        .allow_headers(headers)
+        .allow_credentials(true)
+}
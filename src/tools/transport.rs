@@ -0,0 +1,80 @@
+use std::{env, fs::{self, OpenOptions}, io::Write, path::PathBuf};
+
+use chrono::{DateTime, Utc};
+use handle_error::Error;
+use serde::{Deserialize, Serialize};
+
+use crate::tools::constant::{MESSAGE_OUTBOX, MESSAGE_TRANSPORT};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OutboundMessage {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl OutboundMessage {
+    pub fn new(to: &str, subject: &str, body: String) -> Self {
+        Self { to: to.to_string(), subject: subject.to_string(), body, created_at: Utc::now() }
+    }
+}
+
+// How messages leave the server, email or SMS providers plug in next to the file sink
+pub trait MessageTransport: Send + Sync {
+    fn send(&self, message: &OutboundMessage) -> Result<(), Error>;
+}
+
+// Appends one JSON line per message, for local development and the sandbox
+pub struct FileTransport {
+    path: PathBuf,
+}
+
+impl FileTransport {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl MessageTransport for FileTransport {
+    fn send(&self, message: &OutboundMessage) -> Result<(), Error> {
+        if let Some(parent) = self.path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).map_err(|e| Error::MessageDeliveryError(e.to_string()))?;
+        }
+        let mut line = serde_json::to_string(message).map_err(|e| Error::MessageDeliveryError(e.to_string()))?;
+        line.push('\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .map_err(|e| Error::MessageDeliveryError(e.to_string()))
+    }
+}
+
+// `MESSAGE_TRANSPORT` picks the sink, only `file` exists for now
+pub fn transport_from_env() -> Box<dyn MessageTransport> {
+    let outbox = env::var(MESSAGE_OUTBOX).unwrap_or_else(|_| "logs/outbox.jsonl".to_string());
+    match env::var(MESSAGE_TRANSPORT).as_deref() {
+        Ok("file") | Err(_) => Box::new(FileTransport::new(outbox)),
+        Ok(other) => panic!("Unknown message transport {}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_transport_appends_json_lines() {
+        let path = env::temp_dir().join(format!("outbox-{}.jsonl", uuid::Uuid::new_v4()));
+        let transport = FileTransport::new(&path);
+        transport.send(&OutboundMessage::new("ada@example.com", "first", "one".to_string())).unwrap();
+        transport.send(&OutboundMessage::new("ada@example.com", "second", "two".to_string())).unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+        let lines: Vec<OutboundMessage> = written.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1].subject, "second");
+        fs::remove_file(path).unwrap();
+    }
+}
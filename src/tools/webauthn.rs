@@ -0,0 +1,291 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use ciborium::value::{Integer, Value};
+use p256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+// Authenticator data flags, WebAuthn level 2 section 6.1
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+// COSE ES256 on P-256, the only algorithm we offer in `pubKeyCredParams`
+pub const COSE_ALG_ES256: i64 = -7;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum WebauthnError {
+    Malformed(&'static str),
+    TypeMismatch,
+    ChallengeMismatch,
+    OriginMismatch,
+    RpIdMismatch,
+    UserNotVerified,
+    UnsupportedAttestation,
+    UnsupportedKey,
+    BadSignature,
+    UnknownCredential,
+    UserHandleMismatch,
+    CounterRegressed,
+}
+
+impl std::fmt::Display for WebauthnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            WebauthnError::Malformed(what) => write!(f, "malformed {}", what),
+            WebauthnError::TypeMismatch => write!(f, "client data type mismatch"),
+            WebauthnError::ChallengeMismatch => write!(f, "challenge mismatch"),
+            WebauthnError::OriginMismatch => write!(f, "origin mismatch"),
+            WebauthnError::RpIdMismatch => write!(f, "rp id hash mismatch"),
+            WebauthnError::UserNotVerified => write!(f, "user presence or verification missing"),
+            WebauthnError::UnsupportedAttestation => write!(f, "unsupported attestation format"),
+            WebauthnError::UnsupportedKey => write!(f, "unsupported credential key"),
+            WebauthnError::BadSignature => write!(f, "assertion signature does not verify"),
+            WebauthnError::UserHandleMismatch => write!(f, "user handle does not match the credential owner"),
+            WebauthnError::UnknownCredential => write!(f, "credential is not registered"),
+            WebauthnError::CounterRegressed => write!(f, "sign counter did not increase"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClientData {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub challenge: String,
+    pub origin: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct AttestedCredential {
+    pub credential_id: Vec<u8>,
+    // Uncompressed SEC1 point, what `VerifyingKey::from_sec1_bytes` takes back
+    pub public_key: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AuthenticatorData {
+    pub rp_id_hash: [u8; 32],
+    pub flags: u8,
+    pub sign_count: u32,
+    pub credential: Option<AttestedCredential>,
+}
+
+pub fn decode(value: &str, what: &'static str) -> Result<Vec<u8>, WebauthnError> {
+    URL_SAFE_NO_PAD.decode(value.trim_end_matches('=')).map_err(|_| WebauthnError::Malformed(what))
+}
+
+pub fn encode(bytes: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+pub fn parse_client_data(client_data_json: &[u8]) -> Result<ClientData, WebauthnError> {
+    serde_json::from_slice(client_data_json).map_err(|_| WebauthnError::Malformed("clientDataJSON"))
+}
+
+// `kind` is `webauthn.create` or `webauthn.get`, the challenge is checked against the store by the caller
+pub fn check_client_data(client_data: &ClientData, kind: &str, origin: &str) -> Result<(), WebauthnError> {
+    if client_data.kind != kind {
+        return Err(WebauthnError::TypeMismatch);
+    }
+    if client_data.origin != origin {
+        return Err(WebauthnError::OriginMismatch);
+    }
+    Ok(())
+}
+
+pub fn parse_authenticator_data(bytes: &[u8]) -> Result<AuthenticatorData, WebauthnError> {
+    if bytes.len() < 37 {
+        return Err(WebauthnError::Malformed("authenticatorData"));
+    }
+    let mut rp_id_hash = [0u8; 32];
+    rp_id_hash.copy_from_slice(&bytes[..32]);
+    let flags = bytes[32];
+    let sign_count = u32::from_be_bytes([bytes[33], bytes[34], bytes[35], bytes[36]]);
+    let credential = if flags & FLAG_ATTESTED_CREDENTIAL != 0 {
+        Some(parse_attested_credential(&bytes[37..])?)
+    } else {
+        None
+    };
+    Ok(AuthenticatorData { rp_id_hash, flags, sign_count, credential })
+}
+
+fn parse_attested_credential(bytes: &[u8]) -> Result<AttestedCredential, WebauthnError> {
+    // 16 byte AAGUID, then a big endian length and the credential id, then the COSE key
+    if bytes.len() < 18 {
+        return Err(WebauthnError::Malformed("attestedCredentialData"));
+    }
+    let id_len = u16::from_be_bytes([bytes[16], bytes[17]]) as usize;
+    let id_end = 18 + id_len;
+    if bytes.len() <= id_end {
+        return Err(WebauthnError::Malformed("attestedCredentialData"));
+    }
+    let cose_key: Value = ciborium::de::from_reader(&bytes[id_end..])
+        .map_err(|_| WebauthnError::Malformed("credentialPublicKey"))?;
+    Ok(AttestedCredential {
+        credential_id: bytes[18..id_end].to_vec(),
+        public_key: es256_public_key(&cose_key)?,
+    })
+}
+
+fn cose_field(map: &[(Value, Value)], label: i64) -> Option<&Value> {
+    map.iter()
+        .find(|(key, _)| key.as_integer() == Some(Integer::from(label)))
+        .map(|(_, value)| value)
+}
+
+// COSE_Key with kty EC2, alg ES256, crv P-256
+fn es256_public_key(cose_key: &Value) -> Result<Vec<u8>, WebauthnError> {
+    let map = cose_key.as_map().ok_or(WebauthnError::UnsupportedKey)?;
+    let int = |label: i64| cose_field(map, label).and_then(|v| v.as_integer()).and_then(|v| i64::try_from(v).ok());
+    if int(1) != Some(2) || int(3) != Some(COSE_ALG_ES256) || int(-1) != Some(1) {
+        return Err(WebauthnError::UnsupportedKey);
+    }
+    let x = cose_field(map, -2).and_then(|v| v.as_bytes()).ok_or(WebauthnError::UnsupportedKey)?;
+    let y = cose_field(map, -3).and_then(|v| v.as_bytes()).ok_or(WebauthnError::UnsupportedKey)?;
+    if x.len() != 32 || y.len() != 32 {
+        return Err(WebauthnError::UnsupportedKey);
+    }
+    let mut point = vec![0x04];
+    point.extend_from_slice(x);
+    point.extend_from_slice(y);
+    VerifyingKey::from_sec1_bytes(&point).map_err(|_| WebauthnError::UnsupportedKey)?;
+    Ok(point)
+}
+
+// Only `none` attestation is requested, so that is the only format accepted
+pub fn parse_attestation_object(bytes: &[u8]) -> Result<AuthenticatorData, WebauthnError> {
+    let value: Value = ciborium::de::from_reader(bytes).map_err(|_| WebauthnError::Malformed("attestationObject"))?;
+    let map = value.as_map().ok_or(WebauthnError::Malformed("attestationObject"))?;
+    let field = |name: &str| map.iter().find(|(key, _)| key.as_text() == Some(name)).map(|(_, value)| value);
+    if field("fmt").and_then(|v| v.as_text()) != Some("none") {
+        return Err(WebauthnError::UnsupportedAttestation);
+    }
+    let auth_data = field("authData").and_then(|v| v.as_bytes()).ok_or(WebauthnError::Malformed("authData"))?;
+    parse_authenticator_data(auth_data)
+}
+
+pub fn check_authenticator_data(auth_data: &AuthenticatorData, rp_id: &str) -> Result<(), WebauthnError> {
+    if auth_data.rp_id_hash[..] != Sha256::digest(rp_id.as_bytes())[..] {
+        return Err(WebauthnError::RpIdMismatch);
+    }
+    // Shared shop PCs, so a PIN or biometric is required and not just a tap
+    if auth_data.flags & FLAG_USER_PRESENT == 0 || auth_data.flags & FLAG_USER_VERIFIED == 0 {
+        return Err(WebauthnError::UserNotVerified);
+    }
+    Ok(())
+}
+
+// ES256 signature (DER) over `authenticatorData || SHA-256(clientDataJSON)`
+pub fn verify_assertion(public_key: &[u8], authenticator_data: &[u8], client_data_json: &[u8], signature: &[u8]) -> Result<(), WebauthnError> {
+    let key = VerifyingKey::from_sec1_bytes(public_key).map_err(|_| WebauthnError::UnsupportedKey)?;
+    let signature = Signature::from_der(signature).map_err(|_| WebauthnError::BadSignature)?;
+    let mut signed = authenticator_data.to_vec();
+    signed.extend_from_slice(&Sha256::digest(client_data_json));
+    key.verify(&signed, &signature).map_err(|_| WebauthnError::BadSignature)
+}
+
+// Counters must grow, unless the authenticator does not keep one at all (both zero)
+pub fn sign_count_is_valid(stored: u32, received: u32) -> bool {
+    (stored == 0 && received == 0) || received > stored
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p256::ecdsa::{signature::Signer, SigningKey};
+    use rand_core::OsRng;
+
+    const RP_ID: &str = "localhost";
+    const ORIGIN: &str = "http://localhost:3000";
+
+    // Minimal software authenticator: one P-256 key, a counter and a fixed credential id
+    struct SoftAuthenticator {
+        key: SigningKey,
+        credential_id: Vec<u8>,
+        counter: u32,
+    }
+
+    impl SoftAuthenticator {
+        fn new() -> Self {
+            Self { key: SigningKey::random(&mut OsRng), credential_id: vec![7; 16], counter: 0 }
+        }
+
+        fn authenticator_data(&self, flags: u8, attested: bool) -> Vec<u8> {
+            let mut data = Sha256::digest(RP_ID.as_bytes()).to_vec();
+            data.push(flags | if attested { FLAG_ATTESTED_CREDENTIAL } else { 0 });
+            data.extend_from_slice(&self.counter.to_be_bytes());
+            if attested {
+                let point = self.key.verifying_key().to_encoded_point(false);
+                let cose_key = Value::Map(vec![
+                    (Value::from(1), Value::from(2)),
+                    (Value::from(3), Value::from(COSE_ALG_ES256)),
+                    (Value::from(-1), Value::from(1)),
+                    (Value::from(-2), Value::Bytes(point.x().unwrap().to_vec())),
+                    (Value::from(-3), Value::Bytes(point.y().unwrap().to_vec())),
+                ]);
+                data.extend_from_slice(&[0; 16]);
+                data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+                data.extend_from_slice(&self.credential_id);
+                ciborium::ser::into_writer(&cose_key, &mut data).unwrap();
+            }
+            data
+        }
+
+        fn make_credential(&self, challenge: &str) -> (Vec<u8>, Vec<u8>) {
+            let client_data = client_data_json("webauthn.create", challenge);
+            let attestation = Value::Map(vec![
+                (Value::from("fmt"), Value::from("none")),
+                (Value::from("attStmt"), Value::Map(vec![])),
+                (Value::from("authData"), Value::Bytes(self.authenticator_data(FLAG_USER_PRESENT | FLAG_USER_VERIFIED, true))),
+            ]);
+            let mut attestation_object = Vec::new();
+            ciborium::ser::into_writer(&attestation, &mut attestation_object).unwrap();
+            (client_data, attestation_object)
+        }
+
+        fn get_assertion(&mut self, challenge: &str) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+            self.counter += 1;
+            let client_data = client_data_json("webauthn.get", challenge);
+            let auth_data = self.authenticator_data(FLAG_USER_PRESENT | FLAG_USER_VERIFIED, false);
+            let mut signed = auth_data.clone();
+            signed.extend_from_slice(&Sha256::digest(&client_data));
+            let signature: Signature = self.key.sign(&signed);
+            (client_data, auth_data, signature.to_der().as_bytes().to_vec())
+        }
+    }
+
+    fn client_data_json(kind: &str, challenge: &str) -> Vec<u8> {
+        serde_json::to_vec(&serde_json::json!({"type": kind, "challenge": challenge, "origin": ORIGIN})).unwrap()
+    }
+
+    #[test]
+    fn registers_then_authenticates_with_software_authenticator() {
+        let mut authenticator = SoftAuthenticator::new();
+        let (client_data, attestation_object) = authenticator.make_credential("register-challenge");
+        let parsed = parse_client_data(&client_data).unwrap();
+        assert!(check_client_data(&parsed, "webauthn.create", ORIGIN).is_ok());
+        assert_eq!(parsed.challenge, "register-challenge");
+        let auth_data = parse_attestation_object(&attestation_object).unwrap();
+        assert!(check_authenticator_data(&auth_data, RP_ID).is_ok());
+        let credential = auth_data.credential.unwrap();
+        assert_eq!(credential.credential_id, authenticator.credential_id);
+
+        let (client_data, auth_data, signature) = authenticator.get_assertion("login-challenge");
+        let parsed = parse_client_data(&client_data).unwrap();
+        assert!(check_client_data(&parsed, "webauthn.get", ORIGIN).is_ok());
+        assert_eq!(check_client_data(&parsed, "webauthn.create", ORIGIN), Err(WebauthnError::TypeMismatch));
+        let parsed_auth_data = parse_authenticator_data(&auth_data).unwrap();
+        assert!(check_authenticator_data(&parsed_auth_data, RP_ID).is_ok());
+        assert_eq!(check_authenticator_data(&parsed_auth_data, "example.com"), Err(WebauthnError::RpIdMismatch));
+        assert!(verify_assertion(&credential.public_key, &auth_data, &client_data, &signature).is_ok());
+        assert_eq!(verify_assertion(&credential.public_key, &auth_data, b"{}", &signature), Err(WebauthnError::BadSignature));
+        assert!(sign_count_is_valid(0, parsed_auth_data.sign_count));
+    }
+
+    #[test]
+    fn sign_counter_must_move_forward() {
+        assert!(sign_count_is_valid(0, 0));
+        assert!(sign_count_is_valid(4, 5));
+        assert!(!sign_count_is_valid(5, 5));
+        assert!(!sign_count_is_valid(5, 0));
+    }
+}
@@ -2,12 +2,11 @@ use bytes::Bytes;
 use chrono::{DateTime, Utc};
 use handle_error::Error;
 use serde::{Deserialize, Serialize};
-use sqlx::{postgres::PgRow, Row};
+use sqlx::{postgres::{PgExecutor, PgRow}, Row};
 use uuid::Uuid;
 
 use crate::db_store::Store;
 
-
 #[derive(Serialize, Deserialize, Debug)]
 pub struct AccountResponse {
     pub account_number: Vec<u8>,
@@ -18,6 +17,8 @@ pub struct AccountResponse {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Account {
     pub id: Uuid,
+    // Organization allowed to pay into the account, unowned accounts can only be assigned by an admin
+    pub organization_id: Option<Uuid>,
     pub bank_id: String,        // varchar → String
     pub account_name: String,
     pub account_number: String,
@@ -34,37 +35,65 @@ pub struct AccountItem {
     pub bank_name: String,
 }
 
-
 #[derive(Serialize, Deserialize, Debug)]
 pub struct AccountItemStore {
     pub accounts: Vec<AccountItem>,
 }
 
+pub(crate) async fn add_account_in<'c>(executor: impl PgExecutor<'c>, account: &Account) -> Result<bool, Error> {
+    let query = r#"
+        INSERT INTO accounts (id, bank_id, account_name, account_number, created_at, updated_at, organization_id)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+    "#;
+    sqlx::query(query)
+        .bind(account.id)
+        .bind(&account.bank_id)
+        .bind(&account.account_name)
+        .bind(&account.account_number)
+        .bind(account.created_at)
+        .bind(account.updated_at)
+        .bind(account.organization_id)
+        .execute(executor)
+        .await
+        .map(|_| true)
+        .map_err(Error::DatabaseQueryError)
+}
 
-impl Store {
-    pub async fn add_account(&self, account: &Account) -> Result<bool, Error> {
-        let query = r#"
-            INSERT INTO accounts (id, business_id, bank_id, account_name, account_number, created_at, updated_at)
-            VALUES ($1, $2, $3, $4, $5, $6, $7)
-        "#;
-        sqlx::query(query)
-            .bind(account.id)
-            .bind(&account.bank_id)
-            .bind(&account.account_name)
-            .bind(&account.account_number)
-            .bind(account.created_at)
-            .bind(account.updated_at)
-            .execute(&self.connection)
-            .await
-            .map(|_| true)
-            .map_err(|e| Error::DatabaseQueryError(e))
-    }
+pub(crate) async fn update_account_in<'c>(executor: impl PgExecutor<'c>, account: &Account) -> Result<bool, Error> {
+    let query = r#"
+        UPDATE accounts
+        SET bank_id = $2, account_name = $3, account_number = $4, updated_at = $5, organization_id = $6
+        WHERE id = $1
+    "#;
+    sqlx::query(query)
+        .bind(account.id)
+        .bind(&account.bank_id)
+        .bind(&account.account_name)
+        .bind(&account.account_number)
+        .bind(account.updated_at)
+        .bind(account.organization_id)
+        .execute(executor)
+        .await
+        .map(|_| true)
+        .map_err(Error::DatabaseQueryError)
+}
+
+pub(crate) async fn delete_account_in<'c>(executor: impl PgExecutor<'c>, id: Uuid) -> Result<bool, Error> {
+    sqlx::query("DELETE FROM accounts WHERE id = $1")
+        .bind(id)
+        .execute(executor)
+        .await
+        .map(|_| true)
+        .map_err(Error::DatabaseQueryError)
+}
 
+impl Store {
     pub async fn get_account(&self, id: &Uuid) -> Result<Account, Error> {
         sqlx::query("SELECT * FROM \"accounts\" WHERE id = $1")
             .bind(id)
             .map(|row: PgRow| Account {
                 id: row.get("id"),
+                organization_id: row.get("organization_id"),
                 bank_id: row.get("bank_id"),
                 account_name: row.get("account_name"),
                 account_number: row.get("account_number"),
@@ -79,6 +108,7 @@ impl Store {
         sqlx::query("SELECT * FROM accounts")
             .map(|row: PgRow| Account {
                 id: row.get("id"),
+                organization_id: row.get("organization_id"),
                 bank_id: row.get("bank_id"),
                 account_name: row.get("account_name"),
                 account_number: row.get("account_number"),
@@ -89,10 +119,19 @@ impl Store {
             .await
             .map_err(|e| Error::DatabaseQueryError(e))
     }
-    pub async fn get_accounts(&self) -> Result<Vec<Account>, Error> {
-        sqlx::query("SELECT * FROM accounts")
+    // Accounts owned by every organization the user belongs to, whether or not a device pays into them
+    pub async fn get_accounts_member_id(&self, user_id: Uuid) -> Result<Vec<Account>, Error> {
+        let query = r#"
+            SELECT a.*
+            FROM accounts a
+            WHERE a.organization_id IN (SELECT organization_id FROM organization_members WHERE user_id = $1)
+            ORDER BY a.created_at
+        "#;
+        sqlx::query(query)
+            .bind(user_id)
             .map(|row: PgRow| Account {
                 id: row.get("id"),
+                organization_id: row.get("organization_id"),
                 bank_id: row.get("bank_id"),
                 account_name: row.get("account_name"),
                 account_number: row.get("account_number"),
@@ -101,32 +140,22 @@ impl Store {
             })
             .fetch_all(&self.connection)
             .await
-            .map_err(|e| Error::DatabaseQueryError(e))
+            .map_err(Error::DatabaseQueryError)
     }
-    pub async fn update_account(&self, account: &Account) -> Result<bool, Error> {
-        let query = r#"
-            UPDATE accounts
-            SET business_id = $2, bank_id = $3, account_name = $4, account_number = $5, updated_at = $6
-            WHERE id = $1
-        "#;
-        sqlx::query(query)
-            .bind(account.id)
-            .bind(&account.bank_id)
-            .bind(&account.account_name)
-            .bind(&account.account_number)
-            .bind(account.updated_at)
-            .execute(&self.connection)
+    pub async fn get_accounts(&self) -> Result<Vec<Account>, Error> {
+        sqlx::query("SELECT * FROM accounts")
+            .map(|row: PgRow| Account {
+                id: row.get("id"),
+                organization_id: row.get("organization_id"),
+                bank_id: row.get("bank_id"),
+                account_name: row.get("account_name"),
+                account_number: row.get("account_number"),
+                created_at: row.get("created_at"),
+                updated_at: row.get("updated_at"),
+            })
+            .fetch_all(&self.connection)
             .await
-            .map(|_| true)
             .map_err(|e| Error::DatabaseQueryError(e))
     }
 
-    pub async fn delete_account(&self, id: Uuid) -> Result<bool, Error> {
-        sqlx::query("DELETE FROM accounts WHERE id = $1")
-            .bind(id)
-            .execute(&self.connection)
-            .await
-            .map(|_| true)
-            .map_err(|e| Error::DatabaseQueryError(e))
-    }
 }
\ No newline at end of file
@@ -0,0 +1,191 @@
+use handle_error::Error;
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgRow, Row};
+
+use crate::{db_store::Store, types::{account::Account, bank::Bank, business::Business, customer::Customer, device::{device_from_row, Device, DEVICE_COLUMNS}, role::Role, user::User}};
+
+const DEFAULT_PER_PAGE: i64 = 20;
+const MAX_PER_PAGE: i64 = 100;
+
+// `?q=&page=&per_page=` shared by every admin list route
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PageQuery {
+    pub q: Option<String>,
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Page<T> {
+    pub list: Vec<T>,
+    pub page: i64,
+    pub per_page: i64,
+    pub total: i64,
+}
+
+impl PageQuery {
+    pub fn page(&self) -> i64 {
+        self.page.unwrap_or(1).max(1)
+    }
+    pub fn per_page(&self) -> i64 {
+        self.per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(1, MAX_PER_PAGE)
+    }
+    pub fn offset(&self) -> i64 {
+        (self.page() - 1) * self.per_page()
+    }
+    // ILIKE pattern, None when there is nothing to search for
+    pub fn pattern(&self) -> Option<String> {
+        self.q.as_ref()
+            .map(|q| q.trim())
+            .filter(|q| !q.is_empty())
+            .map(|q| format!("%{}%", q.replace('%', "\\%").replace('_', "\\_")))
+    }
+    pub fn to_page<T>(&self, list: Vec<T>, total: i64) -> Page<T> {
+        Page { list, page: self.page(), per_page: self.per_page(), total }
+    }
+}
+
+impl Store {
+    async fn count_matching(&self, query: &str, pattern: &Option<String>) -> Result<i64, Error> {
+        let (total,): (i64,) = sqlx::query_as(query)
+            .bind(pattern)
+            .fetch_one(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        Ok(total)
+    }
+
+    pub async fn search_users(&self, page: &PageQuery) -> Result<Page<User>, Error> {
+        let pattern = page.pattern();
+        let filter = "$1::varchar IS NULL OR first_name ILIKE $1 OR last_name ILIKE $1 OR email ILIKE $1";
+        let total = self.count_matching(&format!("SELECT COUNT(*) FROM users WHERE {}", filter), &pattern).await?;
+        let list = sqlx::query(&format!("SELECT * FROM users WHERE {} ORDER BY created_at DESC LIMIT $2 OFFSET $3", filter))
+            .bind(&pattern)
+            .bind(page.per_page())
+            .bind(page.offset())
+            .map(|row: PgRow| User {
+                id: row.get("id"),
+                created_at: row.get("created_at"),
+                updated_at: row.get("updated_at"),
+                hashed_password: row.get("hashed_password"),
+                first_name: row.get("first_name"),
+                last_name: row.get("last_name"),
+                email: row.get("email"),
+                role: Role::parse(row.get("role")).unwrap_or(Role::Merchant),
+                deleted_at: row.get("deleted_at"),
+            })
+            .fetch_all(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        Ok(page.to_page(list, total))
+    }
+
+    pub async fn search_banks(&self, page: &PageQuery) -> Result<Page<Bank>, Error> {
+        let pattern = page.pattern();
+        let filter = "$1::varchar IS NULL OR id ILIKE $1";
+        let total = self.count_matching(&format!("SELECT COUNT(*) FROM banks WHERE {}", filter), &pattern).await?;
+        let list = sqlx::query(&format!("SELECT * FROM banks WHERE {} ORDER BY created_at DESC LIMIT $2 OFFSET $3", filter))
+            .bind(&pattern)
+            .bind(page.per_page())
+            .bind(page.offset())
+            .map(|row: PgRow| Bank {
+                id: row.get("id"),
+                user_id: row.get("user_id"),
+                created_at: row.get("created_at"),
+                updated_at: row.get("updated_at"),
+            })
+            .fetch_all(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        Ok(page.to_page(list, total))
+    }
+
+    pub async fn search_businesses(&self, page: &PageQuery) -> Result<Page<Business>, Error> {
+        let pattern = page.pattern();
+        let filter = "$1::varchar IS NULL OR name ILIKE $1 OR location ILIKE $1";
+        let total = self.count_matching(&format!("SELECT COUNT(*) FROM businesses WHERE {}", filter), &pattern).await?;
+        let list = sqlx::query(&format!("SELECT * FROM businesses WHERE {} ORDER BY created_at DESC LIMIT $2 OFFSET $3", filter))
+            .bind(&pattern)
+            .bind(page.per_page())
+            .bind(page.offset())
+            .map(|row: PgRow| Business {
+                id: row.get("id"),
+                user_id: row.get("user_id"),
+                organization_id: row.get("organization_id"),
+                name: row.get("name"),
+                location: row.get("location"),
+                geolocation: (row.get("lon"), row.get("lat")),
+                lat: row.get("lat"),
+                long: row.get("lon"),
+                created_at: row.get("created_at"),
+                updated_at: row.get("updated_at"),
+            })
+            .fetch_all(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        Ok(page.to_page(list, total))
+    }
+
+    pub async fn search_accounts(&self, page: &PageQuery) -> Result<Page<Account>, Error> {
+        let pattern = page.pattern();
+        let filter = "$1::varchar IS NULL OR account_name ILIKE $1 OR account_number ILIKE $1 OR bank_id ILIKE $1";
+        let total = self.count_matching(&format!("SELECT COUNT(*) FROM accounts WHERE {}", filter), &pattern).await?;
+        let list = sqlx::query(&format!("SELECT * FROM accounts WHERE {} ORDER BY created_at DESC LIMIT $2 OFFSET $3", filter))
+            .bind(&pattern)
+            .bind(page.per_page())
+            .bind(page.offset())
+            .map(|row: PgRow| Account {
+                id: row.get("id"),
+                organization_id: row.get("organization_id"),
+                bank_id: row.get("bank_id"),
+                account_name: row.get("account_name"),
+                account_number: row.get("account_number"),
+                created_at: row.get("created_at"),
+                updated_at: row.get("updated_at"),
+            })
+            .fetch_all(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        Ok(page.to_page(list, total))
+    }
+
+    pub async fn search_devices(&self, page: &PageQuery) -> Result<Page<Device>, Error> {
+        let pattern = page.pattern();
+        let filter = "$1::varchar IS NULL OR name ILIKE $1 OR device_id ILIKE $1 OR device_type ILIKE $1";
+        let total = self.count_matching(&format!("SELECT COUNT(*) FROM devices WHERE {}", filter), &pattern).await?;
+        let list = sqlx::query(&format!("SELECT {} FROM devices WHERE {} ORDER BY created_at DESC LIMIT $2 OFFSET $3", DEVICE_COLUMNS, filter))
+            .bind(&pattern)
+            .bind(page.per_page())
+            .bind(page.offset())
+            .map(device_from_row)
+            .fetch_all(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        Ok(page.to_page(list, total))
+    }
+
+    pub async fn search_customers(&self, page: &PageQuery) -> Result<Page<Customer>, Error> {
+        let pattern = page.pattern();
+        let filter = "$1::varchar IS NULL OR first_name ILIKE $1 OR last_name ILIKE $1 OR bank_id ILIKE $1";
+        let total = self.count_matching(&format!("SELECT COUNT(*) FROM customers WHERE {}", filter), &pattern).await?;
+        let list = sqlx::query(&format!("SELECT * FROM customers WHERE {} ORDER BY created_at DESC LIMIT $2 OFFSET $3", filter))
+            .bind(&pattern)
+            .bind(page.per_page())
+            .bind(page.offset())
+            .map(|row: PgRow| Customer {
+                id: row.get("id"),
+                first_name: row.get("first_name"),
+                last_name: row.get("last_name"),
+                public_key: row.get("public_key"),
+                private_key: row.get("private_key"),
+                bank_id: row.get("bank_id"),
+                public_id: row.get("public_id"),
+                created_at: row.get("created_at"),
+                updated_at: row.get("updated_at"),
+            })
+            .fetch_all(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        Ok(page.to_page(list, total))
+    }
+}
@@ -0,0 +1,215 @@
+use chrono::{DateTime, Utc};
+use encrypt::{generate_random_char, hash_secret};
+use handle_error::Error;
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgRow, Row};
+use uuid::Uuid;
+
+use crate::db_store::Store;
+
+const PREFIX_LEN: usize = 8;
+const SECRET_LEN: usize = 32;
+
+// Which header a key is accepted on, stored in `api_keys.scopes`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ApiKeyType {
+    #[serde(rename = "public")]
+    XMinister,
+    #[serde(rename = "metal")]
+    XMinisterMetal,
+}
+
+impl ApiKeyType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiKeyType::XMinister => "public",
+            ApiKeyType::XMinisterMetal => "metal",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<ApiKeyType> {
+        match value {
+            "public" => Some(ApiKeyType::XMinister),
+            "metal" => Some(ApiKeyType::XMinisterMetal),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub bank_id: String,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<ApiKeyType>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl ApiKey {
+    pub fn allows(&self, scope: ApiKeyType) -> bool {
+        self.scopes.contains(&scope)
+    }
+}
+
+// New `(prefix, key)`, the prefix identifies the key in lists, the full key is shown once
+pub fn generate_api_key() -> (String, String) {
+    let prefix = format!("xm_{}", generate_random_char(PREFIX_LEN));
+    let key = format!("{}.{}", prefix, generate_random_char(SECRET_LEN));
+    (prefix, key)
+}
+
+// What the database keeps of a key, the full key never reaches it
+pub struct KeySecret {
+    pub prefix: String,
+    pub secret_hash: String,
+}
+
+impl KeySecret {
+    pub fn new(prefix: String, key: &str) -> Self {
+        Self { prefix, secret_hash: hash_secret(key) }
+    }
+}
+
+fn api_key_from_row(row: PgRow) -> ApiKey {
+    ApiKey {
+        id: row.get("id"),
+        bank_id: row.get("bank_id"),
+        name: row.get("name"),
+        prefix: row.get("prefix"),
+        scopes: row.get::<Vec<String>, _>("scopes").iter().filter_map(|s| ApiKeyType::parse(s)).collect(),
+        expires_at: row.get("expires_at"),
+        last_used_at: row.get("last_used_at"),
+        revoked_at: row.get("revoked_at"),
+        created_at: row.get("created_at"),
+    }
+}
+
+impl Store {
+    pub async fn add_api_key(&self, bank_id: &str, name: &str, secret: &KeySecret, scopes: &[ApiKeyType], expires_at: Option<DateTime<Utc>>, created_by: Uuid) -> Result<ApiKey, Error> {
+        let query = r#"
+            INSERT INTO api_keys (bank_id, name, prefix, secret_hash, scopes, expires_at, created_by)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING *
+        "#;
+        sqlx::query(query)
+            .bind(bank_id)
+            .bind(name)
+            .bind(&secret.prefix)
+            .bind(&secret.secret_hash)
+            .bind(scopes.iter().map(|s| s.as_str().to_string()).collect::<Vec<String>>())
+            .bind(expires_at)
+            .bind(created_by)
+            .map(api_key_from_row)
+            .fetch_one(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    pub async fn get_api_keys_bank_id(&self, bank_id: &str) -> Result<Vec<ApiKey>, Error> {
+        sqlx::query("SELECT * FROM api_keys WHERE bank_id = $1 ORDER BY created_at DESC")
+            .bind(bank_id)
+            .map(api_key_from_row)
+            .fetch_all(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    pub async fn get_api_key_bank_id(&self, bank_id: &str, id: Uuid) -> Result<ApiKey, Error> {
+        sqlx::query("SELECT * FROM api_keys WHERE bank_id = $1 AND id = $2")
+            .bind(bank_id)
+            .bind(id)
+            .map(api_key_from_row)
+            .fetch_one(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    // Key matching the hash, None when it is unknown, revoked or expired
+    pub async fn get_active_api_key(&self, secret_hash: &str) -> Result<Option<ApiKey>, Error> {
+        let query = r#"
+            SELECT * FROM api_keys
+            WHERE secret_hash = $1 AND revoked_at IS NULL AND (expires_at IS NULL OR expires_at > now())
+        "#;
+        sqlx::query(query)
+            .bind(secret_hash)
+            .map(api_key_from_row)
+            .fetch_optional(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    // Written at most once a minute per key so busy keys do not hammer the row
+    pub async fn touch_api_key(&self, id: Uuid) -> Result<bool, Error> {
+        let query = r#"
+            UPDATE api_keys SET last_used_at = now()
+            WHERE id = $1 AND (last_used_at IS NULL OR last_used_at < now() - interval '1 minute')
+        "#;
+        sqlx::query(query)
+            .bind(id)
+            .execute(&self.connection)
+            .await
+            .map(|_| true)
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    pub async fn revoke_api_key(&self, bank_id: &str, id: Uuid) -> Result<bool, Error> {
+        sqlx::query("UPDATE api_keys SET revoked_at = now() WHERE bank_id = $1 AND id = $2 AND revoked_at IS NULL")
+            .bind(bank_id)
+            .bind(id)
+            .execute(&self.connection)
+            .await
+            .map(|result| result.rows_affected() > 0)
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    // Revokes the old key and issues a replacement with the same name, scopes and expiry
+    pub async fn rotate_api_key(&self, bank_id: &str, id: Uuid, secret: &KeySecret, created_by: Uuid) -> Result<ApiKey, Error> {
+        let mut tx = self.connection.begin().await
+            .map_err(Error::DatabaseQueryError)?;
+        let revoked: Option<(String, Vec<String>, Option<DateTime<Utc>>)> = sqlx::query_as("UPDATE api_keys SET revoked_at = now() WHERE bank_id = $1 AND id = $2 AND revoked_at IS NULL RETURNING name, scopes, expires_at")
+            .bind(bank_id)
+            .bind(id)
+            .fetch_optional(&mut tx)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        let (name, scopes, expires_at) = revoked.ok_or(Error::ApiKeyRejection)?;
+        let query = r#"
+            INSERT INTO api_keys (bank_id, name, prefix, secret_hash, scopes, expires_at, created_by)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING *
+        "#;
+        let key = sqlx::query(query)
+            .bind(bank_id)
+            .bind(name)
+            .bind(&secret.prefix)
+            .bind(&secret.secret_hash)
+            .bind(scopes)
+            .bind(expires_at)
+            .bind(created_by)
+            .map(api_key_from_row)
+            .fetch_one(&mut tx)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        tx.commit().await
+            .map_err(Error::DatabaseQueryError)?;
+        Ok(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_key_starts_with_its_prefix() {
+        let (prefix, key) = generate_api_key();
+        assert!(prefix.starts_with("xm_"));
+        assert!(key.starts_with(&format!("{}.", prefix)));
+        assert_eq!(key.len(), 3 + PREFIX_LEN + 1 + SECRET_LEN);
+        assert_eq!(ApiKeyType::parse(ApiKeyType::XMinisterMetal.as_str()), Some(ApiKeyType::XMinisterMetal));
+    }
+}
@@ -0,0 +1,265 @@
+use std::{env, path::{Component, Path, PathBuf}};
+
+use chrono::{DateTime, Utc};
+use handle_error::Error;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{postgres::{PgExecutor, PgRow}, Row};
+use tokio::{fs::File, io::AsyncReadExt};
+use uuid::Uuid;
+
+use crate::{db_store::Store, tools::constant::RELEASE_STORAGE_DIR, types::device_health::version_at_least};
+
+const DEFAULT_RELEASE_STORAGE_DIR: &str = "releases";
+const CHECKSUM_CHUNK_BYTES: usize = 64 * 1024;
+
+// Beta terminals get stable builds too, whichever is newer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReleaseChannel {
+    #[default]
+    Stable,
+    Beta,
+}
+
+impl ReleaseChannel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReleaseChannel::Stable => "stable",
+            ReleaseChannel::Beta => "beta",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<ReleaseChannel> {
+        match value {
+            "stable" => Some(ReleaseChannel::Stable),
+            "beta" => Some(ReleaseChannel::Beta),
+            _ => None,
+        }
+    }
+
+    pub fn receives(&self, release: ReleaseChannel) -> bool {
+        *self == ReleaseChannel::Beta || release == ReleaseChannel::Stable
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AppRelease {
+    pub id: Uuid,
+    pub version: String,
+    pub channel: ReleaseChannel,
+    // Terminals below this are refused payments until they update
+    pub min_supported_version: String,
+    pub file_path: String,
+    pub checksum: String,
+    pub size_bytes: i64,
+    pub notes: Option<String>,
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+fn app_release_from_row(row: PgRow) -> AppRelease {
+    AppRelease {
+        id: row.get("id"),
+        version: row.get("version"),
+        channel: ReleaseChannel::parse(row.get("channel")).unwrap_or_default(),
+        min_supported_version: row.get("min_supported_version"),
+        file_path: row.get("file_path"),
+        checksum: row.get("checksum"),
+        size_bytes: row.get("size_bytes"),
+        notes: row.get("notes"),
+        created_by: row.get("created_by"),
+        created_at: row.get("created_at"),
+    }
+}
+
+// Newest build the channel receives, versions compare numerically so they are ranked here rather than in SQL
+pub fn latest_release(releases: &[AppRelease], channel: ReleaseChannel) -> Option<&AppRelease> {
+    releases.iter()
+        .filter(|release| channel.receives(release.channel))
+        .fold(None, |latest: Option<&AppRelease>, release| match latest {
+            Some(latest) if version_at_least(&latest.version, &release.version) => Some(latest),
+            _ => Some(release),
+        })
+}
+
+// Resolves `file_path` inside the storage directory, anything that could climb out of it is refused
+pub fn release_file(file_path: &str) -> Option<PathBuf> {
+    let relative = Path::new(file_path);
+    if file_path.is_empty() || !relative.components().all(|component| matches!(component, Component::Normal(_))) {
+        return None;
+    }
+    let root = env::var(RELEASE_STORAGE_DIR).unwrap_or(DEFAULT_RELEASE_STORAGE_DIR.to_string());
+    Some(Path::new(&root).join(relative))
+}
+
+// SHA-256 and size of a stored build, read in chunks so a large APK is never held in memory
+pub async fn release_checksum(path: &Path) -> std::io::Result<(String, u64)> {
+    let mut file = File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; CHECKSUM_CHUNK_BYTES];
+    let mut size = 0u64;
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        size += read as u64;
+    }
+    Ok((hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect::<String>(), size))
+}
+
+// Where one terminal stands against the builds of its channel
+#[derive(Debug, Clone)]
+pub struct ReleaseState {
+    pub channel: ReleaseChannel,
+    pub latest: Option<AppRelease>,
+    pub reported_version: Option<String>,
+}
+
+impl ReleaseState {
+    // Once a minimum exists, a terminal whose version is unknown is held back like an outdated one
+    pub fn upgrade_required(&self, version: Option<&str>) -> bool {
+        match (&self.latest, version) {
+            (Some(latest), Some(version)) => !version_at_least(version, &latest.min_supported_version),
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+
+    pub fn update_available(&self, version: Option<&str>) -> bool {
+        match (&self.latest, version) {
+            (Some(latest), Some(version)) => !version_at_least(version, &latest.version),
+            (Some(_), None) => true,
+            _ => false,
+        }
+    }
+}
+
+pub(crate) async fn add_app_release_in<'c>(executor: impl PgExecutor<'c>, release: &AppRelease) -> Result<bool, Error> {
+    let query = r#"
+        INSERT INTO app_releases (id, version, channel, min_supported_version, file_path, checksum, size_bytes, notes, created_by, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+    "#;
+    sqlx::query(query)
+        .bind(release.id)
+        .bind(&release.version)
+        .bind(release.channel.as_str())
+        .bind(&release.min_supported_version)
+        .bind(&release.file_path)
+        .bind(&release.checksum)
+        .bind(release.size_bytes)
+        .bind(&release.notes)
+        .bind(release.created_by)
+        .bind(release.created_at)
+        .execute(executor)
+        .await
+        .map(|_| true)
+        .map_err(Error::DatabaseQueryError)
+}
+
+pub(crate) async fn delete_app_release_in<'c>(executor: impl PgExecutor<'c>, id: Uuid) -> Result<bool, Error> {
+    sqlx::query("DELETE FROM app_releases WHERE id = $1")
+        .bind(id)
+        .execute(executor)
+        .await
+        .map(|result| result.rows_affected() > 0)
+        .map_err(Error::DatabaseQueryError)
+}
+
+impl Store {
+    // The channel comes from the terminal's config profiles, the version from its last heartbeat
+    pub async fn get_release_state(&self, device_id: Uuid) -> Result<ReleaseState, Error> {
+        let channel = self.get_config_sources(device_id).await?.effective().config.release_channel;
+        let releases = self.get_app_releases().await?;
+        let latest = latest_release(&releases, channel).cloned();
+        let reported_version = self.get_reported_app_version(device_id).await?;
+        Ok(ReleaseState { channel, latest, reported_version })
+    }
+
+    pub async fn get_app_release(&self, id: Uuid) -> Result<AppRelease, Error> {
+        sqlx::query("SELECT * FROM app_releases WHERE id = $1")
+            .bind(id)
+            .map(app_release_from_row)
+            .fetch_one(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    pub async fn get_app_releases(&self) -> Result<Vec<AppRelease>, Error> {
+        sqlx::query("SELECT * FROM app_releases ORDER BY created_at DESC")
+            .map(app_release_from_row)
+            .fetch_all(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    // App version from the terminal's last heartbeat
+    pub async fn get_reported_app_version(&self, device_id: Uuid) -> Result<Option<String>, Error> {
+        sqlx::query("SELECT app_version FROM device_health WHERE device_id = $1")
+            .bind(device_id)
+            .map(|row: PgRow| row.get("app_version"))
+            .fetch_optional(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn release(version: &str, channel: ReleaseChannel) -> AppRelease {
+        AppRelease {
+            id: Uuid::new_v4(),
+            version: version.to_string(),
+            channel,
+            min_supported_version: "1.0".to_string(),
+            file_path: format!("terminal-{}.apk", version),
+            checksum: "0".repeat(64),
+            size_bytes: 1,
+            notes: None,
+            created_by: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn beta_terminals_see_the_newest_build() {
+        let releases = vec![release("1.9.0", ReleaseChannel::Stable), release("1.10.0", ReleaseChannel::Stable), release("2.0.0-rc1", ReleaseChannel::Beta)];
+        assert_eq!(latest_release(&releases, ReleaseChannel::Stable).map(|r| r.version.as_str()), Some("1.10.0"));
+        assert_eq!(latest_release(&releases, ReleaseChannel::Beta).map(|r| r.version.as_str()), Some("2.0.0-rc1"));
+    }
+
+    #[test]
+    fn terminals_below_the_minimum_must_upgrade() {
+        let mut latest = release("1.4.0", ReleaseChannel::Stable);
+        latest.min_supported_version = "1.2".to_string();
+        let state = ReleaseState { channel: ReleaseChannel::Stable, latest: Some(latest), reported_version: None };
+        assert!(state.upgrade_required(Some("1.1.9")));
+        assert!(!state.upgrade_required(Some("1.2.0")));
+        assert!(state.update_available(Some("1.2.0")));
+        assert!(!state.update_available(Some("1.4")));
+        assert!(state.upgrade_required(None));
+        assert!(!ReleaseState { channel: ReleaseChannel::Stable, latest: None, reported_version: None }.upgrade_required(None));
+    }
+
+    #[tokio::test]
+    async fn checksum_is_read_in_chunks() {
+        let path = env::temp_dir().join(format!("release-{}.apk", Uuid::new_v4()));
+        let bytes = vec![7u8; CHECKSUM_CHUNK_BYTES * 2 + 5];
+        tokio::fs::write(&path, &bytes).await.unwrap();
+        let (checksum, size) = release_checksum(&path).await.unwrap();
+        tokio::fs::remove_file(&path).await.unwrap();
+        let expected = Sha256::digest(&bytes).iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        assert_eq!((checksum, size), (expected, bytes.len() as u64));
+    }
+
+    #[test]
+    fn release_files_stay_in_storage() {
+        assert!(release_file("stable/terminal-1.2.apk").is_some());
+        assert!(release_file("../secrets.env").is_none());
+        assert!(release_file("/etc/passwd").is_none());
+    }
+}
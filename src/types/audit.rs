@@ -0,0 +1,248 @@
+use chrono::{DateTime, Utc};
+use handle_error::Error;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{postgres::{PgExecutor, PgRow}, Row};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::{db_store::Store, types::admin::{Page, PageQuery}};
+
+pub const USER_LOGIN: &str = "user.login";
+pub const USER_TOKEN_REFRESH: &str = "user.token_refresh";
+pub const USER_LOGOUT: &str = "user.logout";
+pub const USER_PROFILE_UPDATE: &str = "user.profile_update";
+pub const API_KEY_USE: &str = "api_key.use";
+pub const CUSTOMER_CREATE: &str = "customer.create";
+pub const PAYMENT_CREATE: &str = "payment.create";
+pub const DEVICE_CREATE: &str = "device.create";
+pub const DEVICE_UPDATE: &str = "device.update";
+pub const DEVICE_DELETE: &str = "device.delete";
+pub const DEVICE_STATUS_CHANGE: &str = "device.status_change";
+pub const DEVICE_ACTIVATE: &str = "device.activate";
+pub const DEVICE_KEY_ROTATE: &str = "device.key_rotate";
+pub const DEVICE_KEY_REVOKE: &str = "device.key_revoke";
+pub const DEVICE_ACCOUNT_CHANGE: &str = "device.account_change";
+pub const CONFIG_PROFILE_CREATE: &str = "config_profile.create";
+pub const CONFIG_PROFILE_UPDATE: &str = "config_profile.update";
+pub const CONFIG_PROFILE_ATTACH: &str = "config_profile.attach";
+pub const BUSINESS_GEOFENCE_UPDATE: &str = "business.geofence_update";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Success,
+    Failure,
+}
+
+impl AuditOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditOutcome::Success => "success",
+            AuditOutcome::Failure => "failure",
+        }
+    }
+}
+
+// Who acted, stored as `actor_type` plus `actor_id`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditActor {
+    Anonymous,
+    User(Uuid),
+    ApiKey(Uuid),
+    Device(Uuid),
+}
+
+impl AuditActor {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AuditActor::Anonymous => "anonymous",
+            AuditActor::User(_) => "user",
+            AuditActor::ApiKey(_) => "api_key",
+            AuditActor::Device(_) => "device",
+        }
+    }
+    pub fn id(&self) -> Option<Uuid> {
+        match self {
+            AuditActor::Anonymous => None,
+            AuditActor::User(id) | AuditActor::ApiKey(id) | AuditActor::Device(id) => Some(*id),
+        }
+    }
+}
+
+// Set on every request by `request_context`, so events can be tied back to access logs
+#[derive(Debug, Clone)]
+pub struct RequestContext {
+    pub request_id: String,
+    pub ip: String,
+    pub user_agent: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct AuditEvent {
+    pub event_type: &'static str,
+    pub outcome: AuditOutcome,
+    pub actor: AuditActor,
+    pub entity: Option<(&'static str, String)>,
+    pub detail: serde_json::Value,
+}
+
+impl AuditEvent {
+    pub fn success(event_type: &'static str, actor: AuditActor) -> Self {
+        Self { event_type, outcome: AuditOutcome::Success, actor, entity: None, detail: json!({}) }
+    }
+    pub fn failure(event_type: &'static str, actor: AuditActor) -> Self {
+        Self { event_type, outcome: AuditOutcome::Failure, actor, entity: None, detail: json!({}) }
+    }
+    pub fn entity(mut self, entity: &'static str, entity_id: impl ToString) -> Self {
+        self.entity = Some((entity, entity_id.to_string()));
+        self
+    }
+    pub fn detail(mut self, detail: serde_json::Value) -> Self {
+        self.detail = detail;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AuditEventRecord {
+    pub id: Uuid,
+    pub event_type: String,
+    pub outcome: String,
+    pub actor_type: String,
+    pub actor_id: Option<String>,
+    pub entity: Option<String>,
+    pub entity_id: Option<String>,
+    pub ip: String,
+    pub user_agent: String,
+    pub request_id: String,
+    pub detail: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+}
+
+// Filters of `GET /admin/audit-events`, every one optional
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AuditEventQuery {
+    pub event_type: Option<String>,
+    pub outcome: Option<AuditOutcome>,
+    pub actor_id: Option<String>,
+    pub entity_id: Option<String>,
+    pub ip: Option<String>,
+    pub request_id: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+}
+
+impl AuditEventQuery {
+    fn page_query(&self) -> PageQuery {
+        PageQuery { q: None, page: self.page, per_page: self.per_page }
+    }
+}
+
+// Audit writes never fail the request they describe, a lost event is logged instead
+pub async fn record_audit_event(store: &Store, context: &RequestContext, event: AuditEvent) {
+    if let Err(e) = store.add_audit_event(context, &event).await {
+        warn!("audit event {} for request {} was not recorded: {}", event.event_type, context.request_id, e);
+    }
+}
+
+// Also takes a transaction, so an event can commit together with the change it describes
+pub(crate) async fn add_audit_event_in<'c>(executor: impl PgExecutor<'c>, context: &RequestContext, event: &AuditEvent) -> Result<bool, Error> {
+    let query = r#"
+        INSERT INTO audit_events (event_type, outcome, actor_type, actor_id, entity, entity_id, ip, user_agent, request_id, detail)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+    "#;
+    sqlx::query(query)
+        .bind(event.event_type)
+        .bind(event.outcome.as_str())
+        .bind(event.actor.kind())
+        .bind(event.actor.id().map(|id| id.to_string()))
+        .bind(event.entity.as_ref().map(|(entity, _)| *entity))
+        .bind(event.entity.as_ref().map(|(_, entity_id)| entity_id.as_str()))
+        .bind(&context.ip)
+        .bind(&context.user_agent)
+        .bind(&context.request_id)
+        .bind(&event.detail)
+        .execute(executor)
+        .await
+        .map(|_| true)
+        .map_err(Error::DatabaseQueryError)
+}
+
+impl Store {
+    pub async fn add_audit_event(&self, context: &RequestContext, event: &AuditEvent) -> Result<bool, Error> {
+        add_audit_event_in(&self.connection, context, event).await
+    }
+
+    pub async fn search_audit_events(&self, filter: &AuditEventQuery) -> Result<Page<AuditEventRecord>, Error> {
+        let page = filter.page_query();
+        let conditions = r#"
+            ($1::varchar IS NULL OR event_type = $1)
+            AND ($2::varchar IS NULL OR outcome = $2)
+            AND ($3::varchar IS NULL OR actor_id = $3)
+            AND ($4::varchar IS NULL OR entity_id = $4)
+            AND ($5::varchar IS NULL OR ip = $5)
+            AND ($6::varchar IS NULL OR request_id = $6)
+            AND ($7::timestamptz IS NULL OR created_at >= $7)
+            AND ($8::timestamptz IS NULL OR created_at < $8)
+        "#;
+        let (total,): (i64,) = sqlx::query_as(&format!("SELECT COUNT(*) FROM audit_events WHERE {}", conditions))
+            .bind(&filter.event_type)
+            .bind(filter.outcome.map(|outcome| outcome.as_str()))
+            .bind(&filter.actor_id)
+            .bind(&filter.entity_id)
+            .bind(&filter.ip)
+            .bind(&filter.request_id)
+            .bind(filter.from)
+            .bind(filter.to)
+            .fetch_one(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        let list = sqlx::query(&format!("SELECT * FROM audit_events WHERE {} ORDER BY created_at DESC LIMIT $9 OFFSET $10", conditions))
+            .bind(&filter.event_type)
+            .bind(filter.outcome.map(|outcome| outcome.as_str()))
+            .bind(&filter.actor_id)
+            .bind(&filter.entity_id)
+            .bind(&filter.ip)
+            .bind(&filter.request_id)
+            .bind(filter.from)
+            .bind(filter.to)
+            .bind(page.per_page())
+            .bind(page.offset())
+            .map(|row: PgRow| AuditEventRecord {
+                id: row.get("id"),
+                event_type: row.get("event_type"),
+                outcome: row.get("outcome"),
+                actor_type: row.get("actor_type"),
+                actor_id: row.get("actor_id"),
+                entity: row.get("entity"),
+                entity_id: row.get("entity_id"),
+                ip: row.get("ip"),
+                user_agent: row.get("user_agent"),
+                request_id: row.get("request_id"),
+                detail: row.get("detail"),
+                created_at: row.get("created_at"),
+            })
+            .fetch_all(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        Ok(page.to_page(list, total))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn actor_splits_into_type_and_id() {
+        let id = Uuid::new_v4();
+        assert_eq!((AuditActor::ApiKey(id).kind(), AuditActor::ApiKey(id).id()), ("api_key", Some(id)));
+        assert_eq!((AuditActor::Anonymous.kind(), AuditActor::Anonymous.id()), ("anonymous", None));
+        let event = AuditEvent::failure(USER_LOGIN, AuditActor::User(id)).entity("user", id);
+        assert_eq!(event.outcome.as_str(), "failure");
+        assert_eq!(event.entity, Some(("user", id.to_string())));
+    }
+}
@@ -1,34 +1,60 @@
 use chrono::{DateTime, Utc};
 use handle_error::Error;
-use sqlx::{postgres::PgRow, Row};
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::{PgExecutor, PgRow}, Row};
 use uuid::Uuid;
 
 use crate::db_store::Store;
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Bank {
     pub id: String,
     pub user_id: Uuid,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
-    pub apk_key: String, // new field
+}
+
+pub(crate) async fn add_bank_in<'c>(executor: impl PgExecutor<'c>, bank: &Bank) -> Result<bool, Error> {
+    let query = r#"
+        INSERT INTO banks (id, user_id, created_at, updated_at)
+        VALUES ($1, $2, $3, $4)
+    "#;
+    sqlx::query(query)
+        .bind(&bank.id)
+        .bind(bank.user_id)
+        .bind(bank.created_at)
+        .bind(bank.updated_at)
+        .execute(executor)
+        .await
+        .map(|_| true)
+        .map_err(Error::DatabaseQueryError)
+}
+
+pub(crate) async fn update_bank_in<'c>(executor: impl PgExecutor<'c>, bank: &Bank) -> Result<bool, Error> {
+    let query = r#"
+        UPDATE banks
+        SET user_id = $2, updated_at = $3
+        WHERE id = $1
+    "#;
+    sqlx::query(query)
+        .bind(&bank.id)
+        .bind(bank.user_id)
+        .bind(bank.updated_at)
+        .execute(executor)
+        .await
+        .map(|_| true)
+        .map_err(Error::DatabaseQueryError)
+}
+
+pub(crate) async fn delete_bank_in<'c>(executor: impl PgExecutor<'c>, id: &str) -> Result<bool, Error> {
+    sqlx::query("DELETE FROM banks WHERE id = $1")
+        .bind(id)
+        .execute(executor)
+        .await
+        .map(|_| true)
+        .map_err(Error::DatabaseQueryError)
 }
 
 impl Store {
-    pub async fn add_bank(&self, bank: &Bank) -> Result<bool, Error> {
-        let query = r#"
-            INSERT INTO banks (id, user_id, created_at, updated_at, apk_key)
-            VALUES ($1, $2, $3, $4, $5)
-        "#;
-        sqlx::query(query)
-            .bind(&bank.id)
-            .bind(bank.user_id)
-            .bind(bank.created_at)
-            .bind(bank.updated_at)
-            .bind(&bank.apk_key)
-            .execute(&self.connection)
-            .await
-            .map(|_| true)
-            .map_err(|e| Error::DatabaseQueryError(e))
-    }
     pub async fn get_bank_user_id(&self, user_id: &Uuid) -> Result<Bank, Error> {
         sqlx::query("SELECT * FROM banks WHERE user_id = $1")
             .bind(user_id)
@@ -37,7 +63,6 @@ impl Store {
                 user_id: row.get("user_id"),
                 created_at: row.get("created_at"),
                 updated_at: row.get("updated_at"),
-                apk_key: row.get("apk_key"), // fetch new field
             })
             .fetch_one(&self.connection)
             .await
@@ -51,7 +76,6 @@ impl Store {
                 user_id: row.get("user_id"),
                 created_at: row.get("created_at"),
                 updated_at: row.get("updated_at"),
-                apk_key: row.get("apk_key"), // fetch new field
             })
             .fetch_one(&self.connection)
             .await
@@ -65,35 +89,10 @@ impl Store {
                     user_id: row.get("user_id"),
                     created_at: row.get("created_at"),
                     updated_at: row.get("updated_at"),
-                    apk_key: row.get("apk_key"),
                 })
                 .fetch_all(&self.connection)
                 .await
                 .map_err(|e| Error::DatabaseQueryError(e))
         }
-    pub async fn update_bank(&self, bank: &Bank) -> Result<bool, Error> {
-        let query = r#"
-            UPDATE banks
-            SET user_id = $2, updated_at = $3, apk_key = $4
-            WHERE id = $1
-        "#;
-        sqlx::query(query)
-            .bind(&bank.id)
-            .bind(bank.user_id)
-            .bind(bank.updated_at)
-            .bind(&bank.apk_key)
-            .execute(&self.connection)
-            .await
-            .map(|_| true)
-            .map_err(|e| Error::DatabaseQueryError(e))
-    }
 
-    pub async fn delete_bank(&self, id: &str) -> Result<bool, Error> {
-        sqlx::query("DELETE FROM banks WHERE id = $1")
-            .bind(id)
-            .execute(&self.connection)
-            .await
-            .map(|_| true)
-            .map_err(|e| Error::DatabaseQueryError(e))
-    }
 }
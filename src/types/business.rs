@@ -1,15 +1,16 @@
 use chrono::{DateTime, Utc};
 use handle_error::Error;
 use serde::{Deserialize, Serialize};
-use sqlx::{postgres::PgRow, Row};
+use sqlx::{postgres::{PgExecutor, PgRow}, Row};
 use uuid::Uuid;
 
 use crate::db_store::Store;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Business {
     pub id: Uuid,
     pub user_id: Uuid,
+    pub organization_id: Uuid,
     pub name: String,
     pub location: String,
     pub geolocation: (f64, f64), // Postgres POINT → tuple (x=lng, y=lat)
@@ -25,49 +26,60 @@ pub struct GeoPoint {
     pub longitude: f64,
 }
 
-impl Store {
-    pub async fn add_business(&self, business: &Business) -> Result<bool, Error> {
-        let query = r#"
-            INSERT INTO businesses (id, user_id, name, location, geolocation, lat, lon, created_at, updated_at)
-            VALUES ($1, $2, $3, $4, point($5, $6), $7, $8)
-        "#;
-        sqlx::query(query)
-            .bind(business.id)
-            .bind(business.user_id)
-            .bind(&business.name)
-            .bind(&business.location)
-            .bind(business.geolocation.0)
-            .bind(business.geolocation.1)
-            .bind(business.lat)
-            .bind(business.long)
-            .bind(business.created_at)
-            .bind(business.updated_at)
-            .execute(&self.connection)
-            .await
-            .map(|_| true)
-            .map_err(|e| Error::DatabaseQueryError(e))
-    }
-    pub async fn get_businesses_user_id(&self, user_id: Uuid) -> Result<Vec<Business>, Error> {
-        sqlx::query("SELECT * FROM businesses WHERE user_id = $1")
-            .bind(user_id)
-            .map(|row: PgRow| {
-                Business {
-                    id: row.get("id"),
-                    user_id: row.get("user_id"),
-                    name: row.get("name"),
-                    location: row.get("location"),
-                    geolocation: (0.0, 0.0),
-                    lat: row.get("lat"),
-                    long: row.get("lon"),
-                    created_at: row.get("created_at"),
-                    updated_at: row.get("updated_at"),
-                }
-            })
-            .fetch_all(&self.connection)
-            .await
-            .map_err(|e| Error::DatabaseQueryError(e))
-    }
+pub(crate) async fn add_business_in<'c>(executor: impl PgExecutor<'c>, business: &Business) -> Result<bool, Error> {
+    let query = r#"
+        INSERT INTO businesses (id, user_id, name, location, geolocation, lat, lon, created_at, updated_at, organization_id)
+        VALUES ($1, $2, $3, $4, point($5, $6), $7, $8, $9, $10, $11)
+    "#;
+    sqlx::query(query)
+        .bind(business.id)
+        .bind(business.user_id)
+        .bind(&business.name)
+        .bind(&business.location)
+        .bind(business.geolocation.0)
+        .bind(business.geolocation.1)
+        .bind(business.lat)
+        .bind(business.long)
+        .bind(business.created_at)
+        .bind(business.updated_at)
+        .bind(business.organization_id)
+        .execute(executor)
+        .await
+        .map(|_| true)
+        .map_err(Error::DatabaseQueryError)
+}
+
+pub(crate) async fn update_business_in<'c>(executor: impl PgExecutor<'c>, business: &Business) -> Result<bool, Error> {
+    let query = r#"
+        UPDATE businesses
+        SET name = $2, location = $3, geolocation = point($4, $5), updated_at = $6, lat = $7, lon = $8
+        WHERE id = $1
+    "#;
+    sqlx::query(query)
+        .bind(business.id)
+        .bind(&business.name)
+        .bind(&business.location)
+        .bind(business.geolocation.0)
+        .bind(business.geolocation.1)
+        .bind(business.updated_at)
+        .bind(business.lat)
+        .bind(business.long)
+        .execute(executor)
+        .await
+        .map(|_| true)
+        .map_err(Error::DatabaseQueryError)
+}
+
+pub(crate) async fn delete_business_in<'c>(executor: impl PgExecutor<'c>, id: Uuid) -> Result<bool, Error> {
+    sqlx::query("DELETE FROM businesses WHERE id = $1")
+        .bind(id)
+        .execute(executor)
+        .await
+        .map(|_| true)
+        .map_err(Error::DatabaseQueryError)
+}
 
+impl Store {
     pub async fn get_business(&self, id: Uuid) -> Result<Business, Error> {
         sqlx::query("SELECT * FROM businesses WHERE id = $1")
             .bind(id)
@@ -75,6 +87,7 @@ impl Store {
                 Business {
                     id: row.get("id"),
                     user_id: row.get("user_id"),
+                    organization_id: row.get("organization_id"),
                     name: row.get("name"),
                     location: row.get("location"),
                     geolocation: (0.0, 0.0),
@@ -88,33 +101,4 @@ impl Store {
             .await
             .map_err(|e| Error::DatabaseQueryError(e))
 }
-
-
-    pub async fn update_business(&self, business: &Business) -> Result<bool, Error> {
-        let query = r#"
-            UPDATE businesses
-            SET name = $2, location = $3, geolocation = point($4, $5), updated_at = $6
-            WHERE id = $1
-        "#;
-        sqlx::query(query)
-            .bind(business.id)
-            .bind(&business.name)
-            .bind(&business.location)
-            .bind(business.geolocation.0)
-            .bind(business.geolocation.1)
-            .bind(business.updated_at)
-            .execute(&self.connection)
-            .await
-            .map(|_| true)
-            .map_err(|e| Error::DatabaseQueryError(e))
-    }
-
-    pub async fn delete_business(&self, id: Uuid) -> Result<bool, Error> {
-        sqlx::query("DELETE FROM businesses WHERE id = $1")
-            .bind(id)
-            .execute(&self.connection)
-            .await
-            .map(|_| true)
-            .map_err(|e| Error::DatabaseQueryError(e))
-    }
 }
\ No newline at end of file
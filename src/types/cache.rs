@@ -2,7 +2,7 @@ use std::sync::RwLock;
 
 use uuid::Uuid;
 
-use crate::{db_store::Store, types::device::Device};
+use crate::{db_store::Store, tools::transport::{transport_from_env, MessageTransport}, types::{device::Device, token::{KeyPurpose, TokenService}}};
 
 use serde::{Deserialize, Serialize};
 use std::fs;
@@ -52,7 +52,6 @@ pub struct DeviceStream {
 pub struct BankStream {
     pub id: String,
     pub user_id: Uuid,
-    pub apk_key: String, // new field
 }
 pub struct CustomerStream {
     pub public_key: String,
@@ -74,6 +73,9 @@ pub struct Cache {
     pub accounts: RwLock<Vec<AccountStream>>,
     pub banks: RwLock<Vec<BankStream>>,
     pub bank_files: RwLock<Vec<BankFileStream>>,
+    pub tokens: TokenService,
+    pub stickers: TokenService,
+    pub transport: Box<dyn MessageTransport>,
 }
 
 
@@ -133,10 +135,25 @@ impl Cache {
                 |f| BankStream {
                     id: f.id.clone(),
                     user_id: f.user_id.clone(),
-                    apk_key: f.apk_key.clone()
                 }
             ).collect()),
             bank_files: RwLock::new(bank_files),
+            tokens: TokenService::load(store, KeyPurpose::Session).await.expect("Cannot load signing keys"),
+            stickers: TokenService::load(store, KeyPurpose::Sticker).await.expect("Cannot load sticker signing keys"),
+            transport: transport_from_env(),
         }
     }    
+
+    // Called after banks are written outside of startup, e.g. from the admin API
+    pub async fn reload_banks(&self, store: &Store) {
+        let banks = store.get_banks().await.unwrap_or_else(|_| vec![]);
+        if let Ok(mut cached) = self.banks.write() {
+            *cached = banks.iter().map(
+                |f| BankStream {
+                    id: f.id.clone(),
+                    user_id: f.user_id,
+                }
+            ).collect();
+        }
+    }
 }
\ No newline at end of file
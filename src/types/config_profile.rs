@@ -0,0 +1,384 @@
+use std::ops::RangeInclusive;
+
+use chrono::{DateTime, Utc};
+use encrypt::hash_secret;
+use handle_error::Error;
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgRow, Row};
+use uuid::Uuid;
+
+use crate::{db_store::Store, types::app_release::ReleaseChannel};
+
+const DEFAULT_CURRENCY: &str = "NGN";
+const DEFAULT_PAYMENT_TIMEOUT_SECONDS: u32 = 60;
+const DEFAULT_IDLE_TIMEOUT_SECONDS: u32 = 300;
+const TIMEOUT_SECONDS: RangeInclusive<u32> = 5..=3600;
+const MAX_TIP_OPTIONS: usize = 5;
+const MAX_RECEIPT_TEXT_LEN: usize = 512;
+
+// Any setting left out falls through to the business profile, then to the built-in default
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct TerminalSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    // Percentages offered on the tip prompt, an empty list turns the prompt off
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tip_percentages: Option<Vec<u8>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_amount: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payment_timeout_seconds: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idle_timeout_seconds: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub receipt_header: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub receipt_footer: Option<String>,
+    // Beta terminals are offered beta builds of the terminal app
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub release_channel: Option<ReleaseChannel>,
+}
+
+impl TerminalSettings {
+    pub fn validate(&self) -> Result<(), Error> {
+        let currency_ok = self.currency.as_ref().is_none_or(|currency| currency.len() == 3 && currency.chars().all(|c| c.is_ascii_uppercase()));
+        let tips_ok = self.tip_percentages.as_ref().is_none_or(|tips| tips.len() <= MAX_TIP_OPTIONS && tips.iter().all(|tip| (1..=100).contains(tip)));
+        let timeouts_ok = [self.payment_timeout_seconds, self.idle_timeout_seconds].iter().all(|timeout| timeout.is_none_or(|seconds| TIMEOUT_SECONDS.contains(&seconds)));
+        let receipt_ok = [&self.receipt_header, &self.receipt_footer].iter().all(|text| text.as_ref().is_none_or(|text| text.chars().count() <= MAX_RECEIPT_TEXT_LEN));
+        if !currency_ok || !tips_ok || !timeouts_ok || !receipt_ok || self.max_amount.is_some_and(|amount| amount <= 0) {
+            return Err(Error::MissingParameters);
+        }
+        Ok(())
+    }
+
+    // Settings made here win, the rest comes from `base`
+    pub fn or(self, base: TerminalSettings) -> TerminalSettings {
+        TerminalSettings {
+            currency: self.currency.or(base.currency),
+            tip_percentages: self.tip_percentages.or(base.tip_percentages),
+            max_amount: self.max_amount.or(base.max_amount),
+            payment_timeout_seconds: self.payment_timeout_seconds.or(base.payment_timeout_seconds),
+            idle_timeout_seconds: self.idle_timeout_seconds.or(base.idle_timeout_seconds),
+            receipt_header: self.receipt_header.or(base.receipt_header),
+            receipt_footer: self.receipt_footer.or(base.receipt_footer),
+            release_channel: self.release_channel.or(base.release_channel),
+        }
+    }
+}
+
+// What a terminal runs with once every profile and default is applied
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct TerminalConfig {
+    pub currency: String,
+    pub tip_percentages: Vec<u8>,
+    // No cap unless a profile sets one
+    pub max_amount: Option<i64>,
+    pub payment_timeout_seconds: u32,
+    pub idle_timeout_seconds: u32,
+    pub receipt_header: String,
+    pub receipt_footer: String,
+    pub release_channel: ReleaseChannel,
+}
+
+impl From<TerminalSettings> for TerminalConfig {
+    fn from(settings: TerminalSettings) -> Self {
+        TerminalConfig {
+            currency: settings.currency.unwrap_or(DEFAULT_CURRENCY.to_string()),
+            tip_percentages: settings.tip_percentages.unwrap_or_default(),
+            max_amount: settings.max_amount,
+            payment_timeout_seconds: settings.payment_timeout_seconds.unwrap_or(DEFAULT_PAYMENT_TIMEOUT_SECONDS),
+            idle_timeout_seconds: settings.idle_timeout_seconds.unwrap_or(DEFAULT_IDLE_TIMEOUT_SECONDS),
+            receipt_header: settings.receipt_header.unwrap_or_default(),
+            receipt_footer: settings.receipt_footer.unwrap_or_default(),
+            release_channel: settings.release_channel.unwrap_or_default(),
+        }
+    }
+}
+
+// `version` and `settings` are those of the newest version
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ConfigProfile {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub name: String,
+    pub version: i32,
+    pub settings: TerminalSettings,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ConfigProfileVersion {
+    pub version: i32,
+    pub settings: TerminalSettings,
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct ProfileRef {
+    pub id: Uuid,
+    pub version: i32,
+}
+
+// `version` is the content hash terminals send back as ETag and in their acknowledgement
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct EffectiveConfig {
+    pub version: String,
+    pub business_profile: Option<ProfileRef>,
+    pub device_profile: Option<ProfileRef>,
+    pub config: TerminalConfig,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AppliedConfig {
+    pub version: String,
+    pub applied_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ConfigTarget {
+    Business(Uuid),
+    Device(Uuid),
+}
+
+// Profiles attached to a terminal's business and to the terminal itself
+#[derive(Debug, Clone, Default)]
+pub struct ConfigSources {
+    pub business: Option<ConfigProfile>,
+    pub device: Option<ConfigProfile>,
+}
+
+impl ConfigSources {
+    pub fn effective(&self) -> EffectiveConfig {
+        let settings = |profile: &Option<ConfigProfile>| profile.as_ref().map(|profile| profile.settings.clone()).unwrap_or_default();
+        let config = TerminalConfig::from(settings(&self.device).or(settings(&self.business)));
+        let reference = |profile: &Option<ConfigProfile>| profile.as_ref().map(|profile| ProfileRef { id: profile.id, version: profile.version });
+        EffectiveConfig {
+            version: hash_secret(&serde_json::to_string(&config).unwrap_or_default()),
+            business_profile: reference(&self.business),
+            device_profile: reference(&self.device),
+            config,
+        }
+    }
+}
+
+// Each profile joined with its newest version
+const PROFILE_QUERY: &str = r#"
+    SELECT p.id, p.organization_id, p.name, p.created_at, p.updated_at, v.version, v.settings
+    FROM config_profiles p
+    JOIN LATERAL (
+        SELECT version, settings FROM config_profile_versions WHERE profile_id = p.id ORDER BY version DESC LIMIT 1
+    ) v ON true
+"#;
+
+fn settings_from_value(value: serde_json::Value) -> TerminalSettings {
+    serde_json::from_value(value).unwrap_or_default()
+}
+
+fn config_profile_from_row(row: PgRow) -> ConfigProfile {
+    ConfigProfile {
+        id: row.get("id"),
+        organization_id: row.get("organization_id"),
+        name: row.get("name"),
+        version: row.get("version"),
+        settings: settings_from_value(row.get("settings")),
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+    }
+}
+
+impl Store {
+    pub async fn add_config_profile(&self, organization_id: Uuid, name: &str, settings: &TerminalSettings, created_by: Uuid) -> Result<ConfigProfile, Error> {
+        let mut tx = self.connection.begin().await
+            .map_err(Error::DatabaseQueryError)?;
+        let (id,): (Uuid,) = sqlx::query_as("INSERT INTO config_profiles (organization_id, name) VALUES ($1, $2) RETURNING id")
+            .bind(organization_id)
+            .bind(name)
+            .fetch_one(&mut tx)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        sqlx::query("INSERT INTO config_profile_versions (profile_id, version, settings, created_by) VALUES ($1, 1, $2, $3)")
+            .bind(id)
+            .bind(serde_json::json!(settings))
+            .bind(created_by)
+            .execute(&mut tx)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        tx.commit().await
+            .map_err(Error::DatabaseQueryError)?;
+        self.get_config_profile(id).await
+    }
+
+    // The row lock keeps two edits of one profile from picking the same version
+    pub async fn add_config_profile_version(&self, profile_id: Uuid, settings: &TerminalSettings, created_by: Uuid) -> Result<ConfigProfile, Error> {
+        let mut tx = self.connection.begin().await
+            .map_err(Error::DatabaseQueryError)?;
+        sqlx::query("UPDATE config_profiles SET updated_at = now() WHERE id = $1 RETURNING id")
+            .bind(profile_id)
+            .fetch_one(&mut tx)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        let query = r#"
+            INSERT INTO config_profile_versions (profile_id, version, settings, created_by)
+            SELECT $1, COALESCE(MAX(version), 0) + 1, $2, $3 FROM config_profile_versions WHERE profile_id = $1
+        "#;
+        sqlx::query(query)
+            .bind(profile_id)
+            .bind(serde_json::json!(settings))
+            .bind(created_by)
+            .execute(&mut tx)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        tx.commit().await
+            .map_err(Error::DatabaseQueryError)?;
+        self.get_config_profile(profile_id).await
+    }
+
+    pub async fn get_config_profile(&self, id: Uuid) -> Result<ConfigProfile, Error> {
+        sqlx::query(&format!("{} WHERE p.id = $1", PROFILE_QUERY))
+            .bind(id)
+            .map(config_profile_from_row)
+            .fetch_one(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    // Profiles of every organization the user belongs to
+    pub async fn get_config_profiles_member_id(&self, user_id: Uuid) -> Result<Vec<ConfigProfile>, Error> {
+        let query = format!(
+            "{} JOIN organization_members m ON m.organization_id = p.organization_id WHERE m.user_id = $1 ORDER BY p.name",
+            PROFILE_QUERY,
+        );
+        sqlx::query(&query)
+            .bind(user_id)
+            .map(config_profile_from_row)
+            .fetch_all(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    pub async fn get_config_profile_versions(&self, profile_id: Uuid) -> Result<Vec<ConfigProfileVersion>, Error> {
+        sqlx::query("SELECT * FROM config_profile_versions WHERE profile_id = $1 ORDER BY version DESC")
+            .bind(profile_id)
+            .map(|row: PgRow| ConfigProfileVersion {
+                version: row.get("version"),
+                settings: settings_from_value(row.get("settings")),
+                created_by: row.get("created_by"),
+                created_at: row.get("created_at"),
+            })
+            .fetch_all(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    // Replaces whatever profile the business or device had, `None` leaves it without one
+    pub async fn attach_config_profile(&self, target: ConfigTarget, profile_id: Option<Uuid>) -> Result<bool, Error> {
+        let (column, id) = match target {
+            ConfigTarget::Business(id) => ("business_id", id),
+            ConfigTarget::Device(id) => ("device_id", id),
+        };
+        let mut tx = self.connection.begin().await
+            .map_err(Error::DatabaseQueryError)?;
+        sqlx::query(&format!("DELETE FROM config_profile_attachments WHERE {} = $1", column))
+            .bind(id)
+            .execute(&mut tx)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        if let Some(profile_id) = profile_id {
+            sqlx::query(&format!("INSERT INTO config_profile_attachments (profile_id, {}) VALUES ($1, $2)", column))
+                .bind(profile_id)
+                .bind(id)
+                .execute(&mut tx)
+                .await
+                .map_err(Error::DatabaseQueryError)?;
+        }
+        tx.commit().await
+            .map(|_| true)
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    pub async fn get_config_sources(&self, device_id: Uuid) -> Result<ConfigSources, Error> {
+        let business_query = format!(
+            "{} JOIN config_profile_attachments a ON a.profile_id = p.id JOIN devices d ON d.business_id = a.business_id WHERE d.id = $1",
+            PROFILE_QUERY,
+        );
+        let business = sqlx::query(&business_query)
+            .bind(device_id)
+            .map(config_profile_from_row)
+            .fetch_optional(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        let device_query = format!("{} JOIN config_profile_attachments a ON a.profile_id = p.id WHERE a.device_id = $1", PROFILE_QUERY);
+        let device = sqlx::query(&device_query)
+            .bind(device_id)
+            .map(config_profile_from_row)
+            .fetch_optional(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        Ok(ConfigSources { business, device })
+    }
+
+    pub async fn ack_device_config(&self, device_id: Uuid, version: &str) -> Result<bool, Error> {
+        let query = r#"
+            INSERT INTO device_config_acks (device_id, version, applied_at) VALUES ($1, $2, now())
+            ON CONFLICT (device_id) DO UPDATE SET version = EXCLUDED.version, applied_at = EXCLUDED.applied_at
+        "#;
+        sqlx::query(query)
+            .bind(device_id)
+            .bind(version)
+            .execute(&self.connection)
+            .await
+            .map(|_| true)
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    pub async fn get_device_config_ack(&self, device_id: Uuid) -> Result<Option<AppliedConfig>, Error> {
+        sqlx::query("SELECT version, applied_at FROM device_config_acks WHERE device_id = $1")
+            .bind(device_id)
+            .map(|row: PgRow| AppliedConfig {
+                version: row.get("version"),
+                applied_at: row.get("applied_at"),
+            })
+            .fetch_optional(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(settings: TerminalSettings) -> Option<ConfigProfile> {
+        Some(ConfigProfile {
+            id: Uuid::new_v4(),
+            organization_id: Uuid::new_v4(),
+            name: "Default".to_string(),
+            version: 1,
+            settings,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        })
+    }
+
+    #[test]
+    fn device_profile_wins_over_business_profile() {
+        let business = TerminalSettings { currency: Some("GHS".to_string()), max_amount: Some(50_000), ..Default::default() };
+        let device = TerminalSettings { max_amount: Some(10_000), tip_percentages: Some(vec![5, 10]), ..Default::default() };
+        let sources = ConfigSources { business: profile(business), device: profile(device) };
+        let effective = sources.effective();
+        assert_eq!(effective.config.currency, "GHS");
+        assert_eq!(effective.config.max_amount, Some(10_000));
+        assert_eq!(effective.config.payment_timeout_seconds, DEFAULT_PAYMENT_TIMEOUT_SECONDS);
+        assert_ne!(effective.version, ConfigSources::default().effective().version);
+    }
+
+    #[test]
+    fn settings_are_bounded() {
+        assert!(TerminalSettings::default().validate().is_ok());
+        assert!(TerminalSettings { currency: Some("ngn".to_string()), ..Default::default() }.validate().is_err());
+        assert!(TerminalSettings { tip_percentages: Some(vec![0]), ..Default::default() }.validate().is_err());
+        assert!(TerminalSettings { idle_timeout_seconds: Some(1), ..Default::default() }.validate().is_err());
+    }
+}
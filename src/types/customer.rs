@@ -1,12 +1,12 @@
 use chrono::prelude::*;
+use encrypt::ecc::KeyPair;
 use handle_error::Error;
 use serde::{Deserialize, Serialize};
-use sqlx::{postgres::PgRow, Row};
+use sqlx::{postgres::{PgExecutor, PgRow}, Row};
 use uuid::Uuid;
 
 use crate::db_store::Store;
 
-
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Customer {
     pub id: Uuid,
@@ -20,25 +20,58 @@ pub struct Customer {
     pub updated_at: DateTime<Utc>,
 }
 
+pub(crate) async fn add_customer_in<'c>(executor: impl PgExecutor<'c>, first_name: String, last_name: String, keys: &KeyPair, bank_id: &str, public_id: Uuid) -> Result<Uuid, Error> {
+    let query = r#"
+        INSERT INTO customers (first_name, last_name, public_key, private_key, bank_id, file_name, public_id)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id
+    "#;
+    let (id,): (Uuid,) = sqlx::query_as(query)
+        .bind(first_name)
+        .bind(last_name)
+        .bind(&keys.public_key)
+        .bind(&keys.private_key)
+        .bind(bank_id)
+        .bind(&keys.file_name)
+        .bind(public_id)
+        .fetch_one(executor)
+        .await
+        .map_err(Error::DatabaseQueryError)?;
+    Ok(id)
+}
+
+pub(crate) async fn update_customer_in<'c>(executor: impl PgExecutor<'c>, customer: &Customer) -> Result<bool, Error> {
+    let query = r#"
+        UPDATE customers
+        SET first_name = $2, last_name = $3, public_key = $4, private_key = $5, bank_id = $6, updated_at = $7
+        WHERE id = $1
+    "#;
+    sqlx::query(query)
+        .bind(customer.id)
+        .bind(&customer.first_name)
+        .bind(&customer.last_name)
+        .bind(&customer.public_key)
+        .bind(&customer.private_key)
+        .bind(&customer.bank_id)
+        .bind(customer.updated_at)
+        .execute(executor)
+        .await
+        .map(|_| true)
+        .map_err(Error::DatabaseQueryError)
+}
+
+pub(crate) async fn delete_customer_in<'c>(executor: impl PgExecutor<'c>, id: Uuid) -> Result<bool, Error> {
+    sqlx::query("DELETE FROM customers WHERE id = $1")
+        .bind(id)
+        .execute(executor)
+        .await
+        .map(|_| true)
+        .map_err(Error::DatabaseQueryError)
+}
+
 impl Store {
-    pub async fn add_customer(&self, first_name: String, last_name: String, public_key: String, private_key: String, bank_id: &str, file_name: &str, public_id: Uuid) -> Result<Uuid, Error> {
-        let query = r#"
-            INSERT INTO customers (first_name, last_name, public_key, private_key, bank_id, file_name, public_id)
-            VALUES ($1, $2, $3, $4, $5, $6, $7)
-            RETURNING id
-        "#;
-        let (id,): (Uuid,) = sqlx::query_as(query)
-            .bind(first_name)
-            .bind(last_name)
-            .bind(public_key)
-            .bind(private_key)
-            .bind(bank_id)
-            .bind(file_name)
-            .bind(public_id)
-            .fetch_one(&self.connection)
-            .await
-            .map_err(|e| Error::DatabaseQueryError(e))?;
-        Ok(id)
+    pub async fn add_customer(&self, first_name: String, last_name: String, keys: &KeyPair, bank_id: &str, public_id: Uuid) -> Result<Uuid, Error> {
+        add_customer_in(&self.connection, first_name, last_name, keys, bank_id, public_id).await
     }
     pub async fn get_customer_public_id(&self, public_id: Uuid) -> Result<Customer, Error> {
         sqlx::query("SELECT * FROM customers WHERE public_id = $1")
@@ -94,32 +127,4 @@ impl Store {
             .map_err(|e| Error::DatabaseQueryError(e))
     }
 
-    pub async fn update_customer(&self, customer: &Customer) -> Result<bool, Error> {
-        let query = r#"
-            UPDATE customers
-            SET first_name = $2, last_name = $3, public_key = $4, private_key = $5, bank_id = $6, updated_at = $7
-            WHERE id = $1
-        "#;
-        sqlx::query(query)
-            .bind(customer.id)
-            .bind(&customer.first_name)
-            .bind(&customer.last_name)
-            .bind(&customer.public_key)
-            .bind(&customer.private_key)
-            .bind(&customer.bank_id)
-            .bind(customer.updated_at)
-            .execute(&self.connection)
-            .await
-            .map(|_| true)
-            .map_err(|e| Error::DatabaseQueryError(e))
-    }
-
-    pub async fn delete_customer(&self, id: Uuid) -> Result<bool, Error> {
-        sqlx::query("DELETE FROM customers WHERE id = $1")
-            .bind(id)
-            .execute(&self.connection)
-            .await
-            .map(|_| true)
-            .map_err(|e| Error::DatabaseQueryError(e))
-    }
 }
\ No newline at end of file
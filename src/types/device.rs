@@ -1,14 +1,82 @@
 use chrono::{DateTime, Utc};
 use handle_error::Error;
 use serde::{Deserialize, Serialize};
-use sqlx::{postgres::PgRow, Row};
+use sqlx::{postgres::PgRow, Postgres, Row, Transaction};
 use uuid::Uuid;
 
 use crate::db_store::Store;
 
+// Pending terminals exist for the merchant but cannot sign requests until they redeem an activation code.
+// Only active terminals take payments, a suspended one can be reinstated and a retired one is gone for good
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceStatus {
+    Pending,
+    Active,
+    Suspended,
+    Retired,
+}
+
+impl DeviceStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeviceStatus::Pending => "pending",
+            DeviceStatus::Active => "active",
+            DeviceStatus::Suspended => "suspended",
+            DeviceStatus::Retired => "retired",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<DeviceStatus> {
+        match value {
+            "pending" => Some(DeviceStatus::Pending),
+            "active" => Some(DeviceStatus::Active),
+            "suspended" => Some(DeviceStatus::Suspended),
+            "retired" => Some(DeviceStatus::Retired),
+            _ => None,
+        }
+    }
+
+    // Changes allowed through the API, pending only becomes active by redeeming an activation code
+    pub fn can_change_to(&self, next: DeviceStatus) -> bool {
+        matches!(
+            (self, next),
+            (DeviceStatus::Pending, DeviceStatus::Retired)
+                | (DeviceStatus::Active, DeviceStatus::Suspended)
+                | (DeviceStatus::Active, DeviceStatus::Retired)
+                | (DeviceStatus::Suspended, DeviceStatus::Active)
+                | (DeviceStatus::Suspended, DeviceStatus::Retired)
+        )
+    }
+}
+
+// Who put the device in its current status
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StatusAuthority {
+    Merchant,
+    Admin,
+}
+
+impl StatusAuthority {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StatusAuthority::Merchant => "merchant",
+            StatusAuthority::Admin => "admin",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<StatusAuthority> {
+        match value {
+            "merchant" => Some(StatusAuthority::Merchant),
+            "admin" => Some(StatusAuthority::Admin),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Device {
-    pub main_id: Uuid,
     pub id: Uuid,
     pub device_id: String,
     pub name: String,
@@ -18,15 +86,66 @@ pub struct Device {
     pub price_key: String,
     pub id_key: String,
     pub business_id: Uuid,
+    pub status: DeviceStatus,
+    pub activated_at: Option<DateTime<Utc>>,
+    pub status_reason: Option<String>,
+    pub status_changed_at: Option<DateTime<Utc>>,
+    pub status_set_by: Option<StatusAuthority>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+impl Device {
+    // A pending terminal still carries the placeholder `device_id` that enrollment replaces, a sticker for it would point nowhere
+    pub fn can_print_sticker(&self) -> bool {
+        matches!(self.status, DeviceStatus::Active | DeviceStatus::Suspended) && self.activated_at.is_some()
+    }
+}
+
+pub(crate) const DEVICE_COLUMNS: &str = r#"
+    id,
+    device_id,
+    name,
+    account_id,
+    device_type,
+    apk_key,
+    price_key,
+    id_key,
+    business_id,
+    status,
+    activated_at,
+    status_reason,
+    status_changed_at,
+    status_set_by,
+    created_at,
+    updated_at
+"#;
+
+pub(crate) fn device_from_row(row: PgRow) -> Device {
+    Device {
+        id: row.get("id"),
+        device_id: row.get("device_id"),
+        name: row.get("name"),
+        account_id: row.get("account_id"),
+        device_type: row.get("device_type"),
+        apk_key: row.get("apk_key"),
+        price_key: row.get("price_key"),
+        id_key: row.get("id_key"),
+        business_id: row.get("business_id"),
+        status: DeviceStatus::parse(row.get("status")).unwrap_or(DeviceStatus::Pending),
+        activated_at: row.get("activated_at"),
+        status_reason: row.get("status_reason"),
+        status_changed_at: row.get("status_changed_at"),
+        status_set_by: row.get::<Option<&str>, _>("status_set_by").and_then(StatusAuthority::parse),
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct DeviceWithBusinessUserAccount {
     // Device
-    pub main_device_id: Uuid,
+    pub id: Uuid,
     pub device_id: String,
     pub name: String,
     pub device_type: String,
@@ -35,6 +154,7 @@ pub struct DeviceWithBusinessUserAccount {
     pub updated_at: DateTime<Utc>,
     pub price_key: String,
     pub id_key: String,
+    pub status: DeviceStatus,
     // Business
     pub business_id: Uuid,
     pub business_name: String,
@@ -53,42 +173,104 @@ pub struct DeviceWithBusinessUserAccount {
     pub account_number: String,
 }
 
+// The device with its first account assignment, inside the caller's transaction. `user_id` is kept as the creator
+pub(crate) async fn insert_device(tx: &mut Transaction<'_, Postgres>, device: &Device, user_id: Uuid) -> Result<(), Error> {
+    let query = r#"
+        INSERT INTO devices (id, device_id, name, account_id, device_type, apk_key, business_id, created_at, updated_at, created_by, id_key, price_key, status, activated_at, status_reason, status_changed_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)
+    "#;
+    sqlx::query(query)
+        .bind(device.id)
+        .bind(&device.device_id)
+        .bind(&device.name)
+        .bind(device.account_id)
+        .bind(&device.device_type)
+        .bind(&device.apk_key)
+        .bind(device.business_id)
+        .bind(device.created_at)
+        .bind(device.updated_at)
+        .bind(user_id)
+        .bind(&device.id_key)
+        .bind(&device.price_key)
+        .bind(device.status.as_str())
+        .bind(device.activated_at)
+        .bind(&device.status_reason)
+        .bind(device.status_changed_at)
+        .execute(&mut *tx)
+        .await
+        .map_err(Error::DatabaseQueryError)?;
+    sqlx::query("INSERT INTO device_account_assignments (device_id, account_id, effective_from) VALUES ($1, $2, $3)")
+        .bind(device.id)
+        .bind(device.account_id)
+        .bind(device.created_at)
+        .execute(&mut *tx)
+        .await
+        .map(|_| ())
+        .map_err(Error::DatabaseQueryError)
+}
 
+// A new code replaces any code of the device that has not been redeemed yet
+pub(crate) async fn add_device_activation_in(tx: &mut Transaction<'_, Postgres>, device_id: Uuid, code_hash: &str, created_by: Uuid, expires_at: DateTime<Utc>) -> Result<bool, Error> {
+    sqlx::query("DELETE FROM device_activations WHERE device_id = $1 AND used_at IS NULL")
+        .bind(device_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(Error::DatabaseQueryError)?;
+    sqlx::query("INSERT INTO device_activations (device_id, code_hash, created_by, expires_at) VALUES ($1, $2, $3, $4)")
+        .bind(device_id)
+        .bind(code_hash)
+        .bind(created_by)
+        .bind(expires_at)
+        .execute(&mut *tx)
+        .await
+        .map_err(Error::DatabaseQueryError)?;
+    Ok(true)
+}
 
-
-impl Store {
-    pub async fn add_device(&self, device: &Device) -> Result<bool, Error> {
-        let query = r#"
-            INSERT INTO devices (id, device_id, name, type, business_id, created_at, updated_at, apk_key)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
-        "#;
-        sqlx::query(query)
-            .bind(device.id)
-            .bind(&device.device_id)
-            .bind(&device.name)
-            .bind(&device.device_type)
-            .bind(device.business_id)
-            .bind(device.created_at)
-            .bind(device.updated_at)
-            .bind(&device.apk_key)
-            .execute(&self.connection)
+// Compare-and-set on the current status so two operators cannot both act on a stale view.
+// Retiring also retires every key, the terminal can never sign again
+pub(crate) async fn update_device_status_in(tx: &mut Transaction<'_, Postgres>, id: Uuid, from: DeviceStatus, to: DeviceStatus, reason: Option<&str>, set_by: StatusAuthority) -> Result<bool, Error> {
+    let query = r#"
+        UPDATE devices SET status = $3, status_reason = $4, status_set_by = $5, status_changed_at = now(), updated_at = now()
+        WHERE id = $1 AND status = $2
+    "#;
+    let result = sqlx::query(query)
+        .bind(id)
+        .bind(from.as_str())
+        .bind(to.as_str())
+        .bind(reason)
+        .bind(set_by.as_str())
+        .execute(&mut *tx)
+        .await
+        .map_err(Error::DatabaseQueryError)?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+    if to == DeviceStatus::Retired {
+        sqlx::query("UPDATE device_keys SET retires_at = now() WHERE device_id = $1 AND (retires_at IS NULL OR retires_at > now())")
+            .bind(id)
+            .execute(&mut *tx)
             .await
-            .map(|_| true)
-            .map_err(|e| Error::DatabaseQueryError(e))
+            .map_err(Error::DatabaseQueryError)?;
     }
+    Ok(true)
+}
+
+impl Store {
     pub async fn get_device_with_business_user_account(
         &self,
         device_id: String,
     ) -> Result<DeviceWithBusinessUserAccount, Error> {
         let query = r#"
             SELECT 
+                d.id,
                 d.device_id,
                 d.name,
-                d.main_id AS main_device_id,
                 d.device_type,
                 d.apk_key,
                 d.id_key,
                 d.price_key,
+                d.status,
                 d.created_at,
                 d.updated_at,
                 b.id AS business_id,
@@ -112,6 +294,7 @@ impl Store {
         sqlx::query(query)
             .bind(device_id)
             .map(|row: PgRow| DeviceWithBusinessUserAccount {
+                id: row.get("id"),
                 device_id: row.get("device_id"),
                 name: row.get("name"),
                 device_type: row.get("device_type"),
@@ -123,8 +306,8 @@ impl Store {
                 business_location: row.get("business_location"),
                 id_key: row.get("id_key"),
                 price_key: row.get("price_key"),
+                status: DeviceStatus::parse(row.get("status")).unwrap_or(DeviceStatus::Pending),
                 user_id: row.get("user_id"),
-                main_device_id: row.get("main_device_id"),
                 user_first_name: row.get("user_first_name"),
                 user_last_name: row.get("user_last_name"),
                 user_email: row.get("user_email"),
@@ -139,109 +322,182 @@ impl Store {
     }
 
     pub async fn get_device(&self, id: Uuid) -> Result<Device, Error> {
-        sqlx::query("SELECT * FROM devices WHERE id = $1")
+        sqlx::query(&format!("SELECT {} FROM devices WHERE id = $1", DEVICE_COLUMNS))
             .bind(id)
-            .map(|row: PgRow| Device {
-                main_id: row.get("main_id"),
-                id: row.get("id"),
-                device_id: row.get("device_id"),
-                name: row.get("name"),
-                device_type: row.get("device_type"),
-                business_id: row.get("business_id"),
-                created_at: row.get("created_at"),
-                updated_at: row.get("updated_at"),
-                apk_key: row.get("apk_key"),
-                id_key: row.get("id_key"),
-                price_key: row.get("price_key"),
-                account_id: row.get("account_id"),
-            })
+            .map(device_from_row)
             .fetch_one(&self.connection)
             .await
             .map_err(|e| Error::DatabaseQueryError(e))
     }
-    pub async fn setup_device(&self, id: Uuid, device_id: String, price_key: String, id_key: String) -> Result<bool, Error> {
-        let query = r#"
-            UPDATE devices
-            SET device_id = $2, price_key = $3, id_key = $4
-            WHERE id = $1
-        "#;
-        sqlx::query(query)
-            .bind(id)
+    pub async fn get_device_device_id(&self, device_id: &str) -> Result<Option<Device>, Error> {
+        sqlx::query(&format!("SELECT {} FROM devices WHERE device_id = $1", DEVICE_COLUMNS))
             .bind(device_id)
-            .bind(price_key)
-            .bind(id_key)
-            .execute(&self.connection)
+            .map(device_from_row)
+            .fetch_optional(&self.connection)
             .await
-            .map(|_| true)
-            .map_err(|e| Error::DatabaseQueryError(e))
+            .map_err(Error::DatabaseQueryError)
     }
     pub async fn get_devices(&self) -> Result<Vec<Device>, Error> {
-        sqlx::query("SELECT * FROM devices")
-            .map(|row: PgRow| Device {
-                main_id: row.get("main_id"),
-                id: row.get("id"),
-                device_id: row.get("device_id"),
-                name: row.get("name"),
-                device_type: row.get("device_type"),
-                business_id: row.get("business_id"),
-                created_at: row.get("created_at"),
-                id_key: row.get("id_key"),
-                price_key: row.get("price_key"),
-                updated_at: row.get("updated_at"),
-                apk_key: row.get("apk_key"),
-                account_id: row.get("account_id"),
-            })
+        sqlx::query(&format!("SELECT {} FROM devices", DEVICE_COLUMNS))
+            .map(device_from_row)
             .fetch_all(&self.connection)
             .await
             .map_err(|e| Error::DatabaseQueryError(e))
     }
     pub async fn get_devices_by_business_id(&self, business_id: &Uuid) -> Result<Vec<Device>, Error> {
-        sqlx::query("SELECT * FROM devices WHERE business_id = $1")
+        sqlx::query(&format!("SELECT {} FROM devices WHERE business_id = $1", DEVICE_COLUMNS))
             .bind(business_id)
-            .map(|row: PgRow| Device {
-                main_id: row.get("main_id"),
-                id: row.get("id"),
-                device_id: row.get("device_id"),
-                id_key: row.get("id_key"),
-                price_key: row.get("price_key"),
-                name: row.get("name"),
-                device_type: row.get("device_type"),
-                business_id: row.get("business_id"),
-                created_at: row.get("created_at"),
-                updated_at: row.get("updated_at"),
-                apk_key: row.get("apk_key"),
-                account_id: row.get("account_id"),
-            })
+            .map(device_from_row)
+            .fetch_all(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)
+    }
+    // Terminals of every business in the organizations the user belongs to
+    pub async fn get_devices_member_id(&self, user_id: Uuid) -> Result<Vec<Device>, Error> {
+        let query = format!(r#"
+            SELECT {}
+            FROM devices
+            WHERE business_id IN (
+                SELECT b.id
+                FROM businesses b
+                JOIN organization_members m ON m.organization_id = b.organization_id
+                WHERE m.user_id = $1
+            )
+            ORDER BY created_at
+        "#, DEVICE_COLUMNS);
+        sqlx::query(&query)
+            .bind(user_id)
+            .map(device_from_row)
             .fetch_all(&self.connection)
             .await
             .map_err(|e| Error::DatabaseQueryError(e))
     }
+    // The payout account is left alone, it only moves through `reassign_device_account`
     pub async fn update_device(&self, device: &Device) -> Result<bool, Error> {
         let query = r#"
             UPDATE devices
-            SET device_id = $2, name = $3, type = $4, business_id = $5, updated_at = $6, apk_key = $7
+            SET name = $2, device_type = $3, business_id = $4, updated_at = $5
             WHERE id = $1
         "#;
         sqlx::query(query)
             .bind(device.id)
-            .bind(&device.device_id)
             .bind(&device.name)
             .bind(&device.device_type)
             .bind(device.business_id)
             .bind(device.updated_at)
-            .bind(&device.apk_key)
             .execute(&self.connection)
             .await
             .map(|_| true)
             .map_err(|e| Error::DatabaseQueryError(e))
     }
 
-    pub async fn delete_device(&self, main_id: Uuid) -> Result<bool, Error> {
-        sqlx::query("DELETE FROM devices WHERE main_id = $1")
-            .bind(main_id)
-            .execute(&self.connection)
+    // Burns the code and gives the pending device its identity and keys in one transaction.
+    // A terminal that held keys before (revoked and re-enrolled) keeps its `device_id`, stickers and customer references still point at it.
+    // None when the code is unknown, used, expired, for another bank's terminal or the device is no longer pending
+    pub async fn activate_device(&self, code_hash: &str, bank_id: &str, device_id: &str, apk_key: &str, price_key: &str, id_key: &str) -> Result<Option<Device>, Error> {
+        let mut tx = self.connection.begin().await
+            .map_err(Error::DatabaseQueryError)?;
+        let query = r#"
+            UPDATE device_activations da SET used_at = now()
+            FROM devices d
+            JOIN accounts a ON a.id = d.account_id
+            WHERE da.device_id = d.id AND da.code_hash = $1 AND a.bank_id = $2
+                AND da.used_at IS NULL AND da.expires_at > now()
+            RETURNING da.device_id
+        "#;
+        let id: Option<Uuid> = sqlx::query(query)
+            .bind(code_hash)
+            .bind(bank_id)
+            .map(|row: PgRow| row.get("device_id"))
+            .fetch_optional(&mut tx)
             .await
-            .map(|_| true)
-            .map_err(|e| Error::DatabaseQueryError(e))
+            .map_err(Error::DatabaseQueryError)?;
+        let Some(id) = id else {
+            return Ok(None);
+        };
+        let query = format!(r#"
+            UPDATE devices
+            SET device_id = CASE WHEN EXISTS (SELECT 1 FROM device_keys k WHERE k.device_id = devices.id) THEN devices.device_id ELSE $2 END,
+                apk_key = $3, price_key = $4, id_key = $5, status = 'active', activated_at = now(),
+                status_reason = NULL, status_changed_at = now(), status_set_by = NULL, updated_at = now()
+            WHERE id = $1 AND status = 'pending'
+            RETURNING {}
+        "#, DEVICE_COLUMNS);
+        let device = sqlx::query(&query)
+            .bind(id)
+            .bind(device_id)
+            .bind(apk_key)
+            .bind(price_key)
+            .bind(id_key)
+            .map(device_from_row)
+            .fetch_optional(&mut tx)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        let Some(device) = device else {
+            return Ok(None);
+        };
+        // Whatever keys the pending device carried were placeholders, the redeemed ones start at version 1
+        sqlx::query("DELETE FROM device_keys WHERE device_id = $1")
+            .bind(device.id)
+            .execute(&mut tx)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        sqlx::query("INSERT INTO device_keys (device_id, version, price_key, id_key) VALUES ($1, 1, $2, $3)")
+            .bind(device.id)
+            .bind(price_key)
+            .bind(id_key)
+            .execute(&mut tx)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        tx.commit().await
+            .map_err(Error::DatabaseQueryError)?;
+        Ok(Some(device))
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retired_devices_stay_retired() {
+        for status in [DeviceStatus::Pending, DeviceStatus::Active, DeviceStatus::Suspended, DeviceStatus::Retired] {
+            assert_eq!(DeviceStatus::parse(status.as_str()), Some(status));
+            assert!(!DeviceStatus::Retired.can_change_to(status));
+        }
+        assert!(DeviceStatus::Suspended.can_change_to(DeviceStatus::Active));
+        assert!(!DeviceStatus::Pending.can_change_to(DeviceStatus::Active));
+    }
+
+    #[test]
+    fn pending_devices_get_no_sticker() {
+        let mut device = Device {
+            id: Uuid::new_v4(),
+            device_id: "placeholder".to_string(),
+            name: "Till 1".to_string(),
+            account_id: Uuid::new_v4(),
+            device_type: "pos".to_string(),
+            apk_key: String::new(),
+            price_key: String::new(),
+            id_key: String::new(),
+            business_id: Uuid::new_v4(),
+            status: DeviceStatus::Pending,
+            activated_at: None,
+            status_reason: None,
+            status_changed_at: None,
+            status_set_by: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        assert!(!device.can_print_sticker());
+        device.activated_at = Some(Utc::now());
+        assert!(!device.can_print_sticker());
+        device.status = DeviceStatus::Active;
+        assert!(device.can_print_sticker());
+        device.status = DeviceStatus::Suspended;
+        assert!(device.can_print_sticker());
+        device.status = DeviceStatus::Retired;
+        assert!(!device.can_print_sticker());
     }
 }
@@ -0,0 +1,114 @@
+use chrono::{DateTime, Utc};
+use handle_error::Error;
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgRow, Postgres, Row, Transaction};
+use uuid::Uuid;
+
+use crate::{db_store::Store, types::device::Device};
+
+// One period a terminal paid out into an account, `effective_to` stays empty while it is in force
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DeviceAssignment {
+    pub id: Uuid,
+    pub device_id: Uuid,
+    pub account_id: Uuid,
+    pub bank_id: String,
+    pub account_name: String,
+    pub account_number: String,
+    pub effective_from: DateTime<Utc>,
+    pub effective_to: Option<DateTime<Utc>>,
+    pub assigned_by: Option<Uuid>,
+}
+
+fn device_assignment_from_row(row: PgRow) -> DeviceAssignment {
+    DeviceAssignment {
+        id: row.get("id"),
+        device_id: row.get("device_id"),
+        account_id: row.get("account_id"),
+        bank_id: row.get("bank_id"),
+        account_name: row.get("account_name"),
+        account_number: row.get("account_number"),
+        effective_from: row.get("effective_from"),
+        effective_to: row.get("effective_to"),
+        assigned_by: row.get("assigned_by"),
+    }
+}
+
+// Admin edits change the details and the payout account together, or neither
+pub(crate) async fn update_device_and_account_in(tx: &mut Transaction<'_, Postgres>, device: &Device, account_id: Uuid, assigned_by: Uuid) -> Result<bool, Error> {
+    sqlx::query("UPDATE devices SET name = $2, device_type = $3, business_id = $4, updated_at = $5 WHERE id = $1")
+        .bind(device.id)
+        .bind(&device.name)
+        .bind(&device.device_type)
+        .bind(device.business_id)
+        .bind(device.updated_at)
+        .execute(&mut *tx)
+        .await
+        .map_err(Error::DatabaseQueryError)?;
+    reassign_in(tx, device.id, account_id, assigned_by).await
+}
+
+impl Store {
+    pub async fn get_device_assignments(&self, device_id: Uuid) -> Result<Vec<DeviceAssignment>, Error> {
+        let query = r#"
+            SELECT das.id, das.device_id, das.account_id, a.bank_id, a.account_name, a.account_number,
+                das.effective_from, das.effective_to, das.assigned_by
+            FROM device_account_assignments das
+            JOIN accounts a ON a.id = das.account_id
+            WHERE das.device_id = $1
+            ORDER BY das.effective_from DESC
+        "#;
+        sqlx::query(query)
+            .bind(device_id)
+            .map(device_assignment_from_row)
+            .fetch_all(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    // Closes the assignment in force and opens one for `account_id`, false when the terminal already pays into it
+    pub async fn reassign_device_account(&self, device_id: Uuid, account_id: Uuid, assigned_by: Uuid) -> Result<bool, Error> {
+        let mut tx = self.connection.begin().await
+            .map_err(Error::DatabaseQueryError)?;
+        let changed = reassign_in(&mut tx, device_id, account_id, assigned_by).await?;
+        tx.commit().await
+            .map(|_| changed)
+            .map_err(Error::DatabaseQueryError)
+    }
+
+}
+
+// The row lock keeps a payment or a second reassignment from seeing two open assignments
+async fn reassign_in(tx: &mut Transaction<'_, Postgres>, device_id: Uuid, account_id: Uuid, assigned_by: Uuid) -> Result<bool, Error> {
+    let (current,): (Uuid,) = sqlx::query_as("SELECT account_id FROM devices WHERE id = $1 FOR UPDATE")
+        .bind(device_id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(Error::DatabaseQueryError)?;
+    if current == account_id {
+        return Ok(false);
+    }
+    let now = Utc::now();
+    sqlx::query("UPDATE device_account_assignments SET effective_to = $2 WHERE device_id = $1 AND effective_to IS NULL")
+        .bind(device_id)
+        .bind(now)
+        .execute(&mut *tx)
+        .await
+        .map_err(Error::DatabaseQueryError)?;
+    sqlx::query("INSERT INTO device_account_assignments (device_id, account_id, effective_from, assigned_by) VALUES ($1, $2, $3, $4)")
+        .bind(device_id)
+        .bind(account_id)
+        .bind(now)
+        .bind(assigned_by)
+        .execute(&mut *tx)
+        .await
+        .map_err(Error::DatabaseQueryError)?;
+    sqlx::query("UPDATE devices SET account_id = $2, updated_at = $3 WHERE id = $1")
+        .bind(device_id)
+        .bind(account_id)
+        .bind(now)
+        .execute(&mut *tx)
+        .await
+        .map(|_| true)
+        .map_err(Error::DatabaseQueryError)
+}
@@ -0,0 +1,298 @@
+use std::env;
+
+use chrono::{DateTime, Duration, Utc};
+use handle_error::Error;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgRow, Row};
+use uuid::Uuid;
+
+use crate::{db_store::Store, tools::constant::MIN_TERMINAL_FIRMWARE_VERSION, types::{app_release::{latest_release, ReleaseChannel}, device::DeviceStatus}};
+
+// Terminals beat every few minutes, missing three in a row counts as offline
+const OFFLINE_AFTER_MINUTES: i64 = 15;
+// Half the request signature window, so drifting terminals show up before their payments fail
+const MAX_CLOCK_DRIFT_SECONDS: i32 = 30;
+const HEARTBEAT_RETENTION_DAYS: i64 = 7;
+
+#[derive(Debug, Clone)]
+pub struct Heartbeat {
+    pub firmware_version: String,
+    pub app_version: String,
+    pub battery: i16,
+    pub signal: i16,
+    pub device_time: DateTime<Utc>,
+    pub clock_drift_seconds: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthIssue {
+    Offline,
+    OutOfDate,
+    ClockDrift,
+}
+
+// Last reported state of one terminal, the reported fields stay empty until its first heartbeat
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DeviceHealth {
+    pub id: Uuid,
+    pub device_id: String,
+    pub name: String,
+    pub business_id: Uuid,
+    pub status: DeviceStatus,
+    pub last_seen_at: Option<DateTime<Utc>>,
+    pub firmware_version: Option<String>,
+    pub app_version: Option<String>,
+    pub battery: Option<i16>,
+    pub signal: Option<i16>,
+    pub clock_drift_seconds: Option<i32>,
+    pub issues: Vec<HealthIssue>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HeartbeatRecord {
+    pub firmware_version: String,
+    pub app_version: String,
+    pub battery: i16,
+    pub signal: i16,
+    pub device_time: DateTime<Utc>,
+    pub clock_drift_seconds: i32,
+    pub received_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FleetHealthQuery {
+    pub issue: Option<HealthIssue>,
+}
+
+// Oldest app and firmware still considered current, unset means nothing is out of date
+#[derive(Debug, Clone, Default)]
+pub struct FleetPolicy {
+    pub min_app_version: Option<String>,
+    pub min_firmware_version: Option<String>,
+}
+
+impl FleetPolicy {
+
+    // Only active terminals are expected to beat
+    pub fn issues(&self, health: &DeviceHealth, now: DateTime<Utc>) -> Vec<HealthIssue> {
+        let mut issues = Vec::new();
+        if health.status != DeviceStatus::Active {
+            return issues;
+        }
+        if health.last_seen_at.is_none_or(|seen| now - seen > Duration::minutes(OFFLINE_AFTER_MINUTES)) {
+            issues.push(HealthIssue::Offline);
+        }
+        let outdated = |actual: &Option<String>, minimum: &Option<String>| match (actual, minimum) {
+            (Some(actual), Some(minimum)) => !version_at_least(actual, minimum),
+            _ => false,
+        };
+        if outdated(&health.app_version, &self.min_app_version) || outdated(&health.firmware_version, &self.min_firmware_version) {
+            issues.push(HealthIssue::OutOfDate);
+        }
+        if health.clock_drift_seconds.is_some_and(|drift| drift.abs() > MAX_CLOCK_DRIFT_SECONDS) {
+            issues.push(HealthIssue::ClockDrift);
+        }
+        issues
+    }
+}
+
+// Semantic version with the shorthands terminals report: a leading `v` and missing minor or patch parts,
+// `1.2-rc1` reads as `1.2.0-rc1`. Build metadata plays no part in precedence and is dropped
+pub fn parse_version(version: &str) -> Option<Version> {
+    let version = version.trim();
+    let version = version.strip_prefix('v').unwrap_or(version);
+    let version = version.split('+').next().unwrap_or_default();
+    let (core, pre) = match version.split_once('-') {
+        Some((core, pre)) => (core, Some(pre)),
+        None => (version, None),
+    };
+    let mut parts: Vec<&str> = core.split('.').collect();
+    if parts.len() > 3 {
+        return None;
+    }
+    parts.resize(3, "0");
+    let normalized = match pre {
+        Some(pre) => format!("{}-{}", parts.join("."), pre),
+        None => parts.join("."),
+    };
+    Version::parse(&normalized).ok()
+}
+
+// Semver precedence, so `1.10` is newer than `1.9` and `2.0.0-rc1` is older than `2.0.0`.
+// A version that does not parse never satisfies a minimum
+pub fn version_at_least(actual: &str, minimum: &str) -> bool {
+    match (parse_version(actual), parse_version(minimum)) {
+        (Some(actual), Some(minimum)) => actual >= minimum,
+        _ => false,
+    }
+}
+
+impl Store {
+    // The app minimum follows the stable line of the release registry, firmware has no registry and stays configured
+    pub async fn get_fleet_policy(&self) -> Result<FleetPolicy, Error> {
+        let releases = self.get_app_releases().await?;
+        Ok(FleetPolicy {
+            min_app_version: latest_release(&releases, ReleaseChannel::Stable).map(|latest| latest.min_supported_version.clone()),
+            min_firmware_version: env::var(MIN_TERMINAL_FIRMWARE_VERSION).ok().filter(|v| !v.is_empty()),
+        })
+    }
+
+    // Updates the latest state, keeps the heartbeat and drops the ones past the retention window
+    pub async fn record_heartbeat(&self, device_id: Uuid, heartbeat: &Heartbeat) -> Result<bool, Error> {
+        let mut tx = self.connection.begin().await
+            .map_err(Error::DatabaseQueryError)?;
+        let query = r#"
+            INSERT INTO device_health (device_id, last_seen_at, firmware_version, app_version, battery, signal, clock_drift_seconds)
+            VALUES ($1, now(), $2, $3, $4, $5, $6)
+            ON CONFLICT (device_id) DO UPDATE SET
+                last_seen_at = now(),
+                firmware_version = EXCLUDED.firmware_version,
+                app_version = EXCLUDED.app_version,
+                battery = EXCLUDED.battery,
+                signal = EXCLUDED.signal,
+                clock_drift_seconds = EXCLUDED.clock_drift_seconds
+        "#;
+        sqlx::query(query)
+            .bind(device_id)
+            .bind(&heartbeat.firmware_version)
+            .bind(&heartbeat.app_version)
+            .bind(heartbeat.battery)
+            .bind(heartbeat.signal)
+            .bind(heartbeat.clock_drift_seconds)
+            .execute(&mut tx)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        let query = r#"
+            INSERT INTO device_heartbeats (device_id, firmware_version, app_version, battery, signal, device_time, clock_drift_seconds)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#;
+        sqlx::query(query)
+            .bind(device_id)
+            .bind(&heartbeat.firmware_version)
+            .bind(&heartbeat.app_version)
+            .bind(heartbeat.battery)
+            .bind(heartbeat.signal)
+            .bind(heartbeat.device_time)
+            .bind(heartbeat.clock_drift_seconds)
+            .execute(&mut tx)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        sqlx::query("DELETE FROM device_heartbeats WHERE device_id = $1 AND received_at < $2")
+            .bind(device_id)
+            .bind(Utc::now() - Duration::days(HEARTBEAT_RETENTION_DAYS))
+            .execute(&mut tx)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        tx.commit().await
+            .map(|_| true)
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    // Every terminal with its last reported state, limited to the user's organizations when one is given
+    pub async fn get_fleet_health(&self, member_id: Option<Uuid>) -> Result<Vec<DeviceHealth>, Error> {
+        let query = r#"
+            SELECT d.id, d.device_id, d.name, d.business_id, d.status,
+                h.last_seen_at, h.firmware_version, h.app_version, h.battery, h.signal, h.clock_drift_seconds
+            FROM devices d
+            LEFT JOIN device_health h ON h.device_id = d.id
+            WHERE $1::uuid IS NULL OR EXISTS (
+                SELECT 1 FROM businesses b
+                JOIN organization_members m ON m.organization_id = b.organization_id
+                WHERE b.id = d.business_id AND m.user_id = $1
+            )
+            ORDER BY h.last_seen_at ASC NULLS FIRST
+        "#;
+        sqlx::query(query)
+            .bind(member_id)
+            .map(|row: PgRow| DeviceHealth {
+                id: row.get("id"),
+                device_id: row.get("device_id"),
+                name: row.get("name"),
+                business_id: row.get("business_id"),
+                status: DeviceStatus::parse(row.get("status")).unwrap_or(DeviceStatus::Pending),
+                last_seen_at: row.get("last_seen_at"),
+                firmware_version: row.get("firmware_version"),
+                app_version: row.get("app_version"),
+                battery: row.get("battery"),
+                signal: row.get("signal"),
+                clock_drift_seconds: row.get("clock_drift_seconds"),
+                issues: Vec::new(),
+            })
+            .fetch_all(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    pub async fn get_device_heartbeats(&self, device_id: Uuid) -> Result<Vec<HeartbeatRecord>, Error> {
+        sqlx::query("SELECT * FROM device_heartbeats WHERE device_id = $1 ORDER BY received_at DESC LIMIT 100")
+            .bind(device_id)
+            .map(|row: PgRow| HeartbeatRecord {
+                firmware_version: row.get("firmware_version"),
+                app_version: row.get("app_version"),
+                battery: row.get("battery"),
+                signal: row.get("signal"),
+                device_time: row.get("device_time"),
+                clock_drift_seconds: row.get("clock_drift_seconds"),
+                received_at: row.get("received_at"),
+            })
+            .fetch_all(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)
+    }
+}
+
+// Flags each terminal against the policy and keeps only those with the requested issue
+pub fn with_issues(fleet: Vec<DeviceHealth>, policy: &FleetPolicy, filter: Option<HealthIssue>) -> Vec<DeviceHealth> {
+    let now = Utc::now();
+    fleet.into_iter()
+        .map(|mut health| {
+            health.issues = policy.issues(&health, now);
+            health
+        })
+        .filter(|health| filter.is_none_or(|issue| health.issues.contains(&issue)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_follow_semver_precedence() {
+        assert!(version_at_least("1.10.0", "1.9"));
+        assert!(version_at_least("v2.0", "2.0.0"));
+        assert!(!version_at_least("1.2.3", "1.2.4"));
+        assert!(!version_at_least("2.0.0-rc1", "2.0.0"));
+        assert!(version_at_least("2.0.0-rc.10", "2.0.0-rc.2"));
+        assert!(version_at_least("2.0-beta", "2.0.0-alpha"));
+        assert!(version_at_least("1.4.0+build7", "1.4.0"));
+        assert!(!version_at_least("latest", "1.0"));
+    }
+
+    #[test]
+    fn silent_and_drifting_terminals_are_flagged() {
+        let now = Utc::now();
+        let mut health = DeviceHealth {
+            id: Uuid::new_v4(),
+            device_id: "abc".to_string(),
+            name: "Till".to_string(),
+            business_id: Uuid::new_v4(),
+            status: DeviceStatus::Active,
+            last_seen_at: None,
+            firmware_version: Some("1.0".to_string()),
+            app_version: Some("2.3".to_string()),
+            battery: Some(80),
+            signal: Some(60),
+            clock_drift_seconds: Some(-45),
+            issues: Vec::new(),
+        };
+        let policy = FleetPolicy { min_app_version: Some("2.4".to_string()), min_firmware_version: None };
+        assert_eq!(policy.issues(&health, now), vec![HealthIssue::Offline, HealthIssue::OutOfDate, HealthIssue::ClockDrift]);
+        health.last_seen_at = Some(now);
+        health.app_version = Some("2.4.1".to_string());
+        health.clock_drift_seconds = Some(3);
+        assert!(policy.issues(&health, now).is_empty());
+    }
+}
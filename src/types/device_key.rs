@@ -0,0 +1,139 @@
+use chrono::{DateTime, Utc};
+use handle_error::Error;
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgRow, Postgres, Row, Transaction};
+use uuid::Uuid;
+
+use crate::{db_store::Store, types::device::{DeviceStatus, StatusAuthority}};
+
+// One generation of a terminal's `price_key` and `id_key`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DeviceKey {
+    pub id: Uuid,
+    pub device_id: Uuid,
+    pub version: i32,
+    #[serde(skip_serializing)]
+    pub price_key: String,
+    #[serde(skip_serializing)]
+    pub id_key: String,
+    pub activated_at: DateTime<Utc>,
+    pub retires_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+fn device_key_from_row(row: PgRow) -> DeviceKey {
+    DeviceKey {
+        id: row.get("id"),
+        device_id: row.get("device_id"),
+        version: row.get("version"),
+        price_key: row.get("price_key"),
+        id_key: row.get("id_key"),
+        activated_at: row.get("activated_at"),
+        retires_at: row.get("retires_at"),
+        created_at: row.get("created_at"),
+    }
+}
+
+// Retires every key at once and puts the terminal back to pending, it has to redeem a new activation code and keeps its `device_id`.
+// Compare-and-set on the status like any other status change
+pub(crate) async fn revoke_device_keys_in(tx: &mut Transaction<'_, Postgres>, device_id: Uuid, from: DeviceStatus, set_by: StatusAuthority) -> Result<bool, Error> {
+    let query = r#"
+        UPDATE devices SET status = 'pending', activated_at = NULL, status_reason = 'keys revoked', status_set_by = $3,
+            status_changed_at = now(), updated_at = now()
+        WHERE id = $1 AND status = $2
+    "#;
+    let result = sqlx::query(query)
+        .bind(device_id)
+        .bind(from.as_str())
+        .bind(set_by.as_str())
+        .execute(&mut *tx)
+        .await
+        .map_err(Error::DatabaseQueryError)?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+    sqlx::query("UPDATE device_keys SET retires_at = now() WHERE device_id = $1 AND (retires_at IS NULL OR retires_at > now())")
+        .bind(device_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(Error::DatabaseQueryError)?;
+    Ok(true)
+}
+
+impl Store {
+    // The live key of that version, or the newest live key when the terminal did not say
+    pub async fn get_live_device_key(&self, device_id: Uuid, version: Option<i32>) -> Result<Option<DeviceKey>, Error> {
+        let query = r#"
+            SELECT * FROM device_keys
+            WHERE device_id = $1
+                AND ($2::integer IS NULL OR version = $2)
+                AND activated_at <= now()
+                AND (retires_at IS NULL OR retires_at > now())
+            ORDER BY version DESC
+            LIMIT 1
+        "#;
+        sqlx::query(query)
+            .bind(device_id)
+            .bind(version)
+            .map(device_key_from_row)
+            .fetch_optional(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    pub async fn get_device_keys(&self, device_id: Uuid) -> Result<Vec<DeviceKey>, Error> {
+        sqlx::query("SELECT * FROM device_keys WHERE device_id = $1 ORDER BY version DESC")
+            .bind(device_id)
+            .map(device_key_from_row)
+            .fetch_all(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    // Adds the next version and retires every older live key at `retires_at` at the latest.
+    // The row lock keeps two rotations of one terminal from picking the same version
+    pub async fn rotate_device_keys(&self, device_id: Uuid, price_key: &str, id_key: &str, retires_at: DateTime<Utc>) -> Result<DeviceKey, Error> {
+        let mut tx = self.connection.begin().await
+            .map_err(Error::DatabaseQueryError)?;
+        sqlx::query("SELECT id FROM devices WHERE id = $1 FOR UPDATE")
+            .bind(device_id)
+            .fetch_one(&mut tx)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        let query = r#"
+            UPDATE device_keys SET retires_at = $2
+            WHERE device_id = $1 AND (retires_at IS NULL OR retires_at > $2)
+        "#;
+        sqlx::query(query)
+            .bind(device_id)
+            .bind(retires_at)
+            .execute(&mut tx)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        let query = r#"
+            INSERT INTO device_keys (device_id, version, price_key, id_key)
+            SELECT $1, COALESCE(MAX(version), 0) + 1, $2, $3 FROM device_keys WHERE device_id = $1
+            RETURNING *
+        "#;
+        let key = sqlx::query(query)
+            .bind(device_id)
+            .bind(price_key)
+            .bind(id_key)
+            .map(device_key_from_row)
+            .fetch_one(&mut tx)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        // `devices` keeps mirroring the newest key for the admin views that still read it
+        sqlx::query("UPDATE devices SET price_key = $2, id_key = $3, updated_at = now() WHERE id = $1")
+            .bind(device_id)
+            .bind(price_key)
+            .bind(id_key)
+            .execute(&mut tx)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        tx.commit().await
+            .map_err(Error::DatabaseQueryError)?;
+        Ok(key)
+    }
+
+}
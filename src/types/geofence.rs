@@ -0,0 +1,185 @@
+use handle_error::Error;
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::{PgExecutor, PgRow}, Row};
+use uuid::Uuid;
+
+use crate::{db_store::Store, types::business::GeoPoint};
+
+const EARTH_RADIUS_M: f64 = 6_371_000.0;
+const MAX_RADIUS_M: i32 = 50_000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GeofenceAction {
+    #[default]
+    Off,
+    Flag,
+    Reject,
+}
+
+impl GeofenceAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GeofenceAction::Off => "off",
+            GeofenceAction::Flag => "flag",
+            GeofenceAction::Reject => "reject",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<GeofenceAction> {
+        match value {
+            "off" => Some(GeofenceAction::Off),
+            "flag" => Some(GeofenceAction::Flag),
+            "reject" => Some(GeofenceAction::Reject),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct GeofencePolicy {
+    pub radius_m: Option<i32>,
+    pub action: GeofenceAction,
+}
+
+// Where a payment was taken and how it stood against the business's policy
+#[derive(Debug, Clone, Default)]
+pub struct GeofenceCheck {
+    pub location: Option<GeoPoint>,
+    pub distance_m: Option<f64>,
+    pub flagged: bool,
+    pub rejected: bool,
+    // The policy is active but the terminal sent no position, GPS is optional so this is neither flagged nor refused
+    pub location_missing: bool,
+}
+
+impl GeoPoint {
+    pub fn is_valid(&self) -> bool {
+        (-90.0..=90.0).contains(&self.latitude) && (-180.0..=180.0).contains(&self.longitude)
+    }
+
+    // 0,0 is what an unfilled form leaves behind, no shop sits in the Gulf of Guinea
+    pub fn is_set(&self) -> bool {
+        self.is_valid() && !(self.latitude == 0.0 && self.longitude == 0.0)
+    }
+
+    // Great-circle distance, plenty accurate at the scale of a shop
+    pub fn distance_m(&self, other: &GeoPoint) -> f64 {
+        let (lat1, lat2) = (self.latitude.to_radians(), other.latitude.to_radians());
+        let d_lat = lat2 - lat1;
+        let d_lon = (other.longitude - self.longitude).to_radians();
+        let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_M * a.sqrt().asin()
+    }
+}
+
+impl GeofencePolicy {
+    // A fence needs a real position to measure from
+    pub fn validate(&self, business: &GeoPoint) -> Result<(), Error> {
+        match (self.action, self.radius_m) {
+            (GeofenceAction::Off, None) => Ok(()),
+            (GeofenceAction::Off, Some(radius)) if radius > 0 && radius <= MAX_RADIUS_M => Ok(()),
+            (_, Some(radius)) if radius > 0 && radius <= MAX_RADIUS_M && business.is_set() => Ok(()),
+            _ => Err(Error::MissingParameters),
+        }
+    }
+
+    // Only a reported position beyond the radius breaches the fence, a payment without one is let through and marked as such
+    pub fn check(&self, business: &GeoPoint, location: Option<GeoPoint>) -> GeofenceCheck {
+        let location = location.filter(GeoPoint::is_valid);
+        let distance_m = location.as_ref().map(|location| location.distance_m(business));
+        let active = self.action != GeofenceAction::Off;
+        let outside = active && match (self.radius_m, distance_m) {
+            (Some(radius), Some(distance)) => distance > radius as f64,
+            _ => false,
+        };
+        GeofenceCheck {
+            rejected: outside && self.action == GeofenceAction::Reject,
+            flagged: outside,
+            location_missing: active && location.is_none(),
+            location,
+            distance_m,
+        }
+    }
+}
+
+pub(crate) async fn update_business_geofence_in<'c>(executor: impl PgExecutor<'c>, business_id: Uuid, policy: &GeofencePolicy) -> Result<bool, Error> {
+    sqlx::query("UPDATE businesses SET geofence_radius_m = $2, geofence_action = $3, updated_at = now() WHERE id = $1")
+        .bind(business_id)
+        .bind(policy.radius_m)
+        .bind(policy.action.as_str())
+        .execute(executor)
+        .await
+        .map(|result| result.rows_affected() > 0)
+        .map_err(Error::DatabaseQueryError)
+}
+
+impl Store {
+    // The business's registered position with its policy
+    pub async fn get_business_geofence(&self, business_id: Uuid) -> Result<(GeoPoint, GeofencePolicy), Error> {
+        sqlx::query("SELECT lat, lon, geofence_radius_m, geofence_action FROM businesses WHERE id = $1")
+            .bind(business_id)
+            .map(|row: PgRow| {
+                let point = GeoPoint { latitude: row.get("lat"), longitude: row.get("lon") };
+                let policy = GeofencePolicy {
+                    radius_m: row.get("geofence_radius_m"),
+                    action: GeofenceAction::parse(row.get("geofence_action")).unwrap_or_default(),
+                };
+                (point, policy)
+            })
+            .fetch_one(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    pub async fn update_business_geofence(&self, business_id: Uuid, policy: &GeofencePolicy) -> Result<bool, Error> {
+        update_business_geofence_in(&self.connection, business_id, policy).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(latitude: f64, longitude: f64) -> GeoPoint {
+        GeoPoint { latitude, longitude }
+    }
+
+    #[test]
+    fn payments_far_from_the_business_are_caught() {
+        let shop = point(6.5244, 3.3792);
+        let nearby = point(6.5249, 3.3795);
+        let across_town = point(6.6018, 3.3515);
+        assert!(shop.distance_m(&nearby) < 100.0);
+        assert!((shop.distance_m(&across_town) - 9_100.0).abs() < 200.0);
+
+        let flag = GeofencePolicy { radius_m: Some(200), action: GeofenceAction::Flag };
+        assert!(!flag.check(&shop, Some(nearby.clone())).flagged);
+        let far = flag.check(&shop, Some(across_town.clone()));
+        assert!(far.flagged && !far.rejected);
+
+        let reject = GeofencePolicy { radius_m: Some(200), action: GeofenceAction::Reject };
+        assert!(reject.check(&shop, Some(across_town)).rejected);
+        let unknown = reject.check(&shop, None);
+        assert!(unknown.location_missing && !unknown.flagged && !unknown.rejected);
+        assert!(reject.check(&shop, Some(point(120.0, 3.3792))).location_missing);
+        let unknown = flag.check(&shop, None);
+        assert!(unknown.location_missing && !unknown.flagged && !unknown.rejected);
+        assert!(!flag.check(&shop, Some(nearby)).location_missing);
+
+        let off = GeofencePolicy::default().check(&shop, None);
+        assert!(!off.flagged && !off.location_missing);
+    }
+
+    #[test]
+    fn active_policies_need_a_radius_and_a_position() {
+        let shop = point(6.5244, 3.3792);
+        let unset = point(0.0, 0.0);
+        assert!(GeofencePolicy::default().validate(&unset).is_ok());
+        assert!(GeofencePolicy { radius_m: None, action: GeofenceAction::Reject }.validate(&shop).is_err());
+        assert!(GeofencePolicy { radius_m: Some(0), action: GeofenceAction::Flag }.validate(&shop).is_err());
+        assert!(GeofencePolicy { radius_m: Some(500), action: GeofenceAction::Flag }.validate(&shop).is_ok());
+        assert!(GeofencePolicy { radius_m: Some(500), action: GeofenceAction::Reject }.validate(&unset).is_err());
+        assert!(GeofencePolicy { radius_m: Some(500), action: GeofenceAction::Off }.validate(&unset).is_ok());
+    }
+}
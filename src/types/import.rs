@@ -0,0 +1,375 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Utc};
+use handle_error::Error;
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgRow, Postgres, Row, Transaction};
+use uuid::Uuid;
+
+use encrypt::generate_random_char;
+
+use crate::{db_store::Store, types::{account::{add_account_in, Account}, business::{add_business_in, Business, GeoPoint}, device::{insert_device, Device, DeviceStatus}}};
+
+pub const MAX_IMPORT_ROWS: usize = 1000;
+const MAX_FIELD_LEN: usize = 64;
+const MAX_ACCOUNT_NUMBER_LEN: usize = 20;
+
+// One line of the import, a business with optionally an account and a terminal paying into it.
+// Rows naming the same business or account share it, anything not in the organization yet is created
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ImportRow {
+    #[serde(default)]
+    pub business_name: Option<String>,
+    #[serde(default)]
+    pub business_location: Option<String>,
+    #[serde(default)]
+    pub latitude: Option<f64>,
+    #[serde(default)]
+    pub longitude: Option<f64>,
+    #[serde(default)]
+    pub bank_id: Option<String>,
+    #[serde(default)]
+    pub account_name: Option<String>,
+    #[serde(default)]
+    pub account_number: Option<String>,
+    #[serde(default)]
+    pub device_name: Option<String>,
+    #[serde(default)]
+    pub device_type: Option<String>,
+}
+
+// `row` counts data rows from 1, 0 is the file as a whole
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct RowError {
+    pub row: usize,
+    pub field: Option<String>,
+    pub message: String,
+}
+
+impl RowError {
+    fn new(row: usize, field: Option<&str>, message: impl ToString) -> RowError {
+        RowError { row, field: field.map(str::to_string), message: message.to_string() }
+    }
+}
+
+// What already exists, so rows can point at it instead of creating duplicates
+#[derive(Debug, Clone, Default)]
+pub struct ImportContext {
+    pub banks: HashSet<String>,
+    // Lowercased name of each business of the organization
+    pub businesses: HashMap<String, Uuid>,
+    // Accounts of the organization keyed by bank and account number, with the name each is held under
+    pub accounts: HashMap<(String, String), Uuid>,
+    pub account_names: HashMap<Uuid, String>,
+}
+
+#[derive(Debug)]
+pub struct PlannedDevice {
+    pub row: usize,
+    pub device: Device,
+}
+
+// Everything the import will create, ids are assigned up front so rows can refer to each other
+#[derive(Debug, Default)]
+pub struct ImportPlan {
+    pub businesses: Vec<Business>,
+    pub accounts: Vec<Account>,
+    pub devices: Vec<PlannedDevice>,
+}
+
+// A redeemable code for one imported terminal, only its hash is stored
+#[derive(Debug, Clone)]
+pub struct ImportActivation {
+    pub device_id: Uuid,
+    pub code_hash: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+fn text(value: &Option<String>) -> Option<&str> {
+    value.as_deref().map(str::trim).filter(|value| !value.is_empty())
+}
+
+fn required<'a>(row: usize, field: &str, value: &'a Option<String>, errors: &mut Vec<RowError>) -> Option<&'a str> {
+    match text(value) {
+        Some(value) if value.chars().count() > MAX_FIELD_LEN => {
+            errors.push(RowError::new(row, Some(field), format!("must be at most {} characters", MAX_FIELD_LEN)));
+            None
+        }
+        Some(value) => Some(value),
+        None => {
+            errors.push(RowError::new(row, Some(field), "is required"));
+            None
+        }
+    }
+}
+
+// A header row naming the `ImportRow` fields, empty cells are left out
+pub fn parse_csv(bytes: &[u8]) -> Result<Vec<ImportRow>, Vec<RowError>> {
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(bytes);
+    let mut rows = Vec::new();
+    let mut errors = Vec::new();
+    for (index, record) in reader.deserialize::<ImportRow>().enumerate() {
+        match record {
+            Ok(row) => rows.push(row),
+            Err(e) => errors.push(RowError::new(index + 1, None, e)),
+        }
+    }
+    if errors.is_empty() { Ok(rows) } else { Err(errors) }
+}
+
+// An array of `ImportRow` objects
+pub fn parse_json(bytes: &[u8]) -> Result<Vec<ImportRow>, Vec<RowError>> {
+    serde_json::from_slice(bytes).map_err(|e| vec![RowError::new(0, None, e)])
+}
+
+// Checks every row before anything is written, all errors are reported at once
+pub fn plan_import(rows: &[ImportRow], context: &ImportContext, organization_id: Uuid, owner_id: Uuid) -> Result<ImportPlan, Vec<RowError>> {
+    let mut errors = Vec::new();
+    if rows.is_empty() || rows.len() > MAX_IMPORT_ROWS {
+        return Err(vec![RowError::new(0, None, format!("expected between 1 and {} rows", MAX_IMPORT_ROWS))]);
+    }
+    let now = Utc::now();
+    let mut plan = ImportPlan::default();
+    let mut businesses = context.businesses.clone();
+    let mut accounts = context.accounts.clone();
+    // Row that introduced each new business or account, to point conflicting rows at it
+    let mut business_rows: HashMap<Uuid, usize> = HashMap::new();
+    let mut account_rows: HashMap<Uuid, usize> = HashMap::new();
+
+    for (index, line) in rows.iter().enumerate() {
+        let row = index + 1;
+        let before = errors.len();
+
+        let Some(business_name) = required(row, "business_name", &line.business_name, &mut errors) else { continue };
+        let business_id = match businesses.get(&business_name.to_lowercase()) {
+            Some(id) => {
+                let planned = plan.businesses.iter().find(|business| business.id == *id);
+                if let Some(planned) = planned {
+                    let same_place = text(&line.business_location).is_none_or(|location| location == planned.location)
+                        && line.latitude.is_none_or(|latitude| latitude == planned.lat)
+                        && line.longitude.is_none_or(|longitude| longitude == planned.long);
+                    if !same_place {
+                        errors.push(RowError::new(row, Some("business_location"), format!("differs from row {}", business_rows[id])));
+                    }
+                }
+                Some(*id)
+            }
+            None => {
+                let location = required(row, "business_location", &line.business_location, &mut errors);
+                let point = match (line.latitude, line.longitude) {
+                    (Some(latitude), Some(longitude)) => Some(GeoPoint { latitude, longitude }).filter(GeoPoint::is_valid),
+                    _ => None,
+                };
+                if point.is_none() {
+                    errors.push(RowError::new(row, Some("latitude"), "a valid latitude and longitude are required for a new business"));
+                }
+                match (location, point) {
+                    (Some(location), Some(point)) => {
+                        let business = Business {
+                            id: Uuid::new_v4(),
+                            user_id: owner_id,
+                            organization_id,
+                            name: business_name.to_string(),
+                            location: location.to_string(),
+                            geolocation: (point.longitude, point.latitude),
+                            lat: point.latitude,
+                            long: point.longitude,
+                            created_at: now,
+                            updated_at: now,
+                        };
+                        businesses.insert(business_name.to_lowercase(), business.id);
+                        business_rows.insert(business.id, row);
+                        let id = business.id;
+                        plan.businesses.push(business);
+                        Some(id)
+                    }
+                    _ => None,
+                }
+            }
+        };
+
+        let has_account = [&line.bank_id, &line.account_name, &line.account_number].iter().any(|value| text(value).is_some());
+        let account_id = if has_account {
+            let bank_id = required(row, "bank_id", &line.bank_id, &mut errors);
+            if bank_id.is_some_and(|bank_id| !context.banks.contains(bank_id)) {
+                errors.push(RowError::new(row, Some("bank_id"), "is not a known bank"));
+            }
+            let account_number = required(row, "account_number", &line.account_number, &mut errors);
+            if account_number.is_some_and(|number| number.len() > MAX_ACCOUNT_NUMBER_LEN || !number.chars().all(|c| c.is_ascii_digit())) {
+                errors.push(RowError::new(row, Some("account_number"), format!("must be at most {} digits", MAX_ACCOUNT_NUMBER_LEN)));
+            }
+            match (bank_id, account_number) {
+                (Some(bank_id), Some(account_number)) => match accounts.get(&(bank_id.to_string(), account_number.to_string())) {
+                    Some(id) => {
+                        let differs = |held: &str| text(&line.account_name).is_some_and(|name| name != held);
+                        match plan.accounts.iter().find(|account| account.id == *id) {
+                            Some(planned) if differs(&planned.account_name) => {
+                                errors.push(RowError::new(row, Some("account_name"), format!("differs from row {}", account_rows[id])));
+                            }
+                            None if context.account_names.get(id).is_some_and(|held| differs(held)) => {
+                                errors.push(RowError::new(row, Some("account_name"), "differs from the name on the existing account"));
+                            }
+                            _ => {}
+                        }
+                        Some(*id)
+                    }
+                    None => required(row, "account_name", &line.account_name, &mut errors).map(|account_name| {
+                        let account = Account {
+                            id: Uuid::new_v4(),
+                            organization_id: Some(organization_id),
+                            bank_id: bank_id.to_string(),
+                            account_name: account_name.to_string(),
+                            account_number: account_number.to_string(),
+                            created_at: now,
+                            updated_at: now,
+                        };
+                        accounts.insert((bank_id.to_string(), account_number.to_string()), account.id);
+                        account_rows.insert(account.id, row);
+                        let id = account.id;
+                        plan.accounts.push(account);
+                        id
+                    }),
+                },
+                _ => None,
+            }
+        } else {
+            None
+        };
+
+        let has_device = text(&line.device_name).is_some() || text(&line.device_type).is_some();
+        if has_device {
+            let name = required(row, "device_name", &line.device_name, &mut errors);
+            let device_type = required(row, "device_type", &line.device_type, &mut errors);
+            if !has_account {
+                errors.push(RowError::new(row, Some("account_number"), "a device needs an account to pay into"));
+            }
+            if let (Some(name), Some(device_type), Some(business_id), Some(account_id)) = (name, device_type, business_id, account_id) {
+                if errors.len() == before {
+                    plan.devices.push(PlannedDevice {
+                        row,
+                        device: Device {
+                            id: Uuid::new_v4(),
+                            device_id: generate_random_char(16),
+                            name: name.to_string(),
+                            account_id,
+                            device_type: device_type.to_string(),
+                            apk_key: generate_random_char(16),
+                            price_key: generate_random_char(16),
+                            id_key: generate_random_char(16),
+                            business_id,
+                            // Same as a single device, the keys are replaced when the activation code is redeemed
+                            status: DeviceStatus::Pending,
+                            activated_at: None,
+                            status_reason: None,
+                            status_changed_at: Some(now),
+                            status_set_by: None,
+                            created_at: now,
+                            updated_at: now,
+                        },
+                    });
+                }
+            }
+        }
+    }
+    if errors.is_empty() { Ok(plan) } else { Err(errors) }
+}
+
+// All or nothing, a failing row rolls the whole import back
+pub(crate) async fn apply_import_in(tx: &mut Transaction<'_, Postgres>, plan: &ImportPlan, owner_id: Uuid, created_by: Uuid, activations: &[ImportActivation]) -> Result<bool, Error> {
+    for business in plan.businesses.iter() {
+        add_business_in(&mut *tx, business).await?;
+    }
+    for account in plan.accounts.iter() {
+        add_account_in(&mut *tx, account).await?;
+    }
+    for planned in plan.devices.iter() {
+        insert_device(tx, &planned.device, owner_id).await?;
+    }
+    for activation in activations.iter() {
+        sqlx::query("INSERT INTO device_activations (device_id, code_hash, created_by, expires_at) VALUES ($1, $2, $3, $4)")
+            .bind(activation.device_id)
+            .bind(&activation.code_hash)
+            .bind(created_by)
+            .bind(activation.expires_at)
+            .execute(&mut *tx)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+    }
+    Ok(true)
+}
+
+impl Store {
+    pub async fn get_import_context(&self, organization_id: Uuid) -> Result<ImportContext, Error> {
+        let banks = self.get_banks().await?.into_iter().map(|bank| bank.id).collect();
+        let businesses = sqlx::query("SELECT id, name FROM businesses WHERE organization_id = $1")
+            .bind(organization_id)
+            .map(|row: PgRow| (row.get::<String, _>("name").to_lowercase(), row.get("id")))
+            .fetch_all(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)?
+            .into_iter()
+            .collect();
+        let rows: Vec<(Uuid, String, String, String)> = sqlx::query_as("SELECT id, bank_id, account_number, account_name FROM accounts WHERE organization_id = $1")
+            .bind(organization_id)
+            .fetch_all(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        let accounts = rows.iter().map(|(id, bank_id, account_number, _)| ((bank_id.clone(), account_number.clone()), *id)).collect();
+        let account_names = rows.into_iter().map(|(id, _, _, account_name)| (id, account_name)).collect();
+        Ok(ImportContext { banks, businesses, accounts, account_names })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> ImportContext {
+        let mut context = ImportContext::default();
+        context.banks.insert("058".to_string());
+        context.businesses.insert("ikeja mall".to_string(), Uuid::new_v4());
+        context
+    }
+
+    #[test]
+    fn rows_share_businesses_and_accounts() {
+        let csv = b"business_name,business_location,latitude,longitude,bank_id,account_name,account_number,device_name,device_type\n\
+            Lekki Branch,Admiralty Way,6.4474,3.4723,058,Chain Ltd,0123456789,Till 1,pos\n\
+            lekki branch,,,,058,,0123456789,Till 2,pos\n\
+            Ikeja Mall,,,,058,Chain Ltd,0123456789,Till 3,pos\n";
+        let rows = parse_csv(csv).unwrap();
+        let plan = plan_import(&rows, &context(), Uuid::new_v4(), Uuid::new_v4()).unwrap();
+        assert_eq!(plan.businesses.len(), 1);
+        assert_eq!(plan.accounts.len(), 1);
+        assert_eq!(plan.devices.len(), 3);
+        assert!(plan.devices.iter().all(|planned| planned.device.account_id == plan.accounts[0].id));
+    }
+
+    #[test]
+    fn every_bad_row_is_reported() {
+        let rows = vec![
+            ImportRow { business_name: Some("Yaba".to_string()), device_name: Some("Till".to_string()), device_type: Some("pos".to_string()), ..Default::default() },
+            ImportRow { business_name: Some("Ikeja Mall".to_string()), bank_id: Some("999".to_string()), account_name: Some("Chain".to_string()), account_number: Some("12ab".to_string()), ..Default::default() },
+        ];
+        let errors = plan_import(&rows, &context(), Uuid::new_v4(), Uuid::new_v4()).unwrap_err();
+        let fields: Vec<(usize, Option<&str>)> = errors.iter().map(|e| (e.row, e.field.as_deref())).collect();
+        assert!(fields.contains(&(1, Some("business_location"))));
+        assert!(fields.contains(&(1, Some("account_number"))));
+        assert!(fields.contains(&(2, Some("bank_id"))));
+        assert!(fields.contains(&(2, Some("account_number"))));
+        assert_eq!(parse_csv(b"business_name,latitude\nShop,north\n").unwrap_err()[0].row, 1);
+    }
+
+    #[test]
+    fn existing_account_name_has_to_match() {
+        let mut context = context();
+        let id = Uuid::new_v4();
+        context.accounts.insert(("058".to_string(), "0123456789".to_string()), id);
+        context.account_names.insert(id, "Chain Ltd".to_string());
+        let row = |account_name: &str| ImportRow { business_name: Some("Ikeja Mall".to_string()), bank_id: Some("058".to_string()), account_name: Some(account_name.to_string()), account_number: Some("0123456789".to_string()), ..Default::default() };
+        let plan = plan_import(&[row("Chain Ltd")], &context, Uuid::new_v4(), Uuid::new_v4()).unwrap();
+        assert!(plan.accounts.is_empty());
+        let errors = plan_import(&[row("Someone Else")], &context, Uuid::new_v4(), Uuid::new_v4()).unwrap_err();
+        assert_eq!((errors[0].row, errors[0].field.as_deref()), (1, Some("account_name")));
+    }
+}
@@ -0,0 +1,197 @@
+use chrono::{DateTime, Duration, Utc};
+use handle_error::Error;
+use sqlx::{postgres::PgRow, Row};
+
+use crate::db_store::Store;
+
+// Failures allowed before each further attempt has to wait
+const BACKOFF_AFTER: i32 = 3;
+const MAX_BACKOFF_SECONDS: i64 = 300;
+// Failures that lock the email or IP out completely
+const LOCKOUT_AFTER: i32 = 10;
+const LOCKOUT_MINUTES: i64 = 15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttemptScope {
+    Email,
+    Ip,
+    // Activation code guesses per IP, kept apart so a mistyped code never locks out sign-in
+    DeviceActivation,
+    // Every sign-in link request counts, so one inbox cannot be flooded with links
+    MagicLinkSend,
+}
+
+impl AttemptScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AttemptScope::Email => "email",
+            AttemptScope::Ip => "ip",
+            AttemptScope::DeviceActivation => "device_activation",
+            AttemptScope::MagicLinkSend => "magic_link_send",
+        }
+    }
+}
+
+// How long to block after `failures` consecutive failures, and whether it counts as a lockout
+pub fn block_for(failures: i32) -> Option<(Duration, bool)> {
+    if failures >= LOCKOUT_AFTER {
+        return Some((Duration::minutes(LOCKOUT_MINUTES), true));
+    }
+    if failures >= BACKOFF_AFTER {
+        let seconds = 1_i64 << (failures - BACKOFF_AFTER).min(16);
+        return Some((Duration::seconds(seconds.min(MAX_BACKOFF_SECONDS)), false));
+    }
+    None
+}
+
+// Seconds to block after 0, 1, .. LOCKOUT_AFTER failures, 0 for none. Bound as `$3` so the upserts apply `block_for` themselves
+fn block_schedule() -> Vec<f64> {
+    (0..=LOCKOUT_AFTER)
+        .map(|failures| block_for(failures).map(|(duration, _)| duration.num_seconds() as f64).unwrap_or(0.0))
+        .collect()
+}
+
+// The failure count after one more failure, a counter idle for an hour starts over
+const NEXT_FAILURES: &str = "CASE WHEN login_attempts.last_failed_at < now() - interval '1 hour' THEN 1 ELSE login_attempts.failures + 1 END";
+
+// `locked_until` for the failure count `failures` evaluates to, NULL when the schedule does not block yet
+fn locked_until_after(failures: &str) -> String {
+    format!("now() + NULLIF(($3::float8[])[LEAST({}, cardinality($3::float8[]) - 1) + 1], 0) * interval '1 second'", failures)
+}
+
+impl Store {
+    // When the email or IP may try again, None if it is not blocked
+    pub async fn get_login_locked_until(&self, scope: AttemptScope, key: &str) -> Result<Option<DateTime<Utc>>, Error> {
+        sqlx::query("SELECT locked_until FROM login_attempts WHERE scope = $1 AND key = $2")
+            .bind(scope.as_str())
+            .bind(key)
+            .map(|row: PgRow| row.get::<Option<DateTime<Utc>>, _>("locked_until"))
+            .fetch_optional(&self.connection)
+            .await
+            .map(|locked| locked.flatten().filter(|until| *until > Utc::now()))
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    // Counts one more failure and applies backoff or lockout in the same upsert, counters older than an hour start over
+    pub async fn record_login_failure(&self, scope: AttemptScope, key: &str) -> Result<bool, Error> {
+        let query = format!(r#"
+            INSERT INTO login_attempts (scope, key, failures, last_failed_at, locked_until)
+            VALUES ($1, $2, 1, now(), {first_lock})
+            ON CONFLICT (scope, key) DO UPDATE SET
+                failures = {next},
+                last_failed_at = now(),
+                locked_until = {next_lock}
+            RETURNING failures
+        "#, first_lock = locked_until_after("1"), next = NEXT_FAILURES, next_lock = locked_until_after(NEXT_FAILURES));
+        let failures: i32 = sqlx::query_scalar(&query)
+            .bind(scope.as_str())
+            .bind(key)
+            .bind(block_schedule())
+            .fetch_one(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        self.note_lockout(scope, key, failures).await?;
+        Ok(true)
+    }
+
+    // Counts the attempt before the credential is checked, so parallel requests queue on the row and each one sees
+    // the previous count. Returns when the key may try again if it is blocked, the blocked attempt is not counted.
+    // Clear (or release) the claim when the attempt succeeds
+    pub async fn claim_login_attempt(&self, scope: AttemptScope, key: &str) -> Result<Option<DateTime<Utc>>, Error> {
+        let query = format!(r#"
+            INSERT INTO login_attempts (scope, key, failures, last_failed_at, locked_until)
+            VALUES ($1, $2, 1, now(), {first_lock})
+            ON CONFLICT (scope, key) DO UPDATE SET
+                failures = CASE WHEN login_attempts.locked_until > now() THEN login_attempts.failures ELSE {next} END,
+                last_failed_at = CASE WHEN login_attempts.locked_until > now() THEN login_attempts.last_failed_at ELSE now() END,
+                locked_until = CASE WHEN login_attempts.locked_until > now() THEN login_attempts.locked_until ELSE {next_lock} END
+            RETURNING failures, locked_until, last_failed_at = now() AS claimed
+        "#, first_lock = locked_until_after("1"), next = NEXT_FAILURES, next_lock = locked_until_after(NEXT_FAILURES));
+        let (failures, locked_until, claimed): (i32, Option<DateTime<Utc>>, bool) = sqlx::query_as(&query)
+            .bind(scope.as_str())
+            .bind(key)
+            .bind(block_schedule())
+            .fetch_one(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        if !claimed {
+            return Ok(locked_until);
+        }
+        self.note_lockout(scope, key, failures).await?;
+        Ok(None)
+    }
+
+    // Takes back a claim that turned out to be a success without forgetting earlier failures,
+    // for keys like the IP that a successful sign-in must not reset
+    pub async fn release_login_attempt(&self, scope: AttemptScope, key: &str) -> Result<bool, Error> {
+        let query = r#"
+            UPDATE login_attempts SET
+                failures = GREATEST(failures - 1, 0),
+                locked_until = CASE WHEN ($3::float8[])[LEAST(GREATEST(failures - 1, 0), cardinality($3::float8[]) - 1) + 1] > 0 THEN locked_until END
+            WHERE scope = $1 AND key = $2
+        "#;
+        sqlx::query(query)
+            .bind(scope.as_str())
+            .bind(key)
+            .bind(block_schedule())
+            .execute(&self.connection)
+            .await
+            .map(|_| true)
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    pub async fn clear_login_failures(&self, scope: AttemptScope, key: &str) -> Result<bool, Error> {
+        sqlx::query("DELETE FROM login_attempts WHERE scope = $1 AND key = $2")
+            .bind(scope.as_str())
+            .bind(key)
+            .execute(&self.connection)
+            .await
+            .map(|_| true)
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    async fn note_lockout(&self, scope: AttemptScope, key: &str, failures: i32) -> Result<(), Error> {
+        if let Some((duration, true)) = block_for(failures) {
+            self.add_login_lockout(scope, key, failures, Utc::now() + duration).await?;
+        }
+        Ok(())
+    }
+
+    async fn add_login_lockout(&self, scope: AttemptScope, key: &str, failures: i32, locked_until: DateTime<Utc>) -> Result<bool, Error> {
+        let query = r#"
+            INSERT INTO login_lockouts (scope, key, failures, locked_until)
+            VALUES ($1, $2, $3, $4)
+        "#;
+        sqlx::query(query)
+            .bind(scope.as_str())
+            .bind(key)
+            .bind(failures)
+            .bind(locked_until)
+            .execute(&self.connection)
+            .await
+            .map(|_| true)
+            .map_err(Error::DatabaseQueryError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_then_locks_out() {
+        assert!(block_for(BACKOFF_AFTER - 1).is_none());
+        assert_eq!(block_for(BACKOFF_AFTER), Some((Duration::seconds(1), false)));
+        assert_eq!(block_for(BACKOFF_AFTER + 2), Some((Duration::seconds(4), false)));
+        assert_eq!(block_for(LOCKOUT_AFTER), Some((Duration::minutes(LOCKOUT_MINUTES), true)));
+    }
+
+    #[test]
+    fn schedule_matches_block_for() {
+        let schedule = block_schedule();
+        assert_eq!(schedule.len(), LOCKOUT_AFTER as usize + 1);
+        assert_eq!(schedule[(BACKOFF_AFTER - 1) as usize], 0.0);
+        assert_eq!(schedule[BACKOFF_AFTER as usize], 1.0);
+        assert_eq!(schedule[LOCKOUT_AFTER as usize], (LOCKOUT_MINUTES * 60) as f64);
+    }
+}
@@ -0,0 +1,44 @@
+use chrono::{DateTime, Utc};
+use handle_error::Error;
+use sqlx::{postgres::PgRow, Row};
+use uuid::Uuid;
+
+use crate::db_store::Store;
+
+impl Store {
+    // A new link replaces any link the user has not used yet
+    pub async fn add_magic_link(&self, user_id: Uuid, token_hash: &str, user_agent_hash: &str, expires_at: DateTime<Utc>) -> Result<bool, Error> {
+        let mut tx = self.connection.begin().await.map_err(Error::DatabaseQueryError)?;
+        sqlx::query("DELETE FROM magic_links WHERE user_id = $1 AND used_at IS NULL")
+            .bind(user_id)
+            .execute(&mut tx)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        sqlx::query("INSERT INTO magic_links (user_id, token_hash, user_agent_hash, expires_at) VALUES ($1, $2, $3, $4)")
+            .bind(user_id)
+            .bind(token_hash)
+            .bind(user_agent_hash)
+            .bind(expires_at)
+            .execute(&mut tx)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        tx.commit().await.map_err(Error::DatabaseQueryError)?;
+        Ok(true)
+    }
+
+    // Marks the link used and returns its user, None when unknown, used, expired or opened from another browser
+    pub async fn redeem_magic_link(&self, token_hash: &str, user_agent_hash: &str) -> Result<Option<Uuid>, Error> {
+        let query = r#"
+            UPDATE magic_links SET used_at = now()
+            WHERE token_hash = $1 AND user_agent_hash = $2 AND used_at IS NULL AND expires_at > now()
+            RETURNING user_id
+        "#;
+        sqlx::query(query)
+            .bind(token_hash)
+            .bind(user_agent_hash)
+            .map(|row: PgRow| row.get("user_id"))
+            .fetch_optional(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)
+    }
+}
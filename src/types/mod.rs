@@ -7,5 +7,23 @@ pub mod bank;
 pub mod cache;
 pub mod user;
 pub mod payments;
+pub mod role;
+pub mod admin;
+pub mod login_attempt;
+pub mod organization;
+pub mod api_key;
+pub mod oauth;
+pub mod token;
+pub mod passkey;
+pub mod magic_link;
+pub mod audit;
+pub mod device_key;
+pub mod device_health;
+pub mod device_assignment;
+pub mod config_profile;
+pub mod app_release;
+pub mod geofence;
+pub mod import;
+pub mod request_nonce;
 
 pub mod session;
\ No newline at end of file
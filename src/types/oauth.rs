@@ -0,0 +1,112 @@
+use chrono::{DateTime, Utc};
+use handle_error::Error;
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgRow, Row};
+use uuid::Uuid;
+
+use crate::{db_store::Store, types::api_key::ApiKeyType};
+
+// Space separated `scope` parameter, None when it names a scope we do not know
+pub fn parse_scopes(scope: &str) -> Option<Vec<ApiKeyType>> {
+    let mut scopes: Vec<ApiKeyType> = Vec::new();
+    for value in scope.split_whitespace() {
+        let parsed = ApiKeyType::parse(value)?;
+        if !scopes.contains(&parsed) {
+            scopes.push(parsed);
+        }
+    }
+    Some(scopes)
+}
+
+pub fn format_scopes(scopes: &[ApiKeyType]) -> String {
+    scopes.iter().map(|s| s.as_str()).collect::<Vec<&str>>().join(" ")
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OAuthToken {
+    pub id: Uuid,
+    pub api_key_id: Uuid,
+    pub bank_id: String,
+    pub scopes: Vec<ApiKeyType>,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl OAuthToken {
+    pub fn allows(&self, scope: ApiKeyType) -> bool {
+        self.scopes.contains(&scope)
+    }
+}
+
+fn oauth_token_from_row(row: PgRow) -> OAuthToken {
+    OAuthToken {
+        id: row.get("id"),
+        api_key_id: row.get("api_key_id"),
+        bank_id: row.get("bank_id"),
+        scopes: row.get::<Vec<String>, _>("scopes").iter().filter_map(|s| ApiKeyType::parse(s)).collect(),
+        expires_at: row.get("expires_at"),
+        revoked_at: row.get("revoked_at"),
+        created_at: row.get("created_at"),
+    }
+}
+
+impl Store {
+    pub async fn add_oauth_token(&self, api_key_id: Uuid, bank_id: &str, token_hash: &str, scopes: &[ApiKeyType], expires_at: DateTime<Utc>) -> Result<OAuthToken, Error> {
+        let query = r#"
+            INSERT INTO oauth_access_tokens (api_key_id, bank_id, token_hash, scopes, expires_at)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING *
+        "#;
+        sqlx::query(query)
+            .bind(api_key_id)
+            .bind(bank_id)
+            .bind(token_hash)
+            .bind(scopes.iter().map(|s| s.as_str().to_string()).collect::<Vec<String>>())
+            .bind(expires_at)
+            .map(oauth_token_from_row)
+            .fetch_one(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    // Token matching the hash, None once it expired, was revoked or its API key was revoked
+    pub async fn get_active_oauth_token(&self, token_hash: &str) -> Result<Option<OAuthToken>, Error> {
+        let query = r#"
+            SELECT t.* FROM oauth_access_tokens t
+            JOIN api_keys k ON k.id = t.api_key_id
+            WHERE t.token_hash = $1 AND t.revoked_at IS NULL AND t.expires_at > now()
+                AND k.revoked_at IS NULL AND (k.expires_at IS NULL OR k.expires_at > now())
+        "#;
+        sqlx::query(query)
+            .bind(token_hash)
+            .map(oauth_token_from_row)
+            .fetch_optional(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    // Only the bank that owns the token can revoke it
+    pub async fn revoke_oauth_token(&self, token_hash: &str, bank_id: &str) -> Result<bool, Error> {
+        sqlx::query("UPDATE oauth_access_tokens SET revoked_at = now() WHERE token_hash = $1 AND bank_id = $2 AND revoked_at IS NULL")
+            .bind(token_hash)
+            .bind(bank_id)
+            .execute(&self.connection)
+            .await
+            .map(|result| result.rows_affected() > 0)
+            .map_err(Error::DatabaseQueryError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scope_parameter_round_trips() {
+        assert_eq!(parse_scopes("public metal public"), Some(vec![ApiKeyType::XMinister, ApiKeyType::XMinisterMetal]));
+        assert_eq!(parse_scopes(""), Some(vec![]));
+        assert_eq!(parse_scopes("public admin"), None);
+        assert_eq!(format_scopes(&[ApiKeyType::XMinister, ApiKeyType::XMinisterMetal]), "public metal");
+    }
+}
@@ -0,0 +1,359 @@
+use chrono::{DateTime, Utc};
+use handle_error::Error;
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgRow, Postgres, Row, Transaction};
+use uuid::Uuid;
+
+use crate::{db_store::Store, types::business::Business};
+
+// Role a user holds inside one organization, separate from the platform `Role`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MemberRole {
+    Owner,
+    Manager,
+    Viewer,
+}
+
+impl MemberRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MemberRole::Owner => "owner",
+            MemberRole::Manager => "manager",
+            MemberRole::Viewer => "viewer",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<MemberRole> {
+        match value {
+            "owner" => Some(MemberRole::Owner),
+            "manager" => Some(MemberRole::Manager),
+            "viewer" => Some(MemberRole::Viewer),
+            _ => None,
+        }
+    }
+
+    pub fn can_manage_members(&self) -> bool {
+        *self == MemberRole::Owner
+    }
+
+    // Viewers see the terminals of their businesses, only owners and managers change them
+    pub fn can_manage_devices(&self) -> bool {
+        matches!(self, MemberRole::Owner | MemberRole::Manager)
+    }
+
+    // Where a terminal's money goes is the owner's call alone
+    pub fn can_change_payouts(&self) -> bool {
+        *self == MemberRole::Owner
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Organization {
+    pub id: Uuid,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Membership {
+    pub organization_id: Uuid,
+    pub organization_name: String,
+    pub role: MemberRole,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Member {
+    pub user_id: Uuid,
+    pub first_name: String,
+    pub last_name: String,
+    pub email: String,
+    pub role: MemberRole,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Invitation {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub email: String,
+    pub role: MemberRole,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+pub(crate) async fn add_organization_in(tx: &mut Transaction<'_, Postgres>, name: &str, owner_id: Uuid) -> Result<Organization, Error> {
+    let organization = sqlx::query("INSERT INTO organizations (name) VALUES ($1) RETURNING *")
+        .bind(name)
+        .map(|row: PgRow| Organization {
+            id: row.get("id"),
+            name: row.get("name"),
+            created_at: row.get("created_at"),
+            updated_at: row.get("updated_at"),
+        })
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(Error::DatabaseQueryError)?;
+    sqlx::query("INSERT INTO organization_members (organization_id, user_id, role) VALUES ($1, $2, $3)")
+        .bind(organization.id)
+        .bind(owner_id)
+        .bind(MemberRole::Owner.as_str())
+        .execute(&mut *tx)
+        .await
+        .map_err(Error::DatabaseQueryError)?;
+    Ok(organization)
+}
+
+// First organization the user owns, created on demand for businesses added without one
+pub(crate) async fn get_or_create_owned_organization_in(tx: &mut Transaction<'_, Postgres>, user_id: Uuid) -> Result<Uuid, Error> {
+    let owned: Option<(Uuid,)> = sqlx::query_as("SELECT organization_id FROM organization_members WHERE user_id = $1 AND role = $2 ORDER BY created_at LIMIT 1")
+        .bind(user_id)
+        .bind(MemberRole::Owner.as_str())
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(Error::DatabaseQueryError)?;
+    if let Some((id,)) = owned {
+        return Ok(id);
+    }
+    let (first_name, last_name): (String, String) = sqlx::query_as("SELECT first_name, last_name FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(Error::DatabaseQueryError)?;
+    let organization = add_organization_in(tx, &format!("{} {}", first_name, last_name), user_id).await?;
+    Ok(organization.id)
+}
+
+impl Store {
+    pub async fn add_organization(&self, name: &str, owner_id: Uuid) -> Result<Organization, Error> {
+        let mut tx = self.begin().await?;
+        let organization = add_organization_in(&mut tx, name, owner_id).await?;
+        tx.commit().await
+            .map_err(Error::DatabaseQueryError)?;
+        Ok(organization)
+    }
+
+    pub async fn get_memberships_user_id(&self, user_id: Uuid) -> Result<Vec<Membership>, Error> {
+        let query = r#"
+            SELECT m.organization_id, o.name AS organization_name, m.role
+            FROM organization_members m
+            JOIN organizations o ON o.id = m.organization_id
+            WHERE m.user_id = $1
+            ORDER BY o.name
+        "#;
+        sqlx::query(query)
+            .bind(user_id)
+            .map(|row: PgRow| Membership {
+                organization_id: row.get("organization_id"),
+                organization_name: row.get("organization_name"),
+                role: MemberRole::parse(row.get("role")).unwrap_or(MemberRole::Viewer),
+            })
+            .fetch_all(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    pub async fn get_member_role(&self, organization_id: Uuid, user_id: Uuid) -> Result<Option<MemberRole>, Error> {
+        sqlx::query("SELECT role FROM organization_members WHERE organization_id = $1 AND user_id = $2")
+            .bind(organization_id)
+            .bind(user_id)
+            .map(|row: PgRow| MemberRole::parse(row.get("role")).unwrap_or(MemberRole::Viewer))
+            .fetch_optional(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    pub async fn get_members(&self, organization_id: Uuid) -> Result<Vec<Member>, Error> {
+        let query = r#"
+            SELECT m.user_id, u.first_name, u.last_name, u.email, m.role, m.created_at
+            FROM organization_members m
+            JOIN users u ON u.id = m.user_id
+            WHERE m.organization_id = $1
+            ORDER BY m.created_at
+        "#;
+        sqlx::query(query)
+            .bind(organization_id)
+            .map(|row: PgRow| Member {
+                user_id: row.get("user_id"),
+                first_name: row.get("first_name"),
+                last_name: row.get("last_name"),
+                email: row.get("email"),
+                role: MemberRole::parse(row.get("role")).unwrap_or(MemberRole::Viewer),
+                created_at: row.get("created_at"),
+            })
+            .fetch_all(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    pub async fn count_owners(&self, organization_id: Uuid) -> Result<i64, Error> {
+        let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM organization_members WHERE organization_id = $1 AND role = $2")
+            .bind(organization_id)
+            .bind(MemberRole::Owner.as_str())
+            .fetch_one(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        Ok(count)
+    }
+
+    // Organizations that would be left with members but no owner if the user went away
+    pub async fn get_sole_owned_organizations(&self, user_id: Uuid) -> Result<Vec<Uuid>, Error> {
+        let query = r#"
+            SELECT m.organization_id
+            FROM organization_members m
+            WHERE m.user_id = $1 AND m.role = $2
+                AND NOT EXISTS (SELECT 1 FROM organization_members o WHERE o.organization_id = m.organization_id AND o.role = $2 AND o.user_id <> $1)
+                AND EXISTS (SELECT 1 FROM organization_members o WHERE o.organization_id = m.organization_id AND o.user_id <> $1)
+        "#;
+        sqlx::query(query)
+            .bind(user_id)
+            .bind(MemberRole::Owner.as_str())
+            .map(|row: PgRow| row.get("organization_id"))
+            .fetch_all(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    pub async fn update_member_role(&self, organization_id: Uuid, user_id: Uuid, role: MemberRole) -> Result<bool, Error> {
+        sqlx::query("UPDATE organization_members SET role = $3, updated_at = $4 WHERE organization_id = $1 AND user_id = $2")
+            .bind(organization_id)
+            .bind(user_id)
+            .bind(role.as_str())
+            .bind(Utc::now())
+            .execute(&self.connection)
+            .await
+            .map(|result| result.rows_affected() > 0)
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    pub async fn delete_member(&self, organization_id: Uuid, user_id: Uuid) -> Result<bool, Error> {
+        sqlx::query("DELETE FROM organization_members WHERE organization_id = $1 AND user_id = $2")
+            .bind(organization_id)
+            .bind(user_id)
+            .execute(&self.connection)
+            .await
+            .map(|result| result.rows_affected() > 0)
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    pub async fn add_invitation(&self, organization_id: Uuid, email: &str, role: MemberRole, token_hash: &str, invited_by: Uuid, expires_at: DateTime<Utc>) -> Result<Invitation, Error> {
+        let query = r#"
+            INSERT INTO organization_invitations (organization_id, email, role, token_hash, invited_by, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, organization_id, email, role, expires_at, created_at
+        "#;
+        sqlx::query(query)
+            .bind(organization_id)
+            .bind(email)
+            .bind(role.as_str())
+            .bind(token_hash)
+            .bind(invited_by)
+            .bind(expires_at)
+            .map(|row: PgRow| Invitation {
+                id: row.get("id"),
+                organization_id: row.get("organization_id"),
+                email: row.get("email"),
+                role: MemberRole::parse(row.get("role")).unwrap_or(MemberRole::Viewer),
+                expires_at: row.get("expires_at"),
+                created_at: row.get("created_at"),
+            })
+            .fetch_one(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    // Marks the invitation used and adds the member, only for the email it was sent to.
+    // An existing member is refused and the invitation stays open, accepting it must not change their role
+    pub async fn accept_invitation(&self, token_hash: &str, user_id: Uuid, email: &str) -> Result<Membership, Error> {
+        let mut tx = self.connection.begin().await
+            .map_err(Error::DatabaseQueryError)?;
+        let query = r#"
+            UPDATE organization_invitations SET accepted_at = now()
+            WHERE token_hash = $1 AND accepted_at IS NULL AND expires_at > now() AND lower(email) = lower($2)
+            RETURNING organization_id, role
+        "#;
+        let accepted: Option<(Uuid, String)> = sqlx::query_as(query)
+            .bind(token_hash)
+            .bind(email)
+            .fetch_optional(&mut tx)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        let (organization_id, role) = accepted.ok_or(Error::InvalidInvitation)?;
+        let member_query = r#"
+            INSERT INTO organization_members (organization_id, user_id, role)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (organization_id, user_id) DO NOTHING
+        "#;
+        let added = sqlx::query(member_query)
+            .bind(organization_id)
+            .bind(user_id)
+            .bind(&role)
+            .execute(&mut tx)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        if added.rows_affected() == 0 {
+            return Err(Error::Conflict("already a member of the organization".to_string()));
+        }
+        let (organization_name,): (String,) = sqlx::query_as("SELECT name FROM organizations WHERE id = $1")
+            .bind(organization_id)
+            .fetch_one(&mut tx)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        tx.commit().await
+            .map_err(Error::DatabaseQueryError)?;
+        Ok(Membership {
+            organization_id,
+            organization_name,
+            role: MemberRole::parse(&role).unwrap_or(MemberRole::Viewer),
+        })
+    }
+
+    // Businesses of every organization the user belongs to, whatever their member role
+    pub async fn get_businesses_member_id(&self, user_id: Uuid) -> Result<Vec<Business>, Error> {
+        let query = r#"
+            SELECT b.*
+            FROM businesses b
+            JOIN organization_members m ON m.organization_id = b.organization_id
+            WHERE m.user_id = $1
+            ORDER BY b.created_at
+        "#;
+        sqlx::query(query)
+            .bind(user_id)
+            .map(|row: PgRow| {
+                Business {
+                    id: row.get("id"),
+                    user_id: row.get("user_id"),
+                    organization_id: row.get("organization_id"),
+                    name: row.get("name"),
+                    location: row.get("location"),
+                    geolocation: (0.0, 0.0),
+                    lat: row.get("lat"),
+                    long: row.get("lon"),
+                    created_at: row.get("created_at"),
+                    updated_at: row.get("updated_at"),
+                }
+            })
+            .fetch_all(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_owners_manage_members() {
+        for role in [MemberRole::Owner, MemberRole::Manager, MemberRole::Viewer] {
+            assert_eq!(MemberRole::parse(role.as_str()), Some(role));
+        }
+        assert!(MemberRole::Owner.can_manage_members());
+        assert!(!MemberRole::Manager.can_manage_members());
+        assert!(MemberRole::Manager.can_manage_devices());
+        assert!(!MemberRole::Viewer.can_manage_devices());
+        assert!(!MemberRole::Manager.can_change_payouts());
+        assert!(MemberRole::parse("admin").is_none());
+    }
+}
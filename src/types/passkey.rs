@@ -0,0 +1,138 @@
+use chrono::{DateTime, Utc};
+use handle_error::Error;
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgRow, Row};
+use uuid::Uuid;
+
+use crate::db_store::Store;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChallengeKind {
+    Registration,
+    Authentication,
+}
+
+impl ChallengeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChallengeKind::Registration => "registration",
+            ChallengeKind::Authentication => "authentication",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Passkey {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub credential_id: String,
+    #[serde(skip_serializing)]
+    pub public_key: Vec<u8>,
+    pub sign_count: i64,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+fn passkey_from_row(row: PgRow) -> Passkey {
+    Passkey {
+        id: row.get("id"),
+        user_id: row.get("user_id"),
+        credential_id: row.get("credential_id"),
+        public_key: row.get("public_key"),
+        sign_count: row.get("sign_count"),
+        name: row.get("name"),
+        created_at: row.get("created_at"),
+        last_used_at: row.get("last_used_at"),
+    }
+}
+
+impl Store {
+    pub async fn add_webauthn_challenge(&self, challenge: &str, user_id: Option<Uuid>, kind: ChallengeKind, expires_at: DateTime<Utc>) -> Result<bool, Error> {
+        sqlx::query("INSERT INTO webauthn_challenges (challenge, user_id, kind, expires_at) VALUES ($1, $2, $3, $4)")
+            .bind(challenge)
+            .bind(user_id)
+            .bind(kind.as_str())
+            .bind(expires_at)
+            .execute(&self.connection)
+            .await
+            .map(|_| true)
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    // Consumes the challenge so a ceremony response cannot be replayed, returns the user it was issued for
+    pub async fn take_webauthn_challenge(&self, challenge: &str, kind: ChallengeKind) -> Result<Option<Option<Uuid>>, Error> {
+        let query = r#"
+            DELETE FROM webauthn_challenges
+            WHERE challenge = $1 AND kind = $2
+            RETURNING user_id, expires_at
+        "#;
+        let taken = sqlx::query(query)
+            .bind(challenge)
+            .bind(kind.as_str())
+            .map(|row: PgRow| (row.get::<Option<Uuid>, _>("user_id"), row.get::<DateTime<Utc>, _>("expires_at")))
+            .fetch_optional(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        Ok(taken.filter(|(_, expires_at)| *expires_at > Utc::now()).map(|(user_id, _)| user_id))
+    }
+
+    pub async fn add_passkey(&self, user_id: Uuid, credential_id: &str, public_key: &[u8], sign_count: i64, name: &str) -> Result<Passkey, Error> {
+        let query = r#"
+            INSERT INTO passkeys (user_id, credential_id, public_key, sign_count, name)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING *
+        "#;
+        sqlx::query(query)
+            .bind(user_id)
+            .bind(credential_id)
+            .bind(public_key)
+            .bind(sign_count)
+            .bind(name)
+            .map(passkey_from_row)
+            .fetch_one(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    pub async fn get_passkeys_user_id(&self, user_id: Uuid) -> Result<Vec<Passkey>, Error> {
+        sqlx::query("SELECT * FROM passkeys WHERE user_id = $1 ORDER BY created_at")
+            .bind(user_id)
+            .map(passkey_from_row)
+            .fetch_all(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    pub async fn get_passkey_credential_id(&self, credential_id: &str) -> Result<Option<Passkey>, Error> {
+        sqlx::query("SELECT * FROM passkeys WHERE credential_id = $1")
+            .bind(credential_id)
+            .map(passkey_from_row)
+            .fetch_optional(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    // Compare-and-set on the stored counter, so two concurrent assertions cannot both succeed
+    pub async fn update_passkey_counter(&self, id: Uuid, previous: i64, sign_count: i64) -> Result<bool, Error> {
+        sqlx::query("UPDATE passkeys SET sign_count = $3, last_used_at = now() WHERE id = $1 AND sign_count = $2")
+            .bind(id)
+            .bind(previous)
+            .bind(sign_count)
+            .execute(&self.connection)
+            .await
+            .map(|result| result.rows_affected() > 0)
+            .map_err(Error::DatabaseQueryError)
+    }
+
+    pub async fn delete_passkey(&self, user_id: Uuid, id: Uuid) -> Result<bool, Error> {
+        sqlx::query("DELETE FROM passkeys WHERE id = $1 AND user_id = $2")
+            .bind(id)
+            .bind(user_id)
+            .execute(&self.connection)
+            .await
+            .map(|result| result.rows_affected() > 0)
+            .map_err(Error::DatabaseQueryError)
+    }
+}
@@ -1,10 +1,11 @@
+use chrono::{DateTime, Utc};
 use handle_error::Error;
 use serde::{Serialize, Deserialize};
 use sqlx::postgres::PgRow;
 use sqlx::Row;
 use uuid::Uuid;
 
-use crate::db_store::Store;
+use crate::{db_store::Store, types::{admin::{Page, PageQuery}, geofence::GeofenceCheck}};
 
 // ========== Structs ==========
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -21,15 +22,83 @@ pub struct Payment {
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct PaymentResponse {
-   device_id: uuid::Uuid,
+   id: uuid::Uuid,
+   device_id: Option<uuid::Uuid>,
+   business_id: Option<uuid::Uuid>,
    amount: i64,
    bank_id: String,
    account_name: String,
    account_number: String,
-   device_name: String,
-   device_type: String,
+   account_id: Option<uuid::Uuid>,
+   device_name: Option<String>,
+   device_type: Option<String>,
    customer_first_name: String,
    customer_last_name: String,
+   latitude: Option<f64>,
+   longitude: Option<f64>,
+   distance_m: Option<f64>,
+   geofence_flagged: bool,
+   location_missing: bool,
+   created_at: DateTime<Utc>,
+}
+
+// `?flagged=&business_id=&page=&per_page=` for the admin payment list
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PaymentSearchQuery {
+    pub flagged: Option<bool>,
+    pub business_id: Option<Uuid>,
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+}
+
+impl PaymentSearchQuery {
+    fn page_query(&self) -> PageQuery {
+        PageQuery { q: None, page: self.page, per_page: self.per_page }
+    }
+}
+
+const PAYMENT_COLUMNS: &str = r#"
+    p.id,
+    p.device_id,
+    d.business_id,
+    p.amount,
+    p.bank_id,
+    p.account_name,
+    p.account_number,
+    p.account_id,
+    d.name as device_name,
+    d.device_type,
+    c.first_name as customer_first_name,
+    c.last_name as customer_last_name,
+    p.latitude,
+    p.longitude,
+    p.distance_m,
+    p.geofence_flagged,
+    p.location_missing,
+    p.created_at
+"#;
+
+fn payment_response_from_row(row: PgRow) -> PaymentResponse {
+    PaymentResponse {
+        id: row.get("id"),
+        device_id: row.get("device_id"),
+        business_id: row.get("business_id"),
+        amount: row.get("amount"),
+        bank_id: row.get("bank_id"),
+        account_name: row.get("account_name"),
+        account_number: row.get("account_number"),
+        account_id: row.get("account_id"),
+        device_name: row.get("device_name"),
+        device_type: row.get("device_type"),
+        customer_first_name: row.get("customer_first_name"),
+        customer_last_name: row.get("customer_last_name"),
+        latitude: row.get("latitude"),
+        longitude: row.get("longitude"),
+        distance_m: row.get("distance_m"),
+        geofence_flagged: row.get("geofence_flagged"),
+        location_missing: row.get("location_missing"),
+        created_at: row.get("created_at"),
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -45,19 +114,22 @@ pub struct PaymentSend {
 
 // ========== Store Implementation ==========
 impl Store {
+    // The account comes from the assignment in force when the payment lands, not from what the terminal last read
     pub async fn add_payment(
         &self,
         device_id: Uuid,
         amount: i64,
         customer_id: Uuid,
         user_id: Uuid,
-        bank_id: String,
-        account_name: String,
-        account_number: String,
+        geofence: &GeofenceCheck,
     ) -> Result<PaymentSend, Error> {
         let query = r#"
-            INSERT INTO payments (device_id, amount, customer_id, user_id, bank_id, account_name, account_number)
-            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            INSERT INTO payments (device_id, amount, customer_id, user_id, bank_id, account_name, account_number, account_id, latitude, longitude, distance_m, geofence_flagged, location_missing)
+            SELECT $1, $2, $3, $4, a.bank_id, a.account_name, a.account_number, a.id, $5, $6, $7, $8, $9
+            FROM devices d
+            JOIN device_account_assignments das ON das.device_id = d.id AND das.effective_to IS NULL
+            JOIN accounts a ON a.id = das.account_id
+            WHERE d.id = $1
             RETURNING device_id, amount, customer_id, user_id, bank_id, account_name, account_number
         "#;
 
@@ -67,9 +139,11 @@ impl Store {
             .bind(amount)
             .bind(customer_id)
             .bind(user_id)
-            .bind(bank_id.clone())
-            .bind(account_name.clone())
-            .bind(account_number.clone())
+            .bind(geofence.location.as_ref().map(|location| location.latitude))
+            .bind(geofence.location.as_ref().map(|location| location.longitude))
+            .bind(geofence.distance_m)
+            .bind(geofence.flagged)
+            .bind(geofence.location_missing)
             .fetch_one(&self.connection)
             .await
             .map_err(|e| Error::DatabaseQueryError(e))?;
@@ -85,42 +159,59 @@ impl Store {
         })
     }
 
-    pub async fn get_payments_user_id(&self, user_id: Uuid) -> Result<Vec<PaymentResponse>, Error> {
-        let query = r#"
-            SELECT
-                p.device_id,
-                p.amount,
-                p.bank_id,
-                p.account_name,
-                p.account_number,
-                d.name as device_name,
-                d.device_type,
-                c.first_name as customer_first_name,
-                c.last_name as customer_last_name
+    // Payments taken by devices of any business the user can see through organization membership.
+    // Payments whose device was lost before the devices merge have no `device_id`, they stay with the user they were paid to
+    pub async fn get_payments_member_id(&self, user_id: Uuid, flagged_only: bool) -> Result<Vec<PaymentResponse>, Error> {
+        let query = format!(r#"
+            SELECT {}
             FROM payments p
             JOIN customers c ON c.id = p.customer_id
-            JOIN devices_accessible d ON d.id = p.device_id
-            WHERE p.user_id = $1
-        "#;
-        
-        sqlx::query(query)
+            LEFT JOIN devices d ON d.id = p.device_id
+            LEFT JOIN businesses b ON b.id = d.business_id
+            WHERE (NOT $2 OR p.geofence_flagged)
+            AND (
+                b.organization_id IN (SELECT organization_id FROM organization_members WHERE user_id = $1)
+                OR (p.device_id IS NULL AND p.user_id = $1)
+            )
+            ORDER BY p.created_at DESC
+        "#, PAYMENT_COLUMNS);
+
+        sqlx::query(&query)
             .bind(user_id)
-            .map(|row: PgRow| PaymentResponse {
-                device_id: row.get("device_id"),
-                amount: row.get("amount"),
-                bank_id: row.get("bank_id"),
-                account_name: row.get("account_name"),
-                account_number: row.get("account_number"),
-                device_name: row.get("device_name"),
-                device_type: row.get("device_type"),
-                customer_first_name: row.get("customer_first_name"),
-                customer_last_name: row.get("customer_last_name"),
-            })
+            .bind(flagged_only)
+            .map(payment_response_from_row)
             .fetch_all(&self.connection)
             .await
             .map_err(|e| Error::DatabaseQueryError(e))
     }
 
+    pub async fn search_payments(&self, filter: &PaymentSearchQuery) -> Result<Page<PaymentResponse>, Error> {
+        let page = filter.page_query();
+        let from = r#"
+            FROM payments p
+            JOIN customers c ON c.id = p.customer_id
+            LEFT JOIN devices d ON d.id = p.device_id
+            WHERE ($1::boolean IS NULL OR p.geofence_flagged = $1)
+            AND ($2::uuid IS NULL OR d.business_id = $2)
+        "#;
+        let (total,): (i64,) = sqlx::query_as(&format!("SELECT COUNT(*) {}", from))
+            .bind(filter.flagged)
+            .bind(filter.business_id)
+            .fetch_one(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        let list = sqlx::query(&format!("SELECT {} {} ORDER BY p.created_at DESC LIMIT $3 OFFSET $4", PAYMENT_COLUMNS, from))
+            .bind(filter.flagged)
+            .bind(filter.business_id)
+            .bind(page.per_page())
+            .bind(page.offset())
+            .map(payment_response_from_row)
+            .fetch_all(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        Ok(page.to_page(list, total))
+    }
+
     // pub async fn update_payment(
     //     &self,
     //     device_id: &Uuid,
@@ -0,0 +1,28 @@
+use chrono::{DateTime, Utc};
+use handle_error::Error;
+use uuid::Uuid;
+
+use crate::db_store::Store;
+
+impl Store {
+    // False when the terminal already used this nonce inside the window, expired nonces are pruned on the way
+    pub async fn claim_request_nonce(&self, device_id: Uuid, nonce: &str, expires_at: DateTime<Utc>) -> Result<bool, Error> {
+        sqlx::query("DELETE FROM request_nonces WHERE expires_at < now()")
+            .execute(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)?;
+        let query = r#"
+            INSERT INTO request_nonces (device_id, nonce, expires_at)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (device_id, nonce) DO NOTHING
+        "#;
+        sqlx::query(query)
+            .bind(device_id)
+            .bind(nonce)
+            .bind(expires_at)
+            .execute(&self.connection)
+            .await
+            .map(|result| result.rows_affected() == 1)
+            .map_err(Error::DatabaseQueryError)
+    }
+}
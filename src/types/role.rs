@@ -0,0 +1,97 @@
+use serde::{Deserialize, Serialize};
+
+// Platform wide role stored on `users.role`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Merchant,
+    BankOperator,
+    Admin,
+}
+
+// What a route needs before its handler runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    ReadProfile,
+    UpdateProfile,
+    ReadBusinesses,
+    ReadAccounts,
+    ReadPayments,
+    ReadBank,
+    ManageApiKeys,
+    ManageOrganizations,
+    ManageDevices,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Merchant => "merchant",
+            Role::BankOperator => "bank_operator",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Role> {
+        match value {
+            "merchant" => Some(Role::Merchant),
+            "bank_operator" => Some(Role::BankOperator),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+
+    pub fn permissions(&self) -> &'static [Permission] {
+        match self {
+            Role::Merchant => &[
+                Permission::ReadProfile,
+                Permission::UpdateProfile,
+                Permission::ReadBusinesses,
+                Permission::ReadAccounts,
+                Permission::ReadPayments,
+                Permission::ManageOrganizations,
+                Permission::ManageDevices,
+            ],
+            Role::BankOperator => &[
+                Permission::ReadProfile,
+                Permission::UpdateProfile,
+                Permission::ReadBank,
+                Permission::ManageApiKeys,
+            ],
+            // Admins pass every check, see `allows`
+            Role::Admin => &[],
+        }
+    }
+
+    pub fn allows(&self, permission: Permission) -> bool {
+        *self == Role::Admin || self.permissions().contains(&permission)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merchant_cannot_read_bank() {
+        assert!(!Role::Merchant.allows(Permission::ReadBank));
+        assert!(Role::BankOperator.allows(Permission::ReadBank));
+        assert!(Role::Admin.allows(Permission::ReadBank));
+    }
+
+    #[test]
+    fn only_admin_has_admin_permission() {
+        assert!(!Role::Merchant.allows(Permission::Admin));
+        assert!(!Role::BankOperator.allows(Permission::Admin));
+        assert!(Role::Admin.allows(Permission::Admin));
+    }
+
+    #[test]
+    fn role_round_trips_through_db_value() {
+        for role in [Role::Merchant, Role::BankOperator, Role::Admin] {
+            assert_eq!(Role::parse(role.as_str()), Some(role));
+        }
+        assert_eq!(Role::parse("root"), None);
+    }
+}
@@ -0,0 +1,419 @@
+use std::{env, sync::{Mutex, RwLock}, time::Instant};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::{DateTime, Duration, Utc};
+use encrypt::{generate_random_char, paseto};
+use handle_error::Error;
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::{PgExecutor, PgRow}, Postgres, Row, Transaction};
+use uuid::Uuid;
+
+use crate::{db_store::Store, tools::constant::SESSION_KEY};
+
+const KID_LEN: usize = 16;
+pub const ACCESS_TOKEN_HOURS: i64 = 24;
+pub const REFRESHED_ACCESS_TOKEN_MINUTES: i64 = 15;
+pub const REFRESH_TOKEN_DAYS: i64 = 7;
+// Unknown kids reload the keyset from the database at most this often, forged footers cannot hammer it
+const KEYSET_RELOAD_INTERVAL_SECS: u64 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenKind {
+    Access,
+    Refresh,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyStatus {
+    Active,
+    Retired,
+}
+
+impl KeyStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KeyStatus::Active => "active",
+            KeyStatus::Retired => "retired",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<KeyStatus> {
+        match value {
+            "active" => Some(KeyStatus::Active),
+            "retired" => Some(KeyStatus::Retired),
+            _ => None,
+        }
+    }
+}
+
+// Session keys rotate freely, sticker keys sign QR codes that stay printed for years
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyPurpose {
+    Session,
+    Sticker,
+}
+
+impl KeyPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KeyPurpose::Session => "session",
+            KeyPurpose::Sticker => "sticker",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Claims {
+    pub sub: Uuid,
+    pub token_type: TokenKind,
+    pub iat: DateTime<Utc>,
+    pub nbf: DateTime<Utc>,
+    pub exp: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct Footer {
+    kid: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenPair {
+    pub access_token: String,
+    pub refresh_token: String,
+}
+
+// Row of `signing_keys`, the sealed secret never leaves the store layer
+#[derive(Debug, Clone)]
+pub struct SigningKeyRecord {
+    pub kid: String,
+    pub public_key: Vec<u8>,
+    pub sealed_secret_key: Vec<u8>,
+    pub status: KeyStatus,
+    pub created_at: DateTime<Utc>,
+    pub retired_at: Option<DateTime<Utc>>,
+}
+
+// What `/api/token/keys` publishes so other services can verify our tokens
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PublicKey {
+    pub kid: String,
+    pub version: String,
+    pub purpose: String,
+    pub public_key: String,
+    pub status: KeyStatus,
+    pub created_at: DateTime<Utc>,
+    pub retired_at: Option<DateTime<Utc>>,
+}
+
+struct KeyEntry {
+    kid: String,
+    public_key: Vec<u8>,
+    // Only the active key is unsealed, retired keys verify and nothing else
+    secret_key: Option<Vec<u8>>,
+    status: KeyStatus,
+    created_at: DateTime<Utc>,
+    retired_at: Option<DateTime<Utc>>,
+}
+
+// Issues and verifies the tokens of one key purpose, held in `Cache` and reloaded when keys rotate
+pub struct TokenService {
+    purpose: KeyPurpose,
+    keys: RwLock<Vec<KeyEntry>>,
+    reloaded_at: Mutex<Option<Instant>>,
+}
+
+fn key_encryption_key() -> Result<Vec<u8>, Error> {
+    env::var(SESSION_KEY)
+        .map(|key| key.into_bytes())
+        .map_err(Error::EnvError)
+}
+
+impl TokenService {
+    // Creates the first signing key of the purpose when there is none
+    pub async fn load(store: &Store, purpose: KeyPurpose) -> Result<TokenService, Error> {
+        let service = TokenService { purpose, keys: RwLock::new(Vec::new()), reloaded_at: Mutex::new(None) };
+        service.reload(store).await?;
+        let has_active = service.keys.read()
+            .map(|keys| keys.iter().any(|key| key.status == KeyStatus::Active))
+            .unwrap_or(false);
+        if !has_active {
+            let mut tx = store.begin().await?;
+            service.rotate(&mut tx).await?;
+            tx.commit().await
+                .map_err(Error::DatabaseQueryError)?;
+            service.reload(store).await?;
+        }
+        Ok(service)
+    }
+
+    pub async fn reload(&self, store: &Store) -> Result<(), Error> {
+        let kek = key_encryption_key()?;
+        let records = store.get_signing_keys(self.purpose).await?;
+        let mut entries = Vec::new();
+        for record in records {
+            let secret_key = match record.status {
+                KeyStatus::Active => Some(paseto::open_secret(&kek, &record.sealed_secret_key)
+                    .map_err(|e| Error::InvalidSessionKey(e.to_string()))?),
+                KeyStatus::Retired => None,
+            };
+            entries.push(KeyEntry {
+                kid: record.kid,
+                public_key: record.public_key,
+                secret_key,
+                status: record.status,
+                created_at: record.created_at,
+                retired_at: record.retired_at,
+            });
+        }
+        if let Ok(mut keys) = self.keys.write() {
+            *keys = entries;
+        }
+        if let Ok(mut reloaded_at) = self.reloaded_at.lock() {
+            *reloaded_at = Some(Instant::now());
+        }
+        Ok(())
+    }
+
+    // Claims the next reload slot, only one caller per interval gets it
+    fn reload_due(&self) -> bool {
+        let Ok(mut reloaded_at) = self.reloaded_at.lock() else {
+            return false;
+        };
+        match *reloaded_at {
+            Some(at) if at.elapsed().as_secs() < KEYSET_RELOAD_INTERVAL_SECS => false,
+            _ => {
+                *reloaded_at = Some(Instant::now());
+                true
+            }
+        }
+    }
+
+    // New active key, the previous one is retired but keeps verifying until it is deleted.
+    // Signing switches over at the next `reload`, once the caller's transaction has committed
+    pub async fn rotate(&self, tx: &mut Transaction<'_, Postgres>) -> Result<PublicKey, Error> {
+        let kek = key_encryption_key()?;
+        let (secret_key, public_key) = paseto::generate_signing_key();
+        let sealed = paseto::seal_secret(&kek, &secret_key)
+            .map_err(|e| Error::InvalidSessionKey(e.to_string()))?;
+        let kid = generate_random_char(KID_LEN);
+        let record = add_signing_key_in(tx, self.purpose, &kid, &public_key, &sealed).await?;
+        Ok(PublicKey::from(&record))
+    }
+
+    pub fn public_keys(&self) -> Vec<PublicKey> {
+        self.keys.read()
+            .map(|keys| keys.iter().map(|key| PublicKey {
+                kid: key.kid.clone(),
+                version: "v4".to_string(),
+                purpose: "public".to_string(),
+                public_key: URL_SAFE_NO_PAD.encode(&key.public_key),
+                status: key.status,
+                created_at: key.created_at,
+                retired_at: key.retired_at,
+            }).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn issue(&self, user_id: Uuid, kind: TokenKind, lifetime: Duration) -> Result<String, Error> {
+        let now = Utc::now();
+        let claims = Claims { sub: user_id, token_type: kind, iat: now, nbf: now, exp: now + lifetime };
+        self.sign(&claims, b"")
+    }
+
+    // Signs any payload with the active key, `implicit` keeps tokens of one purpose from passing as another
+    pub fn sign<T: Serialize>(&self, payload: &T, implicit: &[u8]) -> Result<String, Error> {
+        let message = serde_json::to_vec(payload)
+            .map_err(|e| Error::TokenCreationError(e.to_string()))?;
+        let keys = self.keys.read()
+            .map_err(|_| Error::TokenCreationError("keyset is unavailable".to_string()))?;
+        let active = keys.iter()
+            .find(|key| key.status == KeyStatus::Active)
+            .ok_or(Error::TokenCreationError("no active signing key".to_string()))?;
+        let secret_key = active.secret_key.as_ref()
+            .ok_or(Error::TokenCreationError("active signing key is sealed".to_string()))?;
+        let footer = serde_json::to_vec(&Footer { kid: active.kid.clone() })
+            .map_err(|e| Error::TokenCreationError(e.to_string()))?;
+        paseto::sign(secret_key, &message, &footer, implicit)
+            .map_err(|e| Error::TokenCreationError(e.to_string()))
+    }
+
+    pub fn issue_pair(&self, user_id: Uuid) -> Result<TokenPair, Error> {
+        Ok(TokenPair {
+            access_token: self.issue(user_id, TokenKind::Access, Duration::hours(ACCESS_TOKEN_HOURS))?,
+            refresh_token: self.issue(user_id, TokenKind::Refresh, Duration::days(REFRESH_TOKEN_DAYS))?,
+        })
+    }
+
+    // A kid we have not seen may come from a rotation on another instance, reload before refusing
+    // unless the keyset was refreshed moments ago
+    pub async fn verify(&self, store: &Store, token: &str, kind: TokenKind) -> Result<Uuid, Error> {
+        let footer = paseto::footer(token).map_err(|_| Error::CannotDecryptToken)?;
+        let footer: Footer = serde_json::from_slice(&footer).map_err(|_| Error::CannotDecryptToken)?;
+        if self.public_key(&footer.kid).is_none() && self.reload_due() {
+            self.reload(store).await?;
+        }
+        let public_key = self.public_key(&footer.kid).ok_or(Error::CannotDecryptToken)?;
+        let message = paseto::verify(&public_key, token, b"").map_err(|_| Error::CannotDecryptToken)?;
+        let claims: Claims = serde_json::from_slice(&message)
+            .map_err(|_| Error::InvalidSessionKey("Missing or invalid claims".to_string()))?;
+        check_claims(&claims, kind, Utc::now())?;
+        Ok(claims.sub)
+    }
+
+    fn public_key(&self, kid: &str) -> Option<Vec<u8>> {
+        self.keys.read().ok()?
+            .iter()
+            .find(|key| key.kid == kid)
+            .map(|key| key.public_key.clone())
+    }
+}
+
+fn check_claims(claims: &Claims, kind: TokenKind, now: DateTime<Utc>) -> Result<(), Error> {
+    if claims.token_type != kind {
+        return Err(Error::InvalidSessionKey("Unexpected token type".to_string()));
+    }
+    if now < claims.nbf || now >= claims.exp {
+        return Err(Error::CannotDecryptToken);
+    }
+    Ok(())
+}
+
+impl From<&SigningKeyRecord> for PublicKey {
+    fn from(record: &SigningKeyRecord) -> Self {
+        PublicKey {
+            kid: record.kid.clone(),
+            version: "v4".to_string(),
+            purpose: "public".to_string(),
+            public_key: URL_SAFE_NO_PAD.encode(&record.public_key),
+            status: record.status,
+            created_at: record.created_at,
+            retired_at: record.retired_at,
+        }
+    }
+}
+
+fn signing_key_from_row(row: PgRow) -> SigningKeyRecord {
+    SigningKeyRecord {
+        kid: row.get("kid"),
+        public_key: row.get("public_key"),
+        sealed_secret_key: row.get("sealed_secret_key"),
+        status: KeyStatus::parse(row.get("status")).unwrap_or(KeyStatus::Retired),
+        created_at: row.get("created_at"),
+        retired_at: row.get("retired_at"),
+    }
+}
+
+// Retires the purpose's current active key and inserts the new one in the same transaction
+pub(crate) async fn add_signing_key_in(tx: &mut Transaction<'_, Postgres>, purpose: KeyPurpose, kid: &str, public_key: &[u8], sealed_secret_key: &[u8]) -> Result<SigningKeyRecord, Error> {
+    sqlx::query("UPDATE signing_keys SET status = $1, retired_at = now() WHERE status = $2 AND purpose = $3")
+        .bind(KeyStatus::Retired.as_str())
+        .bind(KeyStatus::Active.as_str())
+        .bind(purpose.as_str())
+        .execute(&mut *tx)
+        .await
+        .map_err(Error::DatabaseQueryError)?;
+    let query = r#"
+        INSERT INTO signing_keys (kid, public_key, sealed_secret_key, status, purpose)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING *
+    "#;
+    let record = sqlx::query(query)
+        .bind(kid)
+        .bind(public_key)
+        .bind(sealed_secret_key)
+        .bind(KeyStatus::Active.as_str())
+        .bind(purpose.as_str())
+        .map(signing_key_from_row)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(Error::DatabaseQueryError)?;
+    Ok(record)
+}
+
+// Only retired session keys can go, tokens signed by them stop verifying.
+// Sticker keys stay published for as long as printed stickers may be scanned
+pub(crate) async fn delete_signing_key_in<'c>(executor: impl PgExecutor<'c>, kid: &str) -> Result<bool, Error> {
+    sqlx::query("DELETE FROM signing_keys WHERE kid = $1 AND status = $2 AND purpose = $3")
+        .bind(kid)
+        .bind(KeyStatus::Retired.as_str())
+        .bind(KeyPurpose::Session.as_str())
+        .execute(executor)
+        .await
+        .map(|result| result.rows_affected() > 0)
+        .map_err(Error::DatabaseQueryError)
+}
+
+impl Store {
+    pub async fn get_signing_keys(&self, purpose: KeyPurpose) -> Result<Vec<SigningKeyRecord>, Error> {
+        sqlx::query("SELECT * FROM signing_keys WHERE purpose = $1 ORDER BY created_at DESC")
+            .bind(purpose.as_str())
+            .map(signing_key_from_row)
+            .fetch_all(&self.connection)
+            .await
+            .map_err(Error::DatabaseQueryError)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service_with_key(status: KeyStatus) -> TokenService {
+        let (secret_key, public_key) = paseto::generate_signing_key();
+        TokenService {
+            purpose: KeyPurpose::Session,
+            keys: RwLock::new(vec![KeyEntry {
+                kid: "test".to_string(),
+                public_key: public_key.to_vec(),
+                secret_key: Some(secret_key.to_vec()),
+                status,
+                created_at: Utc::now(),
+                retired_at: None,
+            }]),
+            reloaded_at: Mutex::new(None),
+        }
+    }
+
+    #[test]
+    fn signed_payload_is_bound_to_its_assertion() {
+        let service = service_with_key(KeyStatus::Active);
+        let token = service.sign(&serde_json::json!({"device_id": "abc"}), b"device-qr").unwrap();
+        let public_key = service.public_key("test").unwrap();
+        assert!(paseto::verify(&public_key, &token, b"device-qr").is_ok());
+        assert!(paseto::verify(&public_key, &token, b"").is_err());
+    }
+
+    #[test]
+    fn issued_token_carries_kid_and_claims() {
+        let service = service_with_key(KeyStatus::Active);
+        let user_id = Uuid::new_v4();
+        let token = service.issue(user_id, TokenKind::Refresh, Duration::days(1)).unwrap();
+        assert!(token.starts_with("v4.public."));
+        let footer: Footer = serde_json::from_slice(&paseto::footer(&token).unwrap()).unwrap();
+        assert_eq!(footer.kid, "test");
+        let message = paseto::verify(&service.public_key("test").unwrap(), &token, b"").unwrap();
+        let claims: Claims = serde_json::from_slice(&message).unwrap();
+        assert_eq!(claims.sub, user_id);
+        assert!(check_claims(&claims, TokenKind::Refresh, Utc::now()).is_ok());
+        assert!(check_claims(&claims, TokenKind::Access, Utc::now()).is_err());
+        assert!(check_claims(&claims, TokenKind::Refresh, claims.exp).is_err());
+    }
+
+    #[test]
+    fn unknown_kids_reload_once_per_interval() {
+        let service = service_with_key(KeyStatus::Active);
+        assert!(service.reload_due());
+        assert!(!service.reload_due());
+    }
+
+    #[test]
+    fn retired_key_does_not_sign() {
+        let service = service_with_key(KeyStatus::Retired);
+        assert!(service.issue(Uuid::new_v4(), TokenKind::Access, Duration::hours(1)).is_err());
+        assert_eq!(service.public_keys().len(), 1);
+    }
+}
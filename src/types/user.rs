@@ -1,10 +1,10 @@
 use chrono::prelude::*;
 use handle_error::Error;
 use serde::{Deserialize, Serialize};
-use sqlx::{postgres::PgRow, Row};
+use sqlx::{postgres::{PgExecutor, PgRow}, Postgres, Row, Transaction};
 use uuid::Uuid;
 
-use crate::db_store::Store;
+use crate::{db_store::Store, types::role::Role};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Session {
@@ -22,6 +22,8 @@ pub struct User {
     pub first_name: String,
     pub email: String,
     pub last_name: String,
+    pub role: Role,
+    pub deleted_at: Option<DateTime<Utc>>,
 }
 
 pub struct UserSend {
@@ -31,25 +33,93 @@ pub struct UserSend {
     pub email: String
 }
 
-impl Store {
-    pub async fn add_user(&self, first_name: String, hashed_password: String, last_name: String, email: String) -> Result<UserSend, Error> {
-        let query = r#"
-            INSERT INTO users (hashed_password, first_name, last_name, email)
-            VALUES ($1, $2, $3, $4)
-            RETURNING id
-        "#;
-        let (id,): (Uuid,) = sqlx::query_as(query)
-            .bind(hashed_password)
-            .bind(first_name.clone())
-            .bind(last_name.clone())
-            .bind(email.clone())
-            .fetch_one(&self.connection)
+pub(crate) async fn add_user_in<'c>(executor: impl PgExecutor<'c>, first_name: String, hashed_password: String, last_name: String, email: String) -> Result<UserSend, Error> {
+    let query = r#"
+        INSERT INTO users (hashed_password, first_name, last_name, email)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id
+    "#;
+    let (id,): (Uuid,) = sqlx::query_as(query)
+        .bind(hashed_password)
+        .bind(first_name.clone())
+        .bind(last_name.clone())
+        .bind(email.clone())
+        .fetch_one(executor)
+        .await
+        .map_err(Error::DatabaseQueryError)?;
+
+    Ok(
+        UserSend { id: id, first_name: first_name, last_name: last_name, email: email }
+    )
+}
+
+pub(crate) async fn update_user_in<'c>(executor: impl PgExecutor<'c>, first_name: &String, email: &String, last_name: &String, id: &Uuid) -> Result<bool, Error> {
+    let query = r#"
+        UPDATE users
+        SET first_name = $2, last_name = $3, email = $4, updated_at = $5
+        WHERE id = $1
+    "#;
+    sqlx::query(query)
+        .bind(id)
+        .bind(first_name)
+        .bind(last_name)
+        .bind(email)
+        .bind(Utc::now())
+        .execute(executor)
+        .await
+        .map(|_| true)
+        .map_err(Error::DatabaseQueryError)
+}
+
+pub(crate) async fn update_user_role_in<'c>(executor: impl PgExecutor<'c>, id: &Uuid, role: Role) -> Result<bool, Error> {
+    sqlx::query("UPDATE users SET role = $2, updated_at = $3 WHERE id = $1")
+        .bind(id)
+        .bind(role.as_str())
+        .bind(Utc::now())
+        .execute(executor)
+        .await
+        .map(|_| true)
+        .map_err(Error::DatabaseQueryError)
+}
+
+// Overwrites the profile instead of deleting the row, payments keep referencing it for compliance.
+// Everything that could sign the user back in goes in the same transaction.
+pub(crate) async fn anonymize_user_in(tx: &mut Transaction<'_, Postgres>, id: Uuid) -> Result<bool, Error> {
+    let query = r#"
+        UPDATE users
+        SET first_name = 'Deleted', last_name = 'User', email = 'deleted-' || id || '@deleted.invalid',
+            hashed_password = '', deleted_at = now(), updated_at = now()
+        WHERE id = $1 AND deleted_at IS NULL
+    "#;
+    let updated = sqlx::query(query)
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .map_err(Error::DatabaseQueryError)?;
+    if updated.rows_affected() == 0 {
+        return Ok(false);
+    }
+    for cleanup in [
+        "DELETE FROM sessions WHERE user_id = $1",
+        "DELETE FROM passkeys WHERE user_id = $1",
+        "DELETE FROM webauthn_challenges WHERE user_id = $1",
+        "DELETE FROM magic_links WHERE user_id = $1",
+        "DELETE FROM organization_members WHERE user_id = $1",
+        "UPDATE api_keys SET revoked_at = now() WHERE revoked_at IS NULL AND (created_by = $1 OR bank_id IN (SELECT id FROM banks WHERE user_id = $1))",
+        "UPDATE oauth_access_tokens SET revoked_at = now() WHERE revoked_at IS NULL AND bank_id IN (SELECT id FROM banks WHERE user_id = $1)",
+    ] {
+        sqlx::query(cleanup)
+            .bind(id)
+            .execute(&mut *tx)
             .await
-            .map_err(|e| Error::DatabaseQueryError(e))?;
+            .map_err(Error::DatabaseQueryError)?;
+    }
+    Ok(true)
+}
 
-        Ok(
-            UserSend { id: id, first_name: first_name, last_name: last_name, email: email }
-        )
+impl Store {
+    pub async fn add_user(&self, first_name: String, hashed_password: String, last_name: String, email: String) -> Result<UserSend, Error> {
+        add_user_in(&self.connection, first_name, hashed_password, last_name, email).await
     }
 
     pub async fn get_user(&self, id: Uuid) -> Result<User, Error> {
@@ -63,13 +133,15 @@ impl Store {
                 first_name: row.get("first_name"),
                 last_name: row.get("last_name"),
                 email: row.get("email"),
+                role: Role::parse(row.get("role")).unwrap_or(Role::Merchant),
+                deleted_at: row.get("deleted_at"),
             })
             .fetch_one(&self.connection)
             .await
             .map_err(|e| Error::DatabaseQueryError(e))
     }
     
-    pub async fn get_user_by_email(&self, email: String) -> Result<User, Error> {
+    pub async fn get_user_by_email(&self, email: String) -> Result<Option<User>, Error> {
         sqlx::query("SELECT * FROM users WHERE email = $1")
             .bind(email)
             .map(|row: PgRow| User {
@@ -80,36 +152,25 @@ impl Store {
                 first_name: row.get("first_name"),
                 last_name: row.get("last_name"),
                 email: row.get("email"),
+                role: Role::parse(row.get("role")).unwrap_or(Role::Merchant),
+                deleted_at: row.get("deleted_at"),
             })
-            .fetch_one(&self.connection)
+            .fetch_optional(&self.connection)
             .await
             .map_err(|e| Error::DatabaseQueryError(e))
     }
 
     pub async fn update_user(&self, first_name: &String, email: &String, last_name: &String, id: &Uuid) -> Result<bool, Error> {
-        let query = r#"
-            UPDATE users
-            SET first_name = $2, last_name = $3, email = $4, updated_at = $5
-            WHERE id = $1
-        "#;
-        sqlx::query(query)
-            .bind(id)
-            .bind(first_name)
-            .bind(last_name)
-            .bind(email)
-            .bind(Utc::now())
-            .execute(&self.connection)
-            .await
-            .map(|_| true)
-            .map_err(|e| Error::DatabaseQueryError(e))
+        update_user_in(&self.connection, first_name, email, last_name, id).await
     }
 
-    // pub async fn delete_user(&self, id: Uuid) -> Result<bool, Error> {
-    //     sqlx::query("DELETE FROM users WHERE id = $1")
-    //         .bind(id)
-    //         .execute(&self.connection)
-    //         .await
-    //         .map(|_| true)
-    //         .map_err(|e| Error::DatabaseQueryError(e))
-    // }
+    pub async fn anonymize_user(&self, id: Uuid) -> Result<bool, Error> {
+        let mut tx = self.begin().await?;
+        if !anonymize_user_in(&mut tx, id).await? {
+            return Ok(false);
+        }
+        tx.commit().await
+            .map(|_| true)
+            .map_err(Error::DatabaseQueryError)
+    }
 }
\ No newline at end of file